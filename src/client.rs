@@ -0,0 +1,199 @@
+//! 管理APIの型付き非同期クライアント（`client` フィーチャー有効時のみ）
+//!
+//! リクエスト・レスポンスにはサーバーと同じ `models` の型を使う。
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), local_dns_pro::client::ClientError> {
+//! use local_dns_pro::client::LocalDnsClient;
+//! use local_dns_pro::models::{CreateRecordRequest, PatternType};
+//!
+//! let client = LocalDnsClient::new("127.0.0.1:3000").with_api_key("ldns_...");
+//! let ids = client
+//!     .create_record(&CreateRecordRequest {
+//!         domain_pattern: "app.local.test".to_string(),
+//!         record_type: "A".to_string(),
+//!         content: "10.0.0.1".into(),
+//!         ttl: 60,
+//!         priority: 0,
+//!         weight: 1,
+//!         passthrough: false,
+//!         pattern_type: PatternType::Glob,
+//!         client_subnet: None,
+//!         tags: Vec::new(),
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::models::{CreateRecordRequest, Record, UpdateSettingRequest};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, HOST};
+use hyper::Method;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+
+/// クライアントのエラー
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("サーバーへの接続に失敗しました: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("HTTP通信に失敗しました: {0}")]
+    Http(#[from] hyper::Error),
+    #[error("リクエストの作成に失敗しました: {0}")]
+    Request(#[from] http::Error),
+    #[error("レスポンスの解析に失敗しました: {0}")]
+    Decode(#[from] serde_json::Error),
+    /// APIがエラーを返した（`code` は `VALIDATION_ERROR` などの機械判読用コード）
+    #[error("APIエラー (HTTP {status} {code}): {message}")]
+    Api {
+        status: u16,
+        code: String,
+        message: String,
+    },
+}
+
+/// `/resolve` の応答（Google / Cloudflare の JSON 形式）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolveResponse {
+    /// 応答コード（0: NOERROR、3: NXDOMAIN など）
+    #[serde(rename = "Status")]
+    pub status: u16,
+    #[serde(rename = "Answer", default)]
+    pub answers: Vec<ResolveAnswer>,
+}
+
+/// `/resolve` の回答
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolveAnswer {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: u16,
+    #[serde(rename = "TTL")]
+    pub ttl: u32,
+    pub data: String,
+    /// 回答を生成したレコードのID（ローカル応答の場合のみ）
+    #[serde(default)]
+    pub record_id: Option<i64>,
+}
+
+/// エラーレスポンスの本文
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+    code: String,
+}
+
+/// レコード作成の応答
+#[derive(Deserialize)]
+struct CreatedIds {
+    ids: Vec<i64>,
+}
+
+/// 管理APIのクライアント
+/// リクエストごとに接続する（HTTP/1.1）
+#[derive(Debug, Clone)]
+pub struct LocalDnsClient {
+    /// 接続先（`host:port`）
+    addr: String,
+    api_key: Option<String>,
+}
+
+impl LocalDnsClient {
+    /// 接続先（`127.0.0.1:3000` など）を指定して作成
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            api_key: None,
+        }
+    }
+
+    /// APIキー（`Authorization: Bearer`）を設定
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// レコード一覧を取得
+    pub async fn list_records(&self) -> Result<Vec<Record>, ClientError> {
+        self.request(Method::GET, "/api/records", None::<&()>).await
+    }
+
+    /// レコードを作成し、作成したレコードのIDを返す（コンテンツを複数指定した場合は全てのID）
+    pub async fn create_record(&self, req: &CreateRecordRequest) -> Result<Vec<i64>, ClientError> {
+        let created: CreatedIds = self.request(Method::POST, "/api/records", Some(req)).await?;
+        Ok(created.ids)
+    }
+
+    /// 設定を更新
+    pub async fn update_setting(&self, key: &str, value: &str) -> Result<(), ClientError> {
+        let path = format!(
+            "/api/settings/{}",
+            url::form_urlencoded::byte_serialize(key.as_bytes()).collect::<String>()
+        );
+        let req = UpdateSettingRequest {
+            value: value.to_string(),
+        };
+        let _: serde_json::Value = self.request(Method::PUT, &path, Some(&req)).await?;
+        Ok(())
+    }
+
+    /// サーバーで名前解決する（`record_type` は `A`・`AAAA` などのタイプ名）
+    pub async fn resolve(&self, name: &str, record_type: &str) -> Result<ResolveResponse, ClientError> {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("name", name)
+            .append_pair("type", record_type)
+            .finish();
+        self.request(Method::GET, &format!("/resolve?{}", query), None::<&()>)
+            .await
+    }
+
+    /// リクエストを送り、成功した場合は本文のJSONを返す
+    async fn request<B, T>(&self, method: Method, path: &str, body: Option<&B>) -> Result<T, ClientError>
+    where
+        B: Serialize + ?Sized,
+        T: DeserializeOwned,
+    {
+        let stream = TcpStream::connect(&self.addr).await?;
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            let _ = connection.await;
+        });
+
+        let mut builder = hyper::Request::builder()
+            .method(method)
+            .uri(path)
+            .header(HOST, &self.addr);
+        if let Some(api_key) = &self.api_key {
+            builder = builder.header(AUTHORIZATION, format!("Bearer {}", api_key));
+        }
+        let body = match body {
+            Some(body) => {
+                builder = builder.header(CONTENT_TYPE, "application/json");
+                serde_json::to_vec(body)?
+            }
+            None => Vec::new(),
+        };
+        let response = sender
+            .send_request(builder.body(Full::new(Bytes::from(body)))?)
+            .await?;
+
+        let status = response.status();
+        let bytes = response.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            let (code, message) = match serde_json::from_slice::<ErrorBody>(&bytes) {
+                Ok(error) => (error.code, error.error),
+                Err(_) => (String::new(), String::from_utf8_lossy(&bytes).into_owned()),
+            };
+            return Err(ClientError::Api {
+                status: status.as_u16(),
+                code,
+                message,
+            });
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
@@ -0,0 +1,74 @@
+-- レコードテーブル
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    domain_pattern TEXT NOT NULL,
+    record_type TEXT NOT NULL,
+    content TEXT NOT NULL,
+    ttl INTEGER NOT NULL DEFAULT 60,
+    active INTEGER NOT NULL DEFAULT 1,
+    priority INTEGER NOT NULL DEFAULT 0,
+    weight INTEGER NOT NULL DEFAULT 1,
+    passthrough INTEGER NOT NULL DEFAULT 0,
+    pattern_type TEXT NOT NULL DEFAULT 'glob',
+    client_subnet TEXT,
+    tags TEXT NOT NULL DEFAULT '',
+    deleted_at DATETIME,
+    hit_count INTEGER NOT NULL DEFAULT 0,
+    last_hit_at DATETIME
+);
+
+-- クエリログテーブル
+CREATE TABLE IF NOT EXISTS query_logs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    query_name TEXT NOT NULL,
+    q_type TEXT NOT NULL,
+    result_type TEXT NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    upstream_server TEXT,
+    query_id TEXT
+);
+
+-- 設定テーブル
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+-- 初期設定値の投入
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_primary', '8.8.8.8:53');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_secondary', '1.1.1.1:53');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_timeout_ms', '2000');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_retries', '2');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('log_retention_days', '7');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('dns_workers', '1');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('dns_listen_ipv6', '');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('server_hostname', '');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('min_ttl', '0');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('max_ttl', '0');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('ttl_jitter_percent', '0');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_cache_decrement_ttl', 'true');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('query_deadline_ms', '10000');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('negative_cache_max_ttl', '300');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('serve_stale', 'false');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('serve_stale_max_secs', '86400');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('block_private_reverse', 'true');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('chaos_version', '');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('cors_allowed_origins', '');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('max_request_body_bytes', '1048576');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('max_concurrent_requests', '256');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('grpc_port', '0');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('axfr_master', '');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('axfr_zone', '');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('miss_response', 'nxdomain');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('answer_order', 'insertion');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_bind_addr', '');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_ecs', '');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_ecs_prefix_v4', '24');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_ecs_prefix_v6', '56');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('content_env_expansion', 'off');
+INSERT OR IGNORE INTO settings (key, value) VALUES ('log_filter', 'all');
+
+-- インデックス作成
+CREATE INDEX IF NOT EXISTS idx_records_active ON records(active);
+CREATE INDEX IF NOT EXISTS idx_query_logs_timestamp ON query_logs(timestamp);
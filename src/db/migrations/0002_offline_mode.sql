@@ -0,0 +1,2 @@
+-- オフラインモード（上位DNSへ一切転送しない）
+INSERT OR IGNORE INTO settings (key, value) VALUES ('offline_mode', 'false');
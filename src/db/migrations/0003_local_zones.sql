@@ -0,0 +1,2 @@
+-- catch-all レコード（`*`）で応答するローカルゾーン（カンマ区切り）
+INSERT OR IGNORE INTO settings (key, value) VALUES ('local_zones', '');
@@ -0,0 +1,2 @@
+-- 権威を持つゾーン（カンマ区切り。配下の名前は転送せずに権威応答する）
+INSERT OR IGNORE INTO settings (key, value) VALUES ('authoritative_zones', '');
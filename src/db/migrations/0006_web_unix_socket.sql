@@ -0,0 +1,2 @@
+-- Web API を待ち受ける Unix ドメインソケットのパス（空の場合は無効）
+INSERT OR IGNORE INTO settings (key, value) VALUES ('web_unix_socket', '');
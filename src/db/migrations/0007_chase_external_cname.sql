@@ -0,0 +1,2 @@
+-- ローカルの CNAME の参照先がローカルに無い場合に上位DNSへ問い合わせて回答に加える
+INSERT OR IGNORE INTO settings (key, value) VALUES ('chase_external_cname', 'true');
@@ -0,0 +1,2 @@
+-- Web API の待ち受けで PROXY プロトコル v2 のヘッダーから元のクライアントアドレスを受け取る
+INSERT OR IGNORE INTO settings (key, value) VALUES ('web_proxy_protocol', 'false');
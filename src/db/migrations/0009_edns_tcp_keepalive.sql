@@ -0,0 +1,2 @@
+-- TCP の問い合わせに edns-tcp-keepalive で通知するアイドルタイムアウト（秒、TCP 接続のタイムアウトにも使う）
+INSERT OR IGNORE INTO settings (key, value) VALUES ('edns_tcp_keepalive_secs', '5');
@@ -0,0 +1,3 @@
+-- レコードの登録元（ゾーン転送で作成したレコードは `axfr:<ゾーン>`、API などで作成したレコードは NULL）
+-- ゾーン転送の再取得では、利用者が変更できるタグではなくこの値で置き換え対象を選ぶ
+ALTER TABLE records ADD COLUMN source TEXT;
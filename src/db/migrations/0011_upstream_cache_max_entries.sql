@@ -0,0 +1,2 @@
+-- 上位DNS応答キャッシュに保持するエントリ数の上限（超えた場合は最も古く格納されたエントリから破棄する）
+INSERT OR IGNORE INTO settings (key, value) VALUES ('upstream_cache_max_entries', '10000');
@@ -1,4 +1,5 @@
-pub mod models;
+// データモデルはクライアント（`client` フィーチャー）と共有するためライブラリ側で定義する
+pub use local_dns_pro::models;
 
 use anyhow::{Context, Result};
 use sqlx::{
@@ -14,9 +15,144 @@ pub use models::*;
 /// データベース接続プール
 pub type DbPool = Pool<Sqlite>;
 
+/// クエリログ取得件数の上限
+pub const MAX_LOG_LIMIT: i64 = 1000;
+
+/// クエリログの時系列集計で返す時間帯の数の上限
+pub const MAX_TIMESERIES_BUCKETS: i64 = 1000;
+
+/// 番号付きのマイグレーション
+struct Migration {
+    version: i64,
+    name: &'static str,
+    sql: &'static str,
+}
+
+/// 適用するマイグレーション（バージョン順）
+///
+/// スキーマや初期設定値を変更する場合は、既存のファイルを書き換えずに
+/// `migrations/NNNN_<名前>.sql` を追加してここに並べる（各マイグレーションは一度だけ適用される）
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "offline_mode",
+        sql: include_str!("migrations/0002_offline_mode.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "local_zones",
+        sql: include_str!("migrations/0003_local_zones.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "api_keys",
+        sql: include_str!("migrations/0004_api_keys.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "authoritative_zones",
+        sql: include_str!("migrations/0005_authoritative_zones.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "web_unix_socket",
+        sql: include_str!("migrations/0006_web_unix_socket.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "chase_external_cname",
+        sql: include_str!("migrations/0007_chase_external_cname.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "web_proxy_protocol",
+        sql: include_str!("migrations/0008_web_proxy_protocol.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "edns_tcp_keepalive",
+        sql: include_str!("migrations/0009_edns_tcp_keepalive.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "record_source",
+        sql: include_str!("migrations/0010_record_source.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "upstream_cache_max_entries",
+        sql: include_str!("migrations/0011_upstream_cache_max_entries.sql"),
+    },
+];
+
+/// 現在のスキーマバージョン（最後のマイグレーションのバージョン）
+pub const SCHEMA_VERSION: i64 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// 接続プールの設定
+#[derive(Debug, Clone)]
+pub struct DbOptions {
+    /// 接続プールの最大接続数
+    pub max_connections: u32,
+    /// ロック取得を待つ時間（`PRAGMA busy_timeout`）
+    pub busy_timeout: Duration,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            busy_timeout: Duration::from_millis(5000),
+        }
+    }
+}
+
+impl DbOptions {
+    /// 環境変数（`LOCALDNS_DB_MAX_CONNECTIONS` / `LOCALDNS_DB_BUSY_TIMEOUT_MS`）から読み込む
+    /// DB接続前に必要な設定のため、settings テーブルではなく環境変数で指定する
+    pub fn from_env() -> Self {
+        let mut options = Self::default();
+
+        if let Some(max_connections) = env_parse::<u32>("LOCALDNS_DB_MAX_CONNECTIONS") {
+            options.max_connections = max_connections.max(1);
+        }
+        if let Some(busy_timeout_ms) = env_parse::<u64>("LOCALDNS_DB_BUSY_TIMEOUT_MS") {
+            options.busy_timeout = Duration::from_millis(busy_timeout_ms);
+        }
+
+        options
+    }
+}
+
+/// 環境変数をパース（未設定・不正な値は None）
+fn env_parse<T: FromStr>(key: &str) -> Option<T> {
+    let value = std::env::var(key).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            warn!("環境変数 {} の値が不正なため無視します: {}", key, value);
+            None
+        }
+    }
+}
+
 /// データベース接続を初期化
 pub async fn init_db(database_url: &str) -> Result<DbPool> {
-    info!("データベース接続を初期化中: {}", database_url);
+    init_db_with_options(database_url, &DbOptions::default()).await
+}
+
+/// 接続プールの設定を指定してデータベース接続を初期化
+pub async fn init_db_with_options(database_url: &str, options: &DbOptions) -> Result<DbPool> {
+    info!(
+        "データベース接続を初期化中: {} (最大接続数: {}, busy_timeout: {}ms)",
+        database_url,
+        options.max_connections,
+        options.busy_timeout.as_millis()
+    );
 
     // リトライロジック（3回、各1秒間隔）
     let mut attempts = 0;
@@ -25,7 +161,7 @@ pub async fn init_db(database_url: &str) -> Result<DbPool> {
     loop {
         attempts += 1;
 
-        match try_connect(database_url).await {
+        match try_connect(database_url, options).await {
             Ok(pool) => {
                 info!("データベース接続成功");
                 return Ok(pool);
@@ -47,16 +183,34 @@ pub async fn init_db(database_url: &str) -> Result<DbPool> {
     }
 }
 
+/// クエリログ書き込み専用の接続プールを作成（接続1本の単一ライター）
+///
+/// レコード・設定の読み取りや API からの更新とは別の接続で書き込み、大量のログ書き込みが
+/// 他の接続のロック待ちを増やさないようにする。WALモードとマイグレーションは
+/// `init_db_with_options` で適用済みであること（同じDBファイルを開く必要があるためインメモリDBには使えない）。
+pub async fn init_log_pool(database_url: &str, options: &DbOptions) -> Result<DbPool> {
+    let connect_options = SqliteConnectOptions::from_str(database_url)
+        .context("データベースURL解析に失敗")?
+        .busy_timeout(options.busy_timeout);
+
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(connect_options)
+        .await
+        .context("ログ書き込み用の接続プール作成に失敗")
+}
+
 /// データベース接続を試行
-async fn try_connect(database_url: &str) -> Result<DbPool> {
+async fn try_connect(database_url: &str, options: &DbOptions) -> Result<DbPool> {
     // SQLite接続オプション設定（ファイルが存在しない場合は作成）
     let connect_options = SqliteConnectOptions::from_str(database_url)
         .context("データベースURL解析に失敗")?
-        .create_if_missing(true);
+        .create_if_missing(true)
+        .busy_timeout(options.busy_timeout);
 
     // 接続プール作成
     let pool = SqlitePoolOptions::new()
-        .max_connections(5)
+        .max_connections(options.max_connections)
         .connect_with(connect_options)
         .await
         .context("データベース接続プール作成に失敗")?;
@@ -77,82 +231,417 @@ async fn try_connect(database_url: &str) -> Result<DbPool> {
 
 /// マイグレーション実行
 async fn run_migrations(pool: &DbPool) -> Result<()> {
-    info!("マイグレーションを実行中");
+    apply_migrations(pool, MIGRATIONS).await
+}
 
-    let migration_sql = include_str!("migration.sql");
+/// 未適用のマイグレーションを順に適用
+///
+/// 適用済みのバージョンは `schema_migrations` に記録し、次回以降は適用しない。
+/// 各マイグレーションは SQLite にそのまま渡すため、トリガーや文字列中のセミコロンも扱える。
+/// 1つのマイグレーションとその記録は同じトランザクションで行い、失敗した場合は何も適用しない。
+async fn apply_migrations(pool: &DbPool, migrations: &[Migration]) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY, applied_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP)"
+    )
+    .execute(pool)
+    .await
+    .context("schema_migrations テーブルの作成に失敗")?;
 
-    // コメントを除去してからセミコロンで分割
-    let cleaned_sql: String = migration_sql
-        .lines()
-        .filter(|line| {
-            let trimmed = line.trim();
-            !trimmed.is_empty() && !trimmed.starts_with("--")
-        })
-        .collect::<Vec<&str>>()
-        .join("\n");
+    let current: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_migrations")
+        .fetch_one(pool)
+        .await
+        .context("適用済みのスキーマバージョンの取得に失敗")?;
 
-    // セミコロンで分割して各文を実行
-    for statement in cleaned_sql.split(';') {
-        let statement = statement.trim();
-        if statement.is_empty() {
-            continue;
-        }
+    // バージョン管理を導入する前に作成されたDBは、後から追加されたカラムを先に補う
+    if current.is_none() && table_exists(pool, "records").await? {
+        upgrade_legacy_schema(pool).await?;
+    }
+
+    let current = current.unwrap_or(0);
+    for migration in migrations.iter().filter(|migration| migration.version > current) {
+        info!(
+            "マイグレーションを適用中: {:04}_{}",
+            migration.version, migration.name
+        );
 
-        sqlx::query(statement)
-            .execute(pool)
+        let mut tx = pool.begin().await.context("トランザクション開始に失敗")?;
+        sqlx::raw_sql(migration.sql)
+            .execute(&mut *tx)
             .await
-            .context(format!("SQL実行に失敗: {}", statement))?;
+            .context(format!(
+                "マイグレーション {:04}_{} の実行に失敗",
+                migration.version, migration.name
+            ))?;
+        sqlx::query("INSERT INTO schema_migrations (version) VALUES (?)")
+            .bind(migration.version)
+            .execute(&mut *tx)
+            .await
+            .context("スキーマバージョンの記録に失敗")?;
+        tx.commit().await.context("マイグレーションのコミットに失敗")?;
+        info!("マイグレーション {:04}_{} を適用しました", migration.version, migration.name);
     }
 
-    info!("マイグレーション完了");
     Ok(())
 }
 
-/// アクティブなレコードを全て取得
-pub async fn get_active_records(pool: &DbPool) -> Result<Vec<Record>> {
-    let records = sqlx::query_as::<_, Record>("SELECT * FROM records WHERE active = 1")
-        .fetch_all(pool)
+/// テーブルが存在するか
+async fn table_exists(pool: &DbPool, table: &str) -> Result<bool> {
+    let exists: Option<(String,)> =
+        sqlx::query_as("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(table)
+            .fetch_optional(pool)
+            .await
+            .context(format!("テーブル情報の取得に失敗: {}", table))?;
+
+    Ok(exists.is_some())
+}
+
+/// バージョン管理を導入する前のDBに、後から追加されたカラムを補う
+async fn upgrade_legacy_schema(pool: &DbPool) -> Result<()> {
+    info!("バージョン管理導入前のスキーマを更新中");
+
+    add_column_if_missing(pool, "records", "deleted_at", "DATETIME").await?;
+    add_column_if_missing(pool, "records", "priority", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "records", "weight", "INTEGER NOT NULL DEFAULT 1").await?;
+    add_column_if_missing(pool, "records", "passthrough", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "records", "pattern_type", "TEXT NOT NULL DEFAULT 'glob'").await?;
+    add_column_if_missing(pool, "records", "client_subnet", "TEXT").await?;
+    add_column_if_missing(pool, "records", "tags", "TEXT NOT NULL DEFAULT ''").await?;
+    add_column_if_missing(pool, "records", "hit_count", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "records", "last_hit_at", "DATETIME").await?;
+    if table_exists(pool, "query_logs").await? {
+        add_column_if_missing(pool, "query_logs", "upstream_server", "TEXT").await?;
+        add_column_if_missing(pool, "query_logs", "query_id", "TEXT").await?;
+    }
+
+    Ok(())
+}
+
+/// 適用済みの最新のスキーマバージョンを取得（記録が無ければ None）
+pub async fn get_schema_version(pool: &DbPool) -> Result<Option<SchemaMigration>> {
+    let migration = sqlx::query_as::<_, SchemaMigration>(
+        "SELECT version, applied_at FROM schema_migrations ORDER BY version DESC LIMIT 1"
+    )
+    .fetch_optional(pool)
+    .await
+    .context("スキーマバージョンの取得に失敗")?;
+
+    Ok(migration)
+}
+
+/// テーブルにカラムが存在しなければ追加
+async fn add_column_if_missing(
+    pool: &DbPool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let exists: Option<(String,)> = sqlx::query_as(&format!(
+        "SELECT name FROM pragma_table_info('{}') WHERE name = ?",
+        table
+    ))
+    .bind(column)
+    .fetch_optional(pool)
+    .await
+    .context(format!("カラム情報の取得に失敗: {}.{}", table, column))?;
+
+    if exists.is_none() {
+        info!("カラムを追加: {}.{}", table, column);
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(pool)
         .await
-        .context("アクティブレコードの取得に失敗")?;
+        .context(format!("カラム追加に失敗: {}.{}", table, column))?;
+    }
+
+    Ok(())
+}
+
+/// アクティブなレコードを全て取得（論理削除済みは除外）
+pub async fn get_active_records(pool: &DbPool) -> Result<Vec<Record>> {
+    let records = sqlx::query_as::<_, Record>(
+        "SELECT * FROM records WHERE active = 1 AND deleted_at IS NULL ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await
+    .context("アクティブレコードの取得に失敗")?;
 
     Ok(records)
 }
 
-/// 全レコードを取得
+/// 全レコードを取得（論理削除済みは除外）
 pub async fn get_all_records(pool: &DbPool) -> Result<Vec<Record>> {
-    let records = sqlx::query_as::<_, Record>("SELECT * FROM records ORDER BY id DESC")
-        .fetch_all(pool)
-        .await
-        .context("レコード取得に失敗")?;
+    let records = sqlx::query_as::<_, Record>(
+        "SELECT * FROM records WHERE deleted_at IS NULL ORDER BY id DESC"
+    )
+    .fetch_all(pool)
+    .await
+    .context("レコード取得に失敗")?;
 
     Ok(records)
 }
 
-/// レコードをIDで取得
+/// 指定したタグを持つレコードを取得（論理削除済みは除外）
+pub async fn get_records_by_tag(pool: &DbPool, tag: &str) -> Result<Vec<Record>> {
+    // カンマ区切りの両端にカンマを補い、タグ単位で完全一致させる
+    let records = sqlx::query_as::<_, Record>(
+        "SELECT * FROM records WHERE deleted_at IS NULL AND instr(',' || tags || ',', ',' || ? || ',') > 0 ORDER BY id DESC"
+    )
+    .bind(tag.trim())
+    .fetch_all(pool)
+    .await
+    .context("タグによるレコード取得に失敗")?;
+
+    Ok(records)
+}
+
+/// アドレスが指定の範囲（CIDR）に含まれる A / AAAA / AUTO レコードを取得
+pub async fn get_records_in_cidr(pool: &DbPool, net: &ipnet::IpNet) -> Result<Vec<Record>> {
+    let records = sqlx::query_as::<_, Record>(
+        "SELECT * FROM records WHERE deleted_at IS NULL AND record_type IN ('A', 'AAAA', 'AUTO') ORDER BY id DESC"
+    )
+    .fetch_all(pool)
+    .await
+    .context("アドレスレコード取得に失敗")?;
+
+    Ok(records
+        .into_iter()
+        .filter(|record| record.address_in(net))
+        .collect())
+}
+
+/// LIKE 検索用に `%` `_` `\` をエスケープし、部分一致パターンにする
+fn like_contains(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// コンテンツ・ドメインパターンの部分一致でレコードを検索（論理削除済みは除外）
+/// 指定された条件は AND で組み合わせる
+pub async fn search_records(
+    pool: &DbPool,
+    content: Option<&str>,
+    pattern: Option<&str>,
+) -> Result<Vec<Record>> {
+    let content = content.map(like_contains);
+    let pattern = pattern.map(like_contains);
+
+    let records = sqlx::query_as::<_, Record>(
+        "SELECT * FROM records WHERE deleted_at IS NULL \
+         AND (? IS NULL OR content LIKE ? ESCAPE '\\') \
+         AND (? IS NULL OR domain_pattern LIKE ? ESCAPE '\\') \
+         ORDER BY id DESC"
+    )
+    .bind(&content)
+    .bind(&content)
+    .bind(&pattern)
+    .bind(&pattern)
+    .fetch_all(pool)
+    .await
+    .context("レコード検索に失敗")?;
+
+    Ok(records)
+}
+
+/// タグ（完全一致）・ドメインパターン（部分一致）に該当するレコードをまとめて論理削除し、件数を返す
+/// 指定のない条件は無視する（呼び出し側で少なくとも一方を指定すること）。1つのトランザクション内で更新する
+pub async fn delete_records_matching(
+    pool: &DbPool,
+    tag: Option<&str>,
+    pattern: Option<&str>,
+) -> Result<u64> {
+    let tag = tag.map(str::trim);
+    let pattern = pattern.map(like_contains);
+
+    let mut tx = pool.begin().await.context("トランザクション開始に失敗")?;
+    let result = sqlx::query(
+        "UPDATE records SET deleted_at = CURRENT_TIMESTAMP WHERE deleted_at IS NULL \
+         AND (? IS NULL OR instr(',' || tags || ',', ',' || ? || ',') > 0) \
+         AND (? IS NULL OR domain_pattern LIKE ? ESCAPE '\\')"
+    )
+    .bind(tag)
+    .bind(tag)
+    .bind(&pattern)
+    .bind(&pattern)
+    .execute(&mut *tx)
+    .await
+    .context("レコードの一括削除に失敗")?;
+    tx.commit().await.context("トランザクションのコミットに失敗")?;
+
+    Ok(result.rows_affected())
+}
+
+/// 論理削除済みのレコードを取得（削除日時の新しい順）
+pub async fn get_deleted_records(pool: &DbPool) -> Result<Vec<Record>> {
+    let records = sqlx::query_as::<_, Record>(
+        "SELECT * FROM records WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC"
+    )
+    .fetch_all(pool)
+    .await
+    .context("削除済みレコード取得に失敗")?;
+
+    Ok(records)
+}
+
+/// レコードをIDで取得（論理削除済みは除外）
 pub async fn get_record_by_id(pool: &DbPool, id: i64) -> Result<Option<Record>> {
-    let record = sqlx::query_as::<_, Record>("SELECT * FROM records WHERE id = ?")
-        .bind(id)
-        .fetch_optional(pool)
-        .await
-        .context("レコード取得に失敗")?;
+    let record = sqlx::query_as::<_, Record>(
+        "SELECT * FROM records WHERE id = ? AND deleted_at IS NULL"
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .context("レコード取得に失敗")?;
 
     Ok(record)
 }
 
-/// レコードを作成
-pub async fn create_record(pool: &DbPool, req: CreateRecordRequest) -> Result<i64> {
-    let result = sqlx::query(
-        "INSERT INTO records (domain_pattern, record_type, content, ttl, active) VALUES (?, ?, ?, ?, 1)"
+/// 論理削除済みのレコードをIDで取得
+pub async fn get_deleted_record(pool: &DbPool, id: i64) -> Result<Option<Record>> {
+    let record = sqlx::query_as::<_, Record>(
+        "SELECT * FROM records WHERE id = ? AND deleted_at IS NOT NULL"
     )
-    .bind(&req.domain_pattern)
-    .bind(&req.record_type)
-    .bind(&req.content)
-    .bind(req.ttl)
-    .execute(pool)
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .context("削除済みレコード取得に失敗")?;
+
+    Ok(record)
+}
+
+/// 同一の (domain_pattern, record_type, content) を持つ未削除レコードのIDを取得
+pub async fn find_duplicate_record(
+    pool: &DbPool,
+    domain_pattern: &str,
+    record_type: &str,
+    content: &str,
+) -> Result<Option<i64>> {
+    let id: Option<(i64,)> = sqlx::query_as(
+        "SELECT id FROM records WHERE domain_pattern = ? AND record_type = ? AND content = ? AND deleted_at IS NULL LIMIT 1"
+    )
+    .bind(domain_pattern)
+    .bind(record_type)
+    .bind(content)
+    .fetch_optional(pool)
     .await
-    .context("レコード作成に失敗")?;
+    .context("重複レコードの確認に失敗")?;
 
-    Ok(result.last_insert_rowid())
+    Ok(id.map(|(id,)| id))
+}
+
+/// レコードを作成し、作成したレコードのIDを返す
+/// コンテンツを複数指定した場合は値ごとに1件ずつ、同一トランザクション内で作成する
+pub async fn create_record(pool: &DbPool, req: CreateRecordRequest) -> Result<Vec<i64>> {
+    let mut tx = pool.begin().await.context("トランザクション開始に失敗")?;
+
+    let mut ids = Vec::with_capacity(req.content.values().len());
+    for content in req.content.values() {
+        let result = sqlx::query(
+            "INSERT INTO records (domain_pattern, record_type, content, ttl, priority, weight, passthrough, pattern_type, client_subnet, tags, active) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 1)"
+        )
+        .bind(&req.domain_pattern)
+        .bind(&req.record_type)
+        .bind(content)
+        .bind(req.ttl)
+        .bind(req.priority)
+        .bind(req.weight)
+        .bind(req.passthrough)
+        .bind(req.pattern_type)
+        .bind(normalize_client_subnet(req.client_subnet.as_deref()))
+        .bind(normalize_tags(&req.tags))
+        .execute(&mut *tx)
+        .await
+        .context("レコード作成に失敗")?;
+        ids.push(result.last_insert_rowid());
+    }
+
+    tx.commit().await.context("トランザクションのコミットに失敗")?;
+    Ok(ids)
+}
+
+/// 指定の登録元（`source`）のレコードをまとめて置き換える（ゾーン転送の取り込みで使用）
+/// 既存の同じ登録元の有効なレコードは物理削除し、同一トランザクション内で新しいレコードを登録元付きで登録する。
+/// 置き換えたレコードはIDが変わり、ヒット数・最終ヒット日時もリセットされる。
+/// 利用者が論理削除したレコードは削除済みのまま残し、同じ内容のレコードは登録し直さない。
+/// 利用者が作成したレコード（登録元なし）は、同じタグが付いていても置き換えない
+pub async fn replace_records_from_source(
+    pool: &DbPool,
+    source: &str,
+    records: Vec<CreateRecordRequest>,
+) -> Result<()> {
+    let mut tx = pool.begin().await.context("トランザクション開始に失敗")?;
+
+    sqlx::query("DELETE FROM records WHERE source = ? AND deleted_at IS NULL")
+        .bind(source)
+        .execute(&mut *tx)
+        .await
+        .context("登録元が同じレコードの削除に失敗")?;
+
+    let deleted: std::collections::HashSet<(String, String, String)> = sqlx::query_as(
+        "SELECT domain_pattern, record_type, content FROM records WHERE source = ? AND deleted_at IS NOT NULL"
+    )
+    .bind(source)
+    .fetch_all(&mut *tx)
+    .await
+    .context("論理削除済みのレコードの取得に失敗")?
+    .into_iter()
+    .collect();
+
+    for req in records {
+        for content in req.content.values() {
+            let key = (req.domain_pattern.clone(), req.record_type.clone(), content.clone());
+            if deleted.contains(&key) {
+                continue;
+            }
+
+            sqlx::query(
+                "INSERT INTO records (domain_pattern, record_type, content, ttl, priority, weight, passthrough, pattern_type, client_subnet, tags, source, active) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 1)"
+            )
+            .bind(&req.domain_pattern)
+            .bind(&req.record_type)
+            .bind(content)
+            .bind(req.ttl)
+            .bind(req.priority)
+            .bind(req.weight)
+            .bind(req.passthrough)
+            .bind(req.pattern_type)
+            .bind(normalize_client_subnet(req.client_subnet.as_deref()))
+            .bind(normalize_tags(&req.tags))
+            .bind(source)
+            .execute(&mut *tx)
+            .await
+            .context("レコード作成に失敗")?;
+        }
+    }
+
+    tx.commit().await.context("トランザクションのコミットに失敗")?;
+    Ok(())
+}
+
+/// レコードごとのヒット数をまとめて加算し、最終ヒット日時を更新
+/// `hits` は (レコードID, 加算するヒット数, 最終ヒット日時)
+pub async fn add_record_hits(pool: &DbPool, hits: &[(i64, u64, String)]) -> Result<()> {
+    let mut tx = pool.begin().await.context("トランザクション開始に失敗")?;
+
+    for (id, count, last_hit_at) in hits {
+        sqlx::query(
+            "UPDATE records SET hit_count = hit_count + ?, last_hit_at = ? WHERE id = ?"
+        )
+        .bind(*count as i64)
+        .bind(last_hit_at)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .context("ヒット数の更新に失敗")?;
+    }
+
+    tx.commit().await.context("トランザクションのコミットに失敗")?;
+    Ok(())
 }
 
 /// レコードを更新
@@ -179,16 +668,40 @@ pub async fn update_record(pool: &DbPool, id: i64, req: UpdateRecordRequest) ->
     if let Some(active) = req.active {
         record.active = active;
     }
+    if let Some(priority) = req.priority {
+        record.priority = priority;
+    }
+    if let Some(weight) = req.weight {
+        record.weight = weight;
+    }
+    if let Some(passthrough) = req.passthrough {
+        record.passthrough = passthrough;
+    }
+    if let Some(pattern_type) = req.pattern_type {
+        record.pattern_type = pattern_type;
+    }
+    if let Some(client_subnet) = req.client_subnet {
+        record.client_subnet = normalize_client_subnet(Some(&client_subnet));
+    }
+    if let Some(tags) = req.tags {
+        record.tags = normalize_tags(&tags);
+    }
 
     // 更新実行
     sqlx::query(
-        "UPDATE records SET domain_pattern = ?, record_type = ?, content = ?, ttl = ?, active = ? WHERE id = ?"
+        "UPDATE records SET domain_pattern = ?, record_type = ?, content = ?, ttl = ?, active = ?, priority = ?, weight = ?, passthrough = ?, pattern_type = ?, client_subnet = ?, tags = ? WHERE id = ?"
     )
     .bind(&record.domain_pattern)
     .bind(&record.record_type)
     .bind(&record.content)
     .bind(record.ttl)
     .bind(record.active)
+    .bind(record.priority)
+    .bind(record.weight)
+    .bind(record.passthrough)
+    .bind(record.pattern_type)
+    .bind(&record.client_subnet)
+    .bind(&record.tags)
     .bind(id)
     .execute(pool)
     .await
@@ -197,13 +710,28 @@ pub async fn update_record(pool: &DbPool, id: i64, req: UpdateRecordRequest) ->
     Ok(true)
 }
 
-/// レコードを削除
+/// レコードを論理削除（deleted_at に削除日時を記録）
 pub async fn delete_record(pool: &DbPool, id: i64) -> Result<bool> {
-    let result = sqlx::query("DELETE FROM records WHERE id = ?")
-        .bind(id)
-        .execute(pool)
-        .await
-        .context("レコード削除に失敗")?;
+    let result = sqlx::query(
+        "UPDATE records SET deleted_at = CURRENT_TIMESTAMP WHERE id = ? AND deleted_at IS NULL"
+    )
+    .bind(id)
+    .execute(pool)
+    .await
+    .context("レコード削除に失敗")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// 論理削除されたレコードを復元
+pub async fn restore_record(pool: &DbPool, id: i64) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE records SET deleted_at = NULL WHERE id = ? AND deleted_at IS NOT NULL"
+    )
+    .bind(id)
+    .execute(pool)
+    .await
+    .context("レコード復元に失敗")?;
 
     Ok(result.rows_affected() > 0)
 }
@@ -211,12 +739,14 @@ pub async fn delete_record(pool: &DbPool, id: i64) -> Result<bool> {
 /// クエリログを記録
 pub async fn log_query(pool: &DbPool, log: NewQueryLog) -> Result<()> {
     sqlx::query(
-        "INSERT INTO query_logs (query_name, q_type, result_type, duration_ms) VALUES (?, ?, ?, ?)"
+        "INSERT INTO query_logs (query_name, q_type, result_type, duration_ms, upstream_server, query_id) VALUES (?, ?, ?, ?, ?, ?)"
     )
     .bind(&log.query_name)
     .bind(&log.q_type)
     .bind(&log.result_type)
     .bind(log.duration_ms)
+    .bind(&log.upstream_server)
+    .bind(&log.query_id)
     .execute(pool)
     .await
     .context("クエリログ記録に失敗")?;
@@ -224,8 +754,11 @@ pub async fn log_query(pool: &DbPool, log: NewQueryLog) -> Result<()> {
     Ok(())
 }
 
-/// 最新のクエリログを取得
+/// 最新のクエリログを取得（件数は 1〜`MAX_LOG_LIMIT` に丸める）
 pub async fn get_recent_logs(pool: &DbPool, limit: i64) -> Result<Vec<QueryLog>> {
+    // 一度に読み込む件数を制限し、巨大な limit によるメモリ消費を防ぐ
+    let limit = limit.clamp(1, MAX_LOG_LIMIT);
+
     let logs = sqlx::query_as::<_, QueryLog>(
         "SELECT * FROM query_logs ORDER BY timestamp DESC LIMIT ?"
     )
@@ -237,6 +770,100 @@ pub async fn get_recent_logs(pool: &DbPool, limit: i64) -> Result<Vec<QueryLog>>
     Ok(logs)
 }
 
+/// クエリログを新しい順に1ページ分取得（エクスポート用）
+/// `before_id` を指定した場合はそれより古い（IDが小さい）ログのみ返す
+pub async fn get_logs_page(
+    pool: &DbPool,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<QueryLog>> {
+    let logs = sqlx::query_as::<_, QueryLog>(
+        "SELECT * FROM query_logs WHERE ? IS NULL OR id < ? ORDER BY id DESC LIMIT ?"
+    )
+    .bind(before_id)
+    .bind(before_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .context("クエリログ取得に失敗")?;
+
+    Ok(logs)
+}
+
+/// クエリログの件数を時間帯ごとに集計（`end`（UNIX秒）を含む時間帯までの `window_secs` 秒分）
+/// 時間帯は `bucket_secs` 秒ごとに UNIX 時刻で区切り、ログの無い時間帯も件数 0 で返す。
+/// `by_result_type` を指定すると結果種別ごとの件数も返す
+pub async fn get_log_timeseries(
+    pool: &DbPool,
+    end: i64,
+    window_secs: i64,
+    bucket_secs: i64,
+    by_result_type: bool,
+) -> Result<Vec<LogTimeBucket>> {
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+    let format = |secs: i64| {
+        chrono::DateTime::from_timestamp(secs, 0)
+            .map(|dt| dt.format(FORMAT).to_string())
+            .unwrap_or_default()
+    };
+
+    let bucket_count = ((window_secs + bucket_secs - 1) / bucket_secs).max(1);
+    let last_start = end.div_euclid(bucket_secs) * bucket_secs;
+    let first_start = last_start - (bucket_count - 1) * bucket_secs;
+
+    let rows: Vec<(i64, String, i64)> = sqlx::query_as(
+        "SELECT (CAST(strftime('%s', timestamp) AS INTEGER) / ?) * ? AS bucket,
+                result_type, COUNT(*)
+         FROM query_logs
+         WHERE timestamp >= ? AND timestamp < ?
+         GROUP BY bucket, result_type",
+    )
+    .bind(bucket_secs)
+    .bind(bucket_secs)
+    .bind(format(first_start))
+    .bind(format(last_start + bucket_secs))
+    .fetch_all(pool)
+    .await
+    .context("クエリログの集計に失敗")?;
+
+    let mut buckets: Vec<LogTimeBucket> = (0..bucket_count)
+        .map(|index| LogTimeBucket {
+            start: format(first_start + index * bucket_secs),
+            count: 0,
+            by_result_type: by_result_type.then(Default::default),
+        })
+        .collect();
+    for (bucket, result_type, count) in rows {
+        let index = (bucket - first_start) / bucket_secs;
+        let Some(entry) = usize::try_from(index).ok().and_then(|i| buckets.get_mut(i)) else {
+            continue;
+        };
+        entry.count += count;
+        if let Some(by_result_type) = entry.by_result_type.as_mut() {
+            *by_result_type.entry(result_type).or_default() += count;
+        }
+    }
+
+    Ok(buckets)
+}
+
+/// クエリログを削除し、削除件数を返す
+/// `before`（`YYYY-MM-DD HH:MM:SS`、UTC）を指定した場合はそれより前のログのみ削除する
+pub async fn delete_logs(pool: &DbPool, before: Option<&str>) -> Result<u64> {
+    let result = match before {
+        Some(before) => {
+            sqlx::query("DELETE FROM query_logs WHERE timestamp < ?")
+                .bind(before)
+                .execute(pool)
+                .await
+        }
+        None => sqlx::query("DELETE FROM query_logs").execute(pool).await,
+    }
+    .context("クエリログの削除に失敗")?;
+
+    Ok(result.rows_affected())
+}
+
 /// 古いログを削除（定期クリーンアップで使用）
 pub async fn cleanup_old_logs(pool: &DbPool, retention_days: i64) -> Result<u64> {
     let result = sqlx::query(
@@ -250,6 +877,15 @@ pub async fn cleanup_old_logs(pool: &DbPool, retention_days: i64) -> Result<u64>
     Ok(result.rows_affected())
 }
 
+/// DB接続確認（ヘルスチェック用）
+pub async fn ping(pool: &DbPool) -> Result<()> {
+    sqlx::query("SELECT 1")
+        .execute(pool)
+        .await
+        .context("DB接続確認に失敗")?;
+    Ok(())
+}
+
 /// 設定を取得
 pub async fn get_setting(pool: &DbPool, key: &str) -> Result<Option<String>> {
     let setting = sqlx::query_as::<_, Setting>("SELECT * FROM settings WHERE key = ?")
@@ -283,6 +919,165 @@ pub async fn update_setting(pool: &DbPool, key: &str, value: &str) -> Result<()>
     Ok(())
 }
 
+/// APIキーのハッシュ（保存・照合用の SHA-256 の16進表記）
+/// キーは十分な長さの乱数なのでソルトは付けない
+pub fn hash_api_key(key: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// 新しいAPIキーを生成（`ldns_` に続く乱数256ビットの16進表記）
+fn generate_api_key() -> String {
+    use rand::Rng;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill(&mut bytes[..]);
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("ldns_{}", hex)
+}
+
+/// APIキーを作成し、作成したキーの情報とキーそのもの（この時だけ取得できる）を返す
+pub async fn create_api_key(pool: &DbPool, req: &CreateApiKeyRequest) -> Result<(ApiKey, String)> {
+    let key = generate_api_key();
+    let api_key = sqlx::query_as::<_, ApiKey>(
+        "INSERT INTO api_keys (name, scope, key_hash) VALUES (?, ?, ?) RETURNING *",
+    )
+    .bind(&req.name)
+    .bind(req.scope)
+    .bind(hash_api_key(&key))
+    .fetch_one(pool)
+    .await
+    .context("APIキー作成に失敗")?;
+
+    Ok((api_key, key))
+}
+
+/// 全APIキーを取得
+pub async fn get_api_keys(pool: &DbPool) -> Result<Vec<ApiKey>> {
+    let keys = sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys ORDER BY id")
+        .fetch_all(pool)
+        .await
+        .context("APIキー取得に失敗")?;
+
+    Ok(keys)
+}
+
+/// APIキーの件数を取得
+pub async fn count_api_keys(pool: &DbPool) -> Result<i64> {
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM api_keys")
+        .fetch_one(pool)
+        .await
+        .context("APIキー件数取得に失敗")?;
+
+    Ok(count)
+}
+
+/// キーそのものから登録済みのAPIキーを探す
+pub async fn find_api_key(pool: &DbPool, key: &str) -> Result<Option<ApiKey>> {
+    let api_key = sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE key_hash = ?")
+        .bind(hash_api_key(key))
+        .fetch_optional(pool)
+        .await
+        .context("APIキー取得に失敗")?;
+
+    Ok(api_key)
+}
+
+/// APIキーの名前・スコープを更新（キーそのものは変わらない）
+pub async fn update_api_key(pool: &DbPool, id: i64, req: &UpdateApiKeyRequest) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET name = COALESCE(?, name), scope = COALESCE(?, scope) WHERE id = ?",
+    )
+    .bind(&req.name)
+    .bind(req.scope)
+    .bind(id)
+    .execute(pool)
+    .await
+    .context("APIキー更新に失敗")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// APIキーを削除
+pub async fn delete_api_key(pool: &DbPool, id: i64) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM api_keys WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await
+        .context("APIキー削除に失敗")?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// レコード・設定の全体バックアップを作成（クエリログは含まない）
+pub async fn export_backup(pool: &DbPool) -> Result<Backup> {
+    let records = sqlx::query_as::<_, Record>("SELECT * FROM records ORDER BY id")
+        .fetch_all(pool)
+        .await
+        .context("レコード取得に失敗")?;
+    let settings = get_all_settings(pool).await?;
+
+    Ok(Backup {
+        version: BACKUP_VERSION,
+        created_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        records,
+        settings,
+    })
+}
+
+/// バックアップから復元
+/// レコードは全て置き換え（IDも復元）、設定はバックアップに含まれるキーのみ上書きする。
+/// 同一トランザクション内で行い、失敗した場合は何も変更しない
+pub async fn import_backup(pool: &DbPool, backup: &Backup) -> Result<()> {
+    let mut tx = pool.begin().await.context("トランザクション開始に失敗")?;
+
+    sqlx::query("DELETE FROM records")
+        .execute(&mut *tx)
+        .await
+        .context("レコードの削除に失敗")?;
+
+    for record in &backup.records {
+        sqlx::query(
+            "INSERT INTO records (id, domain_pattern, record_type, content, ttl, active, priority, weight, passthrough, pattern_type, client_subnet, tags, deleted_at, hit_count, last_hit_at, source) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(record.id)
+        .bind(&record.domain_pattern)
+        .bind(&record.record_type)
+        .bind(&record.content)
+        .bind(record.ttl)
+        .bind(record.active)
+        .bind(record.priority)
+        .bind(record.weight)
+        .bind(record.passthrough)
+        .bind(record.pattern_type)
+        .bind(&record.client_subnet)
+        .bind(&record.tags)
+        .bind(&record.deleted_at)
+        .bind(record.hit_count)
+        .bind(&record.last_hit_at)
+        .bind(&record.source)
+        .execute(&mut *tx)
+        .await
+        .context(format!("レコードの復元に失敗 (id: {})", record.id))?;
+    }
+
+    for setting in &backup.settings {
+        sqlx::query("INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)")
+            .bind(&setting.key)
+            .bind(&setting.value)
+            .execute(&mut *tx)
+            .await
+            .context(format!("設定の復元に失敗: {}", setting.key))?;
+    }
+
+    tx.commit().await.context("トランザクションのコミットに失敗")?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -292,6 +1087,141 @@ mod tests {
         pool
     }
 
+    #[tokio::test]
+    async fn test_init_db_applies_busy_timeout() {
+        let options = DbOptions {
+            max_connections: 2,
+            busy_timeout: Duration::from_millis(1234),
+        };
+        let pool = init_db_with_options("sqlite::memory:", &options).await.unwrap();
+
+        let busy_timeout: i64 = sqlx::query_scalar("PRAGMA busy_timeout")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(busy_timeout, 1234);
+        assert_eq!(pool.options().get_max_connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_schema_version_recorded_after_init() {
+        let pool = setup_test_db().await;
+
+        let migration = get_schema_version(&pool).await.unwrap().unwrap();
+        assert_eq!(migration.version, SCHEMA_VERSION);
+    }
+
+    /// マイグレーションを適用していない空のDB
+    async fn setup_empty_db() -> DbPool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    async fn applied_versions(pool: &DbPool) -> Vec<i64> {
+        sqlx::query_scalar("SELECT version FROM schema_migrations ORDER BY version")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_second_init_is_noop() {
+        let pool = setup_test_db().await;
+        let first = get_schema_version(&pool).await.unwrap().unwrap();
+
+        run_migrations(&pool).await.unwrap();
+
+        let versions: Vec<i64> = MIGRATIONS.iter().map(|migration| migration.version).collect();
+        assert_eq!(applied_versions(&pool).await, versions);
+        let second = get_schema_version(&pool).await.unwrap().unwrap();
+        assert_eq!(second.applied_at, first.applied_at);
+    }
+
+    #[tokio::test]
+    async fn test_migrations_apply_once_with_triggers_and_semicolons() {
+        let pool = setup_empty_db().await;
+        let initial = Migration {
+            version: 1,
+            name: "notes",
+            sql: "-- 文字列中のセミコロンとトリガー本体の文を含む
+                CREATE TABLE notes (body TEXT NOT NULL);
+                CREATE TABLE note_audit (body TEXT NOT NULL);
+                CREATE TRIGGER note_insert AFTER INSERT ON notes
+                BEGIN
+                    INSERT INTO note_audit (body) VALUES (NEW.body || ';');
+                END;
+                INSERT INTO notes (body) VALUES ('a;b');",
+        };
+        let second = Migration {
+            version: 2,
+            name: "more_notes",
+            sql: "INSERT INTO notes (body) VALUES ('c');",
+        };
+
+        apply_migrations(&pool, std::slice::from_ref(&initial)).await.unwrap();
+        let migrations = [initial, second];
+        apply_migrations(&pool, &migrations).await.unwrap();
+        apply_migrations(&pool, &migrations).await.unwrap();
+
+        let notes: Vec<String> = sqlx::query_scalar("SELECT body FROM notes ORDER BY rowid")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(notes, vec!["a;b", "c"]);
+        let audit: Vec<String> = sqlx::query_scalar("SELECT body FROM note_audit ORDER BY rowid")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(audit, vec!["a;b;", "c;"]);
+        assert_eq!(applied_versions(&pool).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_is_not_recorded() {
+        let pool = setup_empty_db().await;
+        let broken = Migration {
+            version: 1,
+            name: "broken",
+            sql: "CREATE TABLE half (id INTEGER); INSERT INTO missing_table VALUES (1);",
+        };
+
+        assert!(apply_migrations(&pool, &[broken]).await.is_err());
+        assert!(applied_versions(&pool).await.is_empty());
+        assert!(!table_exists(&pool, "half").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_legacy_database_is_upgraded() {
+        let pool = setup_empty_db().await;
+        // バージョン管理を導入する前の最初期のスキーマ
+        sqlx::raw_sql(
+            "CREATE TABLE records (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                domain_pattern TEXT NOT NULL,
+                record_type TEXT NOT NULL,
+                content TEXT NOT NULL,
+                ttl INTEGER NOT NULL DEFAULT 60,
+                active INTEGER NOT NULL DEFAULT 1
+            );
+            INSERT INTO records (domain_pattern, record_type, content) VALUES ('old.local.test', 'A', '10.0.0.1');",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        run_migrations(&pool).await.unwrap();
+
+        let records = get_active_records(&pool).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].domain_pattern, "old.local.test");
+        assert_eq!(records[0].client_subnet, None);
+        let versions: Vec<i64> = MIGRATIONS.iter().map(|migration| migration.version).collect();
+        assert_eq!(applied_versions(&pool).await, versions);
+    }
+
     #[tokio::test]
     async fn test_create_and_get_record() {
         let pool = setup_test_db().await;
@@ -299,11 +1229,17 @@ mod tests {
         let req = CreateRecordRequest {
             domain_pattern: "app.local.test".to_string(),
             record_type: "A".to_string(),
-            content: "127.0.0.1".to_string(),
+            content: "127.0.0.1".into(),
             ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
         };
 
-        let id = create_record(&pool, req).await.unwrap();
+        let id = create_record(&pool, req).await.unwrap()[0];
         assert!(id > 0);
 
         let record = get_record_by_id(&pool, id).await.unwrap().unwrap();
@@ -319,11 +1255,17 @@ mod tests {
         let req = CreateRecordRequest {
             domain_pattern: "app.local.test".to_string(),
             record_type: "A".to_string(),
-            content: "127.0.0.1".to_string(),
+            content: "127.0.0.1".into(),
             ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
         };
 
-        let id = create_record(&pool, req).await.unwrap();
+        let id = create_record(&pool, req).await.unwrap()[0];
 
         let update_req = UpdateRecordRequest {
             domain_pattern: None,
@@ -331,6 +1273,12 @@ mod tests {
             content: Some("192.168.1.1".to_string()),
             ttl: None,
             active: None,
+            priority: None,
+            weight: None,
+            passthrough: None,
+            pattern_type: None,
+            client_subnet: None,
+            tags: None,
         };
 
         let updated = update_record(&pool, id, update_req).await.unwrap();
@@ -347,11 +1295,17 @@ mod tests {
         let req = CreateRecordRequest {
             domain_pattern: "app.local.test".to_string(),
             record_type: "A".to_string(),
-            content: "127.0.0.1".to_string(),
+            content: "127.0.0.1".into(),
             ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
         };
 
-        let id = create_record(&pool, req).await.unwrap();
+        let id = create_record(&pool, req).await.unwrap()[0];
         let deleted = delete_record(&pool, id).await.unwrap();
         assert!(deleted);
 
@@ -359,6 +1313,98 @@ mod tests {
         assert!(record.is_none());
     }
 
+    #[tokio::test]
+    async fn test_get_records_by_tag() {
+        let pool = setup_test_db().await;
+
+        let records = [
+            ("a.local.test", vec!["project-a"]),
+            ("b.local.test", vec!["project-b"]),
+            ("ab.local.test", vec!["project-a", "shared"]),
+            // 部分一致ではヒットしないこと
+            ("c.local.test", vec!["project-ab"]),
+        ];
+        for (domain, tags) in records {
+            create_record(
+                &pool,
+                CreateRecordRequest {
+                    domain_pattern: domain.to_string(),
+                    record_type: "A".to_string(),
+                    content: "127.0.0.1".into(),
+                    ttl: 60,
+                    priority: 0,
+                    weight: 1,
+                    passthrough: false,
+                    pattern_type: PatternType::Glob,
+                    client_subnet: None,
+                    tags: tags.into_iter().map(str::to_string).collect(),
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let tagged = get_records_by_tag(&pool, "project-a").await.unwrap();
+        let mut domains: Vec<&str> = tagged.iter().map(|r| r.domain_pattern.as_str()).collect();
+        domains.sort();
+        assert_eq!(domains, vec!["a.local.test", "ab.local.test"]);
+
+        let shared = get_records_by_tag(&pool, "shared").await.unwrap();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].tags, "project-a,shared");
+    }
+
+    #[tokio::test]
+    async fn test_search_records() {
+        let pool = setup_test_db().await;
+
+        let records = [
+            ("app.local.test", "192.168.1.50"),
+            ("%.dev.local.test", "192.168.1.50"),
+            ("api.local.test", "10.0.0.1"),
+        ];
+        for (domain, content) in records {
+            create_record(
+                &pool,
+                CreateRecordRequest {
+                    domain_pattern: domain.to_string(),
+                    record_type: "A".to_string(),
+                    content: content.into(),
+                    ttl: 60,
+                    priority: 0,
+                    weight: 1,
+                    passthrough: false,
+                    pattern_type: PatternType::Glob,
+                    client_subnet: None,
+                    tags: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        // コンテンツで検索
+        let found = search_records(&pool, Some("192.168.1.50"), None).await.unwrap();
+        assert_eq!(found.len(), 2);
+
+        // ドメインパターンの部分一致で検索
+        let found = search_records(&pool, None, Some("api.")).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].domain_pattern, "api.local.test");
+
+        // ワイルドカード文字はリテラルとして扱う
+        let found = search_records(&pool, None, Some("%.dev")).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].domain_pattern, "%.dev.local.test");
+
+        // 条件の組み合わせ
+        let found = search_records(&pool, Some("192.168.1.50"), Some("app"))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].domain_pattern, "app.local.test");
+    }
+
     #[tokio::test]
     async fn test_settings() {
         let pool = setup_test_db().await;
@@ -388,6 +1434,8 @@ mod tests {
                 q_type: "A".to_string(),
                 result_type: "LOCAL".to_string(),
                 duration_ms: 5,
+                upstream_server: None,
+                query_id: None,
             },
         )
         .await
@@ -400,6 +1448,8 @@ mod tests {
                 q_type: "A".to_string(),
                 result_type: "FORWARDED".to_string(),
                 duration_ms: 25,
+                upstream_server: None,
+                query_id: None,
             },
         )
         .await
@@ -426,6 +1476,8 @@ mod tests {
                 q_type: "A".to_string(),
                 result_type: "LOCAL".to_string(),
                 duration_ms: 1,
+                upstream_server: None,
+                query_id: None,
             },
         )
         .await
@@ -458,6 +1510,60 @@ mod tests {
         assert_eq!(logs[0].query_name, "recent.local");
     }
 
+    #[tokio::test]
+    async fn test_log_timeseries_buckets_by_hour() {
+        let pool = setup_test_db().await;
+
+        for (timestamp, result_type) in [
+            ("2024-05-01 09:59:59", "LOCAL"),
+            ("2024-05-01 10:00:00", "LOCAL"),
+            ("2024-05-01 10:30:00", "FORWARDED"),
+            ("2024-05-01 10:59:59", "LOCAL"),
+            ("2024-05-01 12:15:00", "FORWARDED"),
+            // 集計範囲外
+            ("2024-05-01 08:59:59", "LOCAL"),
+            ("2024-05-01 13:00:00", "LOCAL"),
+        ] {
+            sqlx::query(
+                "INSERT INTO query_logs (query_name, q_type, result_type, duration_ms, timestamp)
+                 VALUES ('app.local', 'A', ?, 1, ?)",
+            )
+            .bind(result_type)
+            .bind(timestamp)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        // 2024-05-01 12:30:00 UTC までの4時間を1時間ごとに集計
+        let end = 1714566600;
+        let buckets = get_log_timeseries(&pool, end, 4 * 3600, 3600, false)
+            .await
+            .unwrap();
+        let summary: Vec<(&str, i64)> = buckets
+            .iter()
+            .map(|bucket| (bucket.start.as_str(), bucket.count))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("2024-05-01 09:00:00", 1),
+                ("2024-05-01 10:00:00", 3),
+                ("2024-05-01 11:00:00", 0),
+                ("2024-05-01 12:00:00", 1),
+            ]
+        );
+        assert!(buckets[0].by_result_type.is_none());
+
+        let buckets = get_log_timeseries(&pool, end, 4 * 3600, 3600, true)
+            .await
+            .unwrap();
+        let by_result_type = buckets[1].by_result_type.as_ref().unwrap();
+        assert_eq!(by_result_type["LOCAL"], 2);
+        assert_eq!(by_result_type["FORWARDED"], 1);
+        assert!(buckets[2].by_result_type.as_ref().unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_cleanup_old_logs_no_old_logs() {
         let pool = setup_test_db().await;
@@ -470,6 +1576,8 @@ mod tests {
                 q_type: "A".to_string(),
                 result_type: "LOCAL".to_string(),
                 duration_ms: 1,
+                upstream_server: None,
+                query_id: None,
             },
         )
         .await
@@ -483,4 +1591,84 @@ mod tests {
         let logs = get_recent_logs(&pool, 10).await.unwrap();
         assert_eq!(logs.len(), 1);
     }
+
+    /// ゾーン転送で作成した想定のレコード登録リクエスト
+    fn transferred_request(domain_pattern: &str) -> CreateRecordRequest {
+        CreateRecordRequest {
+            domain_pattern: domain_pattern.to_string(),
+            record_type: "A".to_string(),
+            content: "192.0.2.10".into(),
+            ttl: 300,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: vec!["axfr:example.test".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backup_round_trip_keeps_record_source() {
+        let pool = setup_test_db().await;
+        replace_records_from_source(
+            &pool,
+            "axfr:example.test",
+            vec![transferred_request("www.example.test")],
+        )
+        .await
+        .unwrap();
+
+        let backup = export_backup(&pool).await.unwrap();
+        let json = serde_json::to_string(&backup).unwrap();
+        import_backup(&pool, &serde_json::from_str::<Backup>(&json).unwrap())
+            .await
+            .unwrap();
+
+        let records = get_active_records(&pool).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].source.as_deref(), Some("axfr:example.test"));
+
+        // 復元後の再取得でも重複しない
+        replace_records_from_source(
+            &pool,
+            "axfr:example.test",
+            vec![transferred_request("www.example.test")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(get_active_records(&pool).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replace_records_from_source_keeps_soft_deleted() {
+        let pool = setup_test_db().await;
+        let zone = || {
+            vec![
+                transferred_request("www.example.test"),
+                transferred_request("mail.example.test"),
+            ]
+        };
+        replace_records_from_source(&pool, "axfr:example.test", zone())
+            .await
+            .unwrap();
+
+        // 利用者が論理削除した転送レコードは、再取得後も削除済みのまま残る
+        let mail = get_active_records(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|record| record.domain_pattern == "mail.example.test")
+            .unwrap();
+        delete_record(&pool, mail.id).await.unwrap();
+
+        replace_records_from_source(&pool, "axfr:example.test", zone())
+            .await
+            .unwrap();
+
+        let active = get_active_records(&pool).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].domain_pattern, "www.example.test");
+        assert!(get_deleted_record(&pool, mail.id).await.unwrap().is_some());
+    }
 }
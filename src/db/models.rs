@@ -10,37 +10,271 @@ pub struct Record {
     pub content: String,
     pub ttl: i64,
     pub active: i64,
+    /// 優先度（値が大きいほど優先。既定値 0）
+    pub priority: i64,
+    /// 同じ名前の複数回答を並べる際の重み（大きいほど先頭に来やすい。既定値 1）
+    pub weight: i64,
+    /// マッチしてもローカルで応答せず上位DNSに転送する（記録用に残しているレコード向け）
+    #[serde(default)]
+    pub passthrough: bool,
+    /// ドメインパターンの種類（`glob` は `%` ワイルドカード、`regex` は正規表現）
+    #[serde(default)]
+    pub pattern_type: PatternType,
+    /// 応答する問い合わせ元の範囲（CIDR。None の場合は全ての問い合わせ元に応答する）
+    #[serde(default)]
+    pub client_subnet: Option<String>,
+    /// タグ（カンマ区切り）
+    pub tags: String,
+    /// 論理削除日時（未削除の場合は None）
+    pub deleted_at: Option<String>,
+    /// ローカル応答に使われた回数（定期的にまとめて反映）
+    pub hit_count: i64,
+    /// 最後にローカル応答に使われた日時
+    pub last_hit_at: Option<String>,
+    /// 登録元（ゾーン転送で作成したレコードは `axfr:<ゾーン>`、それ以外は None）
+    #[serde(default)]
+    pub source: Option<String>,
 }
 
 impl Record {
     /// ドメインパターンがクエリ名にマッチするか判定
-    /// SQLiteの LIKE パターンを使用（% はワイルドカード）
+    /// `glob` はSQLiteの LIKE パターン（% はワイルドカード）、`regex` は名前全体にマッチする正規表現
     pub fn matches(&self, query_name: &str) -> bool {
-        if !self.is_active() {
-            return false;
+        self.match_captures(query_name).is_some()
+    }
+
+    /// ドメインパターンがクエリ名にマッチした場合、各ワイルドカード（%）に対応する部分を順に返す
+    /// `regex` の場合は各キャプチャグループに対応する部分を返す
+    pub fn match_captures(&self, query_name: &str) -> Option<Vec<String>> {
+        // catch-all は通常のマッチには使わない（キャッシュ側で他に一致が無い場合のみ使う）
+        if !self.is_active() || self.is_catch_all() {
+            return None;
+        }
+
+        let re = match self.pattern_type {
+            PatternType::Glob => {
+                // パターンをRust正規表現に変換（ワイルドカードはキャプチャグループにする）
+                let pattern = self.domain_pattern
+                    .replace('.', r"\.")
+                    .replace('%', "(.*)");
+
+                regex::Regex::new(&format!("^{}$", pattern)).ok()?
+            }
+            PatternType::Regex => compile_regex_pattern(&self.domain_pattern).ok()?,
+        };
+        let captures = re.captures(query_name)?;
+        Some(
+            captures
+                .iter()
+                .skip(1)
+                .map(|m| m.map(|m| m.as_str().to_string()).unwrap_or_default())
+                .collect(),
+        )
+    }
+
+    /// コンテンツ中の `$1`〜`$9`（`${label}` は `$1` と同じ）をワイルドカードでマッチした部分で置換
+    /// 例: `%.dev.local` / `$1.backend.local` は `foo.dev.local` に対して `foo.backend.local` になる
+    pub fn expand_content(&self, query_name: &str) -> String {
+        if !self.content.contains('$') {
+            return self.content.clone();
+        }
+
+        let captures = self.match_captures(query_name).unwrap_or_default();
+        let capture = |index: usize| captures.get(index).map(String::as_str).unwrap_or("");
+
+        let mut content = self.content.replace("${label}", capture(0));
+        for index in 1..=9 {
+            content = content.replace(&format!("${}", index), capture(index - 1));
+        }
+        content
+    }
+
+    /// 問い合わせタイプに応答できるレコードか
+    /// AUTO レコードは、対応するアドレスファミリーのアドレスを持つ場合に A / AAAA に応答する
+    pub fn answers_type(&self, record_type: &str) -> bool {
+        if self.record_type == record_type {
+            return true;
+        }
+
+        self.record_type == "AUTO"
+            && match record_type {
+                "A" => self.auto_addresses().any(|ip| ip.is_ipv4()),
+                "AAAA" => self.auto_addresses().any(|ip| ip.is_ipv6()),
+                _ => false,
+            }
+    }
+
+    /// A / AAAA / AUTO レコードのアドレスのいずれかが指定の範囲に含まれるか
+    pub fn address_in(&self, net: &ipnet::IpNet) -> bool {
+        match self.record_type.as_str() {
+            "A" | "AAAA" => self
+                .content
+                .trim()
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| net.contains(&ip)),
+            "AUTO" => self.auto_addresses().any(|ip| net.contains(&ip)),
+            _ => false,
         }
+    }
 
-        // パターンをRust正規表現に変換
-        let pattern = self.domain_pattern
-            .replace('.', r"\.")
-            .replace('%', ".*");
+    /// AUTO レコードのコンテンツ（カンマ区切りのIPアドレス）をパース
+    /// パースできない要素は無視する
+    pub fn auto_addresses(&self) -> impl Iterator<Item = std::net::IpAddr> + '_ {
+        self.content
+            .split(',')
+            .filter_map(|ip| ip.trim().parse().ok())
+    }
 
-        if let Ok(re) = regex::Regex::new(&format!("^{}$", pattern)) {
-            re.is_match(query_name)
-        } else {
-            false
+    /// 問い合わせ元に応答できるレコードか
+    /// 範囲を指定したレコードは、範囲内の問い合わせ元にのみ応答する（問い合わせ元が不明な場合は応答しない）
+    pub fn serves_client(&self, client_ip: Option<std::net::IpAddr>) -> bool {
+        match &self.client_subnet {
+            None => true,
+            Some(subnet) => match (subnet.parse::<ipnet::IpNet>(), client_ip) {
+                // IPv4射影アドレス（::ffff:10.0.0.1）は IPv4 として扱う
+                (Ok(net), Some(ip)) => net.contains(&ip.to_canonical()),
+                _ => false,
+            },
         }
     }
 
+    /// 応答する問い合わせ元の範囲のプレフィックス長（範囲の指定が無い場合は None）
+    pub fn client_subnet_prefix(&self) -> Option<u8> {
+        self.client_subnet
+            .as_deref()
+            .and_then(|subnet| subnet.parse::<ipnet::IpNet>().ok())
+            .map(|net| net.prefix_len())
+    }
+
     /// レコードが有効かどうか
     pub fn is_active(&self) -> bool {
         self.active == 1
     }
 
-    /// 完全一致パターンかどうか（ワイルドカードを含まない）
+    /// 完全一致パターンかどうか（ワイルドカードを含まない glob パターン）
     pub fn is_exact_match(&self) -> bool {
-        !self.domain_pattern.contains('%')
+        self.pattern_type == PatternType::Glob
+            && !self.domain_pattern.contains('%')
+            && !self.is_catch_all()
+    }
+
+    /// catch-all レコードかどうか（glob パターンの `*`。ローカルゾーン内で他に一致しない名前に応答する）
+    pub fn is_catch_all(&self) -> bool {
+        self.pattern_type == PatternType::Glob && self.domain_pattern.trim() == CATCH_ALL_PATTERN
+    }
+
+    /// タグの一覧
+    #[allow(dead_code)]
+    pub fn tag_list(&self) -> Vec<&str> {
+        self.tags.split(',').filter(|tag| !tag.is_empty()).collect()
+    }
+}
+
+/// ドメインパターンの種類
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum PatternType {
+    /// `%` をワイルドカードとするパターン
+    #[default]
+    Glob,
+    /// 名前全体にマッチする正規表現
+    Regex,
+}
+
+/// catch-all レコードのドメインパターン
+pub const CATCH_ALL_PATTERN: &str = "*";
+
+/// 正規表現パターンの長さの上限
+pub const MAX_REGEX_PATTERN_LEN: usize = 256;
+
+/// 正規表現をコンパイルした結果のサイズの上限（バイト）
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// 正規表現のドメインパターンをコンパイル（名前全体にマッチさせる）
+/// regex クレートはマッチングが入力長に対して線形なので、長さ・サイズ・ネストの上限で
+/// コンパイル時のコストを抑えれば ReDoS にはならない
+pub fn compile_regex_pattern(pattern: &str) -> Result<regex::Regex, String> {
+    if pattern.len() > MAX_REGEX_PATTERN_LEN {
+        return Err(format!("正規表現は{}文字以内で指定してください", MAX_REGEX_PATTERN_LEN));
     }
+
+    regex::RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .nest_limit(32)
+        .build()
+        .map_err(|e| format!("正規表現が不正です: {}", e))
+}
+
+/// コンテンツ中の環境変数参照（`${VAR}`）を順に返す
+/// 変数名は英大文字・数字・`_` のみ（ワイルドカードの `${label}` と区別するため）
+fn env_references(content: &str) -> impl Iterator<Item = (usize, usize, &str)> + '_ {
+    let mut offset = 0;
+    std::iter::from_fn(move || loop {
+        let start = offset + content.get(offset..)?.find("${")?;
+        let end = start + content[start..].find('}')?;
+        offset = start + 2;
+
+        let name = &content[start + 2..end];
+        let valid = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_uppercase() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+        if valid {
+            offset = end + 1;
+            return Some((start, end + 1, name));
+        }
+    })
+}
+
+/// コンテンツが環境変数参照（`${VAR}`）を含むか
+pub fn has_env_reference(content: &str) -> bool {
+    env_references(content).next().is_some()
+}
+
+/// コンテンツ中の `${VAR}` を `lookup` で得た値に置き換える
+/// 値が得られない変数は、`strict` の場合はその変数名をエラーとして返し、それ以外はそのまま残す
+pub fn expand_env_vars(
+    content: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    strict: bool,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(content.len());
+    let mut last = 0;
+    for (start, end, name) in env_references(content) {
+        expanded.push_str(&content[last..start]);
+        match lookup(name) {
+            Some(value) => expanded.push_str(&value),
+            None if strict => return Err(name.to_string()),
+            None => expanded.push_str(&content[start..end]),
+        }
+        last = end;
+    }
+    expanded.push_str(&content[last..]);
+    Ok(expanded)
+}
+
+/// 問い合わせ元の範囲をDB保存用に正規化（空の場合は範囲の指定なし）
+pub fn normalize_client_subnet(client_subnet: Option<&str>) -> Option<String> {
+    client_subnet
+        .map(str::trim)
+        .filter(|subnet| !subnet.is_empty())
+        .map(str::to_string)
+}
+
+/// タグ一覧をDB保存用のカンマ区切り文字列に正規化（前後の空白除去・空要素と重複の除去）
+pub fn normalize_tags(tags: &[String]) -> String {
+    let mut normalized: Vec<&str> = Vec::new();
+    for tag in tags.iter().map(|tag| tag.trim()) {
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized.join(",")
 }
 
 /// クエリログ
@@ -52,6 +286,10 @@ pub struct QueryLog {
     pub result_type: String,
     pub duration_ms: i64,
     pub timestamp: String,
+    /// 応答した上位DNSサーバー（転送時のみ）
+    pub upstream_server: Option<String>,
+    /// 問い合わせID（トレースのスパンと関連付ける）
+    pub query_id: Option<String>,
 }
 
 /// 新規クエリログの作成用
@@ -61,6 +299,73 @@ pub struct NewQueryLog {
     pub q_type: String,
     pub result_type: String,
     pub duration_ms: i64,
+    pub upstream_server: Option<String>,
+    pub query_id: Option<String>,
+}
+
+/// 適用済みのスキーマバージョン
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SchemaMigration {
+    pub version: i64,
+    pub applied_at: String,
+}
+
+/// APIキーのスコープ（`read` < `write` < `admin` の順に上位のスコープは下位の操作も行える）
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    /// 参照のみ（GET）
+    Read,
+    /// 参照と変更
+    Write,
+    /// 全ての操作（APIキーの管理を含む）
+    Admin,
+}
+
+impl ApiKeyScope {
+    /// 必要なスコープの操作を行えるか
+    pub fn allows(self, required: ApiKeyScope) -> bool {
+        self >= required
+    }
+}
+
+/// APIキー（キーそのものは保存しない）
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub scope: ApiKeyScope,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub created_at: String,
+}
+
+/// APIキー作成用リクエスト
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scope: ApiKeyScope,
+}
+
+/// APIキー更新用リクエスト（指定した項目のみ更新する）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateApiKeyRequest {
+    pub name: Option<String>,
+    pub scope: Option<ApiKeyScope>,
+}
+
+/// 時間帯ごとのクエリログ件数（グラフ用）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogTimeBucket {
+    /// 時間帯の開始日時（`YYYY-MM-DD HH:MM:SS`、UTC）
+    pub start: String,
+    pub count: i64,
+    /// 結果種別ごとの件数（結果種別で分けた場合のみ）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by_result_type: Option<std::collections::BTreeMap<String, i64>>,
 }
 
 /// 設定
@@ -75,15 +380,63 @@ pub struct Setting {
 pub struct CreateRecordRequest {
     pub domain_pattern: String,
     pub record_type: String,
-    pub content: String,
+    /// 配列で指定した場合は値ごとにレコードを作成する
+    pub content: RecordContent,
     #[serde(default = "default_ttl")]
     pub ttl: i64,
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(default = "default_weight")]
+    pub weight: i64,
+    #[serde(default)]
+    pub passthrough: bool,
+    #[serde(default)]
+    pub pattern_type: PatternType,
+    /// 応答する問い合わせ元の範囲（CIDR）
+    #[serde(default)]
+    pub client_subnet: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// 作成するレコードのコンテンツ（文字列または文字列の配列）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RecordContent {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl RecordContent {
+    /// コンテンツの値の一覧
+    pub fn values(&self) -> &[String] {
+        match self {
+            RecordContent::Single(value) => std::slice::from_ref(value),
+            RecordContent::Multiple(values) => values,
+        }
+    }
+}
+
+impl From<String> for RecordContent {
+    fn from(value: String) -> Self {
+        RecordContent::Single(value)
+    }
+}
+
+impl From<&str> for RecordContent {
+    fn from(value: &str) -> Self {
+        RecordContent::Single(value.to_string())
+    }
 }
 
 fn default_ttl() -> i64 {
     60
 }
 
+fn default_weight() -> i64 {
+    1
+}
+
 /// レコード更新用リクエスト
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateRecordRequest {
@@ -92,6 +445,13 @@ pub struct UpdateRecordRequest {
     pub content: Option<String>,
     pub ttl: Option<i64>,
     pub active: Option<i64>,
+    pub priority: Option<i64>,
+    pub weight: Option<i64>,
+    pub passthrough: Option<bool>,
+    pub pattern_type: Option<PatternType>,
+    /// 空文字列を指定すると範囲の指定を解除する
+    pub client_subnet: Option<String>,
+    pub tags: Option<Vec<String>>,
 }
 
 /// 設定更新用リクエスト
@@ -100,6 +460,21 @@ pub struct UpdateSettingRequest {
     pub value: String,
 }
 
+/// バックアップの形式バージョン（互換性のない変更を加えた場合に上げる）
+pub const BACKUP_VERSION: u32 = 1;
+
+/// 全体のバックアップ（クエリログを除くレコード・設定）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    /// 形式バージョン（これより新しい形式は取り込まない）
+    pub version: u32,
+    /// 作成日時（UTC）
+    pub created_at: String,
+    /// 論理削除済みを含む全レコード
+    pub records: Vec<Record>,
+    pub settings: Vec<Setting>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,6 +488,16 @@ mod tests {
             content: "127.0.0.1".to_string(),
             ttl: 60,
             active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
         };
 
         assert!(record.matches("app.local.test"));
@@ -128,6 +513,16 @@ mod tests {
             content: "127.0.0.1".to_string(),
             ttl: 60,
             active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
         };
 
         assert!(record.matches("app.local.test"));
@@ -136,6 +531,147 @@ mod tests {
         assert!(!record.matches("local.test"));
     }
 
+    #[test]
+    fn test_expand_content_with_wildcard_capture() {
+        let record = Record {
+            id: 1,
+            domain_pattern: "%.dev.local".to_string(),
+            record_type: "CNAME".to_string(),
+            content: "$1.backend.local".to_string(),
+            ttl: 60,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        assert_eq!(record.match_captures("foo.dev.local"), Some(vec!["foo".to_string()]));
+        assert_eq!(record.expand_content("foo.dev.local"), "foo.backend.local");
+
+        let named = Record {
+            content: "${label}.backend.local".to_string(),
+            ..record.clone()
+        };
+        assert_eq!(named.expand_content("bar.dev.local"), "bar.backend.local");
+
+        // テンプレートを含まないコンテンツはそのまま
+        let plain = Record {
+            content: "backend.local".to_string(),
+            ..record
+        };
+        assert_eq!(plain.expand_content("foo.dev.local"), "backend.local");
+    }
+
+    #[test]
+    fn test_record_matches_regex() {
+        let record = Record {
+            id: 1,
+            domain_pattern: r"(api|web)-\d+\.local\.test".to_string(),
+            record_type: "A".to_string(),
+            content: "127.0.0.1".to_string(),
+            ttl: 60,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Regex,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        assert!(record.matches("api-1.local.test"));
+        assert!(record.matches("web-42.local.test"));
+        // 名前全体にマッチする必要がある
+        assert!(!record.matches("x.api-1.local.test"));
+        assert!(!record.matches("db-1.local.test"));
+        assert!(!record.is_exact_match());
+        assert_eq!(
+            record.match_captures("web-42.local.test"),
+            Some(vec!["web".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_compile_regex_pattern_rejects_invalid_and_complex() {
+        assert!(compile_regex_pattern(r"[a-z]+\.local\.test").is_ok());
+        assert!(compile_regex_pattern("(unclosed").is_err());
+        assert!(compile_regex_pattern(&"a".repeat(MAX_REGEX_PATTERN_LEN + 1)).is_err());
+        // 長さの上限内でも、展開後のサイズが上限を超えるものは拒否する
+        assert!(compile_regex_pattern(r"(\w{100}){100}").is_err());
+    }
+
+    #[test]
+    fn test_address_in_cidr_boundaries() {
+        let record = |record_type: &str, content: &str| Record {
+            id: 1,
+            domain_pattern: "host.local.test".to_string(),
+            record_type: record_type.to_string(),
+            content: content.to_string(),
+            ttl: 60,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+        let net: ipnet::IpNet = "192.168.1.0/24".parse().unwrap();
+
+        // 範囲の先頭・末尾は含まれ、その外側は含まれない
+        assert!(record("A", "192.168.1.0").address_in(&net));
+        assert!(record("A", "192.168.1.255").address_in(&net));
+        assert!(!record("A", "192.168.0.255").address_in(&net));
+        assert!(!record("A", "192.168.2.0").address_in(&net));
+
+        // アドレス以外のレコード・ファミリー違いは含まれない
+        assert!(!record("CNAME", "192.168.1.10").address_in(&net));
+        assert!(!record("AAAA", "fd00::1").address_in(&net));
+        assert!(record("AUTO", "fd00::1, 192.168.1.10").address_in(&net));
+    }
+
+    #[test]
+    fn test_address_in_ipv6_cidr() {
+        let record = Record {
+            id: 1,
+            domain_pattern: "host.local.test".to_string(),
+            record_type: "AAAA".to_string(),
+            content: "fd00:1::ffff".to_string(),
+            ttl: 60,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        assert!(record.address_in(&"fd00:1::/64".parse().unwrap()));
+        assert!(record.address_in(&"fd00::/8".parse().unwrap()));
+        assert!(!record.address_in(&"fd00:2::/64".parse().unwrap()));
+        assert!(!record.address_in(&"192.168.1.0/24".parse().unwrap()));
+    }
+
     #[test]
     fn test_record_matches_inactive() {
         let record = Record {
@@ -145,8 +681,53 @@ mod tests {
             content: "127.0.0.1".to_string(),
             ttl: 60,
             active: 0,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
         };
 
         assert!(!record.matches("app.local.test"));
     }
+
+    #[test]
+    fn test_expand_env_vars() {
+        let lookup = |name: &str| (name == "BACKEND_IP").then(|| "10.1.2.3".to_string());
+
+        assert_eq!(expand_env_vars("${BACKEND_IP}", lookup, false).unwrap(), "10.1.2.3");
+        assert_eq!(
+            expand_env_vars("${BACKEND_IP}, ${MISSING}", lookup, false).unwrap(),
+            "10.1.2.3, ${MISSING}"
+        );
+        assert_eq!(
+            expand_env_vars("${BACKEND_IP}, ${MISSING}", lookup, true),
+            Err("MISSING".to_string())
+        );
+
+        // ワイルドカードのテンプレート（小文字）は環境変数として扱わない
+        assert_eq!(
+            expand_env_vars("${label}.backend.local", lookup, true).unwrap(),
+            "${label}.backend.local"
+        );
+        assert!(!has_env_reference("${label}.backend.local"));
+        assert!(has_env_reference("${BACKEND_IP}"));
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        let tags = vec![
+            " project-a ".to_string(),
+            "".to_string(),
+            "project-b".to_string(),
+            "project-a".to_string(),
+        ];
+        assert_eq!(normalize_tags(&tags), "project-a,project-b");
+        assert_eq!(normalize_tags(&[]), "");
+    }
 }
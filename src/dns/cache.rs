@@ -1,37 +1,155 @@
-use crate::db::{get_active_records, DbPool, Record};
-use anyhow::Result;
-use std::sync::Arc;
+use crate::db::{add_record_hits, expand_env_vars, get_active_records, DbPool, Record};
+use crate::dns::resolver::content_is_valid;
+use anyhow::{anyhow, Result};
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::Record as DnsRecord;
+use ipnet::IpNet;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
+
+/// レコードのヒット数をDBに反映する間隔
+const HIT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 上位DNS応答キャッシュに保持するエントリ数の上限（デフォルト）
+pub const UPSTREAM_CACHE_MAX_ENTRIES: usize = 10_000;
+
+/// コンテンツ中の環境変数参照（`${VAR}`）の展開方法
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvExpansion {
+    /// 展開しない
+    Off,
+    /// 展開し、未定義の変数はそのまま残す
+    Keep,
+    /// 展開し、未定義の変数があれば読み込みをエラーにする
+    Strict,
+}
+
+/// 設定値（`off` / `keep` / `strict`）を展開方法に変換
+pub fn parse_env_expansion(value: &str) -> Option<EnvExpansion> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "off" => Some(EnvExpansion::Off),
+        "keep" => Some(EnvExpansion::Keep),
+        "strict" => Some(EnvExpansion::Strict),
+        _ => None,
+    }
+}
+
+/// ゾーン名の一覧の設定値（`local_zones` / `authoritative_zones`、カンマ区切り）を解析（小文字にし、前後のドットを除く）
+pub fn parse_zone_list(value: &str) -> Result<Vec<String>, String> {
+    value
+        .split(',')
+        .map(|zone| zone.trim().trim_matches('.').to_ascii_lowercase())
+        .filter(|zone| !zone.is_empty())
+        .map(|zone| {
+            let valid = zone.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && label
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            });
+            if valid {
+                Ok(zone)
+            } else {
+                Err(format!("不正なゾーン名です: {}", zone))
+            }
+        })
+        .collect()
+}
+
+/// コンテンツから応答を組み立てられないレコードを集める
+fn find_invalid_records(records: &[Record]) -> Vec<Record> {
+    records
+        .iter()
+        .filter(|record| !content_is_valid(record))
+        .inspect(|record| {
+            warn!(
+                "レコード {} ({} {}) のコンテンツを解析できません: {}",
+                record.id, record.domain_pattern, record.record_type, record.content
+            )
+        })
+        .cloned()
+        .collect()
+}
 
 /// レコードキャッシュ
 #[derive(Clone)]
 pub struct RecordCache {
-    records: Arc<RwLock<Vec<Record>>>,
+    /// 読み込み済みのレコード一式（再読み込み時は丸ごと差し替える）
+    records: Arc<RwLock<Arc<Vec<Record>>>>,
     pool: DbPool,
+    /// 未反映のヒット数（レコードID -> (ヒット数, 最終ヒット日時)）
+    hits: Arc<Mutex<HashMap<i64, (u64, String)>>>,
+    /// 読み込み時のコンテンツ中の環境変数の展開方法
+    env_expansion: EnvExpansion,
+    /// catch-all レコードで応答するローカルゾーン（小文字、末尾のドットなし）
+    local_zones: Arc<Vec<String>>,
+    /// 読み込み時にコンテンツを解析できなかったレコード
+    invalid: Arc<RwLock<Vec<Record>>>,
 }
 
 impl RecordCache {
     /// 新しいキャッシュを作成し、DBから初期ロード
     pub async fn new(pool: DbPool) -> Result<Self> {
+        Self::with_env_expansion(pool, EnvExpansion::Off).await
+    }
+
+    /// 環境変数の展開方法を指定してキャッシュを作成し、DBから初期ロード
+    pub async fn with_env_expansion(pool: DbPool, env_expansion: EnvExpansion) -> Result<Self> {
         let cache = Self {
-            records: Arc::new(RwLock::new(Vec::new())),
+            records: Arc::new(RwLock::new(Arc::new(Vec::new()))),
             pool,
+            hits: Arc::new(Mutex::new(HashMap::new())),
+            env_expansion,
+            local_zones: Arc::new(Vec::new()),
+            invalid: Arc::new(RwLock::new(Vec::new())),
         };
 
         cache.reload().await?;
         Ok(cache)
     }
 
+    /// catch-all レコードで応答するローカルゾーンを設定（未設定の場合 catch-all は使われない）
+    pub fn with_local_zones(mut self, local_zones: Vec<String>) -> Self {
+        self.local_zones = Arc::new(local_zones);
+        self
+    }
+
+    /// クエリ名がローカルゾーン（ゾーン頂点を含む）の配下か
+    fn in_local_zone(&self, query_name: &str) -> bool {
+        let query_name = query_name.to_ascii_lowercase();
+        self.local_zones.iter().any(|zone| {
+            query_name == *zone
+                || query_name
+                    .strip_suffix(zone.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+    }
+
     /// キャッシュをDBから再読み込み
+    /// 新しいレコード一式はロックの外で組み立て、書き込みロック中は差し替えだけを行う
     pub async fn reload(&self) -> Result<()> {
         info!("レコードキャッシュを再読み込み中");
 
-        match get_active_records(&self.pool).await {
+        match self.load_records().await {
             Ok(records) => {
                 let count = records.len();
-                let mut cache = self.records.write().await;
-                *cache = records;
+                let invalid = find_invalid_records(&records);
+                if !invalid.is_empty() {
+                    warn!(
+                        "コンテンツを解析できないレコードが {} 件あります（/api/records/invalid で確認できます）",
+                        invalid.len()
+                    );
+                }
+                *self.invalid.write().await = invalid;
+                let records = Arc::new(records);
+                let previous = std::mem::replace(&mut *self.records.write().await, records);
+                // 古いレコード一式の解放もロックの外で行う
+                drop(previous);
                 info!("レコードキャッシュ再読み込み完了: {} 件", count);
                 Ok(())
             }
@@ -42,53 +160,542 @@ impl RecordCache {
         }
     }
 
+    /// 直近の読み込み時にコンテンツを解析できなかったレコード
+    pub async fn invalid_records(&self) -> Vec<Record> {
+        self.invalid.read().await.clone()
+    }
+
+    /// 現在のレコード一式を取得（読み込みロックは参照を複製する間だけ保持する）
+    async fn snapshot(&self) -> Arc<Vec<Record>> {
+        self.records.read().await.clone()
+    }
+
+    /// DBから有効なレコードを読み込み、設定に応じてコンテンツ中の環境変数を展開する
+    async fn load_records(&self) -> Result<Vec<Record>> {
+        let mut records = get_active_records(&self.pool).await?;
+        if self.env_expansion == EnvExpansion::Off {
+            return Ok(records);
+        }
+
+        let strict = self.env_expansion == EnvExpansion::Strict;
+        for record in records.iter_mut() {
+            record.content =
+                expand_env_vars(&record.content, |name| std::env::var(name).ok(), strict)
+                    .map_err(|name| {
+                        anyhow!(
+                            "レコード {} ({}) の環境変数が未定義です: {}",
+                            record.id,
+                            record.domain_pattern,
+                            name
+                        )
+                    })?;
+        }
+        Ok(records)
+    }
+
     /// クエリ名に一致するレコードを検索
-    /// 完全一致を優先し、次にワイルドカードマッチを返す
+    /// 優先度が最も高く、同じ優先度なら完全一致をワイルドカードより優先したものを返す
     pub async fn find_matching_record(
         &self,
         query_name: &str,
         record_type: &str,
     ) -> Option<Record> {
-        let records = self.records.read().await;
+        self.find_matching_records(query_name, record_type, None)
+            .await
+            .into_iter()
+            .next()
+    }
 
-        let mut wildcard_match: Option<&Record> = None;
+    /// クエリ名に一致するレコードを全て取得
+    /// 問い合わせ元の範囲を指定したレコードは `client_ip` が範囲内の場合のみ対象にする。
+    /// ローカルゾーン配下の名前にどのタイプのレコードも一致せず、委任もされていない場合は catch-all レコードを使う。
+    /// 優先度の降順、同じ優先度内では範囲の狭い（プレフィックス長の長い）ものから範囲指定なしの順、
+    /// さらに完全一致→ワイルドカードの順に並べる（それ以外は登録順を維持）
+    pub async fn find_matching_records(
+        &self,
+        query_name: &str,
+        record_type: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Vec<Record> {
+        let records = self.snapshot().await;
+
+        let mut matches: Vec<Record> = records
+            .iter()
+            .filter(|record| {
+                record.answers_type(record_type)
+                    && record.serves_client(client_ip)
+                    && record.matches(query_name)
+            })
+            .cloned()
+            .collect();
+
+        if matches.is_empty()
+            && self.in_local_zone(query_name)
+            && !records.iter().any(|record| record.matches(query_name))
+            && self.find_delegation(query_name).await.is_empty()
+        {
+            matches = records
+                .iter()
+                .filter(|record| {
+                    record.is_catch_all()
+                        && record.is_active()
+                        && record.answers_type(record_type)
+                        && record.serves_client(client_ip)
+                })
+                .cloned()
+                .collect();
+        }
+
+        matches.sort_by_key(|record| {
+            (
+                std::cmp::Reverse(record.priority),
+                std::cmp::Reverse(record.client_subnet_prefix()),
+                !record.is_exact_match(),
+            )
+        });
+
+        matches
+    }
+
+    /// クエリ名に一致する有効なレコードがタイプを問わず存在するか（catch-all は除く）
+    pub async fn name_exists(&self, query_name: &str) -> bool {
+        self.snapshot()
+            .await
+            .iter()
+            .any(|record| record.matches(query_name))
+    }
+
+    /// 応答に使うレコードを取得
+    /// 最も優先されるレコードと同じドメインパターン・優先度・問い合わせ元の範囲のレコードをまとめて返す（複数回答）。
+    /// CNAME は1つの名前に1つしか存在できないため先頭のみ返す
+    pub async fn find_answer_records(
+        &self,
+        query_name: &str,
+        record_type: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Vec<Record> {
+        let mut matches = self
+            .find_matching_records(query_name, record_type, client_ip)
+            .await;
+        let Some(best) = matches.first().cloned() else {
+            return matches;
+        };
+
+        if best.record_type == "CNAME" {
+            matches.truncate(1);
+        } else {
+            matches.retain(|record| {
+                record.priority == best.priority
+                    && record.domain_pattern == best.domain_pattern
+                    && record.client_subnet == best.client_subnet
+                    && record.record_type != "CNAME"
+            });
+        }
+        matches
+    }
+
+    /// クエリ名が委任されたサブゾーン配下であれば、そのゾーンのNSレコードを返す
+    /// 複数のゾーンが該当する場合は最も深いゾーンを採用する
+    pub async fn find_delegation(&self, query_name: &str) -> Vec<Record> {
+        let records = self.snapshot().await;
+        let query_name = query_name.to_lowercase();
+
+        let mut best_zone: Option<String> = None;
+        let mut delegation: Vec<Record> = Vec::new();
 
         for record in records.iter() {
-            if record.record_type != record_type {
+            if record.record_type != "NS" || !record.is_active() || !record.is_exact_match() {
                 continue;
             }
 
-            if !record.matches(query_name) {
+            let zone = record.domain_pattern.trim_end_matches('.').to_lowercase();
+            if query_name != zone && !query_name.ends_with(&format!(".{}", zone)) {
                 continue;
             }
 
-            // 完全一致（ワイルドカードを含まない）の場合は即座に返す
-            if record.is_exact_match() {
-                return Some(record.clone());
-            }
-
-            // ワイルドカードマッチは最初のものを保持
-            if wildcard_match.is_none() {
-                wildcard_match = Some(record);
+            match &best_zone {
+                // より深いゾーンが見つかった場合は置き換え
+                Some(best) if zone.len() > best.len() => {
+                    best_zone = Some(zone);
+                    delegation = vec![record.clone()];
+                }
+                Some(best) if *best == zone => delegation.push(record.clone()),
+                Some(_) => {}
+                None => {
+                    best_zone = Some(zone);
+                    delegation.push(record.clone());
+                }
             }
         }
 
-        // 完全一致がなければワイルドカードマッチを返す
-        wildcard_match.cloned()
+        delegation
     }
 
-    /// キャッシュ内の全レコード数を取得（将来の統計機能用）
-    #[allow(dead_code)]
+    /// キャッシュ内の全レコード数を取得
     pub async fn count(&self) -> usize {
-        let records = self.records.read().await;
+        let records = self.snapshot().await;
         records.len()
     }
+
+    /// レコードのヒットを記録（DBへは `flush_hits` でまとめて反映）
+    pub fn record_hit(&self, id: i64) {
+        let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let mut hits = self.hits.lock().unwrap();
+        let entry = hits.entry(id).or_insert((0, String::new()));
+        entry.0 += 1;
+        entry.1 = now;
+    }
+
+    /// 溜まったヒット数をDBに反映し、反映したレコード数を返す
+    /// 失敗した場合は次回の反映に持ち越す
+    pub async fn flush_hits(&self) -> Result<usize> {
+        let pending = std::mem::take(&mut *self.hits.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let batch: Vec<(i64, u64, String)> = pending
+            .into_iter()
+            .map(|(id, (count, last_hit_at))| (id, count, last_hit_at))
+            .collect();
+
+        if let Err(e) = add_record_hits(&self.pool, &batch).await {
+            let mut hits = self.hits.lock().unwrap();
+            for (id, count, last_hit_at) in batch {
+                let entry = hits.entry(id).or_insert((0, last_hit_at));
+                entry.0 += count;
+            }
+            return Err(e);
+        }
+
+        debug!("レコードのヒット数を反映: {} 件", batch.len());
+        Ok(batch.len())
+    }
+
+    /// バックグラウンドで定期的にヒット数をDBに反映する
+    pub fn spawn_hit_flush(&self) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(HIT_FLUSH_INTERVAL).await;
+                if let Err(e) = cache.flush_hits().await {
+                    error!("レコードのヒット数の反映に失敗: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// 上位DNS応答キャッシュのエントリ
+struct UpstreamCacheEntry {
+    records: Vec<DnsRecord>,
+    cached_at: Instant,
+    /// エントリの有効期間（レコード中の最小TTL）
+    ttl: u32,
+    /// 上位DNSの応答コード（否定応答の NXDOMAIN と NODATA の区別に使う）
+    response_code: ResponseCode,
+    /// 格納順の通し番号（挿入順キューの要素が現在のエントリを指しているかの判定に使う）
+    seq: u64,
+}
+
+/// 上位DNS応答キャッシュの中身
+/// エントリに加えて挿入順のキューを持ち、上限に達したときは全件を走査せず先頭から破棄する
+#[derive(Default)]
+struct UpstreamCacheEntries {
+    map: HashMap<UpstreamCacheKey, UpstreamCacheEntry>,
+    /// (キー, 通し番号) を格納順に並べたもの。上書き・破棄されたエントリの要素は取り出す際に読み飛ばす
+    order: VecDeque<(UpstreamCacheKey, u64)>,
+    next_seq: u64,
+}
+
+impl UpstreamCacheEntries {
+    fn insert(&mut self, key: UpstreamCacheKey, mut entry: UpstreamCacheEntry) {
+        entry.seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.push_back((key.clone(), entry.seq));
+        self.map.insert(key, entry);
+
+        // 同じキーの上書きが続いてキューに読み飛ばす要素が溜まった場合は詰める
+        if self.order.len() > self.map.len().saturating_mul(2).max(64) {
+            self.compact_order();
+        }
+    }
+
+    /// キューから上書き・破棄済みのエントリを指す要素を取り除く
+    fn compact_order(&mut self) {
+        let map = &self.map;
+        self.order
+            .retain(|(key, seq)| map.get(key).is_some_and(|entry| entry.seq == *seq));
+    }
+
+    /// 最も古く格納されたエントリを破棄する（エントリが無ければ false）
+    fn evict_oldest(&mut self) -> bool {
+        while let Some((key, seq)) = self.order.pop_front() {
+            if self.map.get(&key).is_some_and(|entry| entry.seq == seq) {
+                self.map.remove(&key);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.order.clear();
+    }
+}
+
+/// 上位DNS応答キャッシュから取り出した応答
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedAnswer {
+    pub records: Vec<DnsRecord>,
+    /// 上位DNSの応答コード（回答がある応答と NODATA は NoError、名前が無い場合は NXDomain）
+    pub response_code: ResponseCode,
+}
+
+impl CachedAnswer {
+    /// 名前は存在するが該当するタイプのレコードが無い応答（NODATA）か
+    pub fn is_no_data(&self) -> bool {
+        self.records.is_empty() && self.response_code == ResponseCode::NoError
+    }
+}
+
+impl UpstreamCacheEntry {
+    /// TTL に加えて `retention` だけ経過し、古い応答としても返せなくなったか
+    fn is_obsolete(&self, now: Instant, retention: Duration) -> bool {
+        let expires_at = self.cached_at + Duration::from_secs(u64::from(self.ttl));
+        now.saturating_duration_since(expires_at) > retention
+    }
+}
+
+/// 上位DNS応答キャッシュのキー（クエリ名, レコードタイプ, EDNS Client Subnet のスコープ）
+type UpstreamCacheKey = (String, String, Option<IpNet>);
+
+fn upstream_cache_key(
+    query_name: &str,
+    record_type: &str,
+    scope: Option<IpNet>,
+) -> UpstreamCacheKey {
+    (query_name.to_lowercase(), record_type.to_string(), scope)
+}
+
+/// 上位DNS応答キャッシュ
+/// (クエリ名, レコードタイプ) ごとに上位DNSの応答を保持する。
+/// 問い合わせ元ごとに異なる EDNS Client Subnet を送った応答は、送ったサブネット（スコープ）ごとに分けて保持する
+#[derive(Clone)]
+pub struct UpstreamCache {
+    entries: Arc<RwLock<UpstreamCacheEntries>>,
+    /// 返却時に経過秒数だけTTLを減算するか
+    decrement_ttl: bool,
+    /// 保持するエントリ数の上限
+    max_entries: usize,
+}
+
+impl UpstreamCache {
+    pub fn new(decrement_ttl: bool) -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(UpstreamCacheEntries::default())),
+            decrement_ttl,
+            max_entries: UPSTREAM_CACHE_MAX_ENTRIES,
+        }
+    }
+
+    /// 保持するエントリ数の上限を設定
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// エントリを格納する（上限に達していれば最も古く格納されたエントリを先に破棄する）
+    async fn store(&self, key: UpstreamCacheKey, entry: UpstreamCacheEntry) {
+        let mut entries = self.entries.write().await;
+        if !entries.map.contains_key(&key) {
+            let before = entries.map.len();
+            while entries.map.len() >= self.max_entries && entries.evict_oldest() {}
+            if entries.map.len() < before {
+                debug!(
+                    "上位キャッシュを整理: {} エントリを破棄",
+                    before - entries.map.len()
+                );
+            }
+        }
+        entries.insert(key, entry);
+    }
+
+    /// 上位DNSの応答をキャッシュに格納
+    /// 空の応答やTTLが0のレコードを含む応答はキャッシュしない
+    pub async fn insert(
+        &self,
+        query_name: &str,
+        record_type: &str,
+        scope: Option<IpNet>,
+        records: &[DnsRecord],
+    ) {
+        let Some(ttl) = records.iter().map(|r| r.ttl()).min() else {
+            return;
+        };
+        if ttl == 0 {
+            return;
+        }
+
+        self.store(
+            upstream_cache_key(query_name, record_type, scope),
+            UpstreamCacheEntry {
+                records: records.to_vec(),
+                cached_at: Instant::now(),
+                ttl,
+                response_code: ResponseCode::NoError,
+                seq: 0,
+            },
+        )
+        .await;
+    }
+
+    /// 回答が無い応答（NXDOMAIN / NODATA）を応答コードとともに `ttl` 秒の間キャッシュする（否定キャッシュ）
+    /// 有効期間内は回答の無い応答として `get` で返す
+    pub async fn insert_negative(
+        &self,
+        query_name: &str,
+        record_type: &str,
+        scope: Option<IpNet>,
+        response_code: ResponseCode,
+        ttl: u32,
+    ) {
+        if ttl == 0 {
+            return;
+        }
+
+        self.store(
+            upstream_cache_key(query_name, record_type, scope),
+            UpstreamCacheEntry {
+                records: Vec::new(),
+                cached_at: Instant::now(),
+                ttl,
+                response_code,
+                seq: 0,
+            },
+        )
+        .await;
+    }
+
+    /// キャッシュされた応答を取得
+    pub async fn get(
+        &self,
+        query_name: &str,
+        record_type: &str,
+        scope: Option<IpNet>,
+    ) -> Option<CachedAnswer> {
+        self.get_at(query_name, record_type, scope, Instant::now()).await
+    }
+
+    /// 指定時刻時点でのキャッシュされた応答を取得
+    /// 残りTTLが0になったエントリは期限切れとして扱う
+    pub async fn get_at(
+        &self,
+        query_name: &str,
+        record_type: &str,
+        scope: Option<IpNet>,
+        now: Instant,
+    ) -> Option<CachedAnswer> {
+        let entries = self.entries.read().await;
+        let entry = entries
+            .map
+            .get(&upstream_cache_key(query_name, record_type, scope))?;
+
+        let elapsed = now.saturating_duration_since(entry.cached_at).as_secs();
+        let elapsed = u32::try_from(elapsed).unwrap_or(u32::MAX);
+        if elapsed >= entry.ttl {
+            debug!("上位キャッシュ期限切れ: {} ({})", query_name, record_type);
+            return None;
+        }
+
+        let mut records = entry.records.clone();
+        // 経過秒数だけTTLを減算して返す
+        if self.decrement_ttl {
+            for record in &mut records {
+                record.set_ttl(record.ttl().saturating_sub(elapsed));
+            }
+        }
+
+        Some(CachedAnswer {
+            records,
+            response_code: entry.response_code,
+        })
+    }
+
+    /// 期限切れのエントリを古い応答（serve-stale, RFC 8767）として取得
+    /// 期限切れからの経過時間が `max_staleness` 以内のもののみ返し、TTLは `stale_ttl` に置き換える
+    pub async fn get_stale(
+        &self,
+        query_name: &str,
+        record_type: &str,
+        scope: Option<IpNet>,
+        max_staleness: Duration,
+        stale_ttl: u32,
+    ) -> Option<Vec<DnsRecord>> {
+        let entries = self.entries.read().await;
+        let entry = entries
+            .map
+            .get(&upstream_cache_key(query_name, record_type, scope))?;
+        // 否定応答は古い応答として返さない
+        if entry.records.is_empty() {
+            return None;
+        }
+
+        if entry.is_obsolete(Instant::now(), max_staleness) {
+            debug!("古い応答の許容期間を超過: {} ({})", query_name, record_type);
+            return None;
+        }
+
+        let records = entry
+            .records
+            .iter()
+            .map(|record| {
+                let mut record = record.clone();
+                record.set_ttl(stale_ttl);
+                record
+            })
+            .collect();
+
+        Some(records)
+    }
+
+    /// キャッシュを全て破棄し、破棄したエントリ数を返す
+    pub async fn flush(&self) -> usize {
+        let mut entries = self.entries.write().await;
+        let count = entries.map.len();
+        entries.clear();
+        count
+    }
+
+    /// クエリ名が指定の文字列で始まるエントリのみ破棄し、破棄したエントリ数を返す
+    pub async fn flush_prefix(&self, prefix: &str) -> usize {
+        let prefix = prefix.trim().trim_end_matches('.').to_lowercase();
+        let mut entries = self.entries.write().await;
+        let before = entries.map.len();
+        entries
+            .map
+            .retain(|(query_name, _, _), _| !query_name.starts_with(&prefix));
+        entries.compact_order();
+        before - entries.map.len()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::{create_record, init_db, CreateRecordRequest};
+    use crate::db::{create_record, delete_record, init_db, CreateRecordRequest, PatternType};
+    use hickory_proto::rr::{Name, RData};
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    fn upstream_a_record(name: &str, ttl: u32) -> DnsRecord {
+        DnsRecord::from_rdata(
+            Name::from_str(name).unwrap(),
+            ttl,
+            RData::A(Ipv4Addr::new(93, 184, 216, 34).into()),
+        )
+    }
 
     async fn setup_test_cache() -> RecordCache {
         let pool = init_db("sqlite::memory:").await.unwrap();
@@ -106,8 +713,14 @@ mod tests {
         let req = CreateRecordRequest {
             domain_pattern: "app.local.test".to_string(),
             record_type: "A".to_string(),
-            content: "127.0.0.1".to_string(),
+            content: "127.0.0.1".into(),
             ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
         };
         create_record(&cache.pool, req).await.unwrap();
 
@@ -116,6 +729,108 @@ mod tests {
         assert_eq!(cache.count().await, 1);
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_lookups_during_reload_never_miss() {
+        let cache = setup_test_cache().await;
+        create_record(
+            &cache.pool,
+            CreateRecordRequest {
+                domain_pattern: "stable.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "10.0.0.1".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+        cache.reload().await.unwrap();
+
+        let reloader = {
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                for _ in 0..50 {
+                    cache.reload().await.unwrap();
+                }
+            })
+        };
+        let lookups: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    for _ in 0..200 {
+                        let found = cache.find_matching_record("stable.local.test", "A").await;
+                        assert!(found.is_some(), "再読み込み中にレコードが見つからなかった");
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        // デッドロックしていればタイムアウトで失敗する
+        tokio::time::timeout(Duration::from_secs(10), async {
+            reloader.await.unwrap();
+            for lookup in lookups {
+                lookup.await.unwrap();
+            }
+        })
+        .await
+        .expect("再読み込みと検索が完了しない");
+        assert_eq!(cache.count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_env_vars_expanded_on_reload() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "backend.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "${LOCALDNS_TEST_BACKEND_IP}".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        // 未定義の変数は keep ではそのまま残り、strict では読み込みエラー
+        std::env::remove_var("LOCALDNS_TEST_BACKEND_IP");
+        let cache = RecordCache::with_env_expansion(pool.clone(), EnvExpansion::Keep)
+            .await
+            .unwrap();
+        let record = cache.find_matching_record("backend.local.test", "A").await.unwrap();
+        assert_eq!(record.content, "${LOCALDNS_TEST_BACKEND_IP}");
+        assert!(RecordCache::with_env_expansion(pool.clone(), EnvExpansion::Strict)
+            .await
+            .is_err());
+
+        // 再読み込み前に設定した値が回答に使われる
+        std::env::set_var("LOCALDNS_TEST_BACKEND_IP", "10.20.30.40");
+        cache.reload().await.unwrap();
+        let record = cache.find_matching_record("backend.local.test", "A").await.unwrap();
+        let name = Name::from_str("backend.local.test.").unwrap();
+        let answer = crate::dns::build_dns_record(&name, hickory_proto::rr::RecordType::A, &record)
+            .unwrap();
+        assert_eq!(answer.data(), &RData::A(Ipv4Addr::new(10, 20, 30, 40).into()));
+
+        // off では展開しない
+        let raw = RecordCache::new(pool).await.unwrap();
+        let record = raw.find_matching_record("backend.local.test", "A").await.unwrap();
+        assert_eq!(record.content, "${LOCALDNS_TEST_BACKEND_IP}");
+    }
+
     #[tokio::test]
     async fn test_find_matching_record() {
         let cache = setup_test_cache().await;
@@ -124,8 +839,14 @@ mod tests {
         let req = CreateRecordRequest {
             domain_pattern: "%.local.test".to_string(),
             record_type: "A".to_string(),
-            content: "127.0.0.1".to_string(),
+            content: "127.0.0.1".into(),
             ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
         };
         create_record(&cache.pool, req).await.unwrap();
         cache.reload().await.unwrap();
@@ -142,6 +863,39 @@ mod tests {
         assert!(no_match.is_none());
     }
 
+    #[tokio::test]
+    async fn test_auto_record_matches_a_and_aaaa() {
+        let cache = setup_test_cache().await;
+
+        for (domain_pattern, content) in [
+            ("dual.local.test", "192.168.1.10, fd00::10"),
+            ("v4.local.test", "192.168.1.20"),
+        ] {
+            let req = CreateRecordRequest {
+                domain_pattern: domain_pattern.to_string(),
+                record_type: "AUTO".to_string(),
+                content: content.into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            };
+            create_record(&cache.pool, req).await.unwrap();
+        }
+        cache.reload().await.unwrap();
+
+        assert!(cache.find_matching_record("dual.local.test", "A").await.is_some());
+        assert!(cache.find_matching_record("dual.local.test", "AAAA").await.is_some());
+        assert!(cache.find_matching_record("v4.local.test", "A").await.is_some());
+
+        // 対応するアドレスがないタイプや A/AAAA 以外にはマッチしない
+        assert!(cache.find_matching_record("v4.local.test", "AAAA").await.is_none());
+        assert!(cache.find_matching_record("dual.local.test", "CNAME").await.is_none());
+    }
+
     #[tokio::test]
     async fn test_multiple_records_priority() {
         let cache = setup_test_cache().await;
@@ -150,8 +904,14 @@ mod tests {
         let req1 = CreateRecordRequest {
             domain_pattern: "%.local.test".to_string(),
             record_type: "A".to_string(),
-            content: "127.0.0.1".to_string(),
+            content: "127.0.0.1".into(),
             ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
         };
         create_record(&cache.pool, req1).await.unwrap();
 
@@ -159,8 +919,14 @@ mod tests {
         let req2 = CreateRecordRequest {
             domain_pattern: "app.local.test".to_string(),
             record_type: "A".to_string(),
-            content: "192.168.1.1".to_string(),
+            content: "192.168.1.1".into(),
             ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
         };
         create_record(&cache.pool, req2).await.unwrap();
 
@@ -184,4 +950,325 @@ mod tests {
         // ワイルドカードの 127.0.0.1 が返される
         assert_eq!(record2.content, "127.0.0.1");
     }
+
+    #[tokio::test]
+    async fn test_soft_deleted_record_not_resolved() {
+        let cache = setup_test_cache().await;
+
+        let req = CreateRecordRequest {
+            domain_pattern: "gone.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "127.0.0.1".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        let id = create_record(&cache.pool, req).await.unwrap()[0];
+        cache.reload().await.unwrap();
+        assert!(cache.find_matching_record("gone.local.test", "A").await.is_some());
+
+        // 論理削除後は解決されない
+        delete_record(&cache.pool, id).await.unwrap();
+        cache.reload().await.unwrap();
+        assert_eq!(cache.count().await, 0);
+        assert!(cache.find_matching_record("gone.local.test", "A").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_cache_ttl_decrement() {
+        let cache = UpstreamCache::new(true);
+        cache
+            .insert("example.com", "A", None, &[upstream_a_record("example.com.", 60)])
+            .await;
+
+        // 10秒後に読むとTTLは約50になる
+        let later = Instant::now() + Duration::from_secs(10);
+        let records = cache.get_at("example.com", "A", None, later).await.unwrap().records;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].ttl(), 50);
+
+        // TTLを使い切ったエントリは期限切れ
+        let expired = Instant::now() + Duration::from_secs(60);
+        assert!(cache.get_at("example.com", "A", None, expired).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_cache_without_decrement() {
+        let cache = UpstreamCache::new(false);
+        cache
+            .insert("example.com", "A", None, &[upstream_a_record("example.com.", 60)])
+            .await;
+
+        let later = Instant::now() + Duration::from_secs(10);
+        let records = cache.get_at("example.com", "A", None, later).await.unwrap().records;
+        assert_eq!(records[0].ttl(), 60);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_cache_flush_prefix() {
+        let cache = UpstreamCache::new(true);
+        for name in ["api.example.com", "www.example.com", "example.org"] {
+            cache
+                .insert(name, "A", None, &[upstream_a_record(&format!("{}.", name), 60)])
+                .await;
+        }
+
+        assert_eq!(cache.flush_prefix("API.example.com.").await, 1);
+        assert!(cache.get("api.example.com", "A", None).await.is_none());
+        assert!(cache.get("www.example.com", "A", None).await.is_some());
+
+        assert_eq!(cache.flush().await, 2);
+        assert!(cache.get("example.org", "A", None).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_cache_keeps_negative_response_code() {
+        let cache = UpstreamCache::new(true);
+        cache
+            .insert_negative("missing.example.com", "A", None, ResponseCode::NXDomain, 60)
+            .await;
+        cache
+            .insert_negative("example.com", "AAAA", None, ResponseCode::NoError, 60)
+            .await;
+
+        let nxdomain = cache.get("missing.example.com", "A", None).await.unwrap();
+        assert!(nxdomain.records.is_empty());
+        assert_eq!(nxdomain.response_code, ResponseCode::NXDomain);
+        assert!(!nxdomain.is_no_data());
+
+        let no_data = cache.get("example.com", "AAAA", None).await.unwrap();
+        assert!(no_data.is_no_data());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_cache_evicts_when_full() {
+        let cache = UpstreamCache::new(true).with_max_entries(2);
+        cache
+            .insert_negative("gone.example.com", "A", None, ResponseCode::NXDomain, 60)
+            .await;
+        cache
+            .insert("old.example.com", "A", None, &[upstream_a_record("old.example.com.", 60)])
+            .await;
+
+        // 上限に達していれば最も古く格納されたエントリを破棄する
+        cache
+            .insert("new.example.com", "A", None, &[upstream_a_record("new.example.com.", 60)])
+            .await;
+        assert!(cache.get("gone.example.com", "A", None).await.is_none());
+        assert!(cache.get("old.example.com", "A", None).await.is_some());
+        assert_eq!(cache.entries.read().await.map.len(), 2);
+
+        // 上書きしたエントリは格納し直した時点の順序で扱う
+        cache
+            .insert("old.example.com", "A", None, &[upstream_a_record("old.example.com.", 60)])
+            .await;
+        cache
+            .insert("www.example.com", "A", None, &[upstream_a_record("www.example.com.", 60)])
+            .await;
+        assert!(cache.get("new.example.com", "A", None).await.is_none());
+        assert!(cache.get("old.example.com", "A", None).await.is_some());
+        assert!(cache.get("www.example.com", "A", None).await.is_some());
+        assert_eq!(cache.entries.read().await.map.len(), 2);
+
+        // 破棄したエントリはキューからも取り除く
+        assert_eq!(cache.flush_prefix("www").await, 1);
+        assert_eq!(cache.entries.read().await.order.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_priority_ordering() {
+        let cache = setup_test_cache().await;
+
+        // 同じ名前にマッチする2つのワイルドカード（低優先度を先に追加）
+        let req1 = CreateRecordRequest {
+            domain_pattern: "%.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "10.0.0.1".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        create_record(&cache.pool, req1).await.unwrap();
+
+        let req2 = CreateRecordRequest {
+            domain_pattern: "%.app.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "10.0.0.2".into(),
+            ttl: 60,
+            priority: 10,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        create_record(&cache.pool, req2).await.unwrap();
+
+        cache.reload().await.unwrap();
+
+        // 優先度の高いレコードが選ばれる
+        let record = cache
+            .find_matching_record("api.app.local.test", "A")
+            .await
+            .unwrap();
+        assert_eq!(record.content, "10.0.0.2");
+
+        // 全件取得でも優先度順に並ぶ
+        let records = cache
+            .find_matching_records("api.app.local.test", "A", None)
+            .await;
+        let contents: Vec<&str> = records.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(contents, vec!["10.0.0.2", "10.0.0.1"]);
+    }
+
+    #[tokio::test]
+    async fn test_priority_overrides_exact_match() {
+        let cache = setup_test_cache().await;
+
+        let exact = CreateRecordRequest {
+            domain_pattern: "app.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "192.168.1.1".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        create_record(&cache.pool, exact).await.unwrap();
+
+        let wildcard = CreateRecordRequest {
+            domain_pattern: "%.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "127.0.0.1".into(),
+            ttl: 60,
+            priority: 5,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        create_record(&cache.pool, wildcard).await.unwrap();
+
+        cache.reload().await.unwrap();
+
+        // 優先度が完全一致より先に評価される
+        let record = cache
+            .find_matching_record("app.local.test", "A")
+            .await
+            .unwrap();
+        assert_eq!(record.content, "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_catch_all_only_for_unmatched_local_names() {
+        let cache = setup_test_cache()
+            .await
+            .with_local_zones(parse_zone_list("corp.local.").unwrap());
+
+        for (domain_pattern, content) in [("*", "10.99.99.99"), ("app.corp.local", "10.0.0.1")] {
+            let req = CreateRecordRequest {
+                domain_pattern: domain_pattern.to_string(),
+                record_type: "A".to_string(),
+                content: content.into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            };
+            create_record(&cache.pool, req).await.unwrap();
+        }
+        cache.reload().await.unwrap();
+
+        let content = |name: &'static str, record_type: &'static str| {
+            let cache = cache.clone();
+            async move {
+                cache
+                    .find_matching_record(name, record_type)
+                    .await
+                    .map(|record| record.content)
+            }
+        };
+
+        // 通常のレコードが catch-all より優先される
+        assert_eq!(content("app.corp.local", "A").await.as_deref(), Some("10.0.0.1"));
+        // ローカルゾーン内で一致しない名前は catch-all
+        assert_eq!(content("unknown.corp.local", "A").await.as_deref(), Some("10.99.99.99"));
+        assert_eq!(content("CORP.LOCAL", "A").await.as_deref(), Some("10.99.99.99"));
+        // 他のタイプのレコードがある名前・ゾーン外の名前には使わない
+        assert_eq!(content("app.corp.local", "AAAA").await, None);
+        assert_eq!(content("example.com", "A").await, None);
+        assert_eq!(content("evilcorp.local", "A").await, None);
+    }
+
+    #[test]
+    fn test_parse_zone_list() {
+        assert_eq!(
+            parse_zone_list(" corp.local., Home.Arpa ,").unwrap(),
+            vec!["corp.local", "home.arpa"]
+        );
+        assert!(parse_zone_list("").unwrap().is_empty());
+        assert!(parse_zone_list("bad zone").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_subnet_scoped_answers() {
+        let cache = setup_test_cache().await;
+
+        let record = |content: &str, client_subnet: Option<&str>| CreateRecordRequest {
+            domain_pattern: "split.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: content.into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: client_subnet.map(str::to_string),
+            tags: Vec::new(),
+        };
+        create_record(&cache.pool, record("203.0.113.1", None)).await.unwrap();
+        create_record(&cache.pool, record("10.0.0.1", Some("10.0.0.0/8")))
+            .await
+            .unwrap();
+        create_record(&cache.pool, record("10.1.0.1", Some("10.1.0.0/16")))
+            .await
+            .unwrap();
+        cache.reload().await.unwrap();
+
+        let answer = |client_ip: Option<&str>| {
+            let cache = &cache;
+            let client_ip = client_ip.map(|ip| ip.parse().unwrap());
+            async move {
+                cache
+                    .find_answer_records("split.local.test", "A", client_ip)
+                    .await
+                    .into_iter()
+                    .map(|record| record.content)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // 範囲内の問い合わせ元には最も狭い範囲のレコードだけを返す
+        assert_eq!(answer(Some("10.1.2.3")).await, vec!["10.1.0.1"]);
+        assert_eq!(answer(Some("10.2.0.1")).await, vec!["10.0.0.1"]);
+        assert_eq!(answer(Some("::ffff:10.2.0.1")).await, vec!["10.0.0.1"]);
+        // 範囲外・問い合わせ元不明の場合は範囲指定のないレコード
+        assert_eq!(answer(Some("192.168.1.10")).await, vec!["203.0.113.1"]);
+        assert_eq!(answer(None).await, vec!["203.0.113.1"]);
+    }
 }
@@ -1,12 +1,58 @@
-use crate::dns::{build_dns_record, upstream::UpstreamResolver, RecordCache};
+use crate::dns::{
+    build_dns_record,
+    special::{private_reverse_zone, special_use_answer},
+    upstream::UpstreamResolver,
+    AnswerOrder, RecordCache, TtlJitter, UpstreamCache,
+};
 use crate::logger::worker::{LogWorker, QueryLogMessage};
+use crate::logger::LogFilter;
 use hickory_server::authority::MessageResponseBuilder;
-use hickory_server::proto::op::{Header, MessageType, OpCode, ResponseCode};
-use hickory_server::proto::rr::Record as DnsRecord;
+use hickory_server::proto::op::{Edns, Header, MessageType, OpCode, ResponseCode};
+use hickory_server::proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use hickory_server::proto::rr::rdata::{SOA, TXT};
+use hickory_server::proto::rr::{DNSClass, Name, RData, Record as DnsRecord, RecordType};
+use hickory_server::proto::xfer::Protocol;
 use hickory_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Instant;
-use tracing::{debug, warn};
+use std::time::{Duration, Instant};
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
+
+/// serve-stale で返す古い応答のTTL（RFC 8767 推奨値）
+const STALE_ANSWER_TTL: u32 = 30;
+
+/// サーバー自身のホスト名に対する応答のTTL
+const SELF_NAME_TTL: u32 = 60;
+
+/// ローカルの CNAME をたどる段数の上限
+const MAX_CNAME_CHAIN: usize = 16;
+
+/// 権威ゾーンの否定応答に付けるSOAのTTL（SOAの MINIMUM も同じ値にする）
+const AUTHORITATIVE_SOA_TTL: u32 = 60;
+
+/// 問い合わせ処理結果
+#[derive(Default)]
+pub struct QueryOutcome {
+    /// 回答セクション
+    pub answers: Vec<DnsRecord>,
+    /// 権威セクション（委任先NSなど）
+    pub name_servers: Vec<DnsRecord>,
+    /// 追加セクション（ローカルで応答した CNAME・NS の参照先のアドレス）
+    pub additionals: Vec<DnsRecord>,
+    /// 上位DNSへの問い合わせが失敗した（「名前が存在しない」とは区別する）
+    pub upstream_failed: bool,
+    /// 各回答を生成したレコードのID（ローカル応答の場合のみ。回答と同じ順）
+    pub record_ids: Vec<i64>,
+    /// 名前は存在するが該当タイプのレコードが無い（回答なしの NOERROR で応答する）
+    pub no_data: bool,
+    /// ローカルの CNAME が循環していた（SERVFAIL で応答する）
+    pub cname_loop: bool,
+    /// 権威ゾーン内の問い合わせにローカルで応答した（AAフラグを立てる。回答が無ければ NXDOMAIN）
+    pub authoritative: bool,
+    /// 対応していないクラスの問い合わせ（REFUSED で応答する）
+    pub refused: bool,
+}
 
 /// DNSリクエストハンドラ
 #[derive(Clone)]
@@ -14,6 +60,38 @@ pub struct DnsHandler {
     cache: RecordCache,
     log_worker: LogWorker,
     upstream: Option<Arc<UpstreamResolver>>,
+    upstream_cache: UpstreamCache,
+    /// 上位DNS障害時に期限切れの応答を返す許容期間（None の場合は無効）
+    serve_stale: Option<Duration>,
+    /// サーバー自身のホスト名と待ち受けアドレス
+    server_hostname: Option<(String, IpAddr)>,
+    /// 応答TTLの下限と上限
+    min_ttl: u32,
+    max_ttl: u32,
+    /// 回答TTLの揺らぎ
+    ttl_jitter: TtlJitter,
+    /// 上位DNSが無い状態で該当レコードが無い場合の応答コード
+    miss_response: ResponseCode,
+    /// 複数レコード応答の並び順
+    answer_order: AnswerOrder,
+    /// クエリログに記録する問い合わせの絞り込み
+    log_filter: LogFilter,
+    /// プライベートアドレスの逆引きを上位DNSに転送しない
+    block_private_reverse: bool,
+    /// CHAOSクラスの `version.bind` に返すバージョン文字列（None の場合は REFUSED）
+    chaos_version: Option<String>,
+    /// 1つの問い合わせの処理にかけられる時間の上限（None の場合は無制限）
+    query_deadline: Option<Duration>,
+    /// 上位DNSの否定応答をキャッシュする秒数の上限（0 の場合はキャッシュしない）
+    negative_cache_max_ttl: u32,
+    /// オフラインモード（上位DNSへ一切転送せず、ローカルのレコードのみで応答する）
+    offline: bool,
+    /// 権威を持つゾーン（小文字、末尾のドットなし。配下の名前は転送せずに権威応答する）
+    authoritative_zones: Vec<String>,
+    /// ローカルの CNAME の参照先がローカルに無い場合に上位DNSへ問い合わせて回答に加える
+    chase_external_cname: bool,
+    /// TCP の問い合わせに edns-tcp-keepalive で通知するアイドルタイムアウト（None の場合は通知しない）
+    tcp_keepalive: Option<Duration>,
 }
 
 impl DnsHandler {
@@ -22,89 +100,847 @@ impl DnsHandler {
             cache,
             log_worker,
             upstream: None,
+            upstream_cache: UpstreamCache::new(true),
+            serve_stale: None,
+            server_hostname: None,
+            min_ttl: 0,
+            max_ttl: u32::MAX,
+            ttl_jitter: TtlJitter::default(),
+            miss_response: ResponseCode::NXDomain,
+            answer_order: AnswerOrder::default(),
+            log_filter: LogFilter::All,
+            block_private_reverse: true,
+            chaos_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            query_deadline: None,
+            negative_cache_max_ttl: 0,
+            offline: false,
+            authoritative_zones: Vec::new(),
+            chase_external_cname: true,
+            tcp_keepalive: None,
         }
     }
 
     /// 上位DNS転送を有効化
-    pub fn with_upstream(mut self, upstream: UpstreamResolver) -> Self {
-        self.upstream = Some(Arc::new(upstream));
+    pub fn with_upstream(mut self, upstream: impl Into<Arc<UpstreamResolver>>) -> Self {
+        self.upstream = Some(upstream.into());
         self
     }
 
-    /// DNS問い合わせを処理
-    async fn handle_query(&self, request: &Request) -> Vec<DnsRecord> {
-        let start = Instant::now();
+    /// 上位DNSが無い状態で該当レコードが無い場合の応答コードを設定
+    pub fn with_miss_response(mut self, miss_response: ResponseCode) -> Self {
+        self.miss_response = miss_response;
+        self
+    }
+
+    /// 複数レコード応答の並び順を設定
+    pub fn with_answer_order(mut self, answer_order: AnswerOrder) -> Self {
+        self.answer_order = answer_order;
+        self
+    }
+
+    /// クエリログに記録する問い合わせを絞り込む
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = log_filter;
+        self
+    }
+
+    /// プライベートアドレスの逆引きを上位DNSに転送しないかどうかを設定（既定値 true）
+    pub fn with_block_private_reverse(mut self, block_private_reverse: bool) -> Self {
+        self.block_private_reverse = block_private_reverse;
+        self
+    }
+
+    /// CHAOSクラスの `version.bind` / `version.server` に返すバージョン文字列を設定
+    /// None の場合は REFUSED で応答する（既定値はクレートのバージョン）
+    pub fn with_chaos_version(mut self, chaos_version: Option<String>) -> Self {
+        self.chaos_version = chaos_version;
+        self
+    }
+
+    /// CHAOSクラスの問い合わせへの応答（バージョン応答が無効な場合や未対応の名前は None）
+    fn chaos_answer(&self, name: &Name, record_type: RecordType) -> Option<DnsRecord> {
+        let version = self.chaos_version.as_ref()?;
+        let query_name = name.to_ascii().trim_end_matches('.').to_ascii_lowercase();
+        if record_type != RecordType::TXT
+            || !matches!(query_name.as_str(), "version.bind" | "version.server")
+        {
+            return None;
+        }
+
+        let mut record =
+            DnsRecord::from_rdata(name.clone(), 0, RData::TXT(TXT::new(vec![version.clone()])));
+        record.set_dns_class(DNSClass::CH);
+        Some(record)
+    }
+
+    /// 上位DNS応答キャッシュを差し替え
+    pub fn with_upstream_cache(mut self, upstream_cache: UpstreamCache) -> Self {
+        self.upstream_cache = upstream_cache;
+        self
+    }
+
+    /// 上位DNS応答キャッシュ（クローンは同じキャッシュを共有する）
+    pub fn upstream_cache(&self) -> &UpstreamCache {
+        &self.upstream_cache
+    }
+
+    /// 応答する全レコードのTTLを [min_ttl, max_ttl] の範囲に丸める
+    pub fn with_ttl_bounds(mut self, min_ttl: u32, max_ttl: u32) -> Self {
+        self.min_ttl = min_ttl;
+        self.max_ttl = max_ttl.max(min_ttl);
+        self
+    }
+
+    /// 回答TTLを揺らす（揺らした後も [min_ttl, max_ttl] の範囲に丸める）
+    pub fn with_ttl_jitter(mut self, ttl_jitter: TtlJitter) -> Self {
+        self.ttl_jitter = ttl_jitter;
+        self
+    }
+
+    /// レコードのTTLを設定された範囲に丸める
+    /// TTL 0 は「キャッシュさせない」指定のため下限を適用せずそのまま返す
+    fn clamp_ttls(&self, records: &mut [DnsRecord]) {
+        for record in records.iter_mut().filter(|record| record.ttl() > 0) {
+            let ttl = record.ttl().clamp(self.min_ttl, self.max_ttl);
+            record.set_ttl(ttl);
+        }
+    }
+
+    /// サーバー自身のホスト名への問い合わせに待ち受けアドレスで応答する
+    pub fn with_server_hostname(mut self, hostname: &str, listen_ip: IpAddr) -> Self {
+        let hostname = hostname.trim().trim_end_matches('.').to_lowercase();
+        if !hostname.is_empty() {
+            self.server_hostname = Some((hostname, listen_ip));
+        }
+        self
+    }
+
+    /// サーバー自身のホスト名への問い合わせであれば待ち受けアドレスのレコードを返す
+    fn self_name_answer(
+        &self,
+        query_name: &str,
+        query: &Name,
+        record_type: RecordType,
+    ) -> Option<DnsRecord> {
+        let (hostname, listen_ip) = self.server_hostname.as_ref()?;
+        if !query_name.eq_ignore_ascii_case(hostname) {
+            return None;
+        }
+
+        let rdata = match (record_type, listen_ip) {
+            (RecordType::A, IpAddr::V4(ip)) => RData::A((*ip).into()),
+            (RecordType::AAAA, IpAddr::V6(ip)) => RData::AAAA((*ip).into()),
+            _ => return None,
+        };
+        Some(DnsRecord::from_rdata(query.clone(), SELF_NAME_TTL, rdata))
+    }
+
+    /// オフラインモードを設定（上位DNSの設定によらず転送せず、該当レコードが無ければ NXDOMAIN）
+    pub fn with_offline_mode(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// 権威を持つゾーンを設定（配下の名前は該当レコードが無くても転送せず、SOA付きの NXDOMAIN を権威応答する）
+    pub fn with_authoritative_zones(mut self, zones: Vec<String>) -> Self {
+        self.authoritative_zones = zones;
+        self
+    }
+
+    /// ローカルの CNAME の参照先がローカルに無い場合に、上位DNSへ問い合わせて回答に加えるか設定
+    pub fn with_chase_external_cname(mut self, chase: bool) -> Self {
+        self.chase_external_cname = chase;
+        self
+    }
+
+    /// TCP の問い合わせに edns-tcp-keepalive で通知するアイドルタイムアウトを設定（RFC 7828）
+    /// DNSサーバーの TCP 接続のタイムアウトと同じ値を渡す
+    pub fn with_tcp_keepalive(mut self, timeout: Duration) -> Self {
+        self.tcp_keepalive = Some(timeout);
+        self
+    }
+
+    /// edns-tcp-keepalive を含む TCP の問い合わせへの応答に付ける EDNS
+    /// UDP の問い合わせや、クライアントが keepalive を送っていない場合は付けない（RFC 7828 3.2）
+    fn keepalive_edns(&self, request: &Request) -> Option<Edns> {
+        let timeout = self.tcp_keepalive?;
+        if request.protocol() != Protocol::Tcp {
+            return None;
+        }
+        let request_edns = request.edns()?;
+        request_edns.option(EdnsCode::Keepalive)?;
+
+        // タイムアウトは100ミリ秒単位
+        let units = u16::try_from(timeout.as_millis() / 100).unwrap_or(u16::MAX);
+        let mut edns = Edns::new();
+        edns.set_max_payload(request_edns.max_payload().max(512));
+        edns.options_mut().insert(EdnsOption::Unknown(
+            EdnsCode::Keepalive.into(),
+            units.to_be_bytes().to_vec(),
+        ));
+        Some(edns)
+    }
+
+    /// クエリ名を含む権威ゾーンのうち最も深いものを返す
+    fn authoritative_zone(&self, query_name: &str) -> Option<&str> {
+        let query_name = query_name.to_ascii_lowercase();
+        self.authoritative_zones
+            .iter()
+            .filter(|zone| {
+                query_name == **zone
+                    || query_name
+                        .strip_suffix(zone.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            })
+            .max_by_key(|zone| zone.len())
+            .map(String::as_str)
+    }
+
+    /// 権威ゾーンの否定応答の権威セクションに載せるSOA
+    /// プライマリのネームサーバーはサーバー自身のホスト名（未設定の場合はゾーン名）とする
+    fn authoritative_soa(&self, zone: &str) -> Option<DnsRecord> {
+        let zone_name = Name::from_str(&format!("{}.", zone)).ok()?;
+        let mname = match &self.server_hostname {
+            Some((hostname, _)) => Name::from_str(&format!("{}.", hostname)).ok()?,
+            None => zone_name.clone(),
+        };
+        let rname = Name::from_str(&format!("hostmaster.{}.", zone)).ok()?;
+        let soa = SOA::new(
+            mname,
+            rname,
+            1,
+            3600,
+            600,
+            86400,
+            AUTHORITATIVE_SOA_TTL,
+        );
+        Some(DnsRecord::from_rdata(
+            zone_name,
+            AUTHORITATIVE_SOA_TTL,
+            RData::SOA(soa),
+        ))
+    }
+
+    /// 1つの問い合わせの処理時間の上限を設定（超えた場合は SERVFAIL で応答する）
+    pub fn with_query_deadline(mut self, query_deadline: Duration) -> Self {
+        self.query_deadline = Some(query_deadline);
+        self
+    }
+
+    /// 上位DNSの否定応答（NXDOMAIN / NODATA）のキャッシュを有効化
+    /// キャッシュする秒数は応答のSOAから求め、`max_ttl` 秒を上限とする
+    pub fn with_negative_cache_max_ttl(mut self, max_ttl: u32) -> Self {
+        self.negative_cache_max_ttl = max_ttl;
+        self
+    }
+
+    /// 上位DNS障害時の古い応答の返却（serve-stale）を有効化
+    pub fn with_serve_stale(mut self, max_staleness: Duration) -> Self {
+        self.serve_stale = Some(max_staleness);
+        self
+    }
 
+    /// DNS問い合わせを処理
+    async fn handle_query(&self, request: &Request) -> QueryOutcome {
         // リクエストから問い合わせ情報を取得
         let request_info = match request.request_info() {
             Ok(info) => info,
             Err(e) => {
                 warn!("リクエスト情報の取得に失敗: {}", e);
-                return Vec::new();
+                return QueryOutcome::default();
             }
         };
 
         let query = request_info.query;
-        let query_name_raw = query.name().to_string();
+        // IN 以外のクラス（CHAOS は handle_request で処理済み）は解決を試みずに拒否する
+        if query.query_class() != DNSClass::IN {
+            debug!(
+                "未対応のクラスの問い合わせを拒否: {} {} {:?}",
+                query.name(),
+                query.query_class(),
+                query.query_type()
+            );
+            return QueryOutcome {
+                refused: true,
+                ..QueryOutcome::default()
+            };
+        }
+        self.lookup(
+            query.name(),
+            query.query_type(),
+            request.header().checking_disabled(),
+            request.header().recursion_desired(),
+            Some(request.src().ip()),
+        )
+        .await
+    }
+
+    /// 名前とタイプを解決（DNS問い合わせ・JSON形式の解決APIで共通）
+    /// 問い合わせIDを採番したスパン内で処理し、キャッシュ・上位DNSのログを問い合わせ単位で関連付ける。
+    /// RDが立っていない問い合わせ（`recursion_desired = false`）はローカルのデータのみで応答し、上位には転送しない。
+    /// `client_ip` は問い合わせ元のアドレス（上位DNSへの EDNS Client Subnet に使う。APIからの解決では None）
+    /// 処理時間の上限を超えた場合は打ち切り、結果種別 `TIMEOUT` で記録して上位DNSの障害として扱う（SERVFAIL）。
+    pub async fn lookup(
+        &self,
+        name: &Name,
+        record_type: RecordType,
+        checking_disabled: bool,
+        recursion_desired: bool,
+        client_ip: Option<IpAddr>,
+    ) -> QueryOutcome {
+        let query_id = new_query_id();
+        let span = info_span!(
+            "dns_query",
+            id = %query_id,
+            name = field::Empty,
+            qtype = field::Empty
+        );
+        let resolve = self
+            .resolve_query(
+                name,
+                record_type,
+                checking_disabled,
+                recursion_desired,
+                client_ip,
+                query_id.clone(),
+            )
+            .instrument(span.clone());
+
+        let Some(deadline) = self.query_deadline else {
+            return resolve.await;
+        };
+        match tokio::time::timeout(deadline, resolve).await {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                span.in_scope(|| {
+                    warn!("問い合わせが制限時間 {}ms 以内に完了しませんでした", deadline.as_millis())
+                });
+                if self.log_filter.allows("TIMEOUT") {
+                    self.log_worker.log(QueryLogMessage {
+                        query_name: normalize_query_name(&name.to_string()).unwrap_or_default(),
+                        q_type: format!("{:?}", record_type),
+                        result_type: "TIMEOUT".to_string(),
+                        duration_ms: deadline.as_millis() as i64,
+                        upstream_server: None,
+                        query_id: Some(query_id),
+                    });
+                }
+                QueryOutcome {
+                    upstream_failed: true,
+                    ..QueryOutcome::default()
+                }
+            }
+        }
+    }
+
+    /// 上位へ再帰的に転送できるか（応答のRAフラグ）
+    pub fn recursion_available(&self) -> bool {
+        self.upstream.is_some() && !self.offline
+    }
+
+    /// 応答コードを決定（委任応答・NODATA は回答が空でも NoError、上位DNSの障害は ServFail）
+    pub fn response_code(&self, outcome: &QueryOutcome) -> ResponseCode {
+        if outcome.refused {
+            ResponseCode::Refused
+        } else if outcome.authoritative && outcome.answers.is_empty() && !outcome.no_data {
+            // 権威ゾーンの否定応答は権威セクションにSOAがあっても NXDOMAIN
+            ResponseCode::NXDomain
+        } else if !outcome.answers.is_empty() || !outcome.name_servers.is_empty() || outcome.no_data {
+            ResponseCode::NoError
+        } else if outcome.upstream_failed || outcome.cname_loop {
+            ResponseCode::ServFail
+        } else if self.offline {
+            ResponseCode::NXDomain
+        } else if self.upstream.is_none() {
+            self.miss_response
+        } else {
+            ResponseCode::NXDomain
+        }
+    }
+
+    /// CNAME・NS の参照先のアドレスをローカルのレコードから集める（追加セクション用）
+    /// 回答セクションに既に含まれる名前・タイプのものは除く
+    async fn glue_records(
+        &self,
+        records: &[DnsRecord],
+        answers: &[DnsRecord],
+        client_ip: Option<IpAddr>,
+    ) -> Vec<DnsRecord> {
+        let mut targets: Vec<&Name> = Vec::new();
+        for record in records {
+            let target = match record.data() {
+                RData::CNAME(cname) => &cname.0,
+                RData::NS(ns) => &ns.0,
+                _ => continue,
+            };
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+
+        let mut glue = Vec::new();
+        for target in targets {
+            let Some(target_name) = normalize_query_name(&target.to_string()) else {
+                continue;
+            };
+            for record_type in [RecordType::A, RecordType::AAAA] {
+                let answered = answers
+                    .iter()
+                    .any(|answer| answer.name() == target && answer.record_type() == record_type);
+                if answered {
+                    continue;
+                }
+
+                let records = self
+                    .cache
+                    .find_answer_records(&target_name, &format!("{:?}", record_type), client_ip)
+                    .await;
+                glue.extend(
+                    records
+                        .iter()
+                        .filter(|record| !record.passthrough)
+                        .filter_map(|record| build_dns_record(target, record_type, record)),
+                );
+            }
+        }
+        glue
+    }
+
+    /// ローカルで応答した CNAME を参照先のレコードが見つかるまでたどり、回答セクションに追加する
+    /// 参照先がローカルに無い・転送指定の場合は、`forward` なら上位DNSに一度だけ問い合わせて
+    /// その応答（以降の CNAME は上位DNSがたどったもの）を末尾に加え、そこで打ち切る。
+    /// 循環している場合や上限を超えて続く場合はたどった名前の一覧を Err で返す
+    /// （作成時の検査を経ずにデータベースを直接編集した場合などに起こりうる）
+    async fn follow_cname_chain(
+        &self,
+        outcome: &mut QueryOutcome,
+        query_name: &str,
+        record_type: RecordType,
+        checking_disabled: bool,
+        client_ip: Option<IpAddr>,
+        forward: bool,
+    ) -> Result<(), Vec<String>> {
+        let record_type_str = format!("{:?}", record_type);
+        let mut visited = vec![query_name.to_lowercase()];
+
+        loop {
+            let Some(RData::CNAME(cname)) = outcome.answers.last().map(|record| record.data())
+            else {
+                return Ok(());
+            };
+            let target = cname.0.clone();
+            let Some(target_name) = normalize_query_name(&target.to_string()) else {
+                return Ok(());
+            };
+            let target_name = target_name.to_lowercase();
+            let looped = visited.contains(&target_name);
+            visited.push(target_name.clone());
+            if looped || visited.len() > MAX_CNAME_CHAIN {
+                return Err(visited);
+            }
+
+            let records = self
+                .cache
+                .find_answer_records(&target_name, &record_type_str, client_ip)
+                .await;
+            if records.is_empty() || records.iter().any(|record| record.passthrough) {
+                if forward {
+                    let forwarded = self
+                        .forward_cname_target(&target_name, record_type, checking_disabled, client_ip)
+                        .await;
+                    debug!(
+                        "CNAME の参照先を上位DNSに問い合わせ: {} ({} レコード)",
+                        target_name,
+                        forwarded.len()
+                    );
+                    outcome.answers.extend(forwarded);
+                }
+                return Ok(());
+            }
+            for db_record in &records {
+                if let Some(dns_record) = build_dns_record(&target, record_type, db_record) {
+                    outcome.answers.push(dns_record);
+                    outcome.record_ids.push(db_record.id);
+                    self.cache.record_hit(db_record.id);
+                }
+            }
+        }
+    }
+
+    /// ローカルに無い CNAME の参照先を上位DNS応答キャッシュ・上位DNSから取得する（失敗した場合は空）
+    async fn forward_cname_target(
+        &self,
+        target_name: &str,
+        record_type: RecordType,
+        checking_disabled: bool,
+        client_ip: Option<IpAddr>,
+    ) -> Vec<DnsRecord> {
+        let record_type_str = format!("{:?}", record_type);
+        let scope = self
+            .upstream
+            .as_ref()
+            .and_then(|upstream| upstream.cache_scope(client_ip));
+        if let Some(cached) = self
+            .upstream_cache
+            .get(target_name, &record_type_str, scope)
+            .await
+        {
+            return cached.records;
+        }
+        let Some(upstream) = &self.upstream else {
+            return Vec::new();
+        };
+
+        match upstream
+            .query(target_name, record_type, checking_disabled, client_ip)
+            .await
+        {
+            Ok(response) => {
+                if !response.records.is_empty() {
+                    self.upstream_cache
+                        .insert(target_name, &record_type_str, scope, &response.records)
+                        .await;
+                }
+                response.records
+            }
+            Err(e) => {
+                warn!("CNAME の参照先の上位DNS問い合わせエラー: {} ({})", target_name, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// 問い合わせを解決（`lookup` のスパン内で実行される）
+    async fn resolve_query(
+        &self,
+        name: &Name,
+        record_type: RecordType,
+        checking_disabled: bool,
+        recursion_desired: bool,
+        client_ip: Option<IpAddr>,
+        query_id: String,
+    ) -> QueryOutcome {
+        let start = Instant::now();
+
         // 末尾のドットを削除（FQDN表記を正規化）
-        let query_name = query_name_raw.trim_end_matches('.').to_string();
-        let record_type = query.query_type();
+        let Some(query_name) = normalize_query_name(&name.to_string()) else {
+            warn!("空の問い合わせ名を受信");
+            return QueryOutcome::default();
+        };
+        Span::current()
+            .record("name", query_name.as_str())
+            .record("qtype", field::display(record_type));
 
         debug!(
             "DNS問い合わせ受信: {} {:?}",
             query_name, record_type
         );
 
-        let mut answers = Vec::new();
+        let mut outcome = QueryOutcome::default();
         let mut result_type = "ERROR";
+        let mut upstream_server = None;
 
         // キャッシュ検索
         let record_type_str = format!("{:?}", record_type);
-        if let Some(db_record) = self
+        let mut local_records = self
             .cache
-            .find_matching_record(&query_name, &record_type_str)
-            .await
-        {
-            debug!(
-                "キャッシュヒット: {} -> {}",
-                query_name, db_record.content
-            );
+            .find_answer_records(&query_name, &record_type_str, client_ip)
+            .await;
+        // 転送指定のレコードがマッチした場合はローカルで応答せず上位DNSの応答を返す
+        // （オフラインモードでは転送できないためローカルで応答する）
+        let passthrough = !self.offline && local_records.iter().any(|record| record.passthrough);
+        if passthrough {
+            debug!("転送指定のレコードのため上位DNSに問い合わせ: {}", query_name);
+            local_records.clear();
+        }
+        // 重みが設定された複数回答は、並び順の設定によらず重みに応じて並べる
+        let weighted = self
+            .answer_order
+            .apply_weighted(&mut local_records, |record| record.weight);
+        if !local_records.is_empty() {
+            for db_record in &local_records {
+                debug!(
+                    "キャッシュヒット: {} -> {}",
+                    query_name, db_record.content
+                );
 
-            if let Some(dns_record) = build_dns_record(query.name(), &db_record) {
-                answers.push(dns_record);
-                result_type = "LOCAL";
+                if let Some(dns_record) = build_dns_record(name, record_type, db_record) {
+                    outcome.answers.push(dns_record);
+                    outcome.record_ids.push(db_record.id);
+                    result_type = "LOCAL";
+                    self.cache.record_hit(db_record.id);
+                }
+            }
+
+            // CNAME 以外の問い合わせに CNAME で応答した場合は参照先までたどる
+            // （ローカルに無い参照先は設定に応じて上位DNSに問い合わせる）
+            if record_type != RecordType::CNAME {
+                let forward = self.chase_external_cname && !self.offline && recursion_desired;
+                if let Err(chain) = self
+                    .follow_cname_chain(
+                        &mut outcome,
+                        &query_name,
+                        record_type,
+                        checking_disabled,
+                        client_ip,
+                        forward,
+                    )
+                    .await
+                {
+                    warn!("CNAME の循環を検出: {}", chain.join(" -> "));
+                    outcome.answers.clear();
+                    outcome.record_ids.clear();
+                    outcome.cname_loop = true;
+                    result_type = "ERROR";
+                }
             }
+            outcome.authoritative =
+                !outcome.cname_loop && self.authoritative_zone(&query_name).is_some();
         } else {
             debug!("キャッシュミス: {}", query_name);
 
-            // 上位DNSに転送
-            if let Some(upstream) = &self.upstream {
-                match upstream.query(&query_name, &record_type_str).await {
-                    Ok(records) => {
-                        if !records.is_empty() {
-                            debug!("上位DNSから {} レコードを取得", records.len());
-                            answers.extend(records);
+            let delegation = self.cache.find_delegation(&query_name).await;
+            // 問い合わせ元のサブネットを上位DNSに送る場合は、その単位で上位DNS応答キャッシュを分ける
+            let cache_scope = self
+                .upstream
+                .as_ref()
+                .and_then(|upstream| upstream.cache_scope(client_ip));
+
+            // サーバー自身のホスト名なら待ち受けアドレスで応答
+            if let Some(record) = self.self_name_answer(&query_name, name, record_type) {
+                debug!("サーバー自身のホスト名への問い合わせ: {}", query_name);
+                outcome.answers.push(record);
+                result_type = "LOCAL";
+            }
+            // localhost・ループバックの逆引きなどはユーザーのレコードが無ければ組み込みの応答を返す
+            else if let Some(records) = special_use_answer(&query_name, name, record_type) {
+                debug!("特殊用途の名前への問い合わせ: {}", query_name);
+                outcome.no_data = records.is_empty();
+                outcome.answers = records;
+                result_type = "LOCAL";
+            }
+            // 委任されたサブゾーン配下なら権威セクションにNSを載せて返す
+            else if !delegation.is_empty() {
+                debug!("委任先ゾーンへの問い合わせ: {}", query_name);
+                outcome.name_servers = delegation
+                    .iter()
+                    .filter_map(|record| {
+                        let zone = Name::from_str(&record.domain_pattern).ok()?;
+                        build_dns_record(&zone, RecordType::NS, record)
+                    })
+                    .collect();
+                result_type = "DELEGATED";
+            }
+            // 権威ゾーン内の名前は転送せず、SOA付きの NXDOMAIN（名前があれば NODATA）を権威応答する
+            else if let Some(zone) = self
+                .authoritative_zone(&query_name)
+                .filter(|_| !passthrough)
+            {
+                debug!("権威ゾーン内の該当レコードなし: {} (ゾーン: {})", query_name, zone);
+                outcome.authoritative = true;
+                outcome.no_data = self.cache.name_exists(&query_name).await;
+                outcome.name_servers.extend(self.authoritative_soa(zone));
+                result_type = "LOCAL";
+            }
+            // プライベートアドレスの逆引きは転送せずに「存在しない」と応答する（ゾーン頂点は NODATA）
+            else if let Some(zone) = self
+                .block_private_reverse
+                .then(|| private_reverse_zone(&query_name))
+                .flatten()
+            {
+                debug!("プライベートアドレスの逆引きのため転送しない: {}", query_name);
+                outcome.no_data = query_name.eq_ignore_ascii_case(zone);
+                result_type = "LOCAL";
+            }
+            // オフラインモードでは上位DNS応答キャッシュも含めて上位の応答を使わない
+            else if self.offline {
+                info!("オフラインモードのため上位DNSへの転送を抑止: {}", query_name);
+            }
+            // 上位DNS応答キャッシュを確認し、なければ上位DNSに転送
+            else if let Some(cached) = self
+                .upstream_cache
+                .get(&query_name, &record_type_str, cache_scope)
+                .await
+            {
+                debug!(
+                    "上位キャッシュヒット: {} ({} レコード)",
+                    query_name,
+                    cached.records.len()
+                );
+                outcome.no_data = cached.is_no_data();
+                outcome.answers.extend(cached.records);
+                result_type = "CACHED";
+            } else if !recursion_desired {
+                debug!("RDなしのため上位DNSへ転送しない: {}", query_name);
+            } else if let Some(upstream) = &self.upstream {
+                match upstream
+                    .query(&query_name, record_type, checking_disabled, client_ip)
+                    .await
+                {
+                    Ok(response) => {
+                        if !response.records.is_empty() {
+                            debug!(
+                                "上位DNS {} から {} レコードを取得",
+                                response.server,
+                                response.records.len()
+                            );
+                            self.upstream_cache
+                                .insert(
+                                    &query_name,
+                                    &record_type_str,
+                                    cache_scope,
+                                    &response.records,
+                                )
+                                .await;
+                            outcome.answers.extend(response.records);
                             result_type = "FORWARDED";
+                            upstream_server = Some(response.server.to_string());
+                        } else {
+                            // NOERROR で回答が無い応答は NODATA（名前は存在する）として NOERROR で返す
+                            outcome.no_data = response.response_code == ResponseCode::NoError;
+                            if let Some(ttl) = response
+                                .negative_ttl
+                                .map(|ttl| ttl.min(self.negative_cache_max_ttl))
+                                .filter(|ttl| *ttl > 0)
+                            {
+                                debug!(
+                                    "否定応答をキャッシュ: {} ({}, {}秒)",
+                                    query_name, response.response_code, ttl
+                                );
+                                self.upstream_cache
+                                    .insert_negative(
+                                        &query_name,
+                                        &record_type_str,
+                                        cache_scope,
+                                        response.response_code,
+                                        ttl,
+                                    )
+                                    .await;
+                            }
                         }
                     }
                     Err(e) => {
                         warn!("上位DNS問い合わせエラー: {}", e);
+                        outcome.upstream_failed = true;
+
+                        // 期限切れのキャッシュがあれば古い応答を返す
+                        if let Some(max_staleness) = self.serve_stale {
+                            if let Some(records) = self
+                                .upstream_cache
+                                .get_stale(
+                                    &query_name,
+                                    &record_type_str,
+                                    cache_scope,
+                                    max_staleness,
+                                    STALE_ANSWER_TTL,
+                                )
+                                .await
+                            {
+                                info!("上位DNS障害のため古い応答を返却: {}", query_name);
+                                outcome.answers.extend(records);
+                                result_type = "STALE";
+                            }
+                        }
                     }
                 }
             }
         }
 
-        // ログ記録
+        // ローカルで応答した CNAME・NS には参照先のアドレスを追加セクションに載せる
+        if matches!(result_type, "LOCAL" | "DELEGATED") {
+            let referrals: Vec<DnsRecord> = outcome
+                .answers
+                .iter()
+                .chain(&outcome.name_servers)
+                .cloned()
+                .collect();
+            outcome.additionals = self
+                .glue_records(&referrals, &outcome.answers, client_ip)
+                .await;
+        }
+
+        // ログ記録（設定で絞り込まれた結果種別のみ）
         let duration_ms = start.elapsed().as_millis() as i64;
-        self.log_worker.log(QueryLogMessage {
-            query_name,
-            q_type: record_type_str,
-            result_type: result_type.to_string(),
-            duration_ms,
-        });
+        if self.log_filter.allows(result_type) {
+            self.log_worker.log(QueryLogMessage {
+                query_name,
+                q_type: record_type_str,
+                result_type: result_type.to_string(),
+                duration_ms,
+                upstream_server,
+                query_id: Some(query_id),
+            });
+        }
+
+        // ローカル・上位DNSどちらの応答もTTLを揺らしてから範囲内に丸める
+        self.ttl_jitter.apply(&mut outcome.answers);
+        self.clamp_ttls(&mut outcome.answers);
+        self.clamp_ttls(&mut outcome.name_servers);
+        self.clamp_ttls(&mut outcome.additionals);
+        // 重みで並べ替えた場合はその並びを維持する
+        if !weighted {
+            if outcome.record_ids.is_empty() {
+                self.answer_order.apply(&mut outcome.answers, record_type);
+            } else {
+                // ローカル応答はレコードIDも同じ順に並べ替える
+                // （上位DNSから補った CNAME の参照先は並べ替えずに末尾に残す）
+                let forwarded = outcome.answers.split_off(outcome.record_ids.len());
+                let mut paired: Vec<(DnsRecord, i64)> = outcome
+                    .answers
+                    .drain(..)
+                    .zip(outcome.record_ids.drain(..))
+                    .collect();
+                self.answer_order
+                    .apply_by(&mut paired, |(record, _)| record.record_type() == record_type);
+                (outcome.answers, outcome.record_ids) = paired.into_iter().unzip();
+                outcome.answers.extend(forwarded);
+            }
+        }
+
+        outcome
+    }
+}
+
+/// 問い合わせIDを採番（ログの関連付け用のランダムな16進8桁）
+fn new_query_id() -> String {
+    format!("{:08x}", rand::random::<u32>())
+}
+
+/// `miss_response` 設定値を応答コードに変換（`nxdomain` / `refused` / `servfail`）
+pub fn parse_miss_response(value: &str) -> Option<ResponseCode> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "nxdomain" => Some(ResponseCode::NXDomain),
+        "refused" => Some(ResponseCode::Refused),
+        "servfail" => Some(ResponseCode::ServFail),
+        _ => None,
+    }
+}
+
+/// 問い合わせ名を正規化
+/// 末尾のドットを（複数あっても）取り除く。エスケープされたドット（`\.`）は名前の一部として残す。
+/// ルート（`.`）は `.` を返し、空の名前は None を返す。
+fn normalize_query_name(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return None;
+    }
 
-        answers
+    let mut name = raw;
+    while let Some(stripped) = name.strip_suffix('.') {
+        // 直前のバックスラッシュが奇数個ならエスケープされたドット
+        let backslashes = stripped.chars().rev().take_while(|c| *c == '\\').count();
+        if backslashes % 2 == 1 {
+            break;
+        }
+        name = stripped;
+    }
+
+    if name.is_empty() {
+        // ドットのみで構成される名前はルート
+        return Some(".".to_string());
     }
+
+    Some(name.to_string())
 }
 
 #[async_trait::async_trait]
@@ -116,6 +952,11 @@ impl RequestHandler for DnsHandler {
     ) -> ResponseInfo {
         // ヘッダー取得
         let mut header = Header::response_from_request(request.header());
+        // 上位へ再帰的に転送できる場合のみRAを立てる
+        header.set_recursion_available(self.recursion_available());
+        // DNSSEC検証は行わないためADは常にクリアし、CDはリクエストの値を返す
+        header.set_authentic_data(false);
+        header.set_checking_disabled(request.header().checking_disabled());
 
         // クエリタイプチェック
         if request.op_code() != OpCode::Query {
@@ -125,18 +966,48 @@ impl RequestHandler for DnsHandler {
             return response_handle.send_response(response).await.unwrap();
         }
 
-        // クエリ処理
-        let answers = self.handle_query(request).await;
+        // CHAOSクラスの問い合わせ（version.bind など）はレコードを引かずに応答する
+        let chaos = request
+            .request_info()
+            .ok()
+            .filter(|info| info.query.query_class() == DNSClass::CH)
+            .map(|info| self.chaos_answer(info.query.name(), info.query.query_type()));
+
+        let outcome = match chaos {
+            Some(answer) => {
+                header.set_response_code(if answer.is_some() {
+                    ResponseCode::NoError
+                } else {
+                    ResponseCode::Refused
+                });
+                QueryOutcome {
+                    answers: answer.into_iter().collect(),
+                    ..QueryOutcome::default()
+                }
+            }
+            None => {
+                // クエリ処理
+                let outcome = self.handle_query(request).await;
+                header.set_response_code(self.response_code(&outcome));
+                // 権威ゾーン内の問い合わせにローカルで応答した場合のみAAを立てる
+                header.set_authoritative(outcome.authoritative);
+                outcome
+            }
+        };
 
         // レスポンス構築
-        header.set_response_code(if answers.is_empty() {
-            ResponseCode::NXDomain
-        } else {
-            ResponseCode::NoError
-        });
 
-        let response = MessageResponseBuilder::from_message_request(request)
-            .build(header, answers.iter(), &[], &[], &[]);
+        let mut builder = MessageResponseBuilder::from_message_request(request);
+        if let Some(edns) = self.keepalive_edns(request) {
+            builder.edns(edns);
+        }
+        let response = builder.build(
+            header,
+            outcome.answers.iter(),
+            outcome.name_servers.iter(),
+            &[],
+            outcome.additionals.iter(),
+        );
 
         match response_handle.send_response(response).await {
             Ok(info) => info,
@@ -154,8 +1025,146 @@ impl RequestHandler for DnsHandler {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::{create_record, init_db, CreateRecordRequest};
-    use crate::dns::upstream::UpstreamConfig;
+    use crate::db::{create_record, init_db, CreateRecordRequest, PatternType};
+    use crate::dns::upstream::{parse_ecs, UpstreamConfig};
+    use hickory_server::authority::{MessageRequest, MessageResponse};
+    use hickory_server::proto::op::{Message, Query};
+    use hickory_server::proto::serialize::binary::{BinDecodable, BinEncoder};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Mutex;
+    use tokio::net::UdpSocket;
+
+    /// 送信されたレスポンスをバイト列として保持するテスト用ハンドラ
+    #[derive(Clone, Default)]
+    struct TestResponseHandler {
+        buf: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl TestResponseHandler {
+        /// 保持しているレスポンスをデコード
+        fn message(&self) -> Message {
+            Message::from_bytes(&self.buf.lock().unwrap()).unwrap()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ResponseHandler for TestResponseHandler {
+        async fn send_response<'a>(
+            &mut self,
+            response: MessageResponse<
+                '_,
+                'a,
+                impl Iterator<Item = &'a DnsRecord> + Send + 'a,
+                impl Iterator<Item = &'a DnsRecord> + Send + 'a,
+                impl Iterator<Item = &'a DnsRecord> + Send + 'a,
+                impl Iterator<Item = &'a DnsRecord> + Send + 'a,
+            >,
+        ) -> std::io::Result<ResponseInfo> {
+            let mut bytes = Vec::with_capacity(512);
+            let info = {
+                let mut encoder = BinEncoder::new(&mut bytes);
+                response
+                    .destructive_emit(&mut encoder)
+                    .map_err(std::io::Error::other)?
+            };
+            *self.buf.lock().unwrap() = bytes;
+            Ok(info)
+        }
+    }
+
+    /// テスト用のDNSリクエストを作成
+    fn build_request(name: &str, rtype: RecordType, checking_disabled: bool) -> Request {
+        build_request_with_flags(name, rtype, true, checking_disabled)
+    }
+
+    /// RD/CDフラグを指定してテスト用のDNSリクエストを作成
+    fn build_request_with_flags(
+        name: &str,
+        rtype: RecordType,
+        recursion_desired: bool,
+        checking_disabled: bool,
+    ) -> Request {
+        let mut message = Message::new();
+        message
+            .set_id(4321)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(recursion_desired)
+            .set_checking_disabled(checking_disabled);
+        message.add_query(Query::query(Name::from_str(name).unwrap(), rtype));
+
+        let bytes = message.to_vec().unwrap();
+        let message_request = MessageRequest::from_bytes(&bytes).unwrap();
+        let src = SocketAddr::from(([127, 0, 0, 1], 53000));
+        Request::new(message_request, src, Protocol::Udp)
+    }
+
+    /// 固定のAレコードを返す上位DNSを起動し、受信したリクエストを記録する
+    async fn spawn_fake_upstream(answer: Ipv4Addr) -> (SocketAddr, Arc<Mutex<Vec<Message>>>) {
+        spawn_fake_upstream_with_ttl(answer, 300).await
+    }
+
+    /// 指定TTLの固定のAレコードを返す上位DNSを起動する
+    async fn spawn_fake_upstream_with_ttl(
+        answer: Ipv4Addr,
+        ttl: u32,
+    ) -> (SocketAddr, Arc<Mutex<Vec<Message>>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let Ok(request) = Message::from_bytes(&buf[..len]) else {
+                    continue;
+                };
+
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .set_recursion_available(true)
+                    .set_authentic_data(true);
+                for query in request.queries() {
+                    response.add_query(query.clone());
+                    response.add_answer(DnsRecord::from_rdata(
+                        query.name().clone(),
+                        ttl,
+                        RData::A(answer.into()),
+                    ));
+                }
+
+                received_clone.lock().unwrap().push(request);
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        (addr, received)
+    }
+
+    #[test]
+    fn test_normalize_query_name_root() {
+        assert_eq!(normalize_query_name("."), Some(".".to_string()));
+        assert_eq!(normalize_query_name(".."), Some(".".to_string()));
+    }
+
+    #[test]
+    fn test_normalize_query_name_empty() {
+        assert_eq!(normalize_query_name(""), None);
+        assert_eq!(normalize_query_name("   "), None);
+    }
+
+    #[test]
+    fn test_normalize_query_name_trailing_dots() {
+        assert_eq!(normalize_query_name("foo.bar."), Some("foo.bar".to_string()));
+        assert_eq!(normalize_query_name("foo.bar.."), Some("foo.bar".to_string()));
+        assert_eq!(normalize_query_name("foo.bar"), Some("foo.bar".to_string()));
+        // エスケープされたドットは残す
+        assert_eq!(normalize_query_name("foo\\.."), Some("foo\\.".to_string()));
+    }
 
     #[tokio::test]
     async fn test_dns_handler_cache_hit() {
@@ -167,8 +1176,14 @@ mod tests {
             CreateRecordRequest {
                 domain_pattern: "test.local".to_string(),
                 record_type: "A".to_string(),
-                content: "127.0.0.1".to_string(),
+                content: "127.0.0.1".into(),
                 ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
             },
         )
         .await
@@ -220,4 +1235,1774 @@ mod tests {
         // クローンが正常に動作することを確認
         assert!(cloned.upstream.is_none());
     }
+
+    #[tokio::test]
+    async fn test_forwarded_response_header_flags() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) =
+            spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request("example.com.", RecordType::A, true);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        // RAは立ち、上位がADを返してもクリアされ、CDはリクエスト通り
+        assert!(response.header().recursion_available());
+        assert!(!response.header().authentic_data());
+        assert!(response.header().checking_disabled());
+
+        // CDフラグが上位DNSへ引き継がれている
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].header().checking_disabled());
+    }
+
+    #[tokio::test]
+    async fn test_delegation_in_authority_section() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+
+        // sub.local.test を ns1.sub.local.test に委任
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "sub.local.test".to_string(),
+                record_type: "NS".to_string(),
+                content: "ns1.sub.local.test".into(),
+                ttl: 300,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker);
+
+        let request = build_request("host.sub.local.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(response.name_servers().len(), 1);
+
+        let ns = &response.name_servers()[0];
+        assert_eq!(ns.name(), &Name::from_str("sub.local.test.").unwrap());
+        if let RData::NS(target) = ns.data() {
+            assert_eq!(target.0, Name::from_str("ns1.sub.local.test.").unwrap());
+        } else {
+            panic!("Expected NS record");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cname_answer_includes_target_address_as_additional() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        for (domain_pattern, record_type, content) in [
+            ("www.local.test", "CNAME", "web.local.test"),
+            ("web.local.test", "A", "10.0.0.5"),
+        ] {
+            create_record(
+                &pool,
+                CreateRecordRequest {
+                    domain_pattern: domain_pattern.to_string(),
+                    record_type: record_type.to_string(),
+                    content: content.into(),
+                    ttl: 60,
+                    priority: 0,
+                    weight: 1,
+                    passthrough: false,
+                    pattern_type: PatternType::Glob,
+                    client_subnet: None,
+                    tags: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker);
+
+        let request = build_request("www.local.test.", RecordType::CNAME, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].record_type(), RecordType::CNAME);
+
+        let additionals = response.additionals();
+        assert_eq!(additionals.len(), 1);
+        assert_eq!(additionals[0].name(), &Name::from_str("web.local.test.").unwrap());
+        assert_eq!(
+            additionals[0].data(),
+            &RData::A(Ipv4Addr::new(10, 0, 0, 5).into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_local_cname_chain_is_followed() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        for (domain_pattern, record_type, content) in [
+            ("www.local.test", "CNAME", "web.local.test"),
+            ("web.local.test", "CNAME", "Host.local.test"),
+            ("host.local.test", "A", "10.0.0.7"),
+        ] {
+            create_record(
+                &pool,
+                CreateRecordRequest {
+                    domain_pattern: domain_pattern.to_string(),
+                    record_type: record_type.to_string(),
+                    content: content.into(),
+                    ttl: 60,
+                    priority: 0,
+                    weight: 1,
+                    passthrough: false,
+                    pattern_type: PatternType::Glob,
+                    client_subnet: None,
+                    tags: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker);
+
+        let request = build_request("www.local.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let types: Vec<RecordType> = response.answers().iter().map(|r| r.record_type()).collect();
+        assert_eq!(types, vec![RecordType::CNAME, RecordType::CNAME, RecordType::A]);
+        assert_eq!(
+            response.answers()[2].data(),
+            &RData::A(Ipv4Addr::new(10, 0, 0, 7).into())
+        );
+        assert!(response.additionals().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_local_cname_to_remote_target_is_forwarded() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "alias.corp.local".to_string(),
+                record_type: "CNAME".to_string(),
+                content: "www.example.com".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let (upstream_addr, received) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let build_handler = |chase: bool| {
+            let pool = pool.clone();
+            let addr = addr.clone();
+            async move {
+                let cache = RecordCache::new(pool.clone()).await.unwrap();
+                let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+                DnsHandler::new(cache, LogWorker::new(pool))
+                    .with_upstream(UpstreamResolver::new(config))
+                    .with_chase_external_cname(chase)
+            }
+        };
+
+        // CNAME に続けて、上位DNSから取得した参照先の A を返す
+        let handler = build_handler(true).await;
+        let request = build_request("alias.corp.local.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let answers = response.answers();
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0].record_type(), RecordType::CNAME);
+        assert_eq!(answers[1].name(), &Name::from_str("www.example.com.").unwrap());
+        assert_eq!(answers[1].data(), &RData::A(Ipv4Addr::new(93, 184, 216, 34).into()));
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 1);
+            assert_eq!(
+                received[0].queries()[0].name(),
+                &Name::from_str("www.example.com.").unwrap()
+            );
+        }
+
+        // 無効にした場合は CNAME のみを返す
+        let handler = build_handler(false).await;
+        let request = build_request("alias.corp.local.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        assert_eq!(response_handler.message().answers().len(), 1);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cyclic_cname_returns_servfail() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        // 作成時の検査を経ずにデータベースへ直接書き込まれた循環を想定する
+        for (domain_pattern, content) in [
+            ("loop-a.local.test", "loop-b.local.test"),
+            ("loop-b.local.test", "loop-a.local.test"),
+        ] {
+            sqlx::query(
+                "INSERT INTO records (domain_pattern, record_type, content) VALUES (?, 'CNAME', ?)",
+            )
+            .bind(domain_pattern)
+            .bind(content)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker);
+
+        let request = build_request("loop-a.local.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+        assert!(response.answers().is_empty());
+
+        // CNAME そのものの問い合わせは循環をたどらずに応答する
+        let request = build_request("loop-a.local.test.", RecordType::CNAME, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        assert_eq!(response_handler.message().answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_cache_answers_while_database_is_unavailable() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "app.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "10.0.0.8".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache.clone(), log_worker.clone());
+
+        // DBが使えなくなってもキャッシュから応答し、ログの記録失敗で止まらない
+        pool.close().await;
+        for _ in 0..3 {
+            let request = build_request("app.local.test.", RecordType::A, false);
+            let response_handler = TestResponseHandler::default();
+            tokio::time::timeout(
+                Duration::from_secs(1),
+                handler.handle_request(&request, response_handler.clone()),
+            )
+            .await
+            .expect("応答がDBの状態に左右されない");
+
+            let response = response_handler.message();
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+            assert_eq!(
+                response.answers()[0].data(),
+                &RData::A(Ipv4Addr::new(10, 0, 0, 8).into())
+            );
+        }
+
+        // 再読み込みに失敗しても直前のレコードを使い続ける
+        assert!(cache.reload().await.is_err());
+        assert!(cache.find_matching_record("app.local.test", "A").await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(log_worker.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_serve_stale_on_upstream_failure() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        // TTL 1秒の応答をキャッシュし、期限切れにする
+        let upstream_cache = UpstreamCache::new(true);
+        let cached = DnsRecord::from_rdata(
+            Name::from_str("example.com.").unwrap(),
+            1,
+            RData::A(Ipv4Addr::new(93, 184, 216, 34).into()),
+        );
+        upstream_cache.insert("example.com", "A", None, &[cached]).await;
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(upstream_cache.get("example.com", "A", None).await.is_none());
+
+        // 応答しない上位DNS（バインド後に閉じたポート）
+        let dead_addr = {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            socket.local_addr().unwrap().to_string()
+        };
+        let config = UpstreamConfig::new(&dead_addr, &dead_addr, 200).unwrap();
+
+        let handler = DnsHandler::new(cache, log_worker)
+            .with_upstream(UpstreamResolver::new(config))
+            .with_upstream_cache(upstream_cache)
+            .with_serve_stale(Duration::from_secs(3600));
+
+        let request = build_request("example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), STALE_ANSWER_TTL);
+    }
+
+    #[tokio::test]
+    async fn test_server_hostname_answer() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let listen_ip = IpAddr::V4(Ipv4Addr::new(192, 168, 10, 53));
+        let handler =
+            DnsHandler::new(cache, log_worker).with_server_hostname("dns.local.test", listen_ip);
+
+        // 大文字小文字を区別せずに応答
+        let request = build_request("DNS.local.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        if let RData::A(ip) = response.answers()[0].data() {
+            assert_eq!(ip.0, Ipv4Addr::new(192, 168, 10, 53));
+        } else {
+            panic!("Expected A record");
+        }
+
+        // アドレスファミリーが異なるタイプには応答しない
+        let request = build_request("dns.local.test.", RecordType::AAAA, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        assert!(response_handler.message().answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_localhost_answers_without_forwarding() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) =
+            spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let resolve = |name: &'static str, rtype: RecordType| {
+            let handler = handler.clone();
+            async move {
+                let request = build_request(name, rtype, false);
+                let response_handler = TestResponseHandler::default();
+                handler.handle_request(&request, response_handler.clone()).await;
+                response_handler.message()
+            }
+        };
+
+        let response = resolve("localhost.", RecordType::A).await;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].data(), &RData::A(Ipv4Addr::LOCALHOST.into()));
+
+        let response = resolve("localhost.", RecordType::AAAA).await;
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::AAAA(std::net::Ipv6Addr::LOCALHOST.into())
+        );
+
+        let response = resolve("1.0.0.127.in-addr.arpa.", RecordType::PTR).await;
+        assert_eq!(response.answers().len(), 1);
+        if let RData::PTR(ptr) = response.answers()[0].data() {
+            assert_eq!(ptr.0, Name::from_str("localhost.").unwrap());
+        } else {
+            panic!("Expected PTR record");
+        }
+
+        // 該当タイプが無い場合は NODATA
+        let response = resolve("localhost.", RecordType::MX).await;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+
+        // いずれも上位DNSには転送されていない
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_private_reverse_is_not_forwarded() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) =
+            spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request("5.0.0.10.in-addr.arpa.", RecordType::PTR, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+
+        // ゾーン頂点は NODATA
+        let request = build_request("10.in-addr.arpa.", RecordType::SOA, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        assert_eq!(response_handler.message().response_code(), ResponseCode::NoError);
+
+        assert!(received.lock().unwrap().is_empty());
+
+        // 無効にすると上位DNSへ転送する
+        let handler = handler.with_block_private_reverse(false);
+        let request = build_request("5.0.0.10.in-addr.arpa.", RecordType::PTR, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_user_record_overrides_localhost() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "localhost".to_string(),
+                record_type: "A".to_string(),
+                content: "127.0.0.2".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker);
+
+        let request = build_request("localhost.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::A(Ipv4Addr::new(127, 0, 0, 2).into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_record_is_forwarded() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "docs.example.com".to_string(),
+                record_type: "A".to_string(),
+                content: "10.0.0.1".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: true,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) =
+            spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request("docs.example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        // 登録したアドレスではなく上位DNSの応答を返す
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::A(Ipv4Addr::new(93, 184, 216, 34).into())
+        );
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    /// CHAOSクラスのTXT問い合わせを作成
+    fn build_chaos_request(name: &str) -> Request {
+        build_class_request(name, RecordType::TXT, DNSClass::CH)
+    }
+
+    /// 指定したクラスの問い合わせを作成
+    fn build_class_request(name: &str, rtype: RecordType, class: DNSClass) -> Request {
+        let mut query = Query::query(Name::from_str(name).unwrap(), rtype);
+        query.set_query_class(class);
+        let mut message = Message::new();
+        message
+            .set_id(4321)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query);
+        message.add_query(query);
+
+        let bytes = message.to_vec().unwrap();
+        let message_request = MessageRequest::from_bytes(&bytes).unwrap();
+        let src = SocketAddr::from(([127, 0, 0, 1], 53000));
+        Request::new(message_request, src, Protocol::Udp)
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_class_is_refused() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "host.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "10.0.0.1".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let (upstream_addr, received) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler = DnsHandler::new(cache, LogWorker::new(pool.clone()))
+            .with_upstream(UpstreamResolver::new(config));
+
+        // HESIOD クラスはローカルのレコードにも上位DNSにも当てずに REFUSED
+        for name in ["host.local.test.", "example.com."] {
+            let response_handler = TestResponseHandler::default();
+            handler
+                .handle_request(
+                    &build_class_request(name, RecordType::A, DNSClass::HS),
+                    response_handler.clone(),
+                )
+                .await;
+            let response = response_handler.message();
+            assert_eq!(response.response_code(), ResponseCode::Refused);
+            assert!(response.answers().is_empty());
+        }
+        assert!(received.lock().unwrap().is_empty());
+
+        // IN クラスは通常どおり応答する
+        let response_handler = TestResponseHandler::default();
+        handler
+            .handle_request(
+                &build_class_request("host.local.test.", RecordType::A, DNSClass::IN),
+                response_handler.clone(),
+            )
+            .await;
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::A(Ipv4Addr::new(10, 0, 0, 1).into())
+        );
+    }
+
+    /// edns-tcp-keepalive（値なし）付きの問い合わせを作成
+    fn build_keepalive_request(name: &str, protocol: Protocol) -> Request {
+        let mut edns = Edns::new();
+        edns.options_mut().insert(EdnsOption::Unknown(EdnsCode::Keepalive.into(), Vec::new()));
+        let mut message = Message::new();
+        message
+            .set_id(4321)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true);
+        message.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        message.set_edns(edns);
+
+        let bytes = message.to_vec().unwrap();
+        let message_request = MessageRequest::from_bytes(&bytes).unwrap();
+        let src = SocketAddr::from(([127, 0, 0, 1], 53000));
+        Request::new(message_request, src, protocol)
+    }
+
+    #[tokio::test]
+    async fn test_tcp_keepalive_is_advertised() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "host.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "10.0.0.1".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let handler = DnsHandler::new(cache, LogWorker::new(pool.clone()))
+            .with_tcp_keepalive(Duration::from_secs(30));
+
+        // TCP では設定したタイムアウトを100ミリ秒単位で返す
+        let response_handler = TestResponseHandler::default();
+        handler
+            .handle_request(
+                &build_keepalive_request("host.local.test.", Protocol::Tcp),
+                response_handler.clone(),
+            )
+            .await;
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        let edns = response.extensions().as_ref().expect("OPTレコードがありません");
+        assert_eq!(
+            edns.option(EdnsCode::Keepalive),
+            Some(&EdnsOption::Unknown(
+                EdnsCode::Keepalive.into(),
+                300u16.to_be_bytes().to_vec()
+            ))
+        );
+
+        // UDP では返さない
+        let response_handler = TestResponseHandler::default();
+        handler
+            .handle_request(
+                &build_keepalive_request("host.local.test.", Protocol::Udp),
+                response_handler.clone(),
+            )
+            .await;
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        assert!(response
+            .extensions()
+            .as_ref()
+            .and_then(|edns| edns.option(EdnsCode::Keepalive))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chaos_version_bind() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let handler = DnsHandler::new(cache.clone(), LogWorker::new(pool.clone()))
+            .with_chaos_version(Some("local-dns-test".to_string()));
+
+        let response_handler = TestResponseHandler::default();
+        handler
+            .handle_request(&build_chaos_request("version.bind."), response_handler.clone())
+            .await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].dns_class(), DNSClass::CH);
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::TXT(TXT::new(vec!["local-dns-test".to_string()]))
+        );
+
+        // 無効にした場合は REFUSED
+        let handler =
+            DnsHandler::new(cache, LogWorker::new(pool.clone())).with_chaos_version(None);
+        let response_handler = TestResponseHandler::default();
+        handler
+            .handle_request(&build_chaos_request("version.bind."), response_handler.clone())
+            .await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert!(response.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_min_ttl_applied_to_local_record() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "short.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "127.0.0.1".into(),
+                ttl: 5,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker).with_ttl_bounds(30, 3600);
+
+        let request = build_request("short.local.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), 30);
+    }
+
+    #[tokio::test]
+    async fn test_client_subnet_record_served_to_matching_client() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        for (content, client_subnet) in [
+            ("203.0.113.1", None),
+            ("10.0.0.1", Some("10.0.0.0/8")),
+            ("127.0.0.10", Some("127.0.0.0/8")),
+        ] {
+            create_record(
+                &pool,
+                CreateRecordRequest {
+                    domain_pattern: "split.local.test".to_string(),
+                    record_type: "A".to_string(),
+                    content: content.into(),
+                    ttl: 60,
+                    priority: 0,
+                    weight: 1,
+                    passthrough: false,
+                    pattern_type: PatternType::Glob,
+                    client_subnet: client_subnet.map(str::to_string),
+                    tags: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker);
+
+        // テスト用リクエストの問い合わせ元は 127.0.0.1
+        let request = build_request("split.local.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::A(Ipv4Addr::new(127, 0, 0, 10).into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_weighted_records_favor_heavier_answer() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        for (content, weight) in [("10.0.0.1", 10), ("10.0.0.2", 90)] {
+            create_record(
+                &pool,
+                CreateRecordRequest {
+                    domain_pattern: "lb.local.test".to_string(),
+                    record_type: "A".to_string(),
+                    content: content.into(),
+                    ttl: 60,
+                    priority: 0,
+                    weight,
+                    passthrough: false,
+                    pattern_type: PatternType::Glob,
+                    client_subnet: None,
+                    tags: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker).with_answer_order(
+            AnswerOrder::with_seed(crate::dns::order::AnswerOrderMode::Insertion, 1),
+        );
+
+        let iterations = 1000;
+        let mut heavier_first = 0;
+        for _ in 0..iterations {
+            let request = build_request("lb.local.test.", RecordType::A, false);
+            let response_handler = TestResponseHandler::default();
+            handler.handle_request(&request, response_handler.clone()).await;
+
+            // 同じ名前のレコードは全て回答に含まれる
+            let answers = response_handler.message().answers().to_vec();
+            assert_eq!(answers.len(), 2);
+            if answers[0].data().to_string() == "10.0.0.2" {
+                heavier_first += 1;
+            }
+        }
+
+        let ratio = heavier_first as f64 / iterations as f64;
+        assert!((0.85..=0.95).contains(&ratio), "ratio = {}", ratio);
+    }
+
+    #[tokio::test]
+    async fn test_ttl_jitter_stays_within_band() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "jitter.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "127.0.0.1".into(),
+                ttl: 100,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+
+        let answer_ttls = |handler: DnsHandler| async move {
+            let mut ttls = Vec::new();
+            for _ in 0..20 {
+                let request = build_request("jitter.local.test.", RecordType::A, false);
+                let response_handler = TestResponseHandler::default();
+                handler.handle_request(&request, response_handler.clone()).await;
+                ttls.push(response_handler.message().answers()[0].ttl());
+            }
+            ttls
+        };
+
+        // 揺らぎなしでは登録したTTLのまま
+        let handler = DnsHandler::new(cache.clone(), LogWorker::new(pool.clone()));
+        assert!(answer_ttls(handler).await.iter().all(|ttl| *ttl == 100));
+
+        // 10% の揺らぎでは 90〜110 秒に収まる
+        let handler = DnsHandler::new(cache, LogWorker::new(pool.clone()))
+            .with_ttl_jitter(TtlJitter::with_seed(10, 7));
+        let ttls = answer_ttls(handler).await;
+        assert!(ttls.iter().all(|ttl| (90..=110).contains(ttl)), "ttls = {:?}", ttls);
+        assert!(ttls.iter().any(|ttl| *ttl != 100));
+    }
+
+    #[tokio::test]
+    async fn test_zero_ttl_record_is_not_raised_by_min_ttl() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "failover.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "127.0.0.1".into(),
+                ttl: 0,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker).with_ttl_bounds(30, 3600);
+
+        let request = build_request("failover.local.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_ttl_applied_to_forwarded_record() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, _) =
+            spawn_fake_upstream_with_ttl(Ipv4Addr::new(93, 184, 216, 34), 100000).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler = DnsHandler::new(cache, log_worker)
+            .with_upstream(UpstreamResolver::new(config))
+            .with_ttl_bounds(30, 3600);
+
+        let request = build_request("example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), 3600);
+    }
+
+    #[tokio::test]
+    async fn test_catch_all_answers_local_zone_and_public_names_forward() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "*".to_string(),
+                record_type: "A".to_string(),
+                content: "10.99.99.99".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let cache = RecordCache::new(pool.clone())
+            .await
+            .unwrap()
+            .with_local_zones(vec!["corp.local".to_string()]);
+        let log_worker = LogWorker::new(pool.clone());
+        let (upstream_addr, received) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        // ローカルゾーン内の未登録の名前は catch-all で応答し、転送しない
+        let request = build_request("unknown.corp.local.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        let response = response_handler.message();
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::A(Ipv4Addr::new(10, 99, 99, 99).into())
+        );
+        assert!(received.lock().unwrap().is_empty());
+
+        // ゾーン外の名前は従来どおり上位DNSに転送する
+        let request = build_request("www.example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        let response = response_handler.message();
+        assert_eq!(
+            response.answers()[0].data(),
+            &RData::A(Ipv4Addr::new(93, 184, 216, 34).into())
+        );
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_authoritative_zone_miss_is_not_forwarded() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "app.corp.test".to_string(),
+                record_type: "A".to_string(),
+                content: "10.1.0.1".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let (upstream_addr, received) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler = DnsHandler::new(cache, log_worker)
+            .with_upstream(UpstreamResolver::new(config))
+            .with_authoritative_zones(vec!["corp.test".to_string()]);
+
+        // 権威ゾーン内の該当なしは SOA 付きの NXDOMAIN を AA 付きで返し、転送しない
+        let request = build_request("missing.corp.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.header().authoritative());
+        assert!(response.answers().is_empty());
+        assert_eq!(response.name_servers().len(), 1);
+        assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA);
+        assert_eq!(response.name_servers()[0].name(), &Name::from_str("corp.test.").unwrap());
+
+        // 名前はあるがタイプが無い場合は NODATA
+        let request = build_request("app.corp.test.", RecordType::AAAA, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.header().authoritative());
+        assert!(response.answers().is_empty());
+
+        // ローカルの回答にも AA を立てる
+        let request = build_request("app.corp.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        let response = response_handler.message();
+        assert!(response.header().authoritative());
+        assert_eq!(response.answers().len(), 1);
+        assert!(received.lock().unwrap().is_empty());
+
+        // ゾーン外の名前は従来どおり転送し、AA は立てない
+        let request = build_request("www.example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        let response = response_handler.message();
+        assert!(!response.header().authoritative());
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_offline_mode_never_forwards() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) = spawn_fake_upstream(Ipv4Addr::new(10, 9, 9, 9)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler = DnsHandler::new(cache, log_worker)
+            .with_upstream(UpstreamResolver::new(config))
+            .with_offline_mode(true);
+
+        let request = build_request("remote.example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+        assert!(!response.recursion_available());
+
+        // 上位DNSには転送されていない
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_no_forward_without_recursion_desired() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) =
+            spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request_with_flags("example.com.", RecordType::A, false, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+        assert!(!response.header().recursion_desired());
+
+        // 上位DNSには転送されていない
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_query_logs_upstream_server() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, _) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request("example.com.", RecordType::A, false);
+        handler
+            .handle_request(&request, TestResponseHandler::default())
+            .await;
+
+        // ログは非同期に書き込まれるため、記録されるまで待つ
+        let mut logs = Vec::new();
+        for _ in 0..50 {
+            logs = crate::db::get_recent_logs(&pool, 10).await.unwrap();
+            if !logs.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].result_type, "FORWARDED");
+        assert_eq!(logs[0].upstream_server.as_deref(), Some(addr.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_query_deadline_returns_servfail() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        // 受信しても応答しない上位DNS
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while socket.recv_from(&mut buf).await.is_ok() {}
+        });
+        let config = UpstreamConfig::new(&addr, &addr, 5000).unwrap();
+        let handler = DnsHandler::new(cache, log_worker)
+            .with_upstream(UpstreamResolver::new(config))
+            .with_query_deadline(Duration::from_millis(200));
+
+        let request = build_request("slow.example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        let started = Instant::now();
+        handler
+            .handle_request(&request, response_handler.clone())
+            .await;
+
+        // 上位DNSのタイムアウト（5秒）を待たずに応答する
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(response_handler.message().response_code(), ResponseCode::ServFail);
+
+        // ログは非同期に書き込まれるため、記録されるまで待つ
+        let mut logs = Vec::new();
+        for _ in 0..50 {
+            logs = crate::db::get_recent_logs(&pool, 10).await.unwrap();
+            if !logs.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].result_type, "TIMEOUT");
+        assert_eq!(logs[0].query_name, "slow.example.com");
+    }
+
+    /// SOA付きの NXDOMAIN を返す上位DNSを起動し、受信した問い合わせ数を数える
+    async fn spawn_nxdomain_upstream(
+        soa_ttl: u32,
+        minimum: u32,
+    ) -> (SocketAddr, Arc<Mutex<usize>>) {
+        use hickory_server::proto::rr::rdata::SOA;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(0));
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let Ok(request) = Message::from_bytes(&buf[..len]) else {
+                    continue;
+                };
+
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .set_response_code(ResponseCode::NXDomain);
+                response.add_queries(request.queries().to_vec());
+                let soa = SOA::new(
+                    Name::from_str("ns1.example.com.").unwrap(),
+                    Name::from_str("hostmaster.example.com.").unwrap(),
+                    2024010101,
+                    3600,
+                    600,
+                    86400,
+                    minimum,
+                );
+                response.add_name_server(DnsRecord::from_rdata(
+                    Name::from_str("example.com.").unwrap(),
+                    soa_ttl,
+                    RData::SOA(soa),
+                ));
+
+                *received_clone.lock().unwrap() += 1;
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        (addr, received)
+    }
+
+    #[tokio::test]
+    async fn test_negative_answer_cached_for_soa_minimum() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) = spawn_nxdomain_upstream(3600, 120).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler = DnsHandler::new(cache, log_worker)
+            .with_upstream(UpstreamResolver::new(config))
+            .with_negative_cache_max_ttl(300);
+
+        let missing = Name::from_str("missing.example.com.").unwrap();
+        for _ in 0..2 {
+            let outcome = handler
+                .lookup(&missing, RecordType::A, false, true, None)
+                .await;
+            assert_eq!(handler.response_code(&outcome), ResponseCode::NXDomain);
+        }
+        // 2回目は否定キャッシュから応答する
+        assert_eq!(*received.lock().unwrap(), 1);
+
+        // SOAの MINIMUM（120秒）の間だけキャッシュする
+        let upstream_cache = handler.upstream_cache();
+        let now = Instant::now();
+        assert_eq!(
+            upstream_cache
+                .get_at("missing.example.com", "A", None, now + Duration::from_secs(110))
+                .await
+                .map(|cached| cached.response_code),
+            Some(ResponseCode::NXDomain)
+        );
+        assert!(upstream_cache
+            .get_at("missing.example.com", "A", None, now + Duration::from_secs(130))
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_negative_cache_ttl_capped_by_max() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, _) = spawn_nxdomain_upstream(86400, 86400).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler = DnsHandler::new(cache, log_worker)
+            .with_upstream(UpstreamResolver::new(config))
+            .with_negative_cache_max_ttl(60);
+
+        let missing = Name::from_str("missing.example.com.").unwrap();
+        handler.lookup(&missing, RecordType::A, false, true, None).await;
+
+        let upstream_cache = handler.upstream_cache();
+        let now = Instant::now();
+        assert!(upstream_cache
+            .get_at("missing.example.com", "A", None, now + Duration::from_secs(50))
+            .await
+            .is_some());
+        assert!(upstream_cache
+            .get_at("missing.example.com", "A", None, now + Duration::from_secs(70))
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_flushed_upstream_cache_forwards_again() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        // 2回目はキャッシュから応答する
+        for _ in 0..2 {
+            let request = build_request("example.com.", RecordType::A, false);
+            handler
+                .handle_request(&request, TestResponseHandler::default())
+                .await;
+        }
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        // キャッシュを破棄すると再び上位に転送する
+        assert_eq!(handler.upstream_cache().flush_prefix("example.com").await, 1);
+        let request = build_request("example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        assert_eq!(received.lock().unwrap().len(), 2);
+        assert_eq!(response_handler.message().answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_cache_scoped_by_client_subnet() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, received) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000)
+            .unwrap()
+            .with_client_subnet(parse_ecs("client", 24, 56).unwrap().unwrap());
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let name = Name::from_str("example.com.").unwrap();
+        let client = |ip: &str| Some(IpAddr::from_str(ip).unwrap());
+
+        // 別のサブネットの問い合わせ元には、もう一方の応答をキャッシュから返さない
+        for ip in ["198.51.100.10", "203.0.113.20"] {
+            handler.lookup(&name, RecordType::A, false, true, client(ip)).await;
+        }
+        assert_eq!(received.lock().unwrap().len(), 2);
+
+        // 同じサブネットの問い合わせ元にはキャッシュから応答する
+        let outcome = handler
+            .lookup(&name, RecordType::A, false, true, client("198.51.100.99"))
+            .await;
+        assert_eq!(received.lock().unwrap().len(), 2);
+        assert_eq!(outcome.answers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_only_log_filter() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "app.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "127.0.0.1".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (upstream_addr, _) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let addr = upstream_addr.to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler = DnsHandler::new(cache, log_worker.clone())
+            .with_upstream(UpstreamResolver::new(config))
+            .with_log_filter(LogFilter::ForwardedOnly);
+
+        for name in ["app.local.test.", "example.com."] {
+            let request = build_request(name, RecordType::A, false);
+            handler
+                .handle_request(&request, TestResponseHandler::default())
+                .await;
+        }
+
+        // 送信済みのログを全て書き込ませてから確認する
+        log_worker.shutdown().await;
+        let logs = crate::db::get_recent_logs(&pool, 10).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].query_name, "example.com");
+        assert_eq!(logs[0].result_type, "FORWARDED");
+    }
+
+    #[tokio::test]
+    async fn test_local_hits_are_counted_after_flush() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let id = create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "app.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "127.0.0.1".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap()[0];
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache.clone(), log_worker);
+
+        for _ in 0..3 {
+            let request = build_request("app.local.test.", RecordType::A, false);
+            handler
+                .handle_request(&request, TestResponseHandler::default())
+                .await;
+        }
+
+        // 反映前はDBに書き込まれていない
+        let record = crate::db::get_record_by_id(&pool, id).await.unwrap().unwrap();
+        assert_eq!(record.hit_count, 0);
+
+        assert_eq!(cache.flush_hits().await.unwrap(), 1);
+        let record = crate::db::get_record_by_id(&pool, id).await.unwrap().unwrap();
+        assert_eq!(record.hit_count, 3);
+        assert!(record.last_hit_at.is_some());
+
+        // 反映済みのヒットは二重に加算されない
+        assert_eq!(cache.flush_hits().await.unwrap(), 0);
+        let record = crate::db::get_record_by_id(&pool, id).await.unwrap().unwrap();
+        assert_eq!(record.hit_count, 3);
+    }
+
+    /// 上位DNSなしでレコードの無い名前を問い合わせ、応答コードを返す
+    async fn miss_response_code(miss_response: &str) -> ResponseCode {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker)
+            .with_miss_response(parse_miss_response(miss_response).unwrap());
+
+        let request = build_request("missing.local.test.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+        response_handler.message().response_code()
+    }
+
+    #[tokio::test]
+    async fn test_miss_response_nxdomain() {
+        assert_eq!(miss_response_code("nxdomain").await, ResponseCode::NXDomain);
+    }
+
+    #[tokio::test]
+    async fn test_miss_response_refused() {
+        assert_eq!(miss_response_code("refused").await, ResponseCode::Refused);
+    }
+
+    #[tokio::test]
+    async fn test_miss_response_servfail() {
+        assert_eq!(miss_response_code("SERVFAIL").await, ResponseCode::ServFail);
+    }
+
+    #[test]
+    fn test_parse_miss_response_invalid() {
+        assert!(parse_miss_response("drop").is_none());
+    }
+
+    /// テスト用: 生成されたスパンの名前とフィールドを記録するレイヤー
+    #[derive(Clone, Default)]
+    struct SpanCapture {
+        spans: Arc<Mutex<std::collections::HashMap<u64, (String, Vec<(String, String)>)>>>,
+    }
+
+    /// スパンのフィールドを文字列として集める
+    struct FieldCollector<'a>(&'a mut Vec<(String, String)>);
+
+    impl tracing::field::Visit for FieldCollector<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.push((field.name().to_string(), value.to_string()));
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanCapture {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Vec::new();
+            attrs.record(&mut FieldCollector(&mut fields));
+            self.spans
+                .lock()
+                .unwrap()
+                .insert(id.into_u64(), (attrs.metadata().name().to_string(), fields));
+        }
+
+        fn on_record(
+            &self,
+            id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if let Some((_, fields)) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+                values.record(&mut FieldCollector(fields));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_span_carries_id_name_and_type() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let capture = SpanCapture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let handler = DnsHandler::new(cache, log_worker);
+
+        let request = build_request("app.local.test.", RecordType::AAAA, false);
+        handler
+            .handle_request(&request, TestResponseHandler::default())
+            .await;
+
+        let spans = capture.spans.lock().unwrap().clone();
+        let (_, fields) = spans
+            .values()
+            .find(|(name, _)| name == "dns_query")
+            .expect("dns_query スパンが生成されていません");
+        let get = |key: &str| {
+            fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+        };
+
+        let query_id = get("id").unwrap();
+        assert_eq!(query_id.len(), 8);
+        assert_eq!(get("name").as_deref(), Some("app.local.test"));
+        assert_eq!(get("qtype").as_deref(), Some("AAAA"));
+
+        // 同じ問い合わせIDがクエリログにも記録される
+        let mut logs = Vec::new();
+        for _ in 0..50 {
+            logs = crate::db::get_recent_logs(&pool, 10).await.unwrap();
+            if !logs.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].query_id.as_deref(), Some(query_id.as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_failure_returns_servfail() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        // 応答しない上位DNS
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = silent.local_addr().unwrap().to_string();
+        let config = UpstreamConfig::new(&addr, &addr, 50).unwrap().with_retries(0);
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request("example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+        assert!(response.answers().is_empty());
+        drop(silent);
+    }
+
+    #[tokio::test]
+    async fn test_empty_upstream_answer_returns_nodata() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        // 回答なしで応答する上位DNS
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let Ok(request) = Message::from_bytes(&buf[..len]) else {
+                    continue;
+                };
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query);
+                response.add_queries(request.queries().to_vec());
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request("missing.example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        // NOERROR で回答が無い応答は NODATA として NOERROR のまま返す
+        let response = response_handler.message();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+    }
+
+    /// 回答なしで指定の応答コードを返す上位DNSを起動し、受信した問い合わせ数を記録する
+    async fn spawn_rcode_upstream(response_code: ResponseCode) -> (SocketAddr, Arc<Mutex<usize>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(0));
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let Ok(request) = Message::from_bytes(&buf[..len]) else {
+                    continue;
+                };
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .set_response_code(response_code);
+                response.add_queries(request.queries().to_vec());
+
+                *received_clone.lock().unwrap() += 1;
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        (addr, received)
+    }
+
+    #[tokio::test]
+    async fn test_upstream_servfail_falls_back_to_secondary() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (primary, primary_received) = spawn_rcode_upstream(ResponseCode::ServFail).await;
+        let (secondary, _) = spawn_fake_upstream(Ipv4Addr::new(93, 184, 216, 34)).await;
+        let config =
+            UpstreamConfig::new(&primary.to_string(), &secondary.to_string(), 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request("example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        let response = response_handler.message();
+        assert_eq!(*primary_received.lock().unwrap(), 1);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_refusals_return_servfail() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+
+        let (primary, _) = spawn_rcode_upstream(ResponseCode::ServFail).await;
+        let (secondary, secondary_received) = spawn_rcode_upstream(ResponseCode::Refused).await;
+        let config =
+            UpstreamConfig::new(&primary.to_string(), &secondary.to_string(), 2000).unwrap();
+        let handler =
+            DnsHandler::new(cache, log_worker).with_upstream(UpstreamResolver::new(config));
+
+        let request = build_request("example.com.", RecordType::A, false);
+        let response_handler = TestResponseHandler::default();
+        handler.handle_request(&request, response_handler.clone()).await;
+
+        // どちらの上位DNSも障害を応答した場合は SERVFAIL
+        assert_eq!(*secondary_received.lock().unwrap(), 1);
+        assert_eq!(response_handler.message().response_code(), ResponseCode::ServFail);
+    }
 }
@@ -0,0 +1,97 @@
+use hickory_server::proto::rr::Record as DnsRecord;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::sync::{Arc, Mutex};
+
+/// 指定できる揺らぎ幅の上限（%）
+pub const MAX_TTL_JITTER_PERCENT: u32 = 50;
+
+/// 応答TTLの揺らぎ（多数のクライアントのキャッシュが同時に切れて再問い合わせが集中するのを避ける）
+/// 乱数はクローン間で共有する
+#[derive(Clone)]
+pub struct TtlJitter {
+    percent: u32,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl TtlJitter {
+    /// TTLを ±`percent`% の範囲で揺らす（0 の場合は揺らさない。上限は 50%）
+    pub fn new(percent: u32) -> Self {
+        Self::from_rng(percent, StdRng::from_entropy())
+    }
+
+    /// 乱数のシードを固定して作成（テストで揺らぎを再現するため）
+    #[allow(dead_code)]
+    pub fn with_seed(percent: u32, seed: u64) -> Self {
+        Self::from_rng(percent, StdRng::seed_from_u64(seed))
+    }
+
+    fn from_rng(percent: u32, rng: StdRng) -> Self {
+        Self {
+            percent: percent.min(MAX_TTL_JITTER_PERCENT),
+            rng: Arc::new(Mutex::new(rng)),
+        }
+    }
+
+    /// 各レコードのTTLを揺らす
+    /// TTL 0 は「キャッシュさせない」指定のためそのまま、それ以外は1秒未満にしない
+    pub fn apply(&self, records: &mut [DnsRecord]) {
+        if self.percent == 0 {
+            return;
+        }
+
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        for record in records.iter_mut().filter(|record| record.ttl() > 0) {
+            let ttl = i64::from(record.ttl());
+            let band = ttl * i64::from(self.percent) / 100;
+            if band == 0 {
+                continue;
+            }
+            let jittered = ttl + rng.gen_range(-band..=band);
+            record.set_ttl(jittered.clamp(1, i64::from(u32::MAX)) as u32);
+        }
+    }
+}
+
+impl Default for TtlJitter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_server::proto::rr::{Name, RData};
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    fn a_record(ttl: u32) -> DnsRecord {
+        DnsRecord::from_rdata(
+            Name::from_str("app.local.test.").unwrap(),
+            ttl,
+            RData::A(Ipv4Addr::new(10, 0, 0, 1).into()),
+        )
+    }
+
+    #[test]
+    fn test_jitter_is_reproducible_and_capped() {
+        let jittered = |seed: u64| {
+            let jitter = TtlJitter::with_seed(80, seed);
+            (0..20)
+                .map(|_| {
+                    let mut records = vec![a_record(100), a_record(0)];
+                    jitter.apply(&mut records);
+                    (records[0].ttl(), records[1].ttl())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = jittered(42);
+        assert_eq!(first, jittered(42));
+
+        // 80% を指定しても上限の 50% に収まり、TTL 0 は変えない
+        assert!(first.iter().all(|(ttl, zero)| (50..=150).contains(ttl) && *zero == 0));
+        assert!(first.iter().any(|(ttl, _)| *ttl != 100));
+    }
+}
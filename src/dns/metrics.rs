@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// 上位DNS問い合わせ時間のヒストグラムのバケット境界（秒）
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// 上位DNSサーバー1台分の統計
+#[derive(Debug, Default, Clone)]
+struct UpstreamStats {
+    /// 各バケット境界以下だった応答数（累積ではない）
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    /// 全バケットを超えた応答数
+    overflow: u64,
+    latency_sum: f64,
+    latency_count: u64,
+    failures: u64,
+}
+
+/// 上位DNSサーバーごとの応答時間・失敗数
+/// クローンしても同じ統計を共有する
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamMetrics {
+    stats: Arc<Mutex<BTreeMap<SocketAddr, UpstreamStats>>>,
+}
+
+impl UpstreamMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 応答を得られた問い合わせの所要時間を記録
+    pub fn observe_latency(&self, server: SocketAddr, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.entry(server).or_default();
+
+        match LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+            Some(index) => entry.buckets[index] += 1,
+            None => entry.overflow += 1,
+        }
+        entry.latency_sum += secs;
+        entry.latency_count += 1;
+    }
+
+    /// 失敗した問い合わせを記録
+    pub fn record_failure(&self, server: SocketAddr) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.entry(server).or_default().failures += 1;
+    }
+
+    /// Prometheus テキスト形式で出力
+    pub fn render(&self) -> String {
+        let stats = self.stats.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP localdns_upstream_query_duration_seconds 上位DNSへの問い合わせ時間\n");
+        out.push_str("# TYPE localdns_upstream_query_duration_seconds histogram\n");
+        for (server, entry) in stats.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(entry.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "localdns_upstream_query_duration_seconds_bucket{{server=\"{}\",le=\"{}\"}} {}",
+                    server, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "localdns_upstream_query_duration_seconds_bucket{{server=\"{}\",le=\"+Inf\"}} {}",
+                server,
+                cumulative + entry.overflow
+            );
+            let _ = writeln!(
+                out,
+                "localdns_upstream_query_duration_seconds_sum{{server=\"{}\"}} {}",
+                server, entry.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "localdns_upstream_query_duration_seconds_count{{server=\"{}\"}} {}",
+                server, entry.latency_count
+            );
+        }
+
+        out.push_str("# HELP localdns_upstream_query_failures_total 上位DNSへの問い合わせ失敗数\n");
+        out.push_str("# TYPE localdns_upstream_query_failures_total counter\n");
+        for (server, entry) in stats.iter() {
+            let _ = writeln!(
+                out,
+                "localdns_upstream_query_failures_total{{server=\"{}\"}} {}",
+                server, entry.failures
+            );
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_histogram_and_failures() {
+        let metrics = UpstreamMetrics::new();
+        let server: SocketAddr = "8.8.8.8:53".parse().unwrap();
+
+        metrics.observe_latency(server, Duration::from_millis(20));
+        metrics.observe_latency(server, Duration::from_secs(10));
+        metrics.record_failure(server);
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "localdns_upstream_query_duration_seconds_bucket{server=\"8.8.8.8:53\",le=\"0.01\"} 0"
+        ));
+        assert!(text.contains(
+            "localdns_upstream_query_duration_seconds_bucket{server=\"8.8.8.8:53\",le=\"0.025\"} 1"
+        ));
+        assert!(text.contains(
+            "localdns_upstream_query_duration_seconds_bucket{server=\"8.8.8.8:53\",le=\"+Inf\"} 2"
+        ));
+        assert!(text.contains(
+            "localdns_upstream_query_duration_seconds_count{server=\"8.8.8.8:53\"} 2"
+        ));
+        assert!(text.contains("localdns_upstream_query_failures_total{server=\"8.8.8.8:53\"} 1"));
+    }
+
+    #[test]
+    fn test_clones_share_stats() {
+        let metrics = UpstreamMetrics::new();
+        let server: SocketAddr = "1.1.1.1:53".parse().unwrap();
+
+        metrics.clone().record_failure(server);
+
+        assert!(metrics
+            .render()
+            .contains("localdns_upstream_query_failures_total{server=\"1.1.1.1:53\"} 1"));
+    }
+}
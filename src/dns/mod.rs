@@ -1,9 +1,18 @@
 pub mod cache;
 pub mod handler;
+pub mod jitter;
+pub mod metrics;
+pub mod order;
 pub mod resolver;
+pub mod socket;
+pub mod special;
+pub mod transfer;
 pub mod upstream;
 
-pub use cache::RecordCache;
-pub use handler::DnsHandler;
-pub use resolver::build_dns_record;
+pub use cache::{parse_env_expansion, parse_zone_list, RecordCache, UpstreamCache};
+pub use handler::{parse_miss_response, DnsHandler};
+pub use jitter::TtlJitter;
+pub use metrics::UpstreamMetrics;
+pub use order::{parse_answer_order, AnswerOrder};
+pub use resolver::{build_dns_record, parse_caa_content, parse_svcb_content, parse_tlsa_content};
 pub use upstream::UpstreamConfig;
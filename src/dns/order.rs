@@ -0,0 +1,270 @@
+use hickory_server::proto::rr::{Record as DnsRecord, RecordType};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// 複数レコード応答の並び順
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnswerOrderMode {
+    /// 登録・受信した順のまま
+    Insertion,
+    /// 応答ごとにランダムに並べ替える
+    Random,
+    /// 応答ごとに先頭を1つずつずらす
+    RoundRobin,
+}
+
+/// 設定値（`insertion` / `random` / `round_robin`）を並び順に変換
+pub fn parse_answer_order(value: &str) -> Option<AnswerOrderMode> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "insertion" => Some(AnswerOrderMode::Insertion),
+        "random" => Some(AnswerOrderMode::Random),
+        "round_robin" => Some(AnswerOrderMode::RoundRobin),
+        _ => None,
+    }
+}
+
+/// 回答セクションの並べ替え（乱数・巡回位置はクローン間で共有）
+#[derive(Clone)]
+pub struct AnswerOrder {
+    mode: AnswerOrderMode,
+    rng: Arc<Mutex<StdRng>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl AnswerOrder {
+    pub fn new(mode: AnswerOrderMode) -> Self {
+        Self::from_rng(mode, StdRng::from_entropy())
+    }
+
+    /// 乱数のシードを固定して作成（テストで並びを再現するため）
+    #[allow(dead_code)]
+    pub fn with_seed(mode: AnswerOrderMode, seed: u64) -> Self {
+        Self::from_rng(mode, StdRng::seed_from_u64(seed))
+    }
+
+    fn from_rng(mode: AnswerOrderMode, rng: StdRng) -> Self {
+        Self {
+            mode,
+            rng: Arc::new(Mutex::new(rng)),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// 問い合わせタイプのレコードのみを並べ替える
+    /// CNAME など他のタイプは位置を変えない（CNAMEチェーンの順序を崩さないため）
+    pub fn apply(&self, records: &mut [DnsRecord], record_type: RecordType) {
+        self.apply_by(records, |record| record.record_type() == record_type);
+    }
+
+    /// `is_target` を満たす要素のみを並べ替え、それ以外の要素は位置を変えない
+    pub fn apply_by<T: Clone>(&self, items: &mut [T], is_target: impl Fn(&T) -> bool) {
+        if self.mode == AnswerOrderMode::Insertion {
+            return;
+        }
+
+        let positions: Vec<usize> = items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| is_target(item))
+            .map(|(index, _)| index)
+            .collect();
+        if positions.len() < 2 {
+            return;
+        }
+
+        let mut ordered: Vec<T> = positions.iter().map(|&i| items[i].clone()).collect();
+        match self.mode {
+            AnswerOrderMode::Insertion => {}
+            AnswerOrderMode::Random => {
+                let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+                ordered.shuffle(&mut *rng);
+            }
+            AnswerOrderMode::RoundRobin => {
+                let shift = self.next.fetch_add(1, Ordering::Relaxed) % ordered.len();
+                ordered.rotate_left(shift);
+            }
+        }
+
+        for (position, item) in positions.into_iter().zip(ordered) {
+            items[position] = item;
+        }
+    }
+
+    /// 重みに応じてランダムに並べ替える（重みに比例した確率で先頭に選ばれる。重み0以下は末尾）
+    /// 全て同じ重みの場合は並べ替えずに false を返す（並び順の設定に任せる）
+    pub fn apply_weighted<T>(&self, items: &mut Vec<T>, weight: impl Fn(&T) -> i64) -> bool {
+        let weights: Vec<i64> = items.iter().map(&weight).collect();
+        if weights.windows(2).all(|pair| pair[0] == pair[1]) {
+            return false;
+        }
+
+        // 各要素に u^(1/重み) のキーを付けて降順に並べる（重み付き非復元抽出）
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        let mut keyed: Vec<(f64, T)> = items
+            .drain(..)
+            .zip(weights)
+            .map(|(item, weight)| {
+                let key = if weight > 0 {
+                    rng.gen::<f64>().powf(1.0 / weight as f64)
+                } else {
+                    -1.0
+                };
+                (key, item)
+            })
+            .collect();
+        keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        items.extend(keyed.into_iter().map(|(_, item)| item));
+        true
+    }
+}
+
+impl Default for AnswerOrder {
+    fn default() -> Self {
+        Self::new(AnswerOrderMode::Insertion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_server::proto::rr::rdata::{A, CNAME};
+    use hickory_server::proto::rr::{Name, RData};
+    use std::net::Ipv4Addr;
+    use std::str::FromStr;
+
+    /// 同じ名前の3つのAレコード
+    fn three_a_records() -> Vec<DnsRecord> {
+        let name = Name::from_str("app.local.test.").unwrap();
+        (1..=3)
+            .map(|i| {
+                DnsRecord::from_rdata(name.clone(), 60, RData::A(A(Ipv4Addr::new(10, 0, 0, i))))
+            })
+            .collect()
+    }
+
+    /// レコードのアドレスの最終オクテット
+    fn last_octets(records: &[DnsRecord]) -> Vec<u8> {
+        records
+            .iter()
+            .filter_map(|record| match record.data() {
+                RData::A(a) => Some(a.0.octets()[3]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_answer_order() {
+        assert_eq!(parse_answer_order("insertion"), Some(AnswerOrderMode::Insertion));
+        assert_eq!(parse_answer_order(" Random "), Some(AnswerOrderMode::Random));
+        assert_eq!(parse_answer_order("round_robin"), Some(AnswerOrderMode::RoundRobin));
+        assert_eq!(parse_answer_order("sorted"), None);
+    }
+
+    #[test]
+    fn test_insertion_keeps_order() {
+        let order = AnswerOrder::new(AnswerOrderMode::Insertion);
+        let mut records = three_a_records();
+
+        for _ in 0..3 {
+            order.apply(&mut records, RecordType::A);
+            assert_eq!(last_octets(&records), vec![1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn test_random_is_reproducible_with_seed() {
+        let shuffled = |seed: u64| {
+            let order = AnswerOrder::with_seed(AnswerOrderMode::Random, seed);
+            (0..10)
+                .map(|_| {
+                    let mut records = three_a_records();
+                    order.apply(&mut records, RecordType::A);
+                    last_octets(&records)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = shuffled(42);
+        assert_eq!(first, shuffled(42));
+
+        // 要素は保ったまま、登録順以外の並びも現れる
+        assert!(first.iter().all(|octets| {
+            let mut sorted = octets.clone();
+            sorted.sort();
+            sorted == vec![1, 2, 3]
+        }));
+        assert!(first.iter().any(|octets| *octets != vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_round_robin_rotates_per_response() {
+        let order = AnswerOrder::new(AnswerOrderMode::RoundRobin);
+
+        let mut responses = Vec::new();
+        for _ in 0..4 {
+            let mut records = three_a_records();
+            order.apply(&mut records, RecordType::A);
+            responses.push(last_octets(&records));
+        }
+
+        assert_eq!(
+            responses,
+            vec![vec![1, 2, 3], vec![2, 3, 1], vec![3, 1, 2], vec![1, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn test_weighted_order_follows_weights() {
+        let order = AnswerOrder::with_seed(AnswerOrderMode::Insertion, 7);
+        let iterations = 10_000;
+
+        let mut b_first = 0;
+        for _ in 0..iterations {
+            let mut items = vec![("A", 10), ("B", 90)];
+            assert!(order.apply_weighted(&mut items, |(_, weight)| *weight));
+            if items[0].0 == "B" {
+                b_first += 1;
+            }
+        }
+
+        // B が先頭になる割合はおよそ 90%
+        let ratio = b_first as f64 / iterations as f64;
+        assert!((0.87..=0.93).contains(&ratio), "ratio = {}", ratio);
+    }
+
+    #[test]
+    fn test_weighted_order_skips_equal_weights() {
+        let order = AnswerOrder::with_seed(AnswerOrderMode::Insertion, 7);
+
+        let mut items = vec![("A", 1), ("B", 1), ("C", 1)];
+        assert!(!order.apply_weighted(&mut items, |(_, weight)| *weight));
+        assert_eq!(items, vec![("A", 1), ("B", 1), ("C", 1)]);
+
+        // 重み0は常に末尾
+        let mut items = vec![("A", 0), ("B", 1), ("C", 5)];
+        assert!(order.apply_weighted(&mut items, |(_, weight)| *weight));
+        assert_eq!(items[2], ("A", 0));
+    }
+
+    #[test]
+    fn test_other_types_keep_position() {
+        let order = AnswerOrder::new(AnswerOrderMode::RoundRobin);
+        let mut records = vec![DnsRecord::from_rdata(
+            Name::from_str("alias.local.test.").unwrap(),
+            60,
+            RData::CNAME(CNAME(Name::from_str("app.local.test.").unwrap())),
+        )];
+        records.extend(three_a_records());
+
+        order.apply(&mut records, RecordType::A);
+        order.apply(&mut records, RecordType::A);
+
+        assert_eq!(records[0].record_type(), RecordType::CNAME);
+        assert_eq!(last_octets(&records), vec![2, 3, 1]);
+    }
+}
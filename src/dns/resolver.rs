@@ -1,20 +1,28 @@
 use crate::db::Record;
+use hickory_server::proto::rr::rdata::caa::KeyValue;
+use hickory_server::proto::rr::rdata::svcb::{Alpn, SvcParamKey, SvcParamValue, SVCB};
+use hickory_server::proto::rr::rdata::tlsa::{CertUsage, Matching, Selector};
+use hickory_server::proto::rr::rdata::{CAA, HTTPS, NS, TLSA};
 use hickory_server::proto::rr::{Name, RData, Record as DnsRecord, RecordType};
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
-use tracing::warn;
+use tracing::{debug, warn};
 
 /// DNSレコードを構築
+/// `query_type` は AUTO レコードで A / AAAA のどちらを返すかの判定に使う
 pub fn build_dns_record(
     query_name: &Name,
+    query_type: RecordType,
     record: &Record,
 ) -> Option<DnsRecord> {
     let ttl = record.ttl as u32;
+    // ワイルドカードでマッチした部分をコンテンツのテンプレートに埋め込む
+    let content = record.expand_content(query_name.to_ascii().trim_end_matches('.'));
 
     match record.record_type.as_str() {
         "A" => {
             // IPv4アドレスをパース
-            match Ipv4Addr::from_str(&record.content) {
+            match Ipv4Addr::from_str(&content) {
                 Ok(ip) => {
                     let rdata = RData::A(ip.into());
                     Some(DnsRecord::from_rdata(
@@ -26,7 +34,7 @@ pub fn build_dns_record(
                 Err(e) => {
                     warn!(
                         "IPv4アドレスのパースに失敗: {} ({})",
-                        record.content, e
+                        content, e
                     );
                     None
                 }
@@ -34,7 +42,7 @@ pub fn build_dns_record(
         }
         "AAAA" => {
             // IPv6アドレスをパース
-            match Ipv6Addr::from_str(&record.content) {
+            match Ipv6Addr::from_str(&content) {
                 Ok(ip) => {
                     let rdata = RData::AAAA(ip.into());
                     Some(DnsRecord::from_rdata(
@@ -46,7 +54,25 @@ pub fn build_dns_record(
                 Err(e) => {
                     warn!(
                         "IPv6アドレスのパースに失敗: {} ({})",
-                        record.content, e
+                        content, e
+                    );
+                    None
+                }
+            }
+        }
+        "AUTO" => {
+            // 問い合わせタイプに合うアドレスファミリーのアドレスを返す
+            let rdata = record.auto_addresses().find_map(|ip| match (query_type, ip) {
+                (RecordType::A, IpAddr::V4(ip)) => Some(RData::A(ip.into())),
+                (RecordType::AAAA, IpAddr::V6(ip)) => Some(RData::AAAA(ip.into())),
+                _ => None,
+            });
+            match rdata {
+                Some(rdata) => Some(DnsRecord::from_rdata(query_name.clone(), ttl, rdata)),
+                None => {
+                    debug!(
+                        "AUTO レコードに {} 用のアドレスがありません: {}",
+                        query_type, content
                     );
                     None
                 }
@@ -54,7 +80,7 @@ pub fn build_dns_record(
         }
         "CNAME" => {
             // CNAMEターゲットをパース
-            match Name::from_str(&record.content) {
+            match Name::from_str(&content) {
                 Ok(target) => {
                     use hickory_server::proto::rr::rdata::CNAME;
                     let cname = CNAME(target);
@@ -68,12 +94,84 @@ pub fn build_dns_record(
                 Err(e) => {
                     warn!(
                         "CNAME ターゲットのパースに失敗: {} ({})",
-                        record.content, e
+                        content, e
                     );
                     None
                 }
             }
         }
+        "NS" => {
+            // ネームサーバー名をパース
+            match Name::from_str(&content) {
+                Ok(target) => {
+                    let rdata = RData::NS(NS(target));
+                    Some(DnsRecord::from_rdata(
+                        query_name.clone(),
+                        ttl,
+                        rdata,
+                    ))
+                }
+                Err(e) => {
+                    warn!(
+                        "NS ターゲットのパースに失敗: {} ({})",
+                        content, e
+                    );
+                    None
+                }
+            }
+        }
+        "HTTPS" | "SVCB" => {
+            // "優先度 ターゲット [alpn=h2,h3]" 形式をパース
+            match parse_svcb_content(&content) {
+                Ok(svcb) => {
+                    let rdata = if record.record_type == "HTTPS" {
+                        RData::HTTPS(HTTPS(svcb))
+                    } else {
+                        RData::SVCB(svcb)
+                    };
+                    Some(DnsRecord::from_rdata(
+                        query_name.clone(),
+                        ttl,
+                        rdata,
+                    ))
+                }
+                Err(e) => {
+                    warn!(
+                        "{} コンテンツのパースに失敗: {} ({})",
+                        record.record_type, content, e
+                    );
+                    None
+                }
+            }
+        }
+        "CAA" => {
+            // "フラグ タグ 値" 形式をパース
+            match parse_caa_content(&content) {
+                Ok(caa) => Some(DnsRecord::from_rdata(
+                    query_name.clone(),
+                    ttl,
+                    RData::CAA(caa),
+                )),
+                Err(e) => {
+                    warn!("CAA コンテンツのパースに失敗: {} ({})", content, e);
+                    None
+                }
+            }
+        }
+        "TLSA" => {
+            // "用途 セレクタ 照合方法 16進データ" 形式をパース
+            match parse_tlsa_content(&content) {
+                Ok(tlsa) => Some(DnsRecord::from_rdata(
+                    query_name.clone(),
+                    ttl,
+                    RData::TLSA(tlsa),
+                )),
+                Err(e) => {
+                    warn!("TLSA コンテンツのパースに失敗: {} ({})", content, e);
+                    None
+                }
+            }
+        }
         _ => {
             warn!("サポートされていないレコードタイプ: {}", record.record_type);
             None
@@ -81,6 +179,209 @@ pub fn build_dns_record(
     }
 }
 
+/// レコードのコンテンツから応答を組み立てられるか（編集などで壊れたレコードの検出用）
+/// `$1` などの埋め込みを含むコンテンツは問い合わせ名によって変わるため検証しない
+pub fn content_is_valid(record: &Record) -> bool {
+    if record.content.contains('$') {
+        return true;
+    }
+
+    let query_types = match record.record_type.as_str() {
+        "AUTO" => vec![RecordType::A, RecordType::AAAA],
+        other => match RecordType::from_str(other) {
+            Ok(record_type) => vec![record_type],
+            Err(_) => return false,
+        },
+    };
+    query_types
+        .into_iter()
+        .any(|query_type| build_dns_record(&Name::root(), query_type, record).is_some())
+}
+
+/// SVCB/HTTPSレコードのコンテンツをパース
+/// 形式: `<SvcPriority> <TargetName> [alpn=<id>[,<id>...]]`
+/// 例: `1 . alpn=h2,h3`
+pub fn parse_svcb_content(content: &str) -> Result<SVCB, String> {
+    let mut parts = content.split_whitespace();
+
+    let priority = parts
+        .next()
+        .ok_or_else(|| "SvcPriority を指定してください".to_string())?
+        .parse::<u16>()
+        .map_err(|e| format!("SvcPriority が不正です: {}", e))?;
+
+    let target = parts
+        .next()
+        .ok_or_else(|| "TargetName を指定してください".to_string())?;
+    let target = Name::from_str(target)
+        .map_err(|e| format!("TargetName が不正です: {}", e))?;
+
+    let mut params = Vec::new();
+    for param in parts {
+        let (key, value) = param
+            .split_once('=')
+            .ok_or_else(|| format!("パラメータの形式が不正です: {}", param))?;
+
+        match key.to_ascii_lowercase().as_str() {
+            "alpn" => {
+                let ids: Vec<String> = value
+                    .split(',')
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if ids.is_empty() {
+                    return Err("alpn の値を指定してください".to_string());
+                }
+                params.push((SvcParamKey::Alpn, SvcParamValue::Alpn(Alpn(ids))));
+            }
+            _ => return Err(format!("サポートされていないパラメータです: {}", key)),
+        }
+    }
+
+    Ok(SVCB::new(priority, target, params))
+}
+
+/// CAAレコードのコンテンツをパース
+/// 形式: `<flags> <tag> <value>`（tag は issue / issuewild / iodef）
+/// 例: `0 issue "letsencrypt.org"`、`0 issue "ca.local; account=123"`、`128 iodef "mailto:security@local.test"`
+pub fn parse_caa_content(content: &str) -> Result<CAA, String> {
+    let mut parts = content.trim().splitn(3, char::is_whitespace);
+
+    let flags = parts
+        .next()
+        .filter(|flags| !flags.is_empty())
+        .ok_or_else(|| "フラグを指定してください".to_string())?
+        .parse::<u8>()
+        .map_err(|e| format!("フラグが不正です: {}", e))?;
+    // 定義済みのフラグは issuer critical（128）のみ
+    let issuer_critical = match flags {
+        0 => false,
+        128 => true,
+        _ => return Err(format!("フラグは 0 または 128 を指定してください: {}", flags)),
+    };
+
+    let tag = parts
+        .next()
+        .ok_or_else(|| "タグを指定してください".to_string())?
+        .to_ascii_lowercase();
+    let value = parts
+        .next()
+        .map(str::trim)
+        .ok_or_else(|| "値を指定してください".to_string())?;
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+
+    match tag.as_str() {
+        "issue" | "issuewild" => {
+            // "発行者ドメイン[; キー=値...]"（発行者が空の場合は発行を禁止）
+            let mut fields = value.split(';').map(str::trim);
+            let issuer = match fields.next().unwrap_or_default() {
+                "" => None,
+                issuer => Some(
+                    Name::from_str(issuer)
+                        .map_err(|e| format!("発行者ドメインが不正です: {}", e))?,
+                ),
+            };
+            let key_values = fields
+                .filter(|field| !field.is_empty())
+                .map(|field| {
+                    field
+                        .split_once('=')
+                        .map(|(key, value)| KeyValue::new(key.trim(), value.trim()))
+                        .ok_or_else(|| format!("パラメータの形式が不正です: {}", field))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(if tag == "issue" {
+                CAA::new_issue(issuer_critical, issuer, key_values)
+            } else {
+                CAA::new_issuewild(issuer_critical, issuer, key_values)
+            })
+        }
+        "iodef" => {
+            let url = url::Url::parse(value).map_err(|e| format!("iodef のURLが不正です: {}", e))?;
+            if !matches!(url.scheme(), "mailto" | "http" | "https") {
+                return Err(format!(
+                    "iodef には mailto: / http(s): のURLを指定してください: {}",
+                    value
+                ));
+            }
+            Ok(CAA::new_iodef(issuer_critical, url))
+        }
+        _ => Err(format!(
+            "タグは issue / issuewild / iodef のいずれかを指定してください: {}",
+            tag
+        )),
+    }
+}
+
+/// TLSAレコードのコンテンツをパース
+/// 形式: `<usage> <selector> <matching> <hexdata>`（16進データは空白で区切られていてもよい）
+/// 例: `3 1 1 0123...ef`（DANE-EE・公開鍵・SHA-256）
+pub fn parse_tlsa_content(content: &str) -> Result<TLSA, String> {
+    let mut parts = content.split_whitespace();
+    let mut field = |name: &str, max: u8| -> Result<u8, String> {
+        let value = parts
+            .next()
+            .ok_or_else(|| format!("{} を指定してください", name))?
+            .parse::<u8>()
+            .map_err(|e| format!("{} が不正です: {}", name, e))?;
+        if value > max {
+            return Err(format!("{} は 0〜{} を指定してください: {}", name, max, value));
+        }
+        Ok(value)
+    };
+
+    let usage = field("usage", 3)?;
+    let selector = field("selector", 1)?;
+    let matching = field("matching", 2)?;
+
+    let hex: String = parts.collect();
+    if hex.is_empty() {
+        return Err("証明書データ（16進）を指定してください".to_string());
+    }
+    let cert_data = decode_hex(&hex)?;
+
+    // SHA-256 / SHA-512 はハッシュ長が決まっている
+    let expected_len = match matching {
+        1 => Some(32),
+        2 => Some(64),
+        _ => None,
+    };
+    if let Some(expected_len) = expected_len.filter(|len| *len != cert_data.len()) {
+        return Err(format!(
+            "matching {} のデータは {} バイトである必要があります（{} バイト）",
+            matching,
+            expected_len,
+            cert_data.len()
+        ));
+    }
+
+    Ok(TLSA::new(
+        CertUsage::from(usage),
+        Selector::from(selector),
+        Matching::from(matching),
+        cert_data,
+    ))
+}
+
+/// 16進文字列をバイト列に変換
+fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 {
+        return Err("16進データの桁数が奇数です".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            hex.get(index..index + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("16進データが不正です: {}", hex))
+        })
+        .collect()
+}
+
 /// RecordTypeを文字列に変換（将来のロギング拡張用）
 #[allow(dead_code)]
 pub fn record_type_to_string(rt: RecordType) -> String {
@@ -88,6 +389,8 @@ pub fn record_type_to_string(rt: RecordType) -> String {
         RecordType::A => "A".to_string(),
         RecordType::AAAA => "AAAA".to_string(),
         RecordType::CNAME => "CNAME".to_string(),
+        RecordType::CAA => "CAA".to_string(),
+        RecordType::TLSA => "TLSA".to_string(),
         _ => format!("{:?}", rt),
     }
 }
@@ -95,7 +398,7 @@ pub fn record_type_to_string(rt: RecordType) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::Record as DbRecord;
+    use crate::db::{PatternType, Record as DbRecord};
 
     #[test]
     fn test_build_a_record() {
@@ -107,9 +410,19 @@ mod tests {
             content: "127.0.0.1".to_string(),
             ttl: 60,
             active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
         };
 
-        let dns_record = build_dns_record(&query_name, &record).unwrap();
+        let dns_record = build_dns_record(&query_name, RecordType::A, &record).unwrap();
         assert_eq!(dns_record.name(), &query_name);
         assert_eq!(dns_record.ttl(), 60);
 
@@ -120,6 +433,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_build_cname_record_with_wildcard_template() {
+        let query_name = Name::from_str("foo.dev.local.").unwrap();
+        let record = DbRecord {
+            id: 1,
+            domain_pattern: "%.dev.local".to_string(),
+            record_type: "CNAME".to_string(),
+            content: "$1.backend.local".to_string(),
+            ttl: 60,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        let dns_record = build_dns_record(&query_name, RecordType::CNAME, &record).unwrap();
+
+        if let RData::CNAME(cname) = dns_record.data() {
+            assert_eq!(
+                cname.0.to_string().trim_end_matches('.'),
+                "foo.backend.local"
+            );
+        } else {
+            panic!("Expected CNAME record");
+        }
+    }
+
+    #[test]
+    fn test_build_auto_record_answers_a_and_aaaa() {
+        let query_name = Name::from_str("host.local.test").unwrap();
+        let record = DbRecord {
+            id: 1,
+            domain_pattern: "host.local.test".to_string(),
+            record_type: "AUTO".to_string(),
+            content: "192.168.1.10, fd00::10".to_string(),
+            ttl: 60,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        let a = build_dns_record(&query_name, RecordType::A, &record).unwrap();
+        if let RData::A(ip) = a.data() {
+            assert_eq!(ip.to_string(), "192.168.1.10");
+        } else {
+            panic!("Expected A record");
+        }
+
+        let aaaa = build_dns_record(&query_name, RecordType::AAAA, &record).unwrap();
+        if let RData::AAAA(ip) = aaaa.data() {
+            assert_eq!(ip.to_string(), "fd00::10");
+        } else {
+            panic!("Expected AAAA record");
+        }
+
+        // IPv4のみのAUTOレコードはAAAAに応答しない
+        let v4_only = DbRecord {
+            content: "192.168.1.10".to_string(),
+            ..record
+        };
+        assert!(build_dns_record(&query_name, RecordType::AAAA, &v4_only).is_none());
+    }
+
     #[test]
     fn test_build_aaaa_record() {
         let query_name = Name::from_str("app.local.test").unwrap();
@@ -130,9 +521,19 @@ mod tests {
             content: "::1".to_string(),
             ttl: 60,
             active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
         };
 
-        let dns_record = build_dns_record(&query_name, &record).unwrap();
+        let dns_record = build_dns_record(&query_name, RecordType::AAAA, &record).unwrap();
 
         if let RData::AAAA(ip) = dns_record.data() {
             assert_eq!(ip.to_string(), "::1");
@@ -151,9 +552,19 @@ mod tests {
             content: "target.local.test".to_string(),
             ttl: 60,
             active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
         };
 
-        let dns_record = build_dns_record(&query_name, &record).unwrap();
+        let dns_record = build_dns_record(&query_name, RecordType::CNAME, &record).unwrap();
 
         if let RData::CNAME(cname) = dns_record.data() {
             // hickory-serverのCNAMEは末尾にドットを付けない
@@ -177,9 +588,221 @@ mod tests {
             content: "invalid-ip".to_string(),
             ttl: 60,
             active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
         };
 
-        let dns_record = build_dns_record(&query_name, &record);
+        let dns_record = build_dns_record(&query_name, RecordType::A, &record);
         assert!(dns_record.is_none());
     }
+
+    #[test]
+    fn test_build_https_record_with_alpn() {
+        let query_name = Name::from_str("app.local.test").unwrap();
+        let record = DbRecord {
+            id: 1,
+            domain_pattern: "app.local.test".to_string(),
+            record_type: "HTTPS".to_string(),
+            content: "1 . alpn=h2,h3".to_string(),
+            ttl: 60,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        let dns_record = build_dns_record(&query_name, RecordType::HTTPS, &record).unwrap();
+        assert_eq!(dns_record.record_type(), RecordType::HTTPS);
+
+        if let RData::HTTPS(HTTPS(svcb)) = dns_record.data() {
+            assert_eq!(svcb.svc_priority(), 1);
+            assert!(svcb.target_name().is_root());
+            assert_eq!(
+                svcb.svc_params(),
+                &[(
+                    SvcParamKey::Alpn,
+                    SvcParamValue::Alpn(Alpn(vec!["h2".to_string(), "h3".to_string()]))
+                )]
+            );
+        } else {
+            panic!("Expected HTTPS record");
+        }
+    }
+
+    #[test]
+    fn test_parse_svcb_content_invalid() {
+        assert!(parse_svcb_content("").is_err());
+        assert!(parse_svcb_content("abc .").is_err());
+        assert!(parse_svcb_content("1 . alpn=").is_err());
+        assert!(parse_svcb_content("1 . port=443").is_err());
+    }
+
+    #[test]
+    fn test_build_ns_record() {
+        let query_name = Name::from_str("sub.local.test").unwrap();
+        let record = DbRecord {
+            id: 1,
+            domain_pattern: "sub.local.test".to_string(),
+            record_type: "NS".to_string(),
+            content: "ns1.sub.local.test".to_string(),
+            ttl: 300,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        let dns_record = build_dns_record(&query_name, RecordType::NS, &record).unwrap();
+        assert_eq!(dns_record.ttl(), 300);
+
+        if let RData::NS(ns) = dns_record.data() {
+            assert_eq!(ns.0, Name::from_str("ns1.sub.local.test").unwrap());
+        } else {
+            panic!("Expected NS record");
+        }
+    }
+
+    #[test]
+    fn test_build_caa_record() {
+        use hickory_server::proto::rr::rdata::caa::{Property, Value};
+
+        let query_name = Name::from_str("local.test").unwrap();
+        let record = DbRecord {
+            id: 1,
+            domain_pattern: "local.test".to_string(),
+            record_type: "CAA".to_string(),
+            content: "0 issue \"letsencrypt.org\"".to_string(),
+            ttl: 3600,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        let dns_record = build_dns_record(&query_name, RecordType::CAA, &record).unwrap();
+        assert_eq!(dns_record.record_type(), RecordType::CAA);
+
+        if let RData::CAA(caa) = dns_record.data() {
+            assert!(!caa.issuer_critical());
+            assert_eq!(caa.tag(), &Property::Issue);
+            assert_eq!(
+                caa.value(),
+                &Value::Issuer(Some(Name::from_str("letsencrypt.org").unwrap()), vec![])
+            );
+        } else {
+            panic!("Expected CAA record");
+        }
+    }
+
+    #[test]
+    fn test_parse_caa_content() {
+        use hickory_server::proto::rr::rdata::caa::{Property, Value};
+
+        let caa = parse_caa_content("128 issuewild \"ca.local.test; account=123\"").unwrap();
+        assert!(caa.issuer_critical());
+        assert_eq!(caa.tag(), &Property::IssueWild);
+        assert_eq!(
+            caa.value(),
+            &Value::Issuer(
+                Some(Name::from_str("ca.local.test").unwrap()),
+                vec![KeyValue::new("account", "123")]
+            )
+        );
+
+        // 発行者が空の場合は発行禁止
+        let caa = parse_caa_content("0 issue \";\"").unwrap();
+        assert_eq!(caa.value(), &Value::Issuer(None, vec![]));
+
+        let caa = parse_caa_content("0 iodef \"mailto:security@local.test\"").unwrap();
+        assert_eq!(caa.tag(), &Property::Iodef);
+        assert!(matches!(caa.value(), Value::Url(url) if url.scheme() == "mailto"));
+
+        assert!(parse_caa_content("").is_err());
+        assert!(parse_caa_content("1 issue \"ca.local.test\"").is_err());
+        assert!(parse_caa_content("0 contactemail \"admin@local.test\"").is_err());
+        assert!(parse_caa_content("0 iodef \"not a url\"").is_err());
+        assert!(parse_caa_content("0 issue").is_err());
+    }
+
+    #[test]
+    fn test_build_tlsa_record() {
+        let query_name = Name::from_str("_443._tcp.corp.local").unwrap();
+        let digest = "ab".repeat(32);
+        let record = DbRecord {
+            id: 1,
+            domain_pattern: "_443._tcp.corp.local".to_string(),
+            record_type: "TLSA".to_string(),
+            content: format!("3 1 1 {}", digest),
+            ttl: 3600,
+            active: 1,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: String::new(),
+            deleted_at: None,
+            hit_count: 0,
+            last_hit_at: None,
+            source: None,
+        };
+
+        let dns_record = build_dns_record(&query_name, RecordType::TLSA, &record).unwrap();
+        assert_eq!(dns_record.record_type(), RecordType::TLSA);
+
+        if let RData::TLSA(tlsa) = dns_record.data() {
+            assert_eq!(u8::from(tlsa.cert_usage()), 3);
+            assert_eq!(u8::from(tlsa.selector()), 1);
+            assert_eq!(u8::from(tlsa.matching()), 1);
+            assert_eq!(tlsa.cert_data(), &[0xab; 32][..]);
+        } else {
+            panic!("Expected TLSA record");
+        }
+    }
+
+    #[test]
+    fn test_parse_tlsa_content() {
+        // 16進データは大文字・空白区切りも受け付ける
+        let tlsa = parse_tlsa_content("2 0 0 DEAD beef").unwrap();
+        assert_eq!(u8::from(tlsa.cert_usage()), 2);
+        assert_eq!(tlsa.cert_data(), &[0xde, 0xad, 0xbe, 0xef][..]);
+
+        assert!(parse_tlsa_content("").is_err());
+        assert!(parse_tlsa_content("3 1 1").is_err());
+        assert!(parse_tlsa_content("4 1 1 abcd").is_err());
+        assert!(parse_tlsa_content("3 2 0 abcd").is_err());
+        assert!(parse_tlsa_content("3 1 0 abc").is_err());
+        assert!(parse_tlsa_content("3 1 0 zz").is_err());
+        // SHA-256 は32バイト
+        assert!(parse_tlsa_content("3 1 1 abcd").is_err());
+    }
 }
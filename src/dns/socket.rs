@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use hickory_server::server::RequestHandler;
+use hickory_server::ServerFuture;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::{info, warn};
+
+/// DNS(TCP)接続の既定のタイムアウト（シャットダウン時に処理中の問い合わせを待つ猶予にも使う）
+pub const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// DNS用のUDPソケットを指定数バインド
+///
+/// `workers` が2以上の場合は SO_REUSEPORT を設定して同じアドレスに複数のソケットをバインドし、
+/// カーネルに受信の負荷分散を任せる。SO_REUSEPORT による負荷分散は Linux のみ対応のため、
+/// それ以外のOSでは警告を出して1つのソケットにフォールバックする。
+/// ポート0を指定した場合は最初のソケットに割り当てられたポートを残りのソケットでも使用する。
+pub fn bind_udp_sockets(addr: SocketAddr, workers: usize) -> Result<Vec<UdpSocket>> {
+    if workers > 1 && !cfg!(target_os = "linux") {
+        warn!(
+            "SO_REUSEPORT による負荷分散は Linux のみ対応のため、DNSワーカー数 {} を 1 として扱います",
+            workers
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    if workers > 1 {
+        let mut sockets = Vec::with_capacity(workers);
+        let mut bind_addr = addr;
+
+        for _ in 0..workers {
+            let socket = bind_reuse_port(bind_addr)?;
+            bind_addr = socket.local_addr()?;
+            sockets.push(UdpSocket::from_std(socket)?);
+        }
+
+        info!(
+            "SO_REUSEPORT で {} 個のUDPソケットをバインド: {}",
+            sockets.len(),
+            bind_addr
+        );
+        return Ok(sockets);
+    }
+
+    let socket = std::net::UdpSocket::bind(addr)
+        .context(format!("UDPソケットのバインドに失敗: {}", addr))?;
+    socket.set_nonblocking(true)?;
+    Ok(vec![UdpSocket::from_std(socket)?])
+}
+
+/// SO_REUSEADDR/SO_REUSEPORT を設定したUDPソケットを作成
+#[cfg(target_os = "linux")]
+fn bind_reuse_port(addr: SocketAddr) -> Result<std::net::UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))
+        .context("UDPソケットの作成に失敗")?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .context(format!("UDPソケットのバインドに失敗: {}", addr))?;
+
+    Ok(socket.into())
+}
+
+/// IPv6 の待ち受けアドレス設定（`::1` / `::` / `[::1]:5353` 形式）を解析
+///
+/// ポートを省略した場合は `default_port` を使用する。空の場合は IPv6 を使用しないため None を返す。
+pub fn parse_ipv6_listen_addr(value: &str, default_port: u16) -> Result<Option<SocketAddr>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    let addr = match value.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, default_port),
+        Err(_) => value
+            .parse::<SocketAddr>()
+            .context(format!("IPv6の待ち受けアドレスが不正: {}", value))?,
+    };
+    if !addr.is_ipv6() {
+        anyhow::bail!("IPv6アドレスではありません: {}", value);
+    }
+    Ok(Some(addr))
+}
+
+/// IPv6 用の UDP ソケットと TCP リスナーをバインド
+///
+/// IPV6_V6ONLY を設定し、`[::]` を指定しても IPv4 側の待ち受けと衝突しないようにする。
+/// ポート0を指定した場合は UDP に割り当てられたポートを TCP でも使用する。
+pub fn bind_ipv6_sockets(addr: SocketAddr) -> Result<(UdpSocket, TcpListener)> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let udp = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))
+        .context("UDPソケット(IPv6)の作成に失敗")?;
+    udp.set_only_v6(true)?;
+    udp.set_nonblocking(true)?;
+    udp.bind(&addr.into())
+        .context(format!("UDPソケットのバインドに失敗: {}", addr))?;
+    let udp: std::net::UdpSocket = udp.into();
+    let bound_addr = udp.local_addr()?;
+
+    let tcp = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))
+        .context("TCPソケット(IPv6)の作成に失敗")?;
+    tcp.set_only_v6(true)?;
+    tcp.set_reuse_address(true)?;
+    tcp.set_nonblocking(true)?;
+    tcp.bind(&bound_addr.into())
+        .context(format!("TCPソケットのバインドに失敗: {}", bound_addr))?;
+    tcp.listen(1024)?;
+    let tcp: std::net::TcpListener = tcp.into();
+
+    Ok((UdpSocket::from_std(udp)?, TcpListener::from_std(tcp)?))
+}
+
+/// DNSポートのバインド失敗を利用者向けのエラーに変換
+///
+/// 権限不足（1024未満のポートを一般ユーザーでバインドした場合など）は原因と対処方法を示すメッセージにし、
+/// それ以外は `DNSサーバー(UDP)のバインドに失敗` のような共通の文脈を付けて返す。
+pub fn bind_error(error: anyhow::Error, protocol: &str, addr: SocketAddr) -> anyhow::Error {
+    let permission_denied = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|cause| cause.kind() == std::io::ErrorKind::PermissionDenied);
+
+    if permission_denied {
+        anyhow::anyhow!(
+            "DNSサーバー({})を {} で待ち受ける権限がありません。\
+             1024未満のポートには特権が必要です。Linux では root で実行するか、\
+             `sudo setcap cap_net_bind_service=+ep <実行ファイル>` で CAP_NET_BIND_SERVICE を付与してください\
+             （Windows では管理者として実行してください）",
+            protocol,
+            addr
+        )
+    } else {
+        error.context(format!("DNSサーバー({})のバインドに失敗", protocol))
+    }
+}
+
+/// DNSサーバーを停止し、処理中の問い合わせの完了を待つ
+///
+/// 新しい接続・問い合わせの受け付けを止めたうえで、処理中の問い合わせは `grace` まで完了を待つ。
+/// 猶予内に終わらなかった問い合わせは警告を出して打ち切る（`ServerFuture` の破棄時に中断される）。
+pub async fn drain_dns_server<T: RequestHandler>(server: &mut ServerFuture<T>, grace: Duration) {
+    match tokio::time::timeout(grace, server.shutdown_gracefully()).await {
+        Ok(Ok(())) => info!("DNSサーバー停止完了"),
+        Ok(Err(e)) => warn!("DNSサーバーの停止中にエラーが発生: {}", e),
+        Err(_) => warn!(
+            "{}秒以内に完了しなかったDNSの問い合わせを打ち切ります",
+            grace.as_secs()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_server::authority::MessageResponseBuilder;
+    use hickory_server::proto::op::{Header, Message, MessageType, OpCode, Query};
+    use hickory_server::proto::rr::{Name, RecordType};
+    use hickory_server::server::{Request, ResponseHandler, ResponseInfo};
+    use std::str::FromStr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// 応答までに時間のかかるハンドラー
+    struct SlowHandler;
+
+    #[async_trait::async_trait]
+    impl RequestHandler for SlowHandler {
+        async fn handle_request<R: ResponseHandler>(
+            &self,
+            request: &Request,
+            mut response_handle: R,
+        ) -> ResponseInfo {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            let header = Header::response_from_request(request.header());
+            let response =
+                MessageResponseBuilder::from_message_request(request).build_no_records(header);
+            response_handle.send_response(response).await.unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_single_socket() {
+        let sockets = bind_udp_sockets(SocketAddr::from(([127, 0, 0, 1], 0)), 1).unwrap();
+        assert_eq!(sockets.len(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_multiple_sockets_reuse_port() {
+        let sockets = bind_udp_sockets(SocketAddr::from(([127, 0, 0, 1], 0)), 4).unwrap();
+        assert_eq!(sockets.len(), 4);
+
+        // 全てのソケットが同じアドレスを共有している
+        let addr = sockets[0].local_addr().unwrap();
+        for socket in &sockets {
+            assert_eq!(socket.local_addr().unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn test_parse_ipv6_listen_addr() {
+        assert_eq!(parse_ipv6_listen_addr("", 53).unwrap(), None);
+        assert_eq!(
+            parse_ipv6_listen_addr("::1", 53).unwrap(),
+            Some("[::1]:53".parse().unwrap())
+        );
+        assert_eq!(
+            parse_ipv6_listen_addr(" [::]:5353 ", 53).unwrap(),
+            Some("[::]:5353".parse().unwrap())
+        );
+        assert!(parse_ipv6_listen_addr("127.0.0.1", 53).is_err());
+        assert!(parse_ipv6_listen_addr("localhost", 53).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_ipv4_and_ipv6_sockets() {
+        // IPv6 が使えない環境ではスキップ
+        if std::net::UdpSocket::bind("[::1]:0").is_err() {
+            return;
+        }
+
+        let v4 = bind_udp_sockets(SocketAddr::from(([127, 0, 0, 1], 0)), 1).unwrap();
+        let (udp, tcp) = bind_ipv6_sockets("[::1]:0".parse().unwrap()).unwrap();
+
+        assert!(v4[0].local_addr().unwrap().is_ipv4());
+        let udp_addr = udp.local_addr().unwrap();
+        assert!(udp_addr.is_ipv6());
+        assert_eq!(tcp.local_addr().unwrap(), udp_addr);
+    }
+
+    #[test]
+    fn test_bind_error_permission_denied() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 53));
+        let denied = anyhow::Error::from(std::io::Error::from(
+            std::io::ErrorKind::PermissionDenied,
+        ))
+        .context("UDPソケットのバインドに失敗");
+
+        let message = bind_error(denied, "UDP", addr).to_string();
+        assert!(message.contains("127.0.0.1:53"));
+        assert!(message.contains("CAP_NET_BIND_SERVICE"));
+
+        // 権限以外のエラーは共通の文脈を付けて元の原因を残す
+        let in_use = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::AddrInUse));
+        let error = bind_error(in_use, "TCP", addr);
+        assert_eq!(error.to_string(), "DNSサーバー(TCP)のバインドに失敗");
+        assert!(error.chain().any(|cause| cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::AddrInUse)));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_in_flight_tcp_query() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = ServerFuture::new(SlowHandler);
+        server.register_listener(listener, TCP_TIMEOUT);
+
+        let mut message = Message::new();
+        message
+            .set_id(4321)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query);
+        message.add_query(Query::query(
+            Name::from_str("slow.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let bytes = message.to_vec().unwrap();
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(&(bytes.len() as u16).to_be_bytes())
+            .await
+            .unwrap();
+        stream.write_all(&bytes).await.unwrap();
+        let client = tokio::spawn(async move {
+            let len = stream.read_u16().await.unwrap() as usize;
+            let mut response = vec![0u8; len];
+            stream.read_exact(&mut response).await.unwrap();
+            Message::from_vec(&response).unwrap()
+        });
+
+        // 問い合わせの処理中にシャットダウンを開始する
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drain_dns_server(&mut server, TCP_TIMEOUT).await;
+
+        // 処理中だった問い合わせには応答が返る
+        let response = tokio::time::timeout(Duration::from_secs(1), client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.id(), 4321);
+
+        // 停止後は新しい接続を受け付けない
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+}
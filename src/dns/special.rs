@@ -0,0 +1,188 @@
+use hickory_server::proto::rr::rdata::PTR;
+use hickory_server::proto::rr::{Name, RData, Record as DnsRecord, RecordType};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// 特殊用途の名前に対する組み込み応答のTTL
+const SPECIAL_NAME_TTL: u32 = 300;
+
+/// 特殊用途の名前（RFC 6761 の `localhost` など）への組み込み応答
+///
+/// `localhost`（とそのサブドメイン）は 127.0.0.1 / ::1、`broadcasthost` は 255.255.255.255 を返し、
+/// ループバック・ブロードキャストアドレスの逆引きにはそれぞれの名前を返す。
+/// 該当する名前であれば、該当タイプのレコードが無い場合も空の Some を返す（上位DNSに転送しないため）。
+pub fn special_use_answer(
+    query_name: &str,
+    query: &Name,
+    record_type: RecordType,
+) -> Option<Vec<DnsRecord>> {
+    let name = query_name.trim_end_matches('.').to_ascii_lowercase();
+
+    let rdata = if name == "localhost" || name.ends_with(".localhost") {
+        match record_type {
+            RecordType::A => Some(RData::A(Ipv4Addr::LOCALHOST.into())),
+            RecordType::AAAA => Some(RData::AAAA(Ipv6Addr::LOCALHOST.into())),
+            _ => None,
+        }
+    } else if name == "broadcasthost" {
+        (record_type == RecordType::A).then(|| RData::A(Ipv4Addr::BROADCAST.into()))
+    } else {
+        let ip = reverse_name_to_ip(&name)?;
+        let target = if ip.is_loopback() {
+            "localhost."
+        } else if ip == IpAddr::V4(Ipv4Addr::BROADCAST) {
+            "broadcasthost."
+        } else {
+            return None;
+        };
+        (record_type == RecordType::PTR)
+            .then(|| Name::from_ascii(target).ok().map(|target| RData::PTR(PTR(target))))
+            .flatten()
+    };
+
+    Some(
+        rdata
+            .into_iter()
+            .map(|rdata| DnsRecord::from_rdata(query.clone(), SPECIAL_NAME_TTL, rdata))
+            .collect(),
+    )
+}
+
+/// プライベートアドレスの逆引きゾーン（RFC 6303）
+/// 公開の上位DNSでは解決できず、問い合わせると内部のアドレスが外部に漏れるため転送しない
+const PRIVATE_REVERSE_ZONES: &[&str] = &[
+    // 10.0.0.0/8
+    "10.in-addr.arpa",
+    // 172.16.0.0/12
+    "16.172.in-addr.arpa",
+    "17.172.in-addr.arpa",
+    "18.172.in-addr.arpa",
+    "19.172.in-addr.arpa",
+    "20.172.in-addr.arpa",
+    "21.172.in-addr.arpa",
+    "22.172.in-addr.arpa",
+    "23.172.in-addr.arpa",
+    "24.172.in-addr.arpa",
+    "25.172.in-addr.arpa",
+    "26.172.in-addr.arpa",
+    "27.172.in-addr.arpa",
+    "28.172.in-addr.arpa",
+    "29.172.in-addr.arpa",
+    "30.172.in-addr.arpa",
+    "31.172.in-addr.arpa",
+    // 192.168.0.0/16
+    "168.192.in-addr.arpa",
+    // 169.254.0.0/16（リンクローカル）
+    "254.169.in-addr.arpa",
+    // fc00::/7（ユニークローカル）
+    "c.f.ip6.arpa",
+    "d.f.ip6.arpa",
+    // fe80::/10（リンクローカル）
+    "8.e.f.ip6.arpa",
+    "9.e.f.ip6.arpa",
+    "a.e.f.ip6.arpa",
+    "b.e.f.ip6.arpa",
+];
+
+/// プライベートアドレスの逆引きゾーンに属する名前であれば、そのゾーン名を返す
+pub fn private_reverse_zone(query_name: &str) -> Option<&'static str> {
+    let name = query_name.trim_end_matches('.').to_ascii_lowercase();
+    PRIVATE_REVERSE_ZONES.iter().copied().find(|zone| {
+        name == *zone
+            || name
+                .strip_suffix(zone)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// 逆引き名（`1.0.0.127.in-addr.arpa` / `...ip6.arpa`）をIPアドレスに変換
+/// アドレス全体を表す名前でない場合（`127.in-addr.arpa` など）は None
+pub fn reverse_name_to_ip(name: &str) -> Option<IpAddr> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+
+    if let Some(labels) = name.strip_suffix(".in-addr.arpa") {
+        let octets: Vec<u8> = labels
+            .split('.')
+            .rev()
+            .map(|label| label.parse().ok())
+            .collect::<Option<_>>()?;
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        return Some(IpAddr::from(octets));
+    }
+
+    if let Some(labels) = name.strip_suffix(".ip6.arpa") {
+        let nibbles: String = labels
+            .split('.')
+            .rev()
+            .map(|label| (label.len() == 1).then_some(label))
+            .collect::<Option<_>>()?;
+        if nibbles.len() != 32 {
+            return None;
+        }
+        let value = u128::from_str_radix(&nibbles, 16).ok()?;
+        return Some(IpAddr::V6(Ipv6Addr::from(value)));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn answer(name: &str, record_type: RecordType) -> Option<Vec<DnsRecord>> {
+        special_use_answer(name, &Name::from_str(name).unwrap(), record_type)
+    }
+
+    #[test]
+    fn test_reverse_name_to_ip() {
+        assert_eq!(
+            reverse_name_to_ip("1.0.0.127.in-addr.arpa."),
+            Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+        assert_eq!(
+            reverse_name_to_ip(
+                "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.IP6.ARPA"
+            ),
+            Some(IpAddr::V6(Ipv6Addr::LOCALHOST))
+        );
+        assert_eq!(reverse_name_to_ip("127.in-addr.arpa"), None);
+        assert_eq!(reverse_name_to_ip("300.0.0.127.in-addr.arpa"), None);
+        assert_eq!(reverse_name_to_ip("app.local.test"), None);
+    }
+
+    #[test]
+    fn test_localhost_subdomain_and_other_types() {
+        let records = answer("app.localhost", RecordType::A).unwrap();
+        assert_eq!(records.len(), 1);
+
+        // 名前は存在するが該当タイプが無い場合は空（転送しない）
+        assert_eq!(answer("localhost", RecordType::MX).unwrap().len(), 0);
+        assert!(answer("localhost.example.com", RecordType::A).is_none());
+        assert!(answer("1.1.168.192.in-addr.arpa", RecordType::PTR).is_none());
+    }
+
+    #[test]
+    fn test_private_reverse_zone() {
+        assert_eq!(
+            private_reverse_zone("1.0.0.10.in-addr.arpa."),
+            Some("10.in-addr.arpa")
+        );
+        assert_eq!(private_reverse_zone("10.IN-ADDR.ARPA"), Some("10.in-addr.arpa"));
+        assert_eq!(
+            private_reverse_zone("5.1.31.172.in-addr.arpa"),
+            Some("31.172.in-addr.arpa")
+        );
+        assert_eq!(
+            private_reverse_zone("1.1.168.192.in-addr.arpa"),
+            Some("168.192.in-addr.arpa")
+        );
+        assert_eq!(private_reverse_zone("1.0.0.d.f.ip6.arpa"), Some("d.f.ip6.arpa"));
+
+        // 公開アドレスや範囲外は対象外
+        assert_eq!(private_reverse_zone("8.8.8.8.in-addr.arpa"), None);
+        assert_eq!(private_reverse_zone("1.0.32.172.in-addr.arpa"), None);
+        assert_eq!(private_reverse_zone("110.in-addr.arpa"), None);
+        assert_eq!(private_reverse_zone("app.local.test"), None);
+    }
+}
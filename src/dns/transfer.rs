@@ -0,0 +1,426 @@
+use crate::db::{replace_records_from_source, CreateRecordRequest, DbPool, PatternType};
+use crate::dns::RecordCache;
+use crate::validation::{validate_record, MAX_TTL};
+use anyhow::{bail, Context, Result};
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::rdata::SOA;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use hickory_proto::serialize::binary::BinDecodable;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+/// ゾーン転送全体のタイムアウト
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 再取得間隔の下限（SOAのrefreshが極端に短い場合の保護）
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 転送失敗時の再試行間隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// AXFRで取得したゾーン
+#[derive(Debug)]
+pub struct TransferredZone {
+    pub soa: SOA,
+    /// SOA以外のレコード
+    pub records: Vec<Record>,
+}
+
+/// マスターサーバーからのゾーン転送（AXFR）
+#[derive(Clone, Debug)]
+pub struct ZoneTransfer {
+    master: SocketAddr,
+    zone: Name,
+}
+
+impl ZoneTransfer {
+    pub fn new(master: SocketAddr, zone: &str) -> Result<Self> {
+        let zone = Name::from_str(&format!("{}.", zone.trim().trim_end_matches('.')))
+            .context(format!("ゾーン名のパースに失敗: {}", zone))?;
+        Ok(Self { master, zone })
+    }
+
+    /// 転送したレコードの登録元（再取得時の置き換え対象の識別に使い、同じ値をタグにも付ける）
+    pub fn source(&self) -> String {
+        format!("axfr:{}", name_to_pattern(&self.zone))
+    }
+
+    /// マスターからゾーン全体を取得
+    pub async fn fetch(&self) -> Result<TransferredZone> {
+        tokio::time::timeout(TRANSFER_TIMEOUT, self.fetch_inner())
+            .await
+            .context(format!("ゾーン転送がタイムアウト: {}", self.master))?
+    }
+
+    async fn fetch_inner(&self) -> Result<TransferredZone> {
+        let mut stream = TcpStream::connect(self.master)
+            .await
+            .context(format!("マスターへの接続に失敗: {}", self.master))?;
+
+        // AXFR問い合わせを送信（TCPは2バイトの長さ前置）
+        let mut message = Message::new();
+        let id = rand::random::<u16>();
+        message.set_id(id);
+        message.set_message_type(MessageType::Query);
+        message.set_op_code(OpCode::Query);
+        message.add_query(Query::query(self.zone.clone(), RecordType::AXFR));
+        let request_bytes = message.to_vec()?;
+        stream.write_u16(request_bytes.len() as u16).await?;
+        stream.write_all(&request_bytes).await?;
+
+        // 先頭と末尾のSOAに挟まれたレコードを、複数メッセージにわたって受信する
+        let mut soa: Option<SOA> = None;
+        let mut records = Vec::new();
+        loop {
+            let len = stream.read_u16().await? as usize;
+            let mut buf = vec![0u8; len];
+            stream.read_exact(&mut buf).await?;
+            let response = Message::from_bytes(&buf)?;
+
+            if response.id() != id {
+                bail!(
+                    "ゾーン転送の応答のIDが問い合わせと一致しません: {} ({} != {})",
+                    self.zone,
+                    response.id(),
+                    id
+                );
+            }
+            if response.response_code() != ResponseCode::NoError {
+                bail!(
+                    "ゾーン転送が拒否されました: {} ({})",
+                    self.zone,
+                    response.response_code()
+                );
+            }
+            if response.answers().is_empty() {
+                bail!("ゾーン転送の応答が空です: {}", self.zone);
+            }
+
+            for record in response.answers() {
+                match (record.data(), &soa) {
+                    (RData::SOA(first), None) => soa = Some(first.clone()),
+                    (RData::SOA(_), Some(first)) => {
+                        debug!("ゾーン転送完了: {} ({} レコード)", self.zone, records.len());
+                        return Ok(TransferredZone {
+                            soa: first.clone(),
+                            records,
+                        });
+                    }
+                    (_, None) => bail!("ゾーン転送の応答がSOAで始まっていません: {}", self.zone),
+                    (_, Some(_)) => records.push(record.clone()),
+                }
+            }
+        }
+    }
+}
+
+/// 転送したゾーンでDBのレコードを置き換え、キャッシュを再読み込みする
+/// 次回の再取得までの間隔（SOAのrefresh）を返す
+pub async fn refresh_zone(
+    pool: &DbPool,
+    cache: &RecordCache,
+    transfer: &ZoneTransfer,
+) -> Result<Duration> {
+    let zone = transfer.fetch().await?;
+    let source = transfer.source();
+    // APIで登録する場合と同じ検証を通らないレコードは取り込まない
+    let requests: Vec<CreateRecordRequest> =
+        to_create_requests(&zone.records, &transfer.zone, &source)
+            .into_iter()
+            .filter(|request| match validate_record(request) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(
+                        "ゾーン転送: 不正なレコードを無視: {} {} ({:?})",
+                        request.domain_pattern, request.record_type, e
+                    );
+                    false
+                }
+            })
+            .collect();
+    let count = requests.len();
+
+    replace_records_from_source(pool, &source, requests).await?;
+    cache.reload().await?;
+
+    info!(
+        "ゾーン転送でレコードを更新: {} ({} 件, serial={})",
+        transfer.zone,
+        count,
+        zone.soa.serial()
+    );
+
+    let refresh = Duration::from_secs(zone.soa.refresh().max(0) as u64);
+    Ok(refresh.max(MIN_REFRESH_INTERVAL))
+}
+
+/// バックグラウンドでゾーン転送を繰り返す（SOAのrefresh間隔ごと）
+pub fn spawn_zone_refresh(pool: DbPool, cache: RecordCache, transfer: ZoneTransfer) {
+    tokio::spawn(async move {
+        loop {
+            let wait = match refresh_zone(&pool, &cache, &transfer).await {
+                Ok(refresh) => refresh,
+                Err(e) => {
+                    warn!("ゾーン転送に失敗: {} ({:?})", transfer.zone, e);
+                    RETRY_INTERVAL
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    });
+}
+
+/// 転送したレコードをDB登録用のリクエストに変換
+/// DBで扱えるタイプ（A / AAAA / CNAME / NS / CAA / TLSA）のみ取り込む。
+/// ゾーン頂点のNSは取り込まない（自身が応答するため、委任として扱わない）
+fn to_create_requests(records: &[Record], zone: &Name, tag: &str) -> Vec<CreateRecordRequest> {
+    records
+        .iter()
+        .filter(|record| !(record.record_type() == RecordType::NS && record.name() == zone))
+        .filter_map(|record| {
+            let (record_type, content) = match record.data() {
+                RData::A(a) => ("A", a.to_string()),
+                RData::AAAA(aaaa) => ("AAAA", aaaa.to_string()),
+                RData::CNAME(cname) => ("CNAME", name_to_pattern(&cname.0)),
+                RData::NS(ns) => ("NS", name_to_pattern(&ns.0)),
+                RData::CAA(caa) => ("CAA", caa.to_string()),
+                RData::TLSA(tlsa) => ("TLSA", tlsa.to_string()),
+                other => {
+                    debug!(
+                        "ゾーン転送: 未対応のタイプを無視: {} {}",
+                        record.name(),
+                        other.record_type()
+                    );
+                    return None;
+                }
+            };
+
+            // ワイルドカード（`*.`）はDBのパターン表記（`%.`）に変換
+            let name = name_to_pattern(record.name());
+            let domain_pattern = match name.strip_prefix("*.") {
+                Some(rest) => format!("%.{}", rest),
+                None => name,
+            };
+
+            Some(CreateRecordRequest {
+                domain_pattern,
+                record_type: record_type.to_string(),
+                content: content.into(),
+                // ゾーンでは NS などに数日のTTLもよく使うため、取り込めるよう上限に丸める
+                ttl: i64::from(record.ttl()).min(MAX_TTL),
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: vec![tag.to_string()],
+            })
+        })
+        .collect()
+}
+
+/// 名前をDB保存用の表記（小文字・末尾ドットなし）に変換
+fn name_to_pattern(name: &Name) -> String {
+    name.to_ascii()
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{create_record, get_record_by_id, get_records_by_tag, init_db};
+    use hickory_proto::rr::rdata::{A, CNAME, NS};
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpListener;
+
+    /// テスト用ゾーン（example.test）のレコード
+    fn zone_records() -> Vec<Record> {
+        let origin = Name::from_str("example.test.").unwrap();
+        let soa = SOA::new(
+            Name::from_str("ns1.example.test.").unwrap(),
+            Name::from_str("hostmaster.example.test.").unwrap(),
+            2024010101,
+            3600,
+            600,
+            86400,
+            300,
+        );
+
+        vec![
+            Record::from_rdata(origin.clone(), 3600, RData::SOA(soa.clone())),
+            Record::from_rdata(
+                origin.clone(),
+                3600,
+                RData::NS(NS(Name::from_str("ns1.example.test.").unwrap())),
+            ),
+            Record::from_rdata(
+                Name::from_str("www.example.test.").unwrap(),
+                300,
+                RData::A(A(Ipv4Addr::new(192, 0, 2, 10))),
+            ),
+            Record::from_rdata(
+                Name::from_str("*.apps.example.test.").unwrap(),
+                300,
+                RData::A(A(Ipv4Addr::new(192, 0, 2, 20))),
+            ),
+            Record::from_rdata(
+                Name::from_str("alias.example.test.").unwrap(),
+                300,
+                RData::CNAME(CNAME(Name::from_str("www.example.test.").unwrap())),
+            ),
+            // TTLが上限（86400秒）を超えるレコードは上限に丸めて取り込む
+            Record::from_rdata(
+                Name::from_str("long.example.test.").unwrap(),
+                604800,
+                RData::A(A(Ipv4Addr::new(192, 0, 2, 30))),
+            ),
+            Record::from_rdata(origin, 3600, RData::SOA(soa)),
+        ]
+    }
+
+    /// 受け付けたAXFRに対し、ゾーンを2通のメッセージに分けて返すマスターを起動する
+    async fn spawn_mock_master() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let len = stream.read_u16().await.unwrap() as usize;
+                let mut buf = vec![0u8; len];
+                stream.read_exact(&mut buf).await.unwrap();
+                let request = Message::from_bytes(&buf).unwrap();
+
+                let records = zone_records();
+                for chunk in records.chunks(3) {
+                    let mut response = Message::new();
+                    response.set_id(request.id());
+                    response.set_message_type(MessageType::Response);
+                    response.add_queries(request.queries().to_vec());
+                    response.add_answers(chunk.to_vec());
+                    let bytes = response.to_vec().unwrap();
+                    stream.write_u16(bytes.len() as u16).await.unwrap();
+                    stream.write_all(&bytes).await.unwrap();
+                }
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_refresh_zone_populates_records() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let master = spawn_mock_master().await;
+        let transfer = ZoneTransfer::new(master, "example.test").unwrap();
+
+        let refresh = refresh_zone(&pool, &cache, &transfer).await.unwrap();
+        assert_eq!(refresh, Duration::from_secs(3600));
+
+        // ゾーン頂点のNSは取り込まない
+        let records = get_records_by_tag(&pool, "axfr:example.test").await.unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records.iter().all(|record| record.record_type != "NS"));
+        let long = cache.find_matching_record("long.example.test", "A").await.unwrap();
+        assert_eq!(long.ttl, 86400);
+
+        // 転送したレコードで応答できる
+        let www = cache.find_matching_record("www.example.test", "A").await.unwrap();
+        assert_eq!(www.content, "192.0.2.10");
+        assert_eq!(www.ttl, 300);
+        let wildcard = cache.find_matching_record("foo.apps.example.test", "A").await.unwrap();
+        assert_eq!(wildcard.domain_pattern, "%.apps.example.test");
+        let alias = cache.find_matching_record("alias.example.test", "CNAME").await.unwrap();
+        assert_eq!(alias.content, "www.example.test");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_zone_replaces_previous_transfer() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let master = spawn_mock_master().await;
+        let transfer = ZoneTransfer::new(master, "example.test.").unwrap();
+
+        refresh_zone(&pool, &cache, &transfer).await.unwrap();
+        refresh_zone(&pool, &cache, &transfer).await.unwrap();
+
+        // 再取得しても重複しない
+        let records = get_records_by_tag(&pool, "axfr:example.test").await.unwrap();
+        assert_eq!(records.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_zone_keeps_user_records_with_same_tag() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let master = spawn_mock_master().await;
+        let transfer = ZoneTransfer::new(master, "example.test").unwrap();
+
+        // 利用者が転送と同じタグを付けて作成したレコード
+        let ids = create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "manual.example.test".to_string(),
+                record_type: "A".to_string(),
+                content: "192.0.2.99".into(),
+                ttl: 300,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: vec![transfer.source()],
+            },
+        )
+        .await
+        .unwrap();
+
+        refresh_zone(&pool, &cache, &transfer).await.unwrap();
+        refresh_zone(&pool, &cache, &transfer).await.unwrap();
+
+        assert!(get_record_by_id(&pool, ids[0]).await.unwrap().is_some());
+        let records = get_records_by_tag(&pool, "axfr:example.test").await.unwrap();
+        assert_eq!(records.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_rejects_mismatched_response_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let len = stream.read_u16().await.unwrap() as usize;
+                let mut buf = vec![0u8; len];
+                stream.read_exact(&mut buf).await.unwrap();
+                let request = Message::from_bytes(&buf).unwrap();
+
+                let mut response = Message::new();
+                response.set_id(request.id().wrapping_add(1));
+                response.set_message_type(MessageType::Response);
+                response.add_queries(request.queries().to_vec());
+                response.add_answers(zone_records());
+                let bytes = response.to_vec().unwrap();
+                stream.write_u16(bytes.len() as u16).await.unwrap();
+                stream.write_all(&bytes).await.unwrap();
+            }
+        });
+
+        let transfer = ZoneTransfer::new(addr, "example.test").unwrap();
+        assert!(transfer.fetch().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_fails_when_master_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let transfer = ZoneTransfer::new(addr, "example.test").unwrap();
+        assert!(transfer.fetch().await.is_err());
+    }
+}
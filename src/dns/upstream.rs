@@ -1,17 +1,265 @@
+use crate::db::{self, DbPool};
+use crate::dns::metrics::UpstreamMetrics;
 use anyhow::{Context, Result};
-use hickory_proto::op::Query;
-use hickory_proto::rr::{Name, RecordType};
-use std::net::SocketAddr;
+use hickory_proto::op::{Edns, Message, Query, ResponseCode};
+use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsOption};
+use hickory_proto::rr::{Name, RData, RecordType};
+use ipnet::IpNet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::str::FromStr;
-use std::time::Duration;
-use tracing::{debug, warn};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_native_tls::native_tls;
+use tracing::{debug, info, warn};
 
 /// 上位DNS設定
 #[derive(Clone, Debug)]
 pub struct UpstreamConfig {
-    pub primary: SocketAddr,
-    pub secondary: SocketAddr,
+    pub primary: UpstreamServer,
+    pub secondary: UpstreamServer,
+    /// 1回の送信あたりのタイムアウト
     pub timeout: Duration,
+    /// タイムアウト時に同じサーバーへ再送する回数
+    pub retries: u32,
+    /// 上位DNSへの問い合わせに使う送信元アドレス（既定は全インターフェース・任意ポート）
+    pub bind_addr: SocketAddr,
+    /// 上位DNSに送る EDNS Client Subnet（None の場合は送らない）
+    pub client_subnet: Option<EcsMode>,
+}
+
+/// EDNS Client Subnet（RFC 7871）で上位DNSに送るサブネット
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EcsMode {
+    /// 設定した固定のサブネットを送る
+    Fixed(IpNet),
+    /// 問い合わせ元のアドレスをプレフィックス長で切り詰めて送る（グローバルアドレスのみ）
+    Client { v4_prefix: u8, v6_prefix: u8 },
+}
+
+/// プライバシー保護のため送信するIPv4プレフィックス長の上限（RFC 7871 の推奨値）
+pub const MAX_ECS_PREFIX_V4: u8 = 24;
+/// プライバシー保護のため送信するIPv6プレフィックス長の上限（RFC 7871 の推奨値）
+pub const MAX_ECS_PREFIX_V6: u8 = 56;
+
+/// `upstream_ecs` 設定値（空 / `client` / `203.0.113.0/24` 形式）を解析
+/// プレフィックス長は上限を超える場合は上限に切り詰める
+pub fn parse_ecs(value: &str, v4_prefix: u8, v6_prefix: u8) -> Result<Option<EcsMode>> {
+    let v4_prefix = v4_prefix.min(MAX_ECS_PREFIX_V4);
+    let v6_prefix = v6_prefix.min(MAX_ECS_PREFIX_V6);
+
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if value.eq_ignore_ascii_case("client") {
+        return Ok(Some(EcsMode::Client {
+            v4_prefix,
+            v6_prefix,
+        }));
+    }
+
+    let net = match IpNet::from_str(value) {
+        Ok(net) => net,
+        Err(_) => {
+            let ip = IpAddr::from_str(value)
+                .context(format!("不正な EDNS Client Subnet: {}", value))?;
+            IpNet::from(ip)
+        }
+    };
+    let cap = match net {
+        IpNet::V4(_) => v4_prefix,
+        IpNet::V6(_) => v6_prefix,
+    };
+    let net = IpNet::new(net.addr(), net.prefix_len().min(cap))?.trunc();
+    Ok(Some(EcsMode::Fixed(net)))
+}
+
+/// 問い合わせ元として上位DNSに伝えて意味のあるアドレスか（プライベート・ループバックなどは除く）
+fn is_global_client(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast())
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+impl EcsMode {
+    /// 送信するサブネット（問い合わせ元から作れない場合は None）
+    pub fn subnet(&self, client_ip: Option<IpAddr>) -> Option<IpNet> {
+        match self {
+            EcsMode::Fixed(net) => Some(*net),
+            EcsMode::Client {
+                v4_prefix,
+                v6_prefix,
+            } => {
+                let ip = client_ip.filter(|ip| is_global_client(*ip))?;
+                let prefix = if ip.is_ipv4() { *v4_prefix } else { *v6_prefix };
+                IpNet::new(ip, prefix).ok().map(|net| net.trunc())
+            }
+        }
+    }
+}
+
+/// 再送回数のデフォルト値
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// ポート省略時の上位DNSのポート
+const DEFAULT_DNS_PORT: u16 = 53;
+/// ポート省略時の DNS over TLS のポート
+const DEFAULT_TLS_PORT: u16 = 853;
+/// ポート省略時の DNS over HTTPS のポート
+const DEFAULT_HTTPS_PORT: u16 = 443;
+/// パス省略時の DNS over HTTPS のパス
+const DEFAULT_HTTPS_PATH: &str = "/dns-query";
+/// DNS over HTTPS のメディアタイプ（RFC 8484）
+const DNS_MESSAGE_MEDIA_TYPE: &str = "application/dns-message";
+
+/// 上位DNSサーバーと通信方式
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UpstreamServer {
+    /// `udp://`（スキーム省略時も UDP）
+    Udp(SocketAddr),
+    /// `tcp://`
+    Tcp(SocketAddr),
+    /// `tls://`（DNS over TLS、RFC 7858）
+    Tls {
+        addr: SocketAddr,
+        /// 証明書の検証に使う名前（省略時はIPアドレス）
+        server_name: String,
+    },
+    /// `https://`（DNS over HTTPS、RFC 8484）
+    Https {
+        addr: SocketAddr,
+        /// 証明書の検証と Host ヘッダーに使う名前（省略時はIPアドレス）
+        server_name: String,
+        path: String,
+    },
+}
+
+impl UpstreamServer {
+    /// 接続先のアドレス
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            UpstreamServer::Udp(addr) | UpstreamServer::Tcp(addr) => *addr,
+            UpstreamServer::Tls { addr, .. } | UpstreamServer::Https { addr, .. } => *addr,
+        }
+    }
+}
+
+/// UDP は従来どおり `ip:port`、それ以外はスキーム付きで表示する（ログ・ヘルスチェック用）
+impl fmt::Display for UpstreamServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpstreamServer::Udp(addr) => write!(f, "{}", addr),
+            UpstreamServer::Tcp(addr) => write!(f, "tcp://{}", addr),
+            UpstreamServer::Tls { addr, .. } => write!(f, "tls://{}", addr),
+            UpstreamServer::Https { addr, path, .. } => write!(f, "https://{}{}", addr, path),
+        }
+    }
+}
+
+/// 上位DNSアドレスをパース
+/// `ip:port` に加え、ポートを省略した IP アドレスのみ（`8.8.8.8` など）も受け付け、53番ポートとみなす
+pub fn parse_upstream_addr(value: &str) -> Result<SocketAddr> {
+    parse_addr_with_default_port(value, DEFAULT_DNS_PORT)
+}
+
+/// `ip:port` / `ip` / `[IPv6]` 形式のアドレスをパース（ポート省略時は `default_port`）
+fn parse_addr_with_default_port(value: &str, default_port: u16) -> Result<SocketAddr> {
+    let value = value.trim();
+    if let Ok(addr) = SocketAddr::from_str(value) {
+        return Ok(addr);
+    }
+
+    let host = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(value);
+    let ip = IpAddr::from_str(host).context(format!("不正な上位DNSアドレス: {}", value))?;
+    Ok(SocketAddr::new(ip, default_port))
+}
+
+/// 上位DNSサーバーをパース
+/// `udp://` / `tcp://` / `tls://` / `https://` のスキームで通信方式を指定する（省略時は UDP）。
+/// アドレスはIPで指定し、`tls://` と `https://` は末尾の `#<名前>` で証明書の検証に使う名前を指定できる
+/// （例: `tls://1.1.1.1#cloudflare-dns.com`、`https://8.8.8.8/dns-query#dns.google`）
+pub fn parse_upstream_server(value: &str) -> Result<UpstreamServer> {
+    let value = value.trim();
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return Ok(UpstreamServer::Udp(parse_upstream_addr(value)?));
+    };
+    let scheme = scheme.to_ascii_lowercase();
+
+    let (rest, server_name) = match rest.split_once('#') {
+        Some((rest, name)) if !name.trim().is_empty() => (rest, Some(name.trim().to_string())),
+        Some(_) => anyhow::bail!("証明書の検証に使う名前が空です: {}", value),
+        None => (rest, None),
+    };
+    if server_name.is_some() && !matches!(scheme.as_str(), "tls" | "https") {
+        anyhow::bail!("{}:// には名前を指定できません: {}", scheme, value);
+    }
+
+    match scheme.as_str() {
+        "udp" => Ok(UpstreamServer::Udp(parse_upstream_addr(rest)?)),
+        "tcp" => Ok(UpstreamServer::Tcp(parse_upstream_addr(rest)?)),
+        "tls" => {
+            let addr = parse_addr_with_default_port(rest, DEFAULT_TLS_PORT)?;
+            Ok(UpstreamServer::Tls {
+                server_name: server_name.unwrap_or_else(|| addr.ip().to_string()),
+                addr,
+            })
+        }
+        "https" => {
+            let (authority, path) = match rest.find('/') {
+                Some(index) => rest.split_at(index),
+                None => (rest, DEFAULT_HTTPS_PATH),
+            };
+            let addr = parse_addr_with_default_port(authority, DEFAULT_HTTPS_PORT)?;
+            Ok(UpstreamServer::Https {
+                server_name: server_name.unwrap_or_else(|| addr.ip().to_string()),
+                path: path.to_string(),
+                addr,
+            })
+        }
+        _ => anyhow::bail!("対応していない通信方式です: {}", value),
+    }
+}
+
+/// 上位DNSへの送信元アドレスをパースし、実際にバインドできるか確認する
+/// IP アドレスのみ、または `ip:0` を受け付け、任意のポート（0）とみなす。
+/// 問い合わせごとにソケットをバインドするため、固定のポートは同時の問い合わせが衝突するので受け付けない
+pub fn parse_bind_addr(value: &str) -> Result<SocketAddr> {
+    let value = value.trim();
+    let addr = match SocketAddr::from_str(value) {
+        Ok(addr) => addr,
+        Err(_) => {
+            let ip = IpAddr::from_str(value)
+                .context(format!("不正な送信元アドレス: {}", value))?;
+            SocketAddr::new(ip, 0)
+        }
+    };
+    if addr.port() != 0 {
+        anyhow::bail!(
+            "送信元アドレスにポートは指定できません（IPアドレスのみ、または ポート 0 を指定）: {}",
+            value
+        );
+    }
+
+    // ホストに割り当てられていないアドレスは起動時に検出する
+    std::net::UdpSocket::bind(addr)
+        .context(format!("送信元アドレスにバインドできません: {}", addr))?;
+    Ok(addr)
 }
 
 impl UpstreamConfig {
@@ -21,61 +269,227 @@ impl UpstreamConfig {
         secondary: &str,
         timeout_ms: u64,
     ) -> Result<Self> {
-        let primary = SocketAddr::from_str(primary)
+        let primary = parse_upstream_server(primary)
             .context(format!("Primary DNS アドレスのパースに失敗: {}", primary))?;
 
-        let secondary = SocketAddr::from_str(secondary)
+        let secondary = parse_upstream_server(secondary)
             .context(format!("Secondary DNS アドレスのパースに失敗: {}", secondary))?;
 
         Ok(Self {
             primary,
             secondary,
             timeout: Duration::from_millis(timeout_ms),
+            retries: DEFAULT_RETRIES,
+            bind_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            client_subnet: None,
         })
     }
+
+    /// タイムアウト時の再送回数を設定
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// 上位DNSへの問い合わせに使う送信元アドレスを設定
+    pub fn with_bind_addr(mut self, bind_addr: SocketAddr) -> Self {
+        self.bind_addr = bind_addr;
+        self
+    }
+
+    /// 上位DNSに EDNS Client Subnet を送る
+    pub fn with_client_subnet(mut self, client_subnet: EcsMode) -> Self {
+        self.client_subnet = Some(client_subnet);
+        self
+    }
+}
+
+/// 上位DNSからの応答
+#[derive(Debug)]
+pub struct UpstreamResponse {
+    pub records: Vec<hickory_proto::rr::Record>,
+    /// 応答した上位DNSサーバー
+    pub server: UpstreamServer,
+    /// 上位DNSの応答コード（NoError / NXDomain。障害を示す応答コードはエラーとして扱う）
+    pub response_code: ResponseCode,
+    /// 回答が無い応答（NXDOMAIN / NODATA）の否定応答をキャッシュしてよい秒数
+    /// 権威セクションのSOAから求める（SOAが無い場合は None）
+    pub negative_ttl: Option<u32>,
+}
+
+impl UpstreamResponse {
+    fn new(message: Message, server: UpstreamServer) -> Self {
+        Self {
+            negative_ttl: negative_ttl(&message),
+            response_code: message.response_code(),
+            records: message.answers().to_vec(),
+            server,
+        }
+    }
+}
+
+/// 上位DNS自身の障害・拒否を示す応答コードか（次の上位DNSや古い応答で補う）
+fn is_failure_code(response_code: ResponseCode) -> bool {
+    matches!(
+        response_code,
+        ResponseCode::ServFail
+            | ResponseCode::Refused
+            | ResponseCode::FormErr
+            | ResponseCode::NotImp
+    )
+}
+
+/// 否定応答のTTL（RFC 2308: SOAレコード自体のTTLとSOAの MINIMUM の小さい方）
+fn negative_ttl(message: &Message) -> Option<u32> {
+    if !message.answers().is_empty() {
+        return None;
+    }
+    message.name_servers().iter().find_map(|record| match record.data() {
+        RData::SOA(soa) => Some(record.ttl().min(soa.minimum())),
+        _ => None,
+    })
+}
+
+/// 設定テーブルの上位DNS関連の設定（`upstream_*`）から上位DNS設定を作成
+/// 起動時と、APIで設定が変更された際の再構築に使う
+pub async fn load_config(pool: &DbPool) -> Result<UpstreamConfig> {
+    let primary = db::get_setting(pool, "upstream_primary")
+        .await?
+        .unwrap_or_else(|| "8.8.8.8:53".to_string());
+
+    let secondary = db::get_setting(pool, "upstream_secondary")
+        .await?
+        .unwrap_or_else(|| "1.1.1.1:53".to_string());
+
+    let timeout_ms = db::get_setting(pool, "upstream_timeout_ms")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(2000);
+
+    let retries = db::get_setting(pool, "upstream_retries")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_RETRIES);
+
+    let mut config = UpstreamConfig::new(&primary, &secondary, timeout_ms)
+        .context("上位DNS設定の初期化に失敗")?
+        .with_retries(retries);
+
+    // 上位DNSへの送信元アドレス（マルチホーム環境向け。空の場合はOSに任せる）
+    let bind_addr = db::get_setting(pool, "upstream_bind_addr")
+        .await?
+        .unwrap_or_default();
+    if !bind_addr.trim().is_empty() {
+        let bind_addr = parse_bind_addr(&bind_addr).context("upstream_bind_addr の検証に失敗")?;
+        config = config.with_bind_addr(bind_addr);
+        info!("上位DNSへの送信元アドレス: {}", bind_addr);
+    }
+
+    // 上位DNSに送る EDNS Client Subnet（空の場合は送らない）
+    let ecs = db::get_setting(pool, "upstream_ecs")
+        .await?
+        .unwrap_or_default();
+    let ecs_prefix_v4 = db::get_setting(pool, "upstream_ecs_prefix_v4")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(MAX_ECS_PREFIX_V4);
+    let ecs_prefix_v6 = db::get_setting(pool, "upstream_ecs_prefix_v6")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(MAX_ECS_PREFIX_V6);
+    match parse_ecs(&ecs, ecs_prefix_v4, ecs_prefix_v6) {
+        Ok(Some(ecs)) => {
+            info!("上位DNSに EDNS Client Subnet を送信: {:?}", ecs);
+            config = config.with_client_subnet(ecs);
+        }
+        Ok(None) => {}
+        Err(e) => warn!("upstream_ecs の値が不正なため ECS を送信しません: {:#}", e),
+    }
+
+    info!(
+        "上位DNS設定: Primary={}, Secondary={}, Timeout={}ms, Retries={}",
+        config.primary, config.secondary, timeout_ms, retries
+    );
+    Ok(config)
 }
 
 /// 上位DNSクライアント
 pub struct UpstreamResolver {
-    config: UpstreamConfig,
+    /// 設定（APIからの設定変更を再起動なしで反映するため差し替え可能にする）
+    config: RwLock<UpstreamConfig>,
+    metrics: UpstreamMetrics,
 }
 
 impl UpstreamResolver {
     pub fn new(config: UpstreamConfig) -> Self {
-        Self { config }
+        Self {
+            config: RwLock::new(config),
+            metrics: UpstreamMetrics::new(),
+        }
+    }
+
+    /// 現在の設定（問い合わせ中に設定が変わっても1回の問い合わせでは同じ設定を使う）
+    pub fn config(&self) -> UpstreamConfig {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// 設定を差し替え（以降の問い合わせから反映される。DNSハンドラとは同じリゾルバーを共有する）
+    pub fn set_config(&self, config: UpstreamConfig) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+    }
+
+    /// 上位DNSサーバーごとのメトリクス（クローンは同じ統計を共有する）
+    pub fn metrics(&self) -> UpstreamMetrics {
+        self.metrics.clone()
+    }
+
+    /// 問い合わせ元に応じて送る EDNS Client Subnet（`upstream_ecs = client` の場合のみ）
+    /// 応答が問い合わせ元のサブネットごとに異なりうるため、上位DNS応答キャッシュのキーに使う
+    pub fn cache_scope(&self, client_ip: Option<IpAddr>) -> Option<IpNet> {
+        match self.config().client_subnet {
+            Some(mode @ EcsMode::Client { .. }) => mode.subnet(client_ip),
+            _ => None,
+        }
     }
 
     /// 上位DNSに問い合わせ
+    /// 受け取ったレコードタイプをそのまま上位に転送し、応答レコードは（クラスも含め）加工せずに返す
+    /// `checking_disabled` はクライアントのCDフラグをそのまま上位に引き継ぐ。
+    /// `client_ip` は EDNS Client Subnet を問い合わせ元から作る設定の場合に使う
     pub async fn query(
         &self,
         query_name: &str,
-        record_type: &str,
-    ) -> Result<Vec<hickory_proto::rr::Record>> {
+        rtype: RecordType,
+        checking_disabled: bool,
+        client_ip: Option<IpAddr>,
+    ) -> Result<UpstreamResponse> {
         debug!(
             "上位DNS問い合わせ: {} ({})",
-            query_name, record_type
+            query_name, rtype
         );
 
-        // レコードタイプをパース
-        let rtype = match record_type {
-            "A" => RecordType::A,
-            "AAAA" => RecordType::AAAA,
-            "CNAME" => RecordType::CNAME,
-            _ => {
-                warn!("サポートされていないレコードタイプ: {}", record_type);
-                return Ok(vec![]);
-            }
-        };
+        // 未知のタイプも汎用的にそのまま転送する（判別できるよう警告のみ出す）
+        if let RecordType::Unknown(code) = rtype {
+            warn!("未知のレコードタイプを転送: TYPE{} ({})", code, query_name);
+        }
 
         // ドメイン名をパース
         let name = Name::from_str(query_name)
             .context(format!("ドメイン名のパースに失敗: {}", query_name))?;
 
+        let config = self.config();
+
         // まずプライマリDNSに問い合わせ
-        match self.query_upstream(self.config.primary, &name, rtype).await {
-            Ok(records) => {
-                debug!("プライマリDNSから応答を取得: {} レコード", records.len());
-                return Ok(records);
+        match self
+            .query_upstream(&config.primary, &name, rtype, checking_disabled, client_ip)
+            .await {
+            Ok(message) => {
+                let response = UpstreamResponse::new(message, config.primary.clone());
+                debug!("プライマリDNSから応答を取得: {} レコード", response.records.len());
+                return Ok(response);
             }
             Err(e) => {
                 warn!("プライマリDNSへの問い合わせ失敗: {}", e);
@@ -83,10 +497,13 @@ impl UpstreamResolver {
         }
 
         // プライマリが失敗した場合、セカンダリDNSに問い合わせ
-        match self.query_upstream(self.config.secondary, &name, rtype).await {
-            Ok(records) => {
-                debug!("セカンダリDNSから応答を取得: {} レコード", records.len());
-                Ok(records)
+        match self
+            .query_upstream(&config.secondary, &name, rtype, checking_disabled, client_ip)
+            .await {
+            Ok(message) => {
+                let response = UpstreamResponse::new(message, config.secondary.clone());
+                debug!("セカンダリDNSから応答を取得: {} レコード", response.records.len());
+                Ok(response)
             }
             Err(e) => {
                 warn!("セカンダリDNSへの問い合わせ失敗: {}", e);
@@ -95,58 +512,312 @@ impl UpstreamResolver {
         }
     }
 
-    /// 指定した上位DNSに問い合わせ
+    /// 各上位DNSへの疎通確認（ルートのNSを再送なしで1回だけ問い合わせる）
+    pub async fn probe(&self) -> Vec<(UpstreamServer, bool)> {
+        let config = self.config();
+        let root = Name::root();
+        let check = |server: UpstreamServer| {
+            let root = root.clone();
+            async move {
+                let reachable = tokio::time::timeout(
+                    config.timeout,
+                    self.exchange(&server, &root, RecordType::NS, false, None),
+                )
+                .await
+                .is_ok_and(|result| result.is_ok());
+                (server, reachable)
+            }
+        };
+
+        let (primary, secondary) = tokio::join!(
+            check(config.primary.clone()),
+            check(config.secondary.clone())
+        );
+        vec![primary, secondary]
+    }
+
+    /// 指定した上位DNSに問い合わせ、サーバーごとの応答時間・失敗数を記録
+    /// SERVFAIL・REFUSED などの障害を示す応答はエラーとして扱う
     async fn query_upstream(
         &self,
+        server: &UpstreamServer,
+        name: &Name,
+        rtype: RecordType,
+        checking_disabled: bool,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Message> {
+        let start = Instant::now();
+        let result = self
+            .exchange(server, name, rtype, checking_disabled, client_ip)
+            .await
+            .and_then(|message| match message.response_code() {
+                code if is_failure_code(code) => {
+                    Err(anyhow::anyhow!("上位DNSがエラーを応答: {} ({})", server, code))
+                }
+                _ => Ok(message),
+            });
+
+        match &result {
+            Ok(_) => self.metrics.observe_latency(server.addr(), start.elapsed()),
+            Err(_) => self.metrics.record_failure(server.addr()),
+        }
+
+        result
+    }
+
+    /// 設定された送信元アドレスで上位DNS用のUDPソケットを作成
+    async fn bind_socket(bind_addr: SocketAddr) -> Result<tokio::net::UdpSocket> {
+        tokio::net::UdpSocket::bind(bind_addr)
+            .await
+            .context(format!("送信元アドレスへのバインドに失敗: {}", bind_addr))
+    }
+
+    /// 設定された送信元アドレスから上位DNSにTCPで接続（送信元の指定が無い場合はOSに任せる）
+    async fn connect_tcp(bind_addr: SocketAddr, server: SocketAddr) -> Result<TcpStream> {
+        if bind_addr.ip().is_unspecified() && bind_addr.port() == 0 {
+            return TcpStream::connect(server)
+                .await
+                .context(format!("上位DNSへの接続に失敗: {}", server));
+        }
+
+        let socket = if bind_addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket
+            .bind(bind_addr)
+            .context(format!("送信元アドレスへのバインドに失敗: {}", bind_addr))?;
+        socket
+            .connect(server)
+            .await
+            .context(format!("上位DNSへの接続に失敗: {}", server))
+    }
+
+    /// 上位DNSにTLSで接続し、証明書を `server_name` で検証する
+    async fn connect_tls(
+        bind_addr: SocketAddr,
         server: SocketAddr,
+        server_name: &str,
+    ) -> Result<tokio_native_tls::TlsStream<TcpStream>> {
+        let stream = Self::connect_tcp(bind_addr, server).await?;
+        let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+        connector
+            .connect(server_name, stream)
+            .await
+            .context(format!("上位DNSとのTLSハンドシェイクに失敗: {} ({})", server, server_name))
+    }
+
+    /// 指定した上位DNSとメッセージを送受信し、応答メッセージを返す
+    /// UDP は同じソケットで再送し、接続型の通信方式（TCP / TLS / HTTPS）は試行ごとに接続し直す
+    async fn exchange(
+        &self,
+        server: &UpstreamServer,
         name: &Name,
         rtype: RecordType,
-    ) -> Result<Vec<hickory_proto::rr::Record>> {
-        use hickory_proto::op::{Message, MessageType};
+        checking_disabled: bool,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Message> {
+        use hickory_proto::op::MessageType;
         use hickory_proto::serialize::binary::BinDecodable;
-        use tokio::net::UdpSocket;
+
+        let config = self.config();
 
         // UDPソケットを作成
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.connect(server).await?;
+        let socket = match server {
+            UpstreamServer::Udp(addr) => {
+                let socket = Self::bind_socket(config.bind_addr).await?;
+                socket.connect(*addr).await?;
+                Some(socket)
+            }
+            _ => None,
+        };
 
         // DNS問い合わせメッセージを作成
         let mut message = Message::new();
-        let id = rand::random::<u16>();
+        // DNS over HTTPS はHTTPのキャッシュが効くようIDを0にする（RFC 8484 4.1）
+        let id = match server {
+            UpstreamServer::Https { .. } => 0,
+            _ => rand::random::<u16>(),
+        };
         message.set_id(id);
         message.set_message_type(MessageType::Query);
         message.set_op_code(hickory_proto::op::OpCode::Query);
         message.set_recursion_desired(true);
+        message.set_checking_disabled(checking_disabled);
 
         let query = Query::query(name.clone(), rtype);
-        message.add_query(query);
+        message.add_query(query.clone());
+
+        // EDNS Client Subnet をOPTレコードに付ける
+        if let Some(subnet) = config
+            .client_subnet
+            .as_ref()
+            .and_then(|mode| mode.subnet(client_ip))
+        {
+            let mut edns = Edns::new();
+            edns.set_max_payload(4096);
+            edns.options_mut().insert(EdnsOption::Subnet(ClientSubnet::new(
+                subnet.addr(),
+                subnet.prefix_len(),
+                0,
+            )));
+            message.set_edns(edns);
+        }
 
         // メッセージをバイト列にエンコード
         let request_bytes = message.to_vec()?;
 
-        // タイムアウト付きで送受信
-        let result = tokio::time::timeout(
-            self.config.timeout,
-            async {
-                // リクエスト送信
-                socket.send(&request_bytes).await?;
+        // タイムアウト付きで送受信（タイムアウト時は設定回数まで再送）
+        let mut attempt = 0;
+        let result = loop {
+            attempt += 1;
+
+            let received = tokio::time::timeout(config.timeout, async {
+                match &socket {
+                    Some(socket) => {
+                        // リクエスト送信
+                        socket.send(&request_bytes).await?;
+
+                        // 問い合わせと対応しない応答（偽装・遅れて届いた以前の応答）は読み捨てて待ち続ける
+                        loop {
+                            // レスポンス受信（EDNSの最大サイズを考慮して4096バイト）
+                            let mut response_bytes = vec![0u8; 4096];
+                            let len = socket.recv(&mut response_bytes).await?;
+                            response_bytes.truncate(len);
 
-                // レスポンス受信（EDNSの最大サイズを考慮して4096バイト）
-                let mut response_bytes = vec![0u8; 4096];
-                let len = socket.recv(&mut response_bytes).await?;
-                response_bytes.truncate(len);
+                            match Message::from_bytes(&response_bytes) {
+                                Ok(response) if is_response_to(&response, id, &query) => {
+                                    break Ok::<Message, anyhow::Error>(response);
+                                }
+                                _ => warn!("問い合わせと対応しない上位DNSの応答を破棄: {}", server),
+                            }
+                        }
+                    }
+                    None => {
+                        let response_bytes =
+                            Self::exchange_connected(config.bind_addr, server, &request_bytes)
+                                .await?;
 
-                // レスポンスをデコード
-                let response = Message::from_bytes(&response_bytes)?;
-                Ok::<Message, anyhow::Error>(response)
+                        // レスポンスをデコード
+                        let response = Message::from_bytes(&response_bytes)?;
+                        if !is_response_to(&response, id, &query) {
+                            anyhow::bail!("問い合わせと対応しない上位DNSの応答: {}", server);
+                        }
+                        Ok(response)
+                    }
+                }
+            })
+            .await;
+
+            match received {
+                Ok(response) => break response?,
+                Err(_) if attempt <= config.retries => {
+                    debug!(
+                        "上位DNS {} への問い合わせがタイムアウト、再送します ({}/{})",
+                        server, attempt, config.retries
+                    );
+                }
+                Err(_) => {
+                    anyhow::bail!(
+                        "上位DNSへの問い合わせがタイムアウト: {} ({}回試行)",
+                        server,
+                        attempt
+                    );
+                }
             }
-        )
-        .await
-        .context("上位DNSへの問い合わせがタイムアウト")??;
+        };
 
-        // レスポンスから答えを抽出
-        Ok(result.answers().to_vec())
+        Ok(result)
     }
+
+    /// 接続型の通信方式で1回分の問い合わせを送受信し、応答のバイト列を返す
+    async fn exchange_connected(
+        bind_addr: SocketAddr,
+        server: &UpstreamServer,
+        request: &[u8],
+    ) -> Result<Vec<u8>> {
+        match server {
+            UpstreamServer::Udp(addr) => {
+                anyhow::bail!("UDPの上位DNSは接続型の送受信に使えません: {}", addr)
+            }
+            UpstreamServer::Tcp(addr) => {
+                let mut stream = Self::connect_tcp(bind_addr, *addr).await?;
+                exchange_stream(&mut stream, request).await
+            }
+            UpstreamServer::Tls { addr, server_name } => {
+                let mut stream = Self::connect_tls(bind_addr, *addr, server_name).await?;
+                exchange_stream(&mut stream, request).await
+            }
+            UpstreamServer::Https {
+                addr,
+                server_name,
+                path,
+            } => {
+                let stream = Self::connect_tls(bind_addr, *addr, server_name).await?;
+                exchange_https(stream, server_name, path, request).await
+            }
+        }
+    }
+}
+
+/// 応答が送った問い合わせに対するものか（ID・応答フラグ・質問の名前とタイプが一致するか）
+fn is_response_to(response: &Message, id: u16, query: &Query) -> bool {
+    use hickory_proto::op::MessageType;
+
+    response.id() == id
+        && response.message_type() == MessageType::Response
+        && response.queries().len() == 1
+        && response.queries()[0].name() == query.name()
+        && response.queries()[0].query_type() == query.query_type()
+}
+
+/// TCP / TLS 上でメッセージを送受信（先頭2バイトのメッセージ長を付ける。RFC 1035 4.2.2）
+async fn exchange_stream<S>(stream: &mut S, request: &[u8]) -> Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let len = u16::try_from(request.len()).context("問い合わせメッセージが大きすぎます")?;
+    let mut framed = Vec::with_capacity(request.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(request);
+    stream.write_all(&framed).await?;
+    stream.flush().await?;
+
+    let len = stream.read_u16().await? as usize;
+    let mut response = vec![0u8; len];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+/// DNS over HTTPS で問い合わせを POST し、応答のメッセージを返す（HTTP/1.1）
+async fn exchange_https<S>(stream: S, server_name: &str, path: &str, request: &[u8]) -> Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::Bytes;
+    use hyper::header::{ACCEPT, CONTENT_TYPE, HOST};
+
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("DNS over HTTPS の接続が終了: {}", e);
+        }
+    });
+
+    let request = hyper::Request::post(path)
+        .header(HOST, server_name)
+        .header(CONTENT_TYPE, DNS_MESSAGE_MEDIA_TYPE)
+        .header(ACCEPT, DNS_MESSAGE_MEDIA_TYPE)
+        .body(Full::new(Bytes::copy_from_slice(request)))?;
+    let response = sender.send_request(request).await?;
+    if !response.status().is_success() {
+        anyhow::bail!("DNS over HTTPS の応答が失敗しました: HTTP {}", response.status());
+    }
+
+    Ok(response.into_body().collect().await?.to_bytes().to_vec())
 }
 
 #[cfg(test)]
@@ -159,13 +830,121 @@ mod tests {
 
         assert_eq!(
             config.primary,
-            SocketAddr::from_str("8.8.8.8:53").unwrap()
+            UpstreamServer::Udp(SocketAddr::from_str("8.8.8.8:53").unwrap())
         );
         assert_eq!(
             config.secondary,
-            SocketAddr::from_str("1.1.1.1:53").unwrap()
+            UpstreamServer::Udp(SocketAddr::from_str("1.1.1.1:53").unwrap())
         );
         assert_eq!(config.timeout, Duration::from_millis(2000));
+        assert_eq!(config.retries, DEFAULT_RETRIES);
+    }
+
+    #[test]
+    fn test_upstream_config_bare_ip_defaults_to_port_53() {
+        let config = UpstreamConfig::new("9.9.9.9", "9.9.9.9:5353", 2000).unwrap();
+
+        assert_eq!(config.primary.addr(), SocketAddr::from_str("9.9.9.9:53").unwrap());
+        assert_eq!(config.secondary.addr(), SocketAddr::from_str("9.9.9.9:5353").unwrap());
+    }
+
+    #[test]
+    fn test_parse_upstream_server_schemes() {
+        let addr = |value: &str| SocketAddr::from_str(value).unwrap();
+
+        assert_eq!(
+            parse_upstream_server("udp://192.168.1.1").unwrap(),
+            UpstreamServer::Udp(addr("192.168.1.1:53"))
+        );
+        assert_eq!(
+            parse_upstream_server("TCP://192.168.1.1:5353").unwrap(),
+            UpstreamServer::Tcp(addr("192.168.1.1:5353"))
+        );
+        assert_eq!(
+            parse_upstream_server("tls://1.1.1.1#cloudflare-dns.com").unwrap(),
+            UpstreamServer::Tls {
+                addr: addr("1.1.1.1:853"),
+                server_name: "cloudflare-dns.com".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_upstream_server("https://[2606:4700:4700::1111]").unwrap(),
+            UpstreamServer::Https {
+                addr: addr("[2606:4700:4700::1111]:443"),
+                server_name: "2606:4700:4700::1111".to_string(),
+                path: "/dns-query".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_upstream_server("https://8.8.8.8:8443/resolve#dns.google").unwrap(),
+            UpstreamServer::Https {
+                addr: addr("8.8.8.8:8443"),
+                server_name: "dns.google".to_string(),
+                path: "/resolve".to_string(),
+            }
+        );
+
+        assert!(parse_upstream_server("quic://1.1.1.1").is_err());
+        assert!(parse_upstream_server("tcp://1.1.1.1#name").is_err());
+        assert!(parse_upstream_server("tls://dns.google").is_err());
+    }
+
+    #[test]
+    fn test_upstream_config_mixed_protocols() {
+        let config =
+            UpstreamConfig::new("192.168.1.1", "https://1.1.1.1/dns-query", 2000).unwrap();
+
+        assert!(matches!(config.primary, UpstreamServer::Udp(_)));
+        assert!(matches!(config.secondary, UpstreamServer::Https { .. }));
+        // UDP は従来どおりの表示、それ以外はスキーム付き
+        assert_eq!(config.primary.to_string(), "192.168.1.1:53");
+        assert_eq!(config.secondary.to_string(), "https://1.1.1.1:443/dns-query");
+    }
+
+    #[test]
+    fn test_parse_upstream_addr_ipv6() {
+        assert_eq!(
+            parse_upstream_addr("2001:4860:4860::8888").unwrap(),
+            SocketAddr::from_str("[2001:4860:4860::8888]:53").unwrap()
+        );
+        assert_eq!(
+            parse_upstream_addr("[2001:4860:4860::8888]:5353").unwrap(),
+            SocketAddr::from_str("[2001:4860:4860::8888]:5353").unwrap()
+        );
+        assert!(parse_upstream_addr("dns.google").is_err());
+    }
+
+    #[test]
+    fn test_parse_bind_addr() {
+        assert_eq!(
+            parse_bind_addr("127.0.0.1").unwrap(),
+            SocketAddr::from_str("127.0.0.1:0").unwrap()
+        );
+        assert_eq!(
+            parse_bind_addr("127.0.0.1:0").unwrap(),
+            SocketAddr::from_str("127.0.0.1:0").unwrap()
+        );
+        assert!(parse_bind_addr("localhost").is_err());
+        // 固定のポートは同時の問い合わせでバインドが衝突するため受け付けない
+        assert!(parse_bind_addr("127.0.0.1:5353").is_err());
+        assert!(parse_bind_addr("[::1]:5353").is_err());
+        // ホストに存在しないアドレス（TEST-NET-3）はバインドできない
+        assert!(parse_bind_addr("203.0.113.1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socket_binds_to_configured_address() {
+        let config = UpstreamConfig::new("127.0.0.1:53", "127.0.0.1:53", 2000)
+            .unwrap()
+            .with_bind_addr(SocketAddr::from_str("127.0.0.1:0").unwrap());
+        let resolver = UpstreamResolver::new(config);
+
+        let socket = UpstreamResolver::bind_socket(resolver.config().bind_addr)
+            .await
+            .unwrap();
+        let local = socket.local_addr().unwrap();
+        assert_eq!(local.ip(), IpAddr::from_str("127.0.0.1").unwrap());
+        assert_ne!(local.port(), 0);
     }
 
     #[test]
@@ -174,18 +953,489 @@ mod tests {
         assert!(result.is_err());
     }
 
+    /// 問い合わせと同じタイプ・名前で指定したRDataを1件返す上位DNSを起動する
+    /// 受信した問い合わせのタイプを記録する
+    async fn spawn_mock_upstream(
+        rdata: hickory_proto::rr::RData,
+    ) -> (String, std::sync::Arc<std::sync::Mutex<Vec<RecordType>>>) {
+        use hickory_proto::op::{Message, MessageType};
+        use hickory_proto::rr::Record;
+        use hickory_proto::serialize::binary::BinDecodable;
+        use std::sync::{Arc, Mutex};
+        use tokio::net::UdpSocket;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response.set_id(request.id());
+                response.set_message_type(MessageType::Response);
+                for query in request.queries() {
+                    received_clone.lock().unwrap().push(query.query_type());
+                    response.add_query(query.clone());
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        300,
+                        rdata.clone(),
+                    ));
+                }
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        (addr, received)
+    }
+
+    #[tokio::test]
+    async fn test_query_forwards_mx() {
+        use hickory_proto::rr::rdata::MX;
+        use hickory_proto::rr::{DNSClass, RData};
+
+        let exchange = Name::from_str("mail.example.com.").unwrap();
+        let (addr, received) = spawn_mock_upstream(RData::MX(MX::new(10, exchange.clone()))).await;
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let resolver = UpstreamResolver::new(config);
+
+        let response = resolver
+            .query("example.com", RecordType::MX, false, None)
+            .await
+            .unwrap();
+
+        // 受け取ったタイプのまま転送している
+        assert_eq!(*received.lock().unwrap(), vec![RecordType::MX]);
+
+        assert_eq!(response.records.len(), 1);
+        let record = &response.records[0];
+        assert_eq!(record.record_type(), RecordType::MX);
+        assert_eq!(record.dns_class(), DNSClass::IN);
+        match record.data() {
+            RData::MX(mx) => {
+                assert_eq!(mx.preference(), 10);
+                assert_eq!(mx.exchange(), &exchange);
+            }
+            other => panic!("MXレコードではありません: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_forwards_txt() {
+        use hickory_proto::rr::rdata::TXT;
+        use hickory_proto::rr::RData;
+
+        let (addr, received) =
+            spawn_mock_upstream(RData::TXT(TXT::new(vec!["v=spf1 -all".to_string()]))).await;
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let resolver = UpstreamResolver::new(config);
+
+        let response = resolver
+            .query("example.com", RecordType::TXT, false, None)
+            .await
+            .unwrap();
+
+        assert_eq!(*received.lock().unwrap(), vec![RecordType::TXT]);
+        assert_eq!(response.records.len(), 1);
+        match response.records[0].data() {
+            RData::TXT(txt) => assert_eq!(txt.to_string(), "v=spf1 -all"),
+            other => panic!("TXTレコードではありません: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_forwards_soa() {
+        use hickory_proto::rr::rdata::SOA;
+        use hickory_proto::rr::RData;
+
+        let soa = SOA::new(
+            Name::from_str("ns1.example.com.").unwrap(),
+            Name::from_str("hostmaster.example.com.").unwrap(),
+            2024010101,
+            3600,
+            600,
+            86400,
+            300,
+        );
+        let (addr, received) = spawn_mock_upstream(RData::SOA(soa)).await;
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let resolver = UpstreamResolver::new(config);
+
+        let response = resolver
+            .query("example.com", RecordType::SOA, false, None)
+            .await
+            .unwrap();
+
+        assert_eq!(*received.lock().unwrap(), vec![RecordType::SOA]);
+        assert_eq!(response.records.len(), 1);
+        match response.records[0].data() {
+            RData::SOA(soa) => assert_eq!(soa.serial(), 2024010101),
+            other => panic!("SOAレコードではありません: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_reachability() {
+        use hickory_proto::rr::rdata::NS;
+        use hickory_proto::rr::RData;
+        use tokio::net::UdpSocket;
+
+        let (reachable, _) =
+            spawn_mock_upstream(RData::NS(NS(Name::from_str("a.root-servers.net.").unwrap())))
+                .await;
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let unreachable = silent.local_addr().unwrap().to_string();
+
+        let config = UpstreamConfig::new(&reachable, &unreachable, 100).unwrap();
+        let resolver = UpstreamResolver::new(config);
+
+        let results = resolver.probe().await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0.to_string(), reachable);
+        assert!(results[0].1);
+        assert_eq!(results[1].0.to_string(), unreachable);
+        assert!(!results[1].1);
+        drop(silent);
+    }
+
     #[tokio::test]
     async fn test_query_real() {
         let config = UpstreamConfig::new("8.8.8.8:53", "1.1.1.1:53", 5000).unwrap();
         let resolver = UpstreamResolver::new(config);
 
         // 実際のDNS問い合わせテスト (google.com は確実に存在する)
-        let result = resolver.query("google.com", "A").await;
+        let result = resolver.query("google.com", RecordType::A, false, None).await;
 
         // ネットワーク接続がある環境ではOK、ない場合はスキップ
         if result.is_ok() {
-            let records = result.unwrap();
+            let records = result.unwrap().records;
             assert!(!records.is_empty(), "google.com の A レコードが取得できませんでした");
         }
     }
+
+    #[tokio::test]
+    async fn test_query_retries_after_dropped_packet() {
+        use hickory_proto::op::{Message, MessageType};
+        use hickory_proto::rr::{RData, Record};
+        use hickory_proto::serialize::binary::BinDecodable;
+        use std::net::Ipv4Addr;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use tokio::net::UdpSocket;
+
+        // 最初のパケットを捨て、2回目以降に応答する上位DNS
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let received = Arc::new(AtomicUsize::new(0));
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                if received_clone.fetch_add(1, Ordering::SeqCst) == 0 {
+                    continue;
+                }
+
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response.set_id(request.id());
+                response.set_message_type(MessageType::Response);
+                for query in request.queries() {
+                    response.add_query(query.clone());
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        60,
+                        RData::A(Ipv4Addr::new(10, 0, 0, 1).into()),
+                    ));
+                }
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        let config = UpstreamConfig::new(&addr, &addr, 200).unwrap().with_retries(2);
+        let resolver = UpstreamResolver::new(config);
+
+        let response = resolver
+            .query("flaky.example", RecordType::A, false, None)
+            .await
+            .unwrap();
+        assert_eq!(response.records.len(), 1);
+        assert_eq!(response.server.to_string(), addr);
+        // プライマリへの再送で応答を得ている
+        assert_eq!(received.load(Ordering::SeqCst), 2);
+        assert!(resolver.metrics().render().contains(&format!(
+            "localdns_upstream_query_duration_seconds_count{{server=\"{}\"}} 1",
+            addr
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_query_ignores_mismatched_responses() {
+        use hickory_proto::op::{Message, MessageType};
+        use hickory_proto::rr::{RData, Record};
+        use hickory_proto::serialize::binary::BinDecodable;
+        use std::net::Ipv4Addr;
+        use tokio::net::UdpSocket;
+
+        // IDの違う応答と質問の違う応答を先に返してから、正しい応答を返す上位DNS
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                let reply = |id: u16, name: &str, ip: Ipv4Addr| {
+                    let name = Name::from_str(name).unwrap();
+                    let mut response = Message::new();
+                    response.set_id(id);
+                    response.set_message_type(MessageType::Response);
+                    response.add_query(Query::query(name.clone(), query.query_type()));
+                    response.add_answer(Record::from_rdata(name, 60, RData::A(ip.into())));
+                    response.to_vec().unwrap()
+                };
+
+                let spoofed = reply(
+                    request.id().wrapping_add(1),
+                    "target.example.",
+                    Ipv4Addr::new(6, 6, 6, 6),
+                );
+                let _ = socket.send_to(&spoofed, peer).await;
+                let other = reply(request.id(), "other.example.", Ipv4Addr::new(7, 7, 7, 7));
+                let _ = socket.send_to(&other, peer).await;
+                let genuine = reply(request.id(), "target.example.", Ipv4Addr::new(10, 0, 0, 1));
+                let _ = socket.send_to(&genuine, peer).await;
+            }
+        });
+
+        let config = UpstreamConfig::new(&addr, &addr, 2000).unwrap();
+        let resolver = UpstreamResolver::new(config);
+
+        let response = resolver
+            .query("target.example", RecordType::A, false, None)
+            .await
+            .unwrap();
+        assert_eq!(response.records.len(), 1);
+        assert_eq!(
+            response.records[0].data(),
+            &RData::A(Ipv4Addr::new(10, 0, 0, 1).into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_records_per_upstream_metrics() {
+        use tokio::net::UdpSocket;
+
+        // 応答しない上位DNS（ソケットは保持したまま何も返さない）
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = silent.local_addr().unwrap().to_string();
+
+        let config = UpstreamConfig::new(&addr, &addr, 50).unwrap().with_retries(0);
+        let resolver = UpstreamResolver::new(config);
+        let metrics = resolver.metrics();
+
+        assert!(resolver
+            .query("silent.example", RecordType::A, false, None)
+            .await
+            .is_err());
+
+        // プライマリ・セカンダリの両方で失敗が記録される
+        let text = metrics.render();
+        assert!(text.contains(&format!(
+            "localdns_upstream_query_failures_total{{server=\"{}\"}} 2",
+            addr
+        )));
+        drop(silent);
+    }
+
+    #[tokio::test]
+    async fn test_query_dispatches_to_tcp_upstream() {
+        use hickory_proto::op::MessageType;
+        use hickory_proto::rr::Record;
+        use hickory_proto::serialize::binary::BinDecodable;
+        use std::net::Ipv4Addr;
+        use tokio::net::{TcpListener, UdpSocket};
+
+        // メッセージ長を前置して1件のAレコードを返すTCPの上位DNS
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let len = stream.read_u16().await.unwrap() as usize;
+                let mut buf = vec![0u8; len];
+                stream.read_exact(&mut buf).await.unwrap();
+
+                let request = Message::from_bytes(&buf).unwrap();
+                let mut response = Message::new();
+                response.set_id(request.id());
+                response.set_message_type(MessageType::Response);
+                for query in request.queries() {
+                    response.add_query(query.clone());
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        60,
+                        RData::A(Ipv4Addr::new(10, 0, 0, 9).into()),
+                    ));
+                }
+                let bytes = response.to_vec().unwrap();
+                stream.write_u16(bytes.len() as u16).await.unwrap();
+                stream.write_all(&bytes).await.unwrap();
+            }
+        });
+
+        // プライマリは応答しないUDP、セカンダリはTCP
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let udp_addr = silent.local_addr().unwrap().to_string();
+        let config = UpstreamConfig::new(&udp_addr, &format!("tcp://{}", tcp_addr), 200)
+            .unwrap()
+            .with_retries(0);
+        let resolver = UpstreamResolver::new(config);
+
+        let response = resolver
+            .query("tcp.example", RecordType::A, false, None)
+            .await
+            .unwrap();
+        assert_eq!(response.server, UpstreamServer::Tcp(tcp_addr));
+        assert_eq!(response.records.len(), 1);
+        assert_eq!(
+            response.records[0].data(),
+            &RData::A(Ipv4Addr::new(10, 0, 0, 9).into())
+        );
+        drop(silent);
+    }
+
+    #[tokio::test]
+    async fn test_query_dispatches_tls_upstream_with_handshake() {
+        use tokio::net::TcpListener;
+        use tokio::sync::mpsc;
+
+        // TLSのハンドシェイクには応答せず、最初に受け取ったバイトを記録する
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (first_bytes, mut received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let first_bytes = first_bytes.clone();
+                tokio::spawn(async move {
+                    let byte = stream.read_u8().await.unwrap();
+                    let _ = first_bytes.send(byte);
+                    // 応答せずに接続を保持する
+                    let _ = stream.read_u8().await;
+                });
+            }
+        });
+
+        let server = format!("tls://{}#localhost", addr);
+        let config = UpstreamConfig::new(&server, &server, 100)
+            .unwrap()
+            .with_retries(0);
+        let resolver = UpstreamResolver::new(config);
+
+        assert!(resolver
+            .query("tls.example", RecordType::A, false, None)
+            .await
+            .is_err());
+        // TLS のハンドシェイクレコード（ClientHello）から始まる
+        assert_eq!(received.recv().await, Some(0x16));
+    }
+
+    #[test]
+    fn test_parse_ecs() {
+        assert_eq!(parse_ecs("", 24, 56).unwrap(), None);
+        assert_eq!(
+            parse_ecs("client", 32, 64).unwrap(),
+            Some(EcsMode::Client {
+                v4_prefix: 24,
+                v6_prefix: 56
+            })
+        );
+        assert_eq!(
+            parse_ecs("203.0.113.77/24", 24, 56).unwrap(),
+            Some(EcsMode::Fixed(IpNet::from_str("203.0.113.0/24").unwrap()))
+        );
+        // 上限より長いプレフィックスは切り詰める
+        assert_eq!(
+            parse_ecs("203.0.113.77", 16, 56).unwrap(),
+            Some(EcsMode::Fixed(IpNet::from_str("203.0.0.0/16").unwrap()))
+        );
+        assert_eq!(
+            parse_ecs("2001:db8:1:2::1/64", 24, 56).unwrap(),
+            Some(EcsMode::Fixed(IpNet::from_str("2001:db8:1::/56").unwrap()))
+        );
+        assert!(parse_ecs("not-a-subnet", 24, 56).is_err());
+    }
+
+    #[test]
+    fn test_ecs_client_subnet_skips_private_addresses() {
+        let mode = EcsMode::Client {
+            v4_prefix: 24,
+            v6_prefix: 56,
+        };
+
+        assert_eq!(
+            mode.subnet(Some(IpAddr::from_str("198.51.100.25").unwrap())),
+            Some(IpNet::from_str("198.51.100.0/24").unwrap())
+        );
+        assert_eq!(mode.subnet(Some(IpAddr::from_str("192.168.1.10").unwrap())), None);
+        assert_eq!(mode.subnet(Some(IpAddr::from_str("127.0.0.1").unwrap())), None);
+        assert_eq!(mode.subnet(Some(IpAddr::from_str("fd00::1").unwrap())), None);
+        assert_eq!(mode.subnet(None), None);
+    }
+
+    #[tokio::test]
+    async fn test_query_sends_client_subnet() {
+        use hickory_proto::op::{Message, MessageType};
+        use hickory_proto::rr::rdata::opt::EdnsCode;
+        use hickory_proto::serialize::binary::BinDecodable;
+        use std::sync::{Arc, Mutex};
+        use tokio::net::UdpSocket;
+
+        // 受信した問い合わせをそのまま記録して空の応答を返す上位DNS
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let received: Arc<Mutex<Vec<Message>>> = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response.set_id(request.id());
+                response.set_message_type(MessageType::Response);
+                response.add_queries(request.queries().to_vec());
+                received_clone.lock().unwrap().push(request);
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        let config = UpstreamConfig::new(&addr, &addr, 2000)
+            .unwrap()
+            .with_client_subnet(parse_ecs("client", 24, 56).unwrap().unwrap());
+        let resolver = UpstreamResolver::new(config);
+
+        resolver
+            .query(
+                "example.com",
+                RecordType::A,
+                false,
+                Some(IpAddr::from_str("198.51.100.25").unwrap()),
+            )
+            .await
+            .unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let edns = received[0].extensions().as_ref().expect("OPTレコードがありません");
+        let expected = EdnsOption::Subnet(ClientSubnet::new(
+            IpAddr::from_str("198.51.100.0").unwrap(),
+            24,
+            0,
+        ));
+        assert_eq!(edns.option(EdnsCode::Subnet), Some(&expected));
+    }
 }
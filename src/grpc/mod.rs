@@ -0,0 +1,508 @@
+//! gRPC 管理API（`proto/local_dns.proto`）
+//!
+//! REST API と同じ `ApiState` と DB 関数を使い、レコード操作・設定・名前解決を提供する。
+
+use crate::db::{
+    self, normalize_client_subnet, ApiKeyScope, CreateRecordRequest, PatternType, Record,
+};
+use crate::validation::validate_record;
+use crate::web::api::{
+    apply_setting, authorize_api_key, bearer_token, parse_resolve_type, validate_setting,
+    ApiState, AppError,
+};
+use hickory_proto::rr::{Name, Record as DnsRecord};
+use std::str::FromStr;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("localdns.v1");
+}
+
+use proto::local_dns_server::{LocalDns, LocalDnsServer};
+
+/// gRPC サービスの実装
+pub struct LocalDnsService {
+    state: ApiState,
+}
+
+impl LocalDnsService {
+    pub fn new(state: ApiState) -> Self {
+        Self { state }
+    }
+
+    /// tonic のサーバーに登録するサービスを作成
+    pub fn into_server(self) -> LocalDnsServer<Self> {
+        LocalDnsServer::new(self)
+    }
+
+    /// メタデータの `authorization: Bearer <キー>` を REST API と同じ規則で検証する
+    /// 参照（一覧・名前解決）は `read`、それ以外は `write` のスコープが必要
+    async fn authorize<T>(
+        &self,
+        request: &Request<T>,
+        required: ApiKeyScope,
+        method: &str,
+    ) -> Result<(), Status> {
+        let key = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(bearer_token);
+        let client = request
+            .remote_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        authorize_api_key(
+            &self.state.pool,
+            key,
+            required,
+            &format!("gRPC {}", method),
+            &client,
+        )
+        .await
+        .map_err(error_status)
+    }
+
+    /// レコード変更後のキャッシュ再読み込み（失敗してもリクエスト自体は成功扱い）
+    async fn reload_cache(&self) {
+        if let Err(e) = self.state.cache.reload().await {
+            tracing::error!("キャッシュ再読み込み失敗: {}", e);
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl LocalDns for LocalDnsService {
+    async fn list_records(
+        &self,
+        request: Request<proto::ListRecordsRequest>,
+    ) -> Result<Response<proto::ListRecordsResponse>, Status> {
+        self.authorize(&request, ApiKeyScope::Read, "ListRecords").await?;
+        let tag = request.into_inner().tag;
+        let records = match tag.trim() {
+            "" => db::get_all_records(&self.state.pool).await,
+            tag => db::get_records_by_tag(&self.state.pool, tag).await,
+        }
+        .map_err(internal)?;
+
+        Ok(Response::new(proto::ListRecordsResponse {
+            records: records.iter().map(record_message).collect(),
+        }))
+    }
+
+    async fn create_record(
+        &self,
+        request: Request<proto::CreateRecordRequest>,
+    ) -> Result<Response<proto::CreateRecordResponse>, Status> {
+        self.authorize(&request, ApiKeyScope::Write, "CreateRecord").await?;
+        let req = request.into_inner();
+        let req = CreateRecordRequest {
+            domain_pattern: req.domain_pattern,
+            record_type: req.record_type,
+            content: req.content.into(),
+            ttl: req.ttl.unwrap_or(60),
+            priority: req.priority,
+            weight: req.weight.unwrap_or(1),
+            passthrough: req.passthrough,
+            pattern_type: parse_pattern_type(&req.pattern_type)?,
+            client_subnet: normalize_client_subnet(Some(&req.client_subnet)),
+            tags: req.tags,
+        };
+        validate_record(&req).map_err(|errors| error_status(errors.into()))?;
+
+        let pool = &self.state.pool;
+        for content in req.content.values() {
+            if let Some(existing_id) =
+                db::find_duplicate_record(pool, &req.domain_pattern, &req.record_type, content)
+                    .await
+                    .map_err(internal)?
+            {
+                return Err(Status::already_exists(format!(
+                    "同一のレコードが既に存在します (id: {})",
+                    existing_id
+                )));
+            }
+        }
+
+        let ids = db::create_record(pool, req).await.map_err(internal)?;
+        self.reload_cache().await;
+
+        Ok(Response::new(proto::CreateRecordResponse {
+            id: ids.first().copied().unwrap_or_default(),
+        }))
+    }
+
+    async fn delete_record(
+        &self,
+        request: Request<proto::DeleteRecordRequest>,
+    ) -> Result<Response<proto::DeleteRecordResponse>, Status> {
+        self.authorize(&request, ApiKeyScope::Write, "DeleteRecord").await?;
+        let id = request.into_inner().id;
+        let deleted = db::delete_record(&self.state.pool, id)
+            .await
+            .map_err(internal)?;
+        if !deleted {
+            return Err(Status::not_found(format!("レコードが見つかりません (id: {})", id)));
+        }
+        self.reload_cache().await;
+
+        Ok(Response::new(proto::DeleteRecordResponse {}))
+    }
+
+    async fn list_settings(
+        &self,
+        request: Request<proto::ListSettingsRequest>,
+    ) -> Result<Response<proto::ListSettingsResponse>, Status> {
+        self.authorize(&request, ApiKeyScope::Read, "ListSettings").await?;
+        let settings = db::get_all_settings(&self.state.pool)
+            .await
+            .map_err(internal)?;
+
+        Ok(Response::new(proto::ListSettingsResponse {
+            settings: settings
+                .into_iter()
+                .map(|setting| proto::Setting {
+                    key: setting.key,
+                    value: setting.value,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn update_setting(
+        &self,
+        request: Request<proto::UpdateSettingRequest>,
+    ) -> Result<Response<proto::UpdateSettingResponse>, Status> {
+        self.authorize(&request, ApiKeyScope::Write, "UpdateSetting").await?;
+        let req = request.into_inner();
+        validate_setting(&req.key, &req.value).map_err(error_status)?;
+        db::update_setting(&self.state.pool, &req.key, &req.value)
+            .await
+            .map_err(internal)?;
+        apply_setting(&self.state, &req.key)
+            .await
+            .map_err(error_status)?;
+
+        Ok(Response::new(proto::UpdateSettingResponse {}))
+    }
+
+    async fn resolve(
+        &self,
+        request: Request<proto::ResolveRequest>,
+    ) -> Result<Response<proto::ResolveResponse>, Status> {
+        self.authorize(&request, ApiKeyScope::Read, "Resolve").await?;
+        let req = request.into_inner();
+
+        let raw_name = req.name.trim();
+        let mut name = Name::from_str(raw_name)
+            .ok()
+            .filter(|_| !raw_name.is_empty())
+            .ok_or_else(|| {
+                Status::invalid_argument(format!("名前の形式が不正です: {}", raw_name))
+            })?;
+        name.set_fqdn(true);
+
+        let raw_type = match req.r#type.trim() {
+            "" => "A",
+            raw_type => raw_type,
+        };
+        let record_type = parse_resolve_type(raw_type).ok_or_else(|| {
+            Status::invalid_argument(format!("レコードタイプが不正です: {}", raw_type))
+        })?;
+
+        let handler = self
+            .state
+            .dns_handler
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("DNSハンドラが設定されていません"))?;
+        let outcome = handler
+            .lookup(&name, record_type, req.checking_disabled, true, None)
+            .await;
+
+        let answers = outcome
+            .answers
+            .iter()
+            .enumerate()
+            .map(|(index, record)| proto::ResolveAnswer {
+                record_id: outcome.record_ids.get(index).copied(),
+                ..answer_message(record)
+            })
+            .collect();
+
+        Ok(Response::new(proto::ResolveResponse {
+            status: u16::from(handler.response_code(&outcome)).into(),
+            recursion_available: handler.recursion_available(),
+            answers,
+            authority: outcome.name_servers.iter().map(answer_message).collect(),
+        }))
+    }
+}
+
+/// DB のレコードを gRPC のメッセージに変換
+fn record_message(record: &Record) -> proto::Record {
+    proto::Record {
+        id: record.id,
+        domain_pattern: record.domain_pattern.clone(),
+        record_type: record.record_type.clone(),
+        content: record.content.clone(),
+        ttl: record.ttl,
+        active: record.is_active(),
+        priority: record.priority,
+        weight: record.weight,
+        passthrough: record.passthrough,
+        pattern_type: match record.pattern_type {
+            PatternType::Glob => "glob",
+            PatternType::Regex => "regex",
+        }
+        .to_string(),
+        client_subnet: record.client_subnet.clone().unwrap_or_default(),
+        tags: record.tag_list().into_iter().map(String::from).collect(),
+    }
+}
+
+/// ドメインパターンの種類をパース（空文字列は glob）
+fn parse_pattern_type(value: &str) -> Result<PatternType, Status> {
+    match value.trim() {
+        "" | "glob" => Ok(PatternType::Glob),
+        "regex" => Ok(PatternType::Regex),
+        other => Err(Status::invalid_argument(format!(
+            "pattern_type: glob または regex を指定してください ({})",
+            other
+        ))),
+    }
+}
+
+/// DNS レコードを名前解決の回答に変換
+fn answer_message(record: &DnsRecord) -> proto::ResolveAnswer {
+    proto::ResolveAnswer {
+        name: record.name().to_string(),
+        r#type: u16::from(record.record_type()).into(),
+        ttl: record.ttl(),
+        data: record.data().to_string(),
+        record_id: None,
+    }
+}
+
+/// REST API のエラーを gRPC のステータスに変換
+fn error_status(error: AppError) -> Status {
+    match error {
+        AppError::Internal(e) => internal(e),
+        AppError::NotFound => Status::not_found("リソースが見つかりません"),
+        AppError::Validation(errors) => Status::invalid_argument(
+            errors
+                .iter()
+                .map(|error| format!("{}: {}", error.field, error.message))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        AppError::Conflict(message) => Status::already_exists(message),
+        AppError::Unauthorized => Status::unauthenticated("認証が必要です"),
+        AppError::Forbidden => Status::permission_denied("この操作を行う権限がありません"),
+        AppError::TooManyRequests => Status::resource_exhausted("リクエスト数の上限を超えました"),
+    }
+}
+
+/// 内部エラー（詳細はログのみに出力）
+fn internal(error: anyhow::Error) -> Status {
+    tracing::error!("gRPC 内部エラー: {:?}", error);
+    Status::internal("内部エラーが発生しました")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::init_db;
+    use crate::dns::{DnsHandler, RecordCache, UpstreamMetrics};
+    use crate::logger::LogWorker;
+
+    /// テスト用のサービスを作成（上位DNSへの転送なし）
+    async fn setup_service() -> LocalDnsService {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let dns_handler = DnsHandler::new(cache.clone(), log_worker.clone());
+        LocalDnsService::new(ApiState {
+            pool,
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker,
+            upstream: None,
+            dns_handler: Some(dns_handler),
+        })
+    }
+
+    fn create_request(content: &str) -> proto::CreateRecordRequest {
+        proto::CreateRecordRequest {
+            domain_pattern: "grpc.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: None,
+            priority: 0,
+            weight: None,
+            passthrough: false,
+            pattern_type: String::new(),
+            client_subnet: String::new(),
+            tags: vec!["grpc".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_crud_and_resolve() {
+        let service = setup_service().await;
+
+        let id = service
+            .create_record(Request::new(create_request("192.168.10.1")))
+            .await
+            .unwrap()
+            .into_inner()
+            .id;
+
+        let records = service
+            .list_records(Request::new(proto::ListRecordsRequest {
+                tag: "grpc".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .records;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, id);
+        assert_eq!(records[0].ttl, 60);
+        assert_eq!(records[0].tags, vec!["grpc".to_string()]);
+
+        // 作成したレコードで名前解決できる
+        let response = service
+            .resolve(Request::new(proto::ResolveRequest {
+                name: "grpc.local.test".to_string(),
+                r#type: "A".to_string(),
+                checking_disabled: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status, 0);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].data, "192.168.10.1");
+        assert_eq!(response.answers[0].record_id, Some(id));
+
+        service
+            .delete_record(Request::new(proto::DeleteRecordRequest { id }))
+            .await
+            .unwrap();
+        let status = service
+            .delete_record(Request::new(proto::DeleteRecordRequest { id }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        // 削除後は NXDOMAIN
+        let response = service
+            .resolve(Request::new(proto::ResolveRequest {
+                name: "grpc.local.test".to_string(),
+                r#type: String::new(),
+                checking_disabled: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status, 3);
+        assert!(response.answers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_record_errors() {
+        let service = setup_service().await;
+
+        let status = service
+            .create_record(Request::new(create_request("not-an-ip")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(status.message().contains("content"));
+
+        service
+            .create_record(Request::new(create_request("192.168.10.1")))
+            .await
+            .unwrap();
+        let status = service
+            .create_record(Request::new(create_request("192.168.10.1")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+    }
+
+    #[tokio::test]
+    async fn test_update_setting() {
+        let service = setup_service().await;
+
+        service
+            .update_setting(Request::new(proto::UpdateSettingRequest {
+                key: "log_filter".to_string(),
+                value: "errors_only".to_string(),
+            }))
+            .await
+            .unwrap();
+
+        let settings = service
+            .list_settings(Request::new(proto::ListSettingsRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .settings;
+        assert!(settings
+            .iter()
+            .any(|setting| setting.key == "log_filter" && setting.value == "errors_only"));
+    }
+
+    /// APIキーを付けたリクエストを作成
+    fn with_key<T>(message: T, key: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert("authorization", format!("Bearer {}", key).parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn test_read_scoped_key_cannot_create_record() {
+        let service = setup_service().await;
+        let pool = &service.state.pool;
+        db::create_api_key(
+            pool,
+            &db::CreateApiKeyRequest {
+                name: "admin".to_string(),
+                scope: ApiKeyScope::Admin,
+            },
+        )
+        .await
+        .unwrap();
+        let (_, read) = db::create_api_key(
+            pool,
+            &db::CreateApiKeyRequest {
+                name: "viewer".to_string(),
+                scope: ApiKeyScope::Read,
+            },
+        )
+        .await
+        .unwrap();
+
+        // キーが登録されていればキーなしは拒否する
+        let status = service
+            .list_records(Request::new(proto::ListRecordsRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        // read スコープは参照できるが、作成はできない
+        service
+            .list_records(with_key(proto::ListRecordsRequest::default(), &read))
+            .await
+            .unwrap();
+        let status = service
+            .create_record(with_key(create_request("192.168.10.1"), &read))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(db::get_all_records(pool).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,10 @@
+//! LocalDNS Pro のライブラリ部分
+//!
+//! サーバーと共有するデータモデル（`models`）と、`client` フィーチャー有効時は
+//! 他の Rust サービスから管理APIを呼び出すための型付きクライアント（`client`）を提供する。
+
+#[path = "db/models.rs"]
+pub mod models;
+
+#[cfg(feature = "client")]
+pub mod client;
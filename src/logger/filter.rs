@@ -0,0 +1,60 @@
+/// クエリログに記録する問い合わせの絞り込み
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFilter {
+    /// 全て記録
+    #[default]
+    All,
+    /// ローカルのレコードで応答したもの（委任応答を含む）のみ
+    LocalOnly,
+    /// 上位DNSの応答を返したもの（応答キャッシュ・serve-stale を含む）のみ
+    ForwardedOnly,
+    /// 応答できなかったもの（制限時間切れを含む）のみ
+    ErrorsOnly,
+}
+
+/// 設定値（`all` / `local_only` / `forwarded_only` / `errors_only`）を絞り込みに変換
+pub fn parse_log_filter(value: &str) -> Option<LogFilter> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "all" => Some(LogFilter::All),
+        "local_only" => Some(LogFilter::LocalOnly),
+        "forwarded_only" => Some(LogFilter::ForwardedOnly),
+        "errors_only" => Some(LogFilter::ErrorsOnly),
+        _ => None,
+    }
+}
+
+impl LogFilter {
+    /// 結果種別（`LOCAL` / `FORWARDED` など）の問い合わせを記録するか
+    pub fn allows(&self, result_type: &str) -> bool {
+        match self {
+            LogFilter::All => true,
+            LogFilter::LocalOnly => matches!(result_type, "LOCAL" | "DELEGATED"),
+            LogFilter::ForwardedOnly => matches!(result_type, "FORWARDED" | "CACHED" | "STALE"),
+            LogFilter::ErrorsOnly => matches!(result_type, "ERROR" | "TIMEOUT"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_filter() {
+        assert_eq!(parse_log_filter("all"), Some(LogFilter::All));
+        assert_eq!(parse_log_filter(" Forwarded_Only "), Some(LogFilter::ForwardedOnly));
+        assert_eq!(parse_log_filter("none"), None);
+    }
+
+    #[test]
+    fn test_log_filter_allows() {
+        assert!(LogFilter::All.allows("LOCAL"));
+        assert!(LogFilter::LocalOnly.allows("DELEGATED"));
+        assert!(!LogFilter::LocalOnly.allows("FORWARDED"));
+        assert!(LogFilter::ForwardedOnly.allows("CACHED"));
+        assert!(!LogFilter::ForwardedOnly.allows("LOCAL"));
+        assert!(LogFilter::ErrorsOnly.allows("ERROR"));
+        assert!(LogFilter::ErrorsOnly.allows("TIMEOUT"));
+        assert!(!LogFilter::ErrorsOnly.allows("STALE"));
+    }
+}
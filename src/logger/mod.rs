@@ -1,3 +1,5 @@
+pub mod filter;
 pub mod worker;
 
+pub use filter::{parse_log_filter, LogFilter};
 pub use worker::LogWorker;
@@ -1,7 +1,10 @@
 use crate::db::{cleanup_old_logs, get_setting, log_query, DbPool, NewQueryLog};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tracing::{debug, error, info};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
 
 /// クエリログメッセージ
 #[derive(Debug, Clone)]
@@ -10,48 +13,124 @@ pub struct QueryLogMessage {
     pub q_type: String,
     pub result_type: String,
     pub duration_ms: i64,
+    /// 応答した上位DNSサーバー（転送時のみ）
+    pub upstream_server: Option<String>,
+    /// 問い合わせID（トレースのスパンと関連付ける）
+    pub query_id: Option<String>,
 }
 
 /// ログクリーンアップのデフォルト間隔（1時間）
 const CLEANUP_INTERVAL_SECS: u64 = 3600;
 
+/// 書き込み待ちのログの上限（DBが応答しない間に溜まり続けないよう、超えた分は破棄する）
+const LOG_QUEUE_CAPACITY: usize = 10_000;
+
+/// 破棄したログの警告を出す間隔（件数）
+const DROP_WARN_INTERVAL: u64 = 1000;
+
 /// 非同期ログワーカー
+/// クローンは送信側・バックグラウンドタスクを共有する
+#[derive(Clone)]
 pub struct LogWorker {
-    sender: mpsc::UnboundedSender<QueryLogMessage>,
+    /// 送信側（`shutdown` で取り除かれると全クローンから送信できなくなる）
+    sender: Arc<RwLock<Option<mpsc::Sender<QueryLogMessage>>>>,
+    /// 書き込み待ちが上限に達して破棄したログの件数
+    dropped: Arc<AtomicU64>,
+    writer: Arc<Mutex<Option<JoinHandle<()>>>>,
+    cleanup: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 impl LogWorker {
     /// 新しいログワーカーを作成し、バックグラウンドタスクを起動
     pub fn new(pool: DbPool) -> Self {
-        let (sender, receiver) = mpsc::unbounded_channel();
+        let (sender, receiver) = mpsc::channel(LOG_QUEUE_CAPACITY);
 
         // バックグラウンドでログ書き込みタスクを起動
         let pool_for_writer = pool.clone();
-        tokio::spawn(async move {
+        let writer = tokio::spawn(async move {
             Self::run_worker(pool_for_writer, receiver).await;
         });
 
         // バックグラウンドでログクリーンアップタスクを起動
-        tokio::spawn(async move {
+        let cleanup = tokio::spawn(async move {
             Self::run_cleanup_worker(pool).await;
         });
 
-        Self { sender }
+        Self {
+            sender: Arc::new(RwLock::new(Some(sender))),
+            dropped: Arc::new(AtomicU64::new(0)),
+            writer: Arc::new(Mutex::new(Some(writer))),
+            cleanup: Arc::new(Mutex::new(Some(cleanup))),
+        }
     }
 
     /// ログメッセージを送信
+    /// 待たずに返す（DBが遅い・使えない間も名前解決を止めない）。書き込み待ちが上限に達している場合は破棄する
     pub fn log(&self, message: QueryLogMessage) {
-        if let Err(e) = self.sender.send(message) {
-            error!("ログメッセージの送信に失敗: {}", e);
+        match self.sender.read().unwrap().as_ref() {
+            Some(sender) => match sender.try_send(message) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(message)) => {
+                    let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    if dropped % DROP_WARN_INTERVAL == 1 {
+                        warn!(
+                            "クエリログの書き込みが追いつかないため破棄: {} (累計 {} 件)",
+                            message.query_name, dropped
+                        );
+                    }
+                }
+                Err(e) => {
+                    error!("ログメッセージの送信に失敗: {}", e);
+                }
+            },
+            None => {
+                warn!("ログワーカー停止後のため破棄: {}", message.query_name);
+            }
+        }
+    }
+
+    /// 書き込み待ちが上限に達して破棄したログの件数
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// ログ書き込みタスクが稼働中か（停止済み・異常終了していないか）
+    pub fn is_running(&self) -> bool {
+        self.sender.read().unwrap().is_some()
+            && self
+                .writer
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|writer| !writer.is_finished())
+    }
+
+    /// ログワーカーを停止
+    /// 送信側を閉じ、チャネルに残っているログを全て書き込むまで待つ
+    pub async fn shutdown(&self) {
+        // 全クローンで共有している送信側を取り除くとチャネルが閉じる
+        drop(self.sender.write().unwrap().take());
+
+        if let Some(cleanup) = self.cleanup.lock().unwrap().take() {
+            cleanup.abort();
+        }
+
+        let writer = self.writer.lock().unwrap().take();
+        if let Some(writer) = writer {
+            if let Err(e) = writer.await {
+                error!("ログワーカーの終了待ちに失敗: {}", e);
+            }
         }
     }
 
     /// バックグラウンドでログを書き込み続ける
+    /// 書き込みに失敗したログは再試行せずに破棄する（DBが使えない間は最初の失敗のみエラーを出し、復旧時に件数を出す）
     async fn run_worker(
         pool: DbPool,
-        mut receiver: mpsc::UnboundedReceiver<QueryLogMessage>,
+        mut receiver: mpsc::Receiver<QueryLogMessage>,
     ) {
         debug!("ログワーカー起動");
+        let mut failures: u64 = 0;
 
         while let Some(message) = receiver.recv().await {
             let log = NewQueryLog {
@@ -59,12 +138,26 @@ impl LogWorker {
                 q_type: message.q_type,
                 result_type: message.result_type,
                 duration_ms: message.duration_ms,
+                upstream_server: message.upstream_server,
+                query_id: message.query_id,
             };
 
-            if let Err(e) = log_query(&pool, log).await {
-                error!("クエリログの記録に失敗: {}", e);
-            } else {
-                debug!("クエリログ記録完了");
+            match log_query(&pool, log).await {
+                Ok(()) => {
+                    if failures > 0 {
+                        info!("クエリログの記録が復旧: 失敗した {} 件は破棄", failures);
+                        failures = 0;
+                    }
+                    debug!("クエリログ記録完了");
+                }
+                Err(e) => {
+                    if failures == 0 {
+                        error!("クエリログの記録に失敗（復旧するまで以降の失敗は破棄）: {}", e);
+                    } else {
+                        debug!("クエリログの記録に失敗: {}", e);
+                    }
+                    failures += 1;
+                }
             }
         }
 
@@ -105,14 +198,6 @@ impl LogWorker {
     }
 }
 
-impl Clone for LogWorker {
-    fn clone(&self) -> Self {
-        Self {
-            sender: self.sender.clone(),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -129,6 +214,8 @@ mod tests {
             q_type: "A".to_string(),
             result_type: "LOCAL".to_string(),
             duration_ms: 5,
+            upstream_server: None,
+            query_id: None,
         });
 
         // 少し待機してログが書き込まれるまで待つ
@@ -153,6 +240,8 @@ mod tests {
                 q_type: "A".to_string(),
                 result_type: "LOCAL".to_string(),
                 duration_ms: i,
+                upstream_server: None,
+                query_id: None,
             });
         }
 
@@ -163,4 +252,111 @@ mod tests {
         let logs = get_recent_logs(&pool, 10).await.unwrap();
         assert_eq!(logs.len(), 5);
     }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_pending_messages() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let worker = LogWorker::new(pool.clone());
+        let handler_side = worker.clone();
+
+        // 停止直前に送信したログ
+        for i in 0..20 {
+            handler_side.log(QueryLogMessage {
+                query_name: format!("shutdown{}.local", i),
+                q_type: "A".to_string(),
+                result_type: "LOCAL".to_string(),
+                duration_ms: i,
+                upstream_server: None,
+                query_id: None,
+            });
+        }
+
+        // 待機なしで停止しても全て書き込まれている
+        assert!(worker.is_running());
+        worker.shutdown().await;
+        assert!(!handler_side.is_running());
+
+        let logs = get_recent_logs(&pool, 100).await.unwrap();
+        assert_eq!(logs.len(), 20);
+
+        // 停止後の送信は破棄される
+        handler_side.log(QueryLogMessage {
+            query_name: "late.local".to_string(),
+            q_type: "A".to_string(),
+            result_type: "LOCAL".to_string(),
+            duration_ms: 0,
+            upstream_server: None,
+            query_id: None,
+        });
+        let logs = get_recent_logs(&pool, 100).await.unwrap();
+        assert_eq!(logs.len(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_logging_with_dedicated_pool_does_not_block_reads() {
+        use crate::db::{
+            create_record, get_active_records, get_setting, init_db_with_options, init_log_pool,
+            CreateRecordRequest, DbOptions, PatternType,
+        };
+
+        // 同じDBファイルを読み取り用とログ書き込み用の2つのプールで開く
+        let path =
+            std::env::temp_dir().join(format!("localdns-log-pool-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite:{}", path.display());
+        let options = DbOptions::default();
+        let pool = init_db_with_options(&url, &options).await.unwrap();
+        let log_pool = init_log_pool(&url, &options).await.unwrap();
+        let worker = LogWorker::new(log_pool);
+
+        let logging = {
+            let worker = worker.clone();
+            tokio::spawn(async move {
+                for i in 0..500 {
+                    worker.log(QueryLogMessage {
+                        query_name: format!("load{}.local", i),
+                        q_type: "A".to_string(),
+                        result_type: "LOCAL".to_string(),
+                        duration_ms: 0,
+                        upstream_server: None,
+                        query_id: None,
+                    });
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        // ログの書き込み中もレコード・設定の読み書きがロックエラーにならない
+        for i in 0..50 {
+            create_record(
+                &pool,
+                CreateRecordRequest {
+                    domain_pattern: format!("host{}.local", i),
+                    record_type: "A".to_string(),
+                    content: "10.0.0.1".into(),
+                    ttl: 60,
+                    priority: 0,
+                    weight: 1,
+                    passthrough: false,
+                    pattern_type: PatternType::Glob,
+                    client_subnet: None,
+                    tags: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+            get_active_records(&pool).await.unwrap();
+            get_setting(&pool, "upstream_primary").await.unwrap();
+        }
+
+        logging.await.unwrap();
+        worker.shutdown().await;
+        assert_eq!(get_recent_logs(&pool, 1000).await.unwrap().len(), 500);
+        assert_eq!(get_active_records(&pool).await.unwrap().len(), 50);
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
 }
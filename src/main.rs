@@ -1,33 +1,83 @@
 mod db;
 mod dns;
+#[cfg(feature = "grpc")]
+mod grpc;
 mod logger;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod validation;
 mod web;
 
 use anyhow::{Context, Result};
-use db::init_db;
-use dns::{upstream::UpstreamResolver, DnsHandler, RecordCache, UpstreamConfig};
+use db::{init_db_with_options, init_log_pool, DbOptions};
+use dns::{
+    cache::{EnvExpansion, UPSTREAM_CACHE_MAX_ENTRIES},
+    socket::{
+        bind_error, bind_ipv6_sockets, bind_udp_sockets, drain_dns_server, parse_ipv6_listen_addr,
+        TCP_TIMEOUT,
+    },
+    transfer::{spawn_zone_refresh, ZoneTransfer},
+    upstream::{parse_upstream_addr, UpstreamResolver},
+    parse_answer_order, parse_env_expansion, parse_miss_response, parse_zone_list, AnswerOrder,
+    DnsHandler, RecordCache, TtlJitter, UpstreamCache,
+};
 use hickory_server::ServerFuture;
-use logger::LogWorker;
+use logger::{parse_log_filter, LogWorker};
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::net::{TcpListener as TokioTcpListener, UdpSocket};
 use tokio::signal;
 use tracing::{error, info, warn};
+use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
-use web::{api::ApiState, create_api_routes, create_router};
+use web::{api::ApiState, build_cors_layer, create_api_routes, create_router, RequestLimits};
+
+/// データベースファイル
+const DATABASE_URL: &str = "sqlite:dns.db";
 
 #[tokio::main]
 async fn main() {
     // ロギング初期化
-    tracing_subscriber::fmt()
-        .with_env_filter(
+    let subscriber = tracing_subscriber::registry()
+        .with(
             EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| EnvFilter::new("info")),
         )
-        .init();
+        .with(tracing_subscriber::fmt::layer());
+
+    // OTLP の送信先が設定されていればスパンを OpenTelemetry に出力する
+    #[cfg(feature = "otel")]
+    let otlp = telemetry::otlp_endpoint_from_env()
+        .map(|endpoint| (telemetry::init_tracer_provider(&endpoint), endpoint));
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(
+        otlp.as_ref()
+            .and_then(|(provider, _)| provider.as_ref().ok())
+            .map(telemetry::layer),
+    );
+
+    subscriber.init();
 
     info!("LocalDNS Pro 起動中...");
 
-    if let Err(e) = run().await {
+    #[cfg(feature = "otel")]
+    match &otlp {
+        Some((Ok(_), endpoint)) => info!("OpenTelemetry のトレース出力有効: {}", endpoint),
+        Some((Err(e), _)) => warn!("OpenTelemetry のトレース出力を無効にします: {:?}", e),
+        None => {}
+    }
+
+    let result = run().await;
+
+    // 送信待ちのスパンを書き出す
+    #[cfg(feature = "otel")]
+    if let Some((Ok(provider), _)) = &otlp {
+        if let Err(e) = provider.shutdown() {
+            warn!("OpenTelemetry の終了処理に失敗: {}", e);
+        }
+    }
+
+    if let Err(e) = result {
         error!("エラー: {:?}", e);
         std::process::exit(1);
     }
@@ -35,80 +85,369 @@ async fn main() {
 
 async fn run() -> Result<()> {
     // データベース初期化
-    let pool = init_db("sqlite:dns.db")
+    let db_options = DbOptions::from_env();
+    let pool = init_db_with_options(DATABASE_URL, &db_options)
         .await
         .context("データベース初期化に失敗")?;
+    // クエリログは専用の接続で書き込む（レコード・設定の読み取りとロックを競合させない）
+    let log_pool = init_log_pool(DATABASE_URL, &db_options)
+        .await
+        .context("ログ書き込み用のデータベース接続に失敗")?;
 
     info!("データベース初期化完了");
 
+    // レコードのコンテンツ中の環境変数（`${VAR}`）の展開方法
+    let env_expansion_setting = db::get_setting(&pool, "content_env_expansion")
+        .await?
+        .unwrap_or_default();
+    let env_expansion = parse_env_expansion(&env_expansion_setting).unwrap_or_else(|| {
+        if !env_expansion_setting.trim().is_empty() {
+            warn!("content_env_expansion の値が不正なため off を使用: {}", env_expansion_setting);
+        }
+        EnvExpansion::Off
+    });
+
+    // catch-all レコード（`*`）で応答するローカルゾーン
+    let local_zones_setting = db::get_setting(&pool, "local_zones")
+        .await?
+        .unwrap_or_default();
+    let local_zones = parse_zone_list(&local_zones_setting).unwrap_or_else(|e| {
+        warn!("local_zones の値が不正なため catch-all を無効にします: {}", e);
+        Vec::new()
+    });
+
     // レコードキャッシュ初期化
-    let cache = RecordCache::new(pool.clone())
+    let cache = RecordCache::with_env_expansion(pool.clone(), env_expansion)
         .await
-        .context("レコードキャッシュ初期化に失敗")?;
+        .context("レコードキャッシュ初期化に失敗")?
+        .with_local_zones(local_zones);
 
     info!("レコードキャッシュ初期化完了");
 
+    // レコードのヒット数を定期的にDBへ反映
+    cache.spawn_hit_flush();
+
+    // オフラインモード（上位DNSへの転送・ゾーン転送・疎通確認を一切行わない）
+    let offline_mode = db::get_setting(&pool, "offline_mode")
+        .await?
+        .map(|s| s == "true")
+        .unwrap_or(false);
+    if offline_mode {
+        warn!("オフラインモード有効: 上位DNSへは一切問い合わせず、ローカルのレコードのみで応答します");
+    }
+
+    // セカンダリゾーン（マスターからのAXFR）
+    let axfr_master = db::get_setting(&pool, "axfr_master")
+        .await?
+        .unwrap_or_default();
+    let axfr_zone = db::get_setting(&pool, "axfr_zone")
+        .await?
+        .unwrap_or_default();
+    if offline_mode && !axfr_master.trim().is_empty() {
+        warn!("オフラインモードのためゾーン転送を行いません: {}", axfr_zone);
+    } else if !axfr_master.trim().is_empty() && !axfr_zone.trim().is_empty() {
+        let master = parse_upstream_addr(&axfr_master).context("axfr_master のパースに失敗")?;
+        let transfer = ZoneTransfer::new(master, &axfr_zone)?;
+        spawn_zone_refresh(pool.clone(), cache.clone(), transfer);
+        info!("ゾーン転送有効: {} (マスター: {})", axfr_zone, master);
+    }
+
     // ログワーカー起動
-    let log_worker = LogWorker::new(pool.clone());
+    let log_worker = LogWorker::new(log_pool);
     info!("ログワーカー起動完了");
 
     // 上位DNS設定取得
-    let primary = db::get_setting(&pool, "upstream_primary")
+    let upstream_config = dns::upstream::load_config(&pool).await?;
+
+    // 上位DNSリゾルバー作成
+    let upstream_resolver = Arc::new(UpstreamResolver::new(upstream_config));
+    let upstream_metrics = upstream_resolver.metrics();
+
+    // 上位DNS応答キャッシュ作成
+    let decrement_ttl = db::get_setting(&pool, "upstream_cache_decrement_ttl")
+        .await?
+        .map(|s| s != "false")
+        .unwrap_or(true);
+    let cache_max_entries = db::get_setting(&pool, "upstream_cache_max_entries")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(UPSTREAM_CACHE_MAX_ENTRIES);
+    // 上位DNS障害時に古い応答を返す許容期間
+    let serve_stale = if db::get_setting(&pool, "serve_stale").await?.as_deref() == Some("true") {
+        let max_stale_secs = db::get_setting(&pool, "serve_stale_max_secs")
+            .await?
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(86400);
+        Some(std::time::Duration::from_secs(max_stale_secs))
+    } else {
+        None
+    };
+    let upstream_cache = UpstreamCache::new(decrement_ttl).with_max_entries(cache_max_entries);
+
+    // DNSハンドラー作成（上位転送機能付き）
+    let mut dns_handler = DnsHandler::new(cache.clone(), log_worker.clone())
+        .with_upstream(upstream_resolver.clone())
+        .with_upstream_cache(upstream_cache)
+        .with_offline_mode(offline_mode);
+
+    // ローカルの CNAME の参照先がローカルに無い場合は上位DNSに問い合わせる（デフォルト true）
+    let chase_external_cname = db::get_setting(&pool, "chase_external_cname")
+        .await?
+        .map(|s| s != "false")
+        .unwrap_or(true);
+    dns_handler = dns_handler.with_chase_external_cname(chase_external_cname);
+
+    // 権威を持つゾーン（配下の名前は転送せずに AA 付きで応答する）
+    let authoritative_zones_setting = db::get_setting(&pool, "authoritative_zones")
+        .await?
+        .unwrap_or_default();
+    match parse_zone_list(&authoritative_zones_setting) {
+        Ok(zones) if !zones.is_empty() => {
+            info!("権威ゾーン: {}", zones.join(", "));
+            dns_handler = dns_handler.with_authoritative_zones(zones);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("authoritative_zones の値が不正なため無効にします: {}", e),
+    }
+
+    // プライベートアドレスの逆引きを上位DNSに転送しない（デフォルト true）
+    let block_private_reverse = db::get_setting(&pool, "block_private_reverse")
         .await?
-        .unwrap_or_else(|| "8.8.8.8:53".to_string());
+        .map(|s| s != "false")
+        .unwrap_or(true);
+    dns_handler = dns_handler.with_block_private_reverse(block_private_reverse);
 
-    let secondary = db::get_setting(&pool, "upstream_secondary")
+    // CHAOSクラスの version.bind への応答（空はクレートのバージョン、off で REFUSED）
+    let chaos_version = db::get_setting(&pool, "chaos_version")
         .await?
-        .unwrap_or_else(|| "1.1.1.1:53".to_string());
+        .unwrap_or_default();
+    dns_handler = dns_handler.with_chaos_version(match chaos_version.trim() {
+        "" => Some(env!("CARGO_PKG_VERSION").to_string()),
+        "off" => None,
+        version => Some(version.to_string()),
+    });
 
-    let timeout_ms = db::get_setting(&pool, "upstream_timeout_ms")
+    // 上位DNS障害時の古い応答の返却（serve-stale）
+    if let Some(max_staleness) = serve_stale {
+        dns_handler = dns_handler.with_serve_stale(max_staleness);
+        info!("serve-stale 有効: 許容期間={}秒", max_staleness.as_secs());
+    }
+
+    // 上位DNSの否定応答をキャッシュする秒数の上限（SOAから求めた値をこの秒数で丸める。0 は無効）
+    let negative_cache_max_ttl = db::get_setting(&pool, "negative_cache_max_ttl")
         .await?
         .and_then(|s| s.parse().ok())
-        .unwrap_or(2000);
+        .unwrap_or(300);
+    dns_handler = dns_handler.with_negative_cache_max_ttl(negative_cache_max_ttl);
 
-    let upstream_config = UpstreamConfig::new(&primary, &secondary, timeout_ms)
-        .context("上位DNS設定の初期化に失敗")?;
+    // 1つの問い合わせの処理時間の上限（0 は無制限）
+    let query_deadline_ms = db::get_setting(&pool, "query_deadline_ms")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(10000);
+    if query_deadline_ms > 0 {
+        dns_handler = dns_handler
+            .with_query_deadline(std::time::Duration::from_millis(query_deadline_ms));
+        info!("問い合わせの処理時間の上限: {}ms", query_deadline_ms);
+    }
 
-    info!(
-        "上位DNS設定: Primary={}, Secondary={}, Timeout={}ms",
-        primary, secondary, timeout_ms
-    );
+    // TCP 接続のタイムアウト。edns-tcp-keepalive を送ってきたクライアントにも通知する（0 は通知しない）
+    let edns_tcp_keepalive_secs: u64 = db::get_setting(&pool, "edns_tcp_keepalive_secs")
+        .await?
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(5);
+    let dns_tcp_timeout = if edns_tcp_keepalive_secs > 0 {
+        let timeout = std::time::Duration::from_secs(edns_tcp_keepalive_secs);
+        dns_handler = dns_handler.with_tcp_keepalive(timeout);
+        timeout
+    } else {
+        TCP_TIMEOUT
+    };
 
-    // 上位DNSリゾルバー作成
-    let upstream_resolver = UpstreamResolver::new(upstream_config);
+    // 応答TTLの下限・上限（max_ttl = 0 は上限なし）
+    let min_ttl = db::get_setting(&pool, "min_ttl")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    let max_ttl = db::get_setting(&pool, "max_ttl")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .filter(|ttl| *ttl > 0)
+        .unwrap_or(u32::MAX);
+    dns_handler = dns_handler.with_ttl_bounds(min_ttl, max_ttl);
 
-    // DNSハンドラー作成（上位転送機能付き）
-    let dns_handler = DnsHandler::new(cache.clone(), log_worker)
-        .with_upstream(upstream_resolver);
+    // 回答TTLの揺らぎ（±N%、0 は無効）
+    let ttl_jitter_percent = db::get_setting(&pool, "ttl_jitter_percent")
+        .await?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    if ttl_jitter_percent > 0 {
+        dns_handler = dns_handler.with_ttl_jitter(TtlJitter::new(ttl_jitter_percent));
+        info!(
+            "回答TTLの揺らぎ: ±{}%",
+            ttl_jitter_percent.min(dns::jitter::MAX_TTL_JITTER_PERCENT)
+        );
+    }
+
+    // 上位DNSなしで該当レコードが無い場合の応答コード
+    let miss_response = db::get_setting(&pool, "miss_response")
+        .await?
+        .unwrap_or_default();
+    match parse_miss_response(&miss_response) {
+        Some(code) => dns_handler = dns_handler.with_miss_response(code),
+        None if miss_response.trim().is_empty() => {}
+        None => warn!("miss_response の値が不正なため nxdomain を使用: {}", miss_response),
+    }
+
+    // クエリログに記録する問い合わせの絞り込み
+    let log_filter = db::get_setting(&pool, "log_filter")
+        .await?
+        .unwrap_or_default();
+    match parse_log_filter(&log_filter) {
+        Some(filter) => dns_handler = dns_handler.with_log_filter(filter),
+        None if log_filter.trim().is_empty() => {}
+        None => warn!("log_filter の値が不正なため all を使用: {}", log_filter),
+    }
+
+    // 複数レコード応答の並び順
+    let answer_order = db::get_setting(&pool, "answer_order")
+        .await?
+        .unwrap_or_default();
+    match parse_answer_order(&answer_order) {
+        Some(mode) => dns_handler = dns_handler.with_answer_order(AnswerOrder::new(mode)),
+        None if answer_order.trim().is_empty() => {}
+        None => warn!("answer_order の値が不正なため insertion を使用: {}", answer_order),
+    }
+
+    let dns_addr = SocketAddr::from(([127, 0, 0, 1], 53));
+
+    // サーバー自身のホスト名への問い合わせには待ち受けアドレスで応答
+    let server_hostname = db::get_setting(&pool, "server_hostname")
+        .await?
+        .unwrap_or_default();
+    if !server_hostname.trim().is_empty() {
+        dns_handler = dns_handler.with_server_hostname(&server_hostname, dns_addr.ip());
+        info!("サーバーホスト名: {} -> {}", server_hostname, dns_addr.ip());
+    }
     info!("DNSハンドラー初期化完了");
 
     // DNSサーバー起動 (UDP)
-    let dns_addr = SocketAddr::from(([127, 0, 0, 1], 53));
-    let udp_socket = UdpSocket::bind(dns_addr)
-        .await
-        .context("DNSサーバー(UDP)のバインドに失敗")?;
-    info!("DNSサーバー(UDP)起動: {}", dns_addr);
+    let dns_workers = db::get_setting(&pool, "dns_workers")
+        .await?
+        .and_then(|s| s.parse::<usize>().ok())
+        .unwrap_or(1);
+    let udp_sockets = if dns_workers <= 1 {
+        let udp_socket = UdpSocket::bind(dns_addr)
+            .await
+            .map_err(|e| bind_error(e.into(), "UDP", dns_addr))?;
+        vec![udp_socket]
+    } else {
+        bind_udp_sockets(dns_addr, dns_workers).map_err(|e| bind_error(e, "UDP", dns_addr))?
+    };
+    info!("DNSサーバー(UDP)起動: {} (ソケット数: {})", dns_addr, udp_sockets.len());
 
     // DNSサーバー起動 (TCP)
     let tcp_listener = TokioTcpListener::bind(dns_addr)
         .await
-        .context("DNSサーバー(TCP)のバインドに失敗")?;
+        .map_err(|e| bind_error(e.into(), "TCP", dns_addr))?;
     info!("DNSサーバー(TCP)起動: {}", dns_addr);
 
     // hickory-server の ServerFuture 作成
-    let mut dns_server = ServerFuture::new(dns_handler);
-    dns_server.register_socket(udp_socket);
-    dns_server.register_listener(tcp_listener, std::time::Duration::from_secs(5));
+    let mut dns_server = ServerFuture::new(dns_handler.clone());
+    for udp_socket in udp_sockets {
+        dns_server.register_socket(udp_socket);
+    }
+    dns_server.register_listener(tcp_listener, dns_tcp_timeout);
+
+    // DNSサーバー起動 (IPv6、設定時のみ。バインドできない場合は IPv4 のみで継続)
+    let dns_listen_ipv6 = db::get_setting(&pool, "dns_listen_ipv6")
+        .await?
+        .unwrap_or_default();
+    match parse_ipv6_listen_addr(&dns_listen_ipv6, dns_addr.port()) {
+        Ok(Some(addr)) => match bind_ipv6_sockets(addr) {
+            Ok((udp_socket, tcp_listener)) => {
+                dns_server.register_socket(udp_socket);
+                dns_server.register_listener(tcp_listener, dns_tcp_timeout);
+                info!("DNSサーバー(UDP/TCP)起動: {}", addr);
+            }
+            Err(e) => warn!("IPv6でのバインドに失敗したため IPv4 のみで待ち受けます: {:#}", e),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("dns_listen_ipv6 の値が不正なため無視します: {:#}", e),
+    }
 
     // Web API状態
     let api_state = ApiState {
         pool: pool.clone(),
         cache: cache.clone(),
+        upstream_metrics,
+        log_worker: log_worker.clone(),
+        // オフラインモードではヘルスチェックの疎通確認も行わない
+        upstream: (!offline_mode).then_some(upstream_resolver),
+        dns_handler: Some(dns_handler),
     };
 
+    // gRPC 管理API起動（grpc_port が 0 の場合は無効）
+    #[cfg(feature = "grpc")]
+    {
+        let grpc_port = db::get_setting(&pool, "grpc_port")
+            .await?
+            .and_then(|s| s.trim().parse::<u16>().ok())
+            .unwrap_or(0);
+        if grpc_port > 0 {
+            let grpc_addr = SocketAddr::from(([0, 0, 0, 0], grpc_port));
+            let service = grpc::LocalDnsService::new(api_state.clone()).into_server();
+            info!("gRPC API起動: {}", grpc_addr);
+            tokio::spawn(async move {
+                if let Err(e) = tonic::transport::Server::builder()
+                    .add_service(service)
+                    .serve_with_shutdown(grpc_addr, shutdown_signal())
+                    .await
+                {
+                    error!("gRPC APIの実行に失敗: {}", e);
+                }
+            });
+        }
+    }
+
     // Webルーター作成
     let api_router = create_api_routes(api_state);
-    let app = create_router(api_router);
+    let cors_allowed_origins = db::get_setting(&pool, "cors_allowed_origins")
+        .await?
+        .unwrap_or_default();
+    let defaults = RequestLimits::default();
+    let request_limits = RequestLimits {
+        max_body_bytes: db::get_setting(&pool, "max_request_body_bytes")
+            .await?
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(defaults.max_body_bytes),
+        max_concurrent_requests: db::get_setting(&pool, "max_concurrent_requests")
+            .await?
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(defaults.max_concurrent_requests),
+    };
+    let app = create_router(
+        api_router,
+        build_cors_layer(&cors_allowed_origins),
+        request_limits,
+    );
+
+    // Unix ドメインソケットでも Web API を待ち受ける（web_unix_socket が空の場合は無効）
+    #[cfg(unix)]
+    let _web_socket_file = {
+        let path = db::get_setting(&pool, "web_unix_socket")
+            .await?
+            .unwrap_or_default();
+        let path = path.trim();
+        if path.is_empty() {
+            None
+        } else {
+            let (listener, socket_file) = web::unix::bind_unix_socket(std::path::Path::new(path))?;
+            info!("Web API起動 (Unix ドメインソケット): {}", path);
+            tokio::spawn(web::unix::serve_unix(listener, app.clone()));
+            Some(socket_file)
+        }
+    };
 
     // Webサーバー起動
     let web_addr = SocketAddr::from(([0, 0, 0, 0], 3000));
@@ -118,12 +457,28 @@ async fn run() -> Result<()> {
         .await
         .context("Webサーバーのバインドに失敗")?;
 
+    // ロードバランサー配下では PROXY プロトコルのヘッダーから元のクライアントアドレスを受け取る
+    let web_proxy_protocol = db::get_setting(&pool, "web_proxy_protocol")
+        .await?
+        .is_some_and(|s| s == "true");
+    let web_server = async move {
+        if web_proxy_protocol {
+            info!("Web API で PROXY プロトコル v2 を有効化");
+            web::proxy::serve_proxy_protocol(listener, app).await;
+            Ok(())
+        } else {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+        }
+    };
+
     // DNSサーバーとWebサーバーを並行実行
     tokio::select! {
         result = dns_server.block_until_done() => {
             result.context("DNSサーバーの実行に失敗")?;
         }
-        result = axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()) => {
+        result = web_server => {
             result.context("Webサーバーの実行に失敗")?;
         }
         _ = shutdown_signal() => {
@@ -131,6 +486,14 @@ async fn run() -> Result<()> {
         }
     }
 
+    // 処理中のDNS問い合わせを完了させ、そのクエリログも含めて書き出してから終了
+    drain_dns_server(&mut dns_server, TCP_TIMEOUT).await;
+    log_worker.shutdown().await;
+    info!("ログワーカー停止完了");
+    if let Err(e) = cache.flush_hits().await {
+        warn!("レコードのヒット数の反映に失敗: {}", e);
+    }
+
     info!("LocalDNS Pro を終了します");
     Ok(())
 }
@@ -0,0 +1,68 @@
+//! OpenTelemetry（OTLP）へのトレース出力（`otel` フィーチャー有効時のみ）
+//!
+//! `tracing` のスパン（DNS問い合わせの `lookup`、Web API の `http_request` など）を
+//! OTLP（gRPC）でコレクターに送る。
+
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// OTLP の送信先を指定する環境変数（OpenTelemetry の標準の名前）
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// 環境変数から OTLP の送信先を取得（未設定・空の場合は None で、トレースを出力しない）
+pub fn otlp_endpoint_from_env() -> Option<String> {
+    std::env::var(OTLP_ENDPOINT_ENV)
+        .ok()
+        .map(|endpoint| endpoint.trim().to_string())
+        .filter(|endpoint| !endpoint.is_empty())
+}
+
+/// OTLP（gRPC）でスパンを送るトレーサープロバイダーを作成
+/// 送信はバックグラウンドでまとめて行う（tokio ランタイム内で呼び出すこと）
+pub fn init_tracer_provider(endpoint: &str) -> Result<TracerProvider> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .with_context(|| format!("OTLP エクスポーターの作成に失敗: {}", endpoint))?;
+
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            env!("CARGO_PKG_NAME"),
+        )]))
+        .build())
+}
+
+/// スパンをトレーサープロバイダーに渡す `tracing_subscriber` のレイヤー
+pub fn layer<S>(provider: &TracerProvider) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[tokio::test]
+    async fn test_tracer_initializes_with_endpoint() {
+        // 送信先に接続できなくても作成・スパンの記録・終了で失敗しない
+        let provider = init_tracer_provider("http://127.0.0.1:4317").unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer(&provider));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("lookup", name = "app.local.test").in_scope(|| {});
+        });
+        let _ = provider.shutdown();
+    }
+}
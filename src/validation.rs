@@ -0,0 +1,533 @@
+//! レコードの入力検証
+//!
+//! 管理API・gRPC からの登録と、ゾーン転送で取り込むレコードで同じ検証を使う。
+
+use crate::db::{compile_regex_pattern, has_env_reference, CreateRecordRequest, PatternType};
+use crate::dns::{parse_caa_content, parse_svcb_content, parse_tlsa_content};
+use hickory_proto::rr::Name;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// レコードに指定できるTTLの上限（24時間）
+pub const MAX_TTL: i64 = 86400;
+
+/// フィールド単位のバリデーションエラー
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+/// レコードのバリデーション
+/// 全ての違反をまとめて返す（API・gRPC での登録と、ゾーン転送の取り込みで共通）
+pub fn validate_record(req: &CreateRecordRequest) -> Result<(), Vec<FieldError>> {
+    let mut errors = Vec::new();
+
+    // ドメインパターンの検証
+    if req.domain_pattern.trim().is_empty() {
+        errors.push(FieldError::new(
+            "domain_pattern",
+            "ドメインパターンを指定してください",
+        ));
+    } else {
+        errors.extend(check_domain_pattern(&req.domain_pattern, req.pattern_type));
+    }
+
+    // レコードタイプの検証
+    let record_type_valid = matches!(
+        req.record_type.as_str(),
+        "A" | "AAAA" | "AUTO" | "CNAME" | "NS" | "HTTPS" | "SVCB" | "CAA" | "TLSA"
+    );
+    if !record_type_valid {
+        errors.push(FieldError::new(
+            "record_type",
+            format!("サポートされていないレコードタイプです: {}", req.record_type),
+        ));
+    }
+
+    // コンテンツの検証（配列で複数指定した場合は値ごとに検証）
+    let contents = req.content.values();
+    if contents.is_empty() || contents.iter().any(|content| content.trim().is_empty()) {
+        errors.push(FieldError::new(
+            "content",
+            "コンテンツを指定してください",
+        ));
+    } else if req.record_type == "CNAME" && contents.len() > 1 {
+        errors.push(FieldError::new(
+            "content",
+            "CNAMEには複数のコンテンツを指定できません",
+        ));
+    } else {
+        let unique: std::collections::HashSet<&str> =
+            contents.iter().map(|content| content.trim()).collect();
+        if unique.len() != contents.len() {
+            errors.push(FieldError::new("content", "コンテンツが重複しています"));
+        }
+
+        // レコードタイプごとのコンテンツ検証
+        // 環境変数参照（`${VAR}`）を含む場合は読み込み時に展開されるため形式チェックを省略する
+        if record_type_valid {
+            errors.extend(
+                contents
+                    .iter()
+                    .filter(|content| !has_env_reference(content))
+                    .filter_map(|content| check_content(&req.record_type, content)),
+            );
+        }
+    }
+
+    // TTLの検証（0 はクライアントにキャッシュさせないレコード）
+    if req.ttl < 0 || req.ttl > MAX_TTL {
+        errors.push(FieldError::new(
+            "ttl",
+            "TTLは0秒から86400秒(24時間)の範囲で指定してください",
+        ));
+    }
+
+    // 重みの検証（0 は常に末尾に並べる）
+    if req.weight < 0 {
+        errors.push(FieldError::new("weight", "重みは0以上で指定してください"));
+    }
+
+    // 転送指定の検証（NS は委任に使うため転送の対象にならない）
+    errors.extend(check_passthrough(&req.record_type, req.passthrough));
+
+    // 問い合わせ元の範囲の検証
+    errors.extend(check_client_subnet(req.client_subnet.as_deref()));
+
+    // タグの検証
+    errors.extend(check_tags(&req.tags));
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// レコードタイプごとのコンテンツ検証
+fn check_content(record_type: &str, content: &str) -> Option<FieldError> {
+    let message = match record_type {
+        "A" => {
+            use std::net::Ipv4Addr;
+            use std::str::FromStr;
+            Ipv4Addr::from_str(content)
+                .is_err()
+                .then(|| "無効なIPv4アドレス形式です".to_string())
+        }
+        "AAAA" => {
+            use std::net::Ipv6Addr;
+            use std::str::FromStr;
+            Ipv6Addr::from_str(content)
+                .is_err()
+                .then(|| "無効なIPv6アドレス形式です".to_string())
+        }
+        "AUTO" => {
+            // カンマ区切りのIPv4/IPv6アドレス（各ファミリー1つまで）
+            let addresses: Vec<_> = content.split(',').map(str::trim).collect();
+            let parsed: Vec<std::net::IpAddr> =
+                addresses.iter().filter_map(|ip| ip.parse().ok()).collect();
+            let v4_count = parsed.iter().filter(|ip| ip.is_ipv4()).count();
+            let v6_count = parsed.len() - v4_count;
+            if parsed.len() != addresses.len() {
+                Some("AUTOには IPv4/IPv6 アドレスをカンマ区切りで指定してください".to_string())
+            } else if v4_count > 1 || v6_count > 1 {
+                Some("AUTOに指定できるアドレスは IPv4・IPv6 それぞれ1つまでです".to_string())
+            } else {
+                None
+            }
+        }
+        "CNAME" => {
+            // `$1` などのテンプレートは仮のラベルに置き換えてから名前として検証する
+            let target = (1..=9).fold(content.replace("${label}", "x"), |target, index| {
+                target.replace(&format!("${}", index), "x")
+            });
+            if content.contains(' ') {
+                Some("CNAMEに空白文字を含めることはできません".to_string())
+            } else if content.parse::<std::net::IpAddr>().is_ok() {
+                Some("CNAMEにIPアドレスは指定できません。A / AAAA レコードを使用してください".to_string())
+            } else if Name::from_str(&target).is_err() {
+                Some(format!("CNAMEの参照先が有効なホスト名ではありません: {}", content))
+            } else {
+                None
+            }
+        }
+        "NS" => {
+            // ネームサーバーはホスト名である必要がある
+            (content.contains(' ') || content.parse::<std::net::IpAddr>().is_ok())
+                .then(|| "NSにはネームサーバーのホスト名を指定してください".to_string())
+        }
+        "HTTPS" | "SVCB" => parse_svcb_content(content)
+            .err()
+            .map(|e| format!("無効な{}コンテンツです: {}", record_type, e)),
+        "CAA" => parse_caa_content(content)
+            .err()
+            .map(|e| format!("無効なCAAコンテンツです: {}", e)),
+        "TLSA" => parse_tlsa_content(content)
+            .err()
+            .map(|e| format!("無効なTLSAコンテンツです: {}", e)),
+        _ => None,
+    };
+
+    message.map(|message| FieldError::new("content", message))
+}
+
+/// 正規表現パターンのバリデーション（コンパイルできない・複雑すぎるものは不可）
+pub fn check_domain_pattern(domain_pattern: &str, pattern_type: PatternType) -> Option<FieldError> {
+    match pattern_type {
+        PatternType::Glob => None,
+        PatternType::Regex => compile_regex_pattern(domain_pattern)
+            .err()
+            .map(|message| FieldError::new("domain_pattern", message)),
+    }
+}
+
+/// 転送指定のバリデーション（NSレコードは問い合わせへの回答に使わないため指定不可）
+pub fn check_passthrough(record_type: &str, passthrough: bool) -> Option<FieldError> {
+    (passthrough && record_type == "NS").then(|| {
+        FieldError::new("passthrough", "NSレコードには passthrough を指定できません")
+    })
+}
+
+/// 問い合わせ元の範囲のバリデーション（空文字列は範囲の指定なし）
+pub fn check_client_subnet(client_subnet: Option<&str>) -> Option<FieldError> {
+    let subnet = client_subnet.map(str::trim).filter(|subnet| !subnet.is_empty())?;
+    subnet.parse::<ipnet::IpNet>().is_err().then(|| {
+        FieldError::new(
+            "client_subnet",
+            format!("CIDR形式で指定してください（例: 10.0.0.0/8）: {}", subnet),
+        )
+    })
+}
+
+/// タグのバリデーション（カンマ区切りで保存するためカンマは使用不可）
+pub fn check_tags(tags: &[String]) -> Option<FieldError> {
+    tags.iter()
+        .any(|tag| tag.contains(','))
+        .then(|| FieldError::new("tags", "タグにカンマを含めることはできません"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::RecordContent;
+
+    #[tokio::test]
+    async fn test_validate_record_ipv6() {
+        // 有効なIPv6
+        let req = CreateRecordRequest {
+            domain_pattern: "app.local.test".to_string(),
+            record_type: "AAAA".to_string(),
+            content: "::1".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_ok());
+
+        // 無効なIPv6
+        let req = CreateRecordRequest {
+            domain_pattern: "app.local.test".to_string(),
+            record_type: "AAAA".to_string(),
+            content: "invalid-ipv6".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_auto() {
+        let auto = |content: &str| CreateRecordRequest {
+            domain_pattern: "host.local.test".to_string(),
+            record_type: "AUTO".to_string(),
+            content: content.into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+
+        assert!(validate_record(&auto("192.168.1.10, fd00::10")).is_ok());
+        assert!(validate_record(&auto("fd00::10")).is_ok());
+        assert!(validate_record(&auto("192.168.1.10, not-an-ip")).is_err());
+        assert!(validate_record(&auto("192.168.1.10, 192.168.1.11")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_cname() {
+        // 有効なCNAME
+        let req = CreateRecordRequest {
+            domain_pattern: "alias.local.test".to_string(),
+            record_type: "CNAME".to_string(),
+            content: "target.local.test".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_ok());
+
+        // 空白を含むCNAME（無効）
+        let req = CreateRecordRequest {
+            domain_pattern: "alias.local.test".to_string(),
+            record_type: "CNAME".to_string(),
+            content: "invalid target".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_err());
+
+        let cname = |content: &str| CreateRecordRequest {
+            domain_pattern: "%.dev.local".to_string(),
+            record_type: "CNAME".to_string(),
+            content: content.into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+
+        // IPアドレスを指定したCNAME（A / AAAA を使うよう案内する）
+        match validate_record(&cname("10.0.0.1")) {
+            Err(errors) => {
+                assert!(errors[0].message.contains("A / AAAA"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(validate_record(&cname("fd00::1")).is_err());
+
+        // 名前として不正（ラベルが63文字超）
+        assert!(validate_record(&cname(&format!("{}.local.test", "a".repeat(64)))).is_err());
+
+        // 有効なホスト名・テンプレートを含む参照先
+        assert!(validate_record(&cname("backend.local.test")).is_ok());
+        assert!(validate_record(&cname("$1.backend.local")).is_ok());
+        assert!(validate_record(&cname("${label}.backend.local")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_empty_content() {
+        let req = CreateRecordRequest {
+            domain_pattern: "app.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "   ".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_env_reference() {
+        let req = CreateRecordRequest {
+            domain_pattern: "backend.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "${BACKEND_IP}".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_ok());
+    }
+
+    #[test]
+    fn test_validate_record_regex_pattern() {
+        let regex = |domain_pattern: &str| CreateRecordRequest {
+            domain_pattern: domain_pattern.to_string(),
+            record_type: "A".to_string(),
+            content: "10.0.0.1".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Regex,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+
+        assert!(validate_record(&regex(r"(api|web)-\d+\.local\.test")).is_ok());
+        assert!(validate_record(&regex("(api.local.test")).is_err());
+        assert!(validate_record(&regex(r"(\w{100}){100}")).is_err());
+    }
+
+    #[test]
+    fn test_validate_record_client_subnet() {
+        let scoped = |client_subnet: &str| CreateRecordRequest {
+            domain_pattern: "split.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "10.0.0.1".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: Some(client_subnet.to_string()),
+            tags: Vec::new(),
+        };
+
+        assert!(validate_record(&scoped("10.0.0.0/8")).is_ok());
+        assert!(validate_record(&scoped("fd00::/64")).is_ok());
+        assert!(validate_record(&scoped("")).is_ok());
+        assert!(validate_record(&scoped("10.0.0.0/33")).is_err());
+        assert!(validate_record(&scoped("office")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_ttl_zero() {
+        let req = CreateRecordRequest {
+            domain_pattern: "failover.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "192.168.1.1".into(),
+            ttl: 0,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_ttl_too_high() {
+        let req = CreateRecordRequest {
+            domain_pattern: "app.local.test".to_string(),
+            record_type: "A".to_string(),
+            content: "192.168.1.1".into(),
+            ttl: 100000,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_err());
+    }
+
+    #[test]
+    fn test_validate_record_multiple_contents() {
+        let req = |record_type: &str, content: &[&str]| CreateRecordRequest {
+            domain_pattern: "app.local.test".to_string(),
+            record_type: record_type.to_string(),
+            content: RecordContent::Multiple(content.iter().map(|c| c.to_string()).collect()),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+
+        assert!(validate_record(&req("A", &["10.0.0.1", "10.0.0.2"])).is_ok());
+        assert!(validate_record(&req("A", &[])).is_err());
+        assert!(validate_record(&req("A", &["10.0.0.1", "10.0.0.1"])).is_err());
+        assert!(validate_record(&req("CNAME", &["a.local.test", "b.local.test"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_https() {
+        let req = CreateRecordRequest {
+            domain_pattern: "app.local.test".to_string(),
+            record_type: "HTTPS".to_string(),
+            content: "1 . alpn=h2,h3".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_ok());
+
+        let req = CreateRecordRequest {
+            domain_pattern: "app.local.test".to_string(),
+            record_type: "SVCB".to_string(),
+            content: "not-a-priority .".into(),
+            ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_ns() {
+        let req = CreateRecordRequest {
+            domain_pattern: "sub.local.test".to_string(),
+            record_type: "NS".to_string(),
+            content: "ns1.sub.local.test".into(),
+            ttl: 300,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_ok());
+
+        let req = CreateRecordRequest {
+            domain_pattern: "sub.local.test".to_string(),
+            record_type: "NS".to_string(),
+            content: "10.0.0.53".into(),
+            ttl: 300,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
+        };
+        assert!(validate_record(&req).is_err());
+    }
+}
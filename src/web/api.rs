@@ -1,13 +1,32 @@
 use crate::db::*;
-use crate::dns::RecordCache;
+use crate::dns::{
+    parse_answer_order, parse_env_expansion, parse_miss_response, parse_zone_list,
+    socket::parse_ipv6_listen_addr,
+    upstream::{self, parse_bind_addr, parse_ecs, parse_upstream_server, UpstreamResolver},
+    DnsHandler, RecordCache, UpstreamMetrics,
+};
+use crate::logger::{parse_log_filter, LogWorker};
+use crate::validation::{
+    check_client_subnet, check_domain_pattern, check_passthrough, check_tags, validate_record,
+    FieldError,
+};
+use crate::web::import::{parse_dnsmasq, SkippedLine};
+use anyhow::anyhow;
+use futures_util::StreamExt;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     Json, Router,
     routing::{delete, get, post, put},
 };
+use hickory_proto::rr::{Name, Record as DnsRecord, RecordType};
+use serde::Deserialize;
 use serde_json::json;
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 
 /// API状態
@@ -15,35 +34,209 @@ use std::sync::Arc;
 pub struct ApiState {
     pub pool: DbPool,
     pub cache: RecordCache,
+    pub upstream_metrics: UpstreamMetrics,
+    pub log_worker: LogWorker,
+    /// 上位DNS（疎通確認に使用。転送無効時は None）
+    pub upstream: Option<Arc<UpstreamResolver>>,
+    /// DNSハンドラ（JSON形式の名前解決 `/resolve` に使用）
+    pub dns_handler: Option<DnsHandler>,
 }
 
 /// APIルートを作成
 pub fn create_api_routes(state: ApiState) -> Router {
+    let state = Arc::new(state);
     Router::new()
         // レコード関連
         .route("/api/records", get(get_records))
         .route("/api/records", post(create_record_handler))
+        .route("/api/records", delete(delete_records_handler))
+        .route("/api/records/deleted", get(get_deleted_records_handler))
+        .route("/api/records/search", get(search_records_handler))
+        .route("/api/records/by-cidr", get(records_by_cidr_handler))
+        .route("/api/records/import/dnsmasq", post(import_dnsmasq_handler))
+        .route("/api/records/validate", post(validate_record_handler))
+        .route("/api/records/invalid", get(get_invalid_records_handler))
         .route("/api/records/:id", get(get_record))
         .route("/api/records/:id", put(update_record_handler))
         .route("/api/records/:id", delete(delete_record_handler))
+        .route("/api/records/:id/restore", post(restore_record_handler))
         // ログ関連
         .route("/api/logs", get(get_logs))
+        .route("/api/logs", delete(delete_logs_handler))
+        .route("/api/logs/export", get(export_logs_handler))
+        .route("/api/logs/timeseries", get(log_timeseries_handler))
         // 設定関連
         .route("/api/settings", get(get_settings))
         .route("/api/settings/:key", put(update_setting_handler))
+        // APIキー関連（admin スコープのみ）
+        .route("/api/keys", get(get_api_keys_handler))
+        .route("/api/keys", post(create_api_key_handler))
+        .route("/api/keys/:id", put(update_api_key_handler))
+        .route("/api/keys/:id", delete(delete_api_key_handler))
+        // バックアップ（レコード・設定の全体）
+        .route("/api/export/backup", get(export_backup_handler))
+        .route("/api/import/backup", post(import_backup_handler))
+        // キャッシュ関連
+        .route("/api/cache/upstream/flush", post(flush_upstream_cache_handler))
         // ヘルスチェック
         .route("/api/health", get(health_check))
-        .with_state(Arc::new(state))
+        .route("/api/health/detailed", get(detailed_health_check))
+        // バージョン情報
+        .route("/api/version", get(version_info))
+        .route("/api/db/info", get(db_info))
+        // メトリクス（Prometheus形式）
+        .route("/metrics", get(metrics_handler))
+        // JSON形式の名前解決（Google / Cloudflare 互換）
+        .route("/resolve", get(resolve_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_api_key))
+        .with_state(state)
+}
+
+/// 操作に必要なAPIキーのスコープ（None の場合は認証不要）
+/// APIキーの管理は `admin`、参照（GET / HEAD）は `read`、それ以外は `write` が必要
+fn required_scope(method: &Method, path: &str) -> Option<ApiKeyScope> {
+    if path == "/api/health" {
+        // 死活監視はキーなしで応答する
+        None
+    } else if path == "/api/keys" || path.starts_with("/api/keys/") {
+        Some(ApiKeyScope::Admin)
+    } else if method == Method::GET || method == Method::HEAD {
+        Some(ApiKeyScope::Read)
+    } else {
+        Some(ApiKeyScope::Write)
+    }
+}
+
+/// APIキーによる認証（`Authorization: Bearer <キー>`）
+/// APIキーが1つも登録されていない間は認証しない。キーが無い・不正な場合は 401、
+/// スコープが足りない場合は 403 を返す
+async fn require_api_key(
+    State(state): State<Arc<ApiState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(required) = required_scope(req.method(), req.uri().path()) else {
+        return Ok(next.run(req).await);
+    };
+
+    let key = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(bearer_token);
+    let operation = format!("{} {}", req.method(), req.uri().path());
+    authorize_api_key(&state.pool, key, required, &operation, &client_addr(&req)).await?;
+
+    Ok(next.run(req).await)
+}
+
+/// `Bearer <キー>` 形式の値からキーを取り出す
+pub(crate) fn bearer_token(value: &str) -> Option<&str> {
+    value
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// APIキーを検証し、操作に必要なスコープを持つか確認する（REST API・gRPC 共通）
+/// APIキーが1つも登録されていない間は認証しない。`operation` と `client` はログ用
+pub(crate) async fn authorize_api_key(
+    pool: &DbPool,
+    key: Option<&str>,
+    required: ApiKeyScope,
+    operation: &str,
+    client: &str,
+) -> Result<(), AppError> {
+    if count_api_keys(pool).await? == 0 {
+        return Ok(());
+    }
+
+    let Some(key) = key else {
+        return Err(AppError::Unauthorized);
+    };
+    let Some(api_key) = find_api_key(pool, key).await? else {
+        tracing::warn!("不正なAPIキーによるリクエスト: {} (接続元: {})", operation, client);
+        return Err(AppError::Unauthorized);
+    };
+    if !api_key.scope.allows(required) {
+        tracing::warn!(
+            "APIキーのスコープ不足: {} ({:?}) {} (接続元: {})",
+            api_key.name,
+            api_key.scope,
+            operation,
+            client
+        );
+        return Err(AppError::Forbidden);
+    }
+    Ok(())
+}
+
+/// リクエストの接続元（PROXY プロトコル有効時は元のクライアント。Unix ドメインソケットでは不明）
+fn client_addr(req: &Request) -> String {
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// レコード一覧の絞り込み条件
+#[derive(Debug, Deserialize)]
+struct RecordFilter {
+    tag: Option<String>,
 }
 
-/// レコード一覧取得
+/// レコード一覧取得（`?tag=` でタグ絞り込み）
 async fn get_records(
     State(state): State<Arc<ApiState>>,
+    Query(filter): Query<RecordFilter>,
 ) -> Result<Json<Vec<Record>>, AppError> {
-    let records = get_all_records(&state.pool).await?;
+    let records = match filter.tag.as_deref().map(str::trim) {
+        Some(tag) if !tag.is_empty() => get_records_by_tag(&state.pool, tag).await?,
+        _ => get_all_records(&state.pool).await?,
+    };
     Ok(Json(records))
 }
 
+/// レコード一括削除の条件
+#[derive(Debug, Deserialize)]
+struct RecordDeleteFilter {
+    tag: Option<String>,
+    pattern: Option<String>,
+}
+
+/// 条件に該当するレコードの一括削除（`?tag=` 完全一致・`?pattern=` 部分一致、併用時は AND）
+/// 全件削除を防ぐため、少なくとも一方の条件を必須とする
+async fn delete_records_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(filter): Query<RecordDeleteFilter>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let non_empty = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    let tag = non_empty(&filter.tag);
+    let pattern = non_empty(&filter.pattern);
+    if tag.is_none() && pattern.is_none() {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "filter",
+            "一括削除には tag または pattern を指定してください",
+        )]));
+    }
+
+    let deleted = delete_records_matching(&state.pool, tag.as_deref(), pattern.as_deref()).await?;
+    if deleted > 0 {
+        // キャッシュを再読み込み
+        if let Err(e) = state.cache.reload().await {
+            tracing::error!("キャッシュ再読み込み失敗: {}", e);
+        }
+    }
+    tracing::info!("レコードを一括削除: {} 件", deleted);
+    Ok(Json(json!({ "deleted": deleted })))
+}
+
 /// レコード取得
 async fn get_record(
     State(state): State<Arc<ApiState>>,
@@ -63,79 +256,99 @@ async fn create_record_handler(
     // バリデーション
     validate_record(&req)?;
 
-    let id = create_record(&state.pool, req).await?;
+    // 重複チェック
+    for content in req.content.values() {
+        if let Some(existing_id) =
+            find_duplicate_record(&state.pool, &req.domain_pattern, &req.record_type, content)
+                .await?
+        {
+            return Err(AppError::Conflict(format!(
+                "同一のレコードが既に存在します (id: {})",
+                existing_id
+            )));
+        }
+    }
+
+    let ids = create_record(&state.pool, req).await?;
 
     // キャッシュを再読み込み
     if let Err(e) = state.cache.reload().await {
         tracing::error!("キャッシュ再読み込み失敗: {}", e);
     }
 
-    Ok(Json(json!({ "id": id })))
+    // コンテンツを複数指定した場合は作成した全てのIDを `ids` で返す
+    Ok(Json(json!({ "id": ids.first(), "ids": ids })))
 }
 
-/// レコードのバリデーション
-fn validate_record(req: &CreateRecordRequest) -> Result<(), AppError> {
-    // ドメインパターンの検証
-    if req.domain_pattern.trim().is_empty() {
-        return Err(AppError::BadRequest(
-            "ドメインパターンを指定してください".to_string(),
-        ));
-    }
-
-    // レコードタイプの検証
-    if !matches!(req.record_type.as_str(), "A" | "AAAA" | "CNAME") {
-        return Err(AppError::BadRequest(format!(
-            "サポートされていないレコードタイプです: {}",
-            req.record_type
-        )));
+/// レコードの検証のみ（保存しない）
+/// 作成時と同じ検証と重複チェックを行い、問題があれば全てまとめて 400 で返す
+async fn validate_record_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(req): Json<CreateRecordRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut errors = validate_record(&req).err().unwrap_or_default();
+
+    for content in req.content.values() {
+        if let Some(existing_id) =
+            find_duplicate_record(&state.pool, &req.domain_pattern, &req.record_type, content)
+                .await?
+        {
+            errors.push(FieldError::new(
+                "content",
+                format!("同一のレコードが既に存在します (id: {})", existing_id),
+            ));
+        }
     }
 
-    // コンテンツの検証
-    if req.content.trim().is_empty() {
-        return Err(AppError::BadRequest(
-            "コンテンツを指定してください".to_string(),
-        ));
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
     }
+    Ok(Json(json!({ "valid": true })))
+}
 
-    // レコードタイプごとのコンテンツ検証
-    match req.record_type.as_str() {
-        "A" => {
-            use std::net::Ipv4Addr;
-            use std::str::FromStr;
-            if Ipv4Addr::from_str(&req.content).is_err() {
-                return Err(AppError::BadRequest(
-                    "無効なIPv4アドレス形式です".to_string(),
-                ));
-            }
-        }
-        "AAAA" => {
-            use std::net::Ipv6Addr;
-            use std::str::FromStr;
-            if Ipv6Addr::from_str(&req.content).is_err() {
-                return Err(AppError::BadRequest(
-                    "無効なIPv6アドレス形式です".to_string(),
-                ));
-            }
+/// dnsmasq の設定（`address=` / `cname=` の行）からレコードを取り込む
+/// 不正なレコードや既に存在するレコードは取り込まず、取り込まなかった行と理由を `skipped` で返す
+async fn import_dnsmasq_handler(
+    State(state): State<Arc<ApiState>>,
+    body: String,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let import = parse_dnsmasq(&body);
+    let mut skipped = import.skipped;
+
+    let mut ids = Vec::new();
+    for (line, req) in import.records {
+        let label = format!("{} {}", req.domain_pattern, req.record_type);
+        if let Err(errors) = validate_record(&req) {
+            let reasons: Vec<String> = errors.into_iter().map(|error| error.message).collect();
+            skipped.push(SkippedLine {
+                line,
+                reason: format!("{}: {}", label, reasons.join(", ")),
+            });
+            continue;
         }
-        "CNAME" => {
-            // CNAMEは基本的な文字列チェックのみ
-            if req.content.contains(' ') {
-                return Err(AppError::BadRequest(
-                    "CNAMEに空白文字を含めることはできません".to_string(),
-                ));
-            }
+
+        let content = &req.content.values()[0];
+        if find_duplicate_record(&state.pool, &req.domain_pattern, &req.record_type, content)
+            .await?
+            .is_some()
+        {
+            skipped.push(SkippedLine {
+                line,
+                reason: format!("{}: 同一のレコードが既に存在します", label),
+            });
+            continue;
         }
-        _ => {}
+        ids.extend(create_record(&state.pool, req).await?);
     }
 
-    // TTLの検証
-    if req.ttl < 1 || req.ttl > 86400 {
-        return Err(AppError::BadRequest(
-            "TTLは1秒から86400秒(24時間)の範囲で指定してください".to_string(),
-        ));
+    if !ids.is_empty() {
+        if let Err(e) = state.cache.reload().await {
+            tracing::error!("キャッシュ再読み込み失敗: {}", e);
+        }
     }
 
-    Ok(())
+    skipped.sort_by_key(|skipped| skipped.line);
+    Ok(Json(json!({ "imported": ids.len(), "ids": ids, "skipped": skipped })))
 }
 
 /// レコード更新
@@ -144,6 +357,32 @@ async fn update_record_handler(
     Path(id): Path<i64>,
     Json(req): Json<UpdateRecordRequest>,
 ) -> Result<StatusCode, AppError> {
+    let errors: Vec<FieldError> = [
+        req.tags.as_deref().and_then(check_tags),
+        check_client_subnet(req.client_subnet.as_deref()),
+        req.record_type
+            .as_deref()
+            .zip(req.passthrough)
+            .and_then(|(record_type, passthrough)| check_passthrough(record_type, passthrough)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+
+    // パターンか種類のどちらかだけ変える場合も、変更後の組み合わせで検証する
+    if req.domain_pattern.is_some() || req.pattern_type.is_some() {
+        if let Some(record) = get_record_by_id(&state.pool, id).await? {
+            let domain_pattern = req.domain_pattern.as_deref().unwrap_or(&record.domain_pattern);
+            let pattern_type = req.pattern_type.unwrap_or(record.pattern_type);
+            if let Some(error) = check_domain_pattern(domain_pattern, pattern_type) {
+                return Err(AppError::Validation(vec![error]));
+            }
+        }
+    }
+
     let updated = update_record(&state.pool, id, req).await?;
 
     if updated {
@@ -175,14 +414,345 @@ async fn delete_record_handler(
     }
 }
 
-/// ログ一覧取得
+/// レコード検索条件
+#[derive(Debug, Deserialize)]
+struct RecordSearch {
+    content: Option<String>,
+    pattern: Option<String>,
+}
+
+/// レコード検索（`?content=` と `?pattern=` の部分一致、併用時は AND）
+async fn search_records_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(search): Query<RecordSearch>,
+) -> Result<Json<Vec<Record>>, AppError> {
+    let non_empty = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    let content = non_empty(&search.content);
+    let pattern = non_empty(&search.pattern);
+
+    let records = search_records(&state.pool, content.as_deref(), pattern.as_deref()).await?;
+    Ok(Json(records))
+}
+
+/// CIDR検索条件
+#[derive(Debug, Deserialize)]
+struct CidrFilter {
+    cidr: String,
+}
+
+/// アドレスが指定のCIDRに含まれる A / AAAA / AUTO レコードの一覧取得
+async fn records_by_cidr_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(filter): Query<CidrFilter>,
+) -> Result<Json<Vec<Record>>, AppError> {
+    let cidr = filter.cidr.trim();
+    let net: ipnet::IpNet = cidr.parse().map_err(|_| {
+        AppError::Validation(vec![FieldError::new(
+            "cidr",
+            format!("CIDRの形式が不正です: {}", cidr),
+        )])
+    })?;
+
+    let records = get_records_in_cidr(&state.pool, &net).await?;
+    Ok(Json(records))
+}
+
+/// 削除済みレコード一覧取得
+async fn get_deleted_records_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<Json<Vec<Record>>, AppError> {
+    let records = get_deleted_records(&state.pool).await?;
+    Ok(Json(records))
+}
+
+/// コンテンツを解析できず応答に使われていないレコード一覧（直近のキャッシュ読み込み時点）
+async fn get_invalid_records_handler(State(state): State<Arc<ApiState>>) -> Json<Vec<Record>> {
+    Json(state.cache.invalid_records().await)
+}
+
+/// 削除済みレコードの復元
+async fn restore_record_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    let Some(record) = get_deleted_record(&state.pool, id).await? else {
+        return Err(AppError::NotFound);
+    };
+    // 削除後に同じレコードが作り直されている場合、復元すると重複した回答になるため拒否する
+    if let Some(existing_id) = find_duplicate_record(
+        &state.pool,
+        &record.domain_pattern,
+        &record.record_type,
+        &record.content,
+    )
+    .await?
+    {
+        return Err(AppError::Conflict(format!(
+            "同一のレコードが既に存在するため復元できません (id: {})",
+            existing_id
+        )));
+    }
+
+    let restored = restore_record(&state.pool, id).await?;
+
+    if restored {
+        // キャッシュを再読み込み
+        if let Err(e) = state.cache.reload().await {
+            tracing::error!("キャッシュ再読み込み失敗: {}", e);
+        }
+        Ok(StatusCode::OK)
+    } else {
+        Err(AppError::NotFound)
+    }
+}
+
+/// ログ一覧の取得条件
+#[derive(Debug, Deserialize)]
+struct LogFilter {
+    /// 取得件数（既定 100、上限 `MAX_LOG_LIMIT` を超える値は上限に丸める）
+    limit: Option<i64>,
+}
+
+/// ログ一覧取得（`?limit=` で件数指定）
 async fn get_logs(
     State(state): State<Arc<ApiState>>,
+    Query(filter): Query<LogFilter>,
 ) -> Result<Json<Vec<QueryLog>>, AppError> {
-    let logs = get_recent_logs(&state.pool, 100).await?;
+    let logs = get_recent_logs(&state.pool, filter.limit.unwrap_or(100)).await?;
     Ok(Json(logs))
 }
 
+/// ログのエクスポート条件
+#[derive(Debug, Deserialize)]
+struct LogExportFilter {
+    /// 出力形式（現在は `csv` のみ、省略時は `csv`）
+    format: Option<String>,
+    /// 出力件数（新しい順、省略時は全件）
+    limit: Option<i64>,
+}
+
+/// ログのエクスポートで一度にDBから読み込む件数
+const LOG_EXPORT_PAGE_SIZE: i64 = 500;
+
+/// CSVのヘッダー行
+const LOG_CSV_HEADER: &str =
+    "id,timestamp,query_name,q_type,result_type,duration_ms,upstream_server,query_id\n";
+
+/// ログのCSVエクスポート（`?format=csv`、`?limit=` は `/api/logs` と同じく新しい順の件数）
+/// 全件をメモリに載せないよう、ページごとに読み込みながらストリームで返す
+async fn export_logs_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(filter): Query<LogExportFilter>,
+) -> Result<impl IntoResponse, AppError> {
+    let format = filter.format.as_deref().map(str::trim).unwrap_or("csv");
+    if !format.eq_ignore_ascii_case("csv") {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "format",
+            format!("対応していない出力形式です: {}（対応: csv）", format),
+        )]));
+    }
+
+    let remaining = filter.limit.map(|limit| limit.max(0)).unwrap_or(i64::MAX);
+    let pool = state.pool.clone();
+    let rows = futures_util::stream::unfold(
+        (pool, None::<i64>, remaining),
+        |(pool, before_id, remaining)| async move {
+            if remaining <= 0 {
+                return None;
+            }
+            match get_logs_page(&pool, before_id, remaining.min(LOG_EXPORT_PAGE_SIZE)).await {
+                Ok(logs) => {
+                    let last_id = logs.last()?.id;
+                    let chunk: String = logs.iter().map(log_csv_row).collect();
+                    let remaining = remaining - logs.len() as i64;
+                    Some((Ok(Bytes::from(chunk)), (pool, Some(last_id), remaining)))
+                }
+                Err(e) => {
+                    tracing::error!("ログのエクスポートに失敗: {:#}", e);
+                    Some((Err(std::io::Error::other(e.to_string())), (pool, before_id, 0)))
+                }
+            }
+        },
+    );
+    let header_row = futures_util::stream::once(async {
+        Ok(Bytes::from_static(LOG_CSV_HEADER.as_bytes()))
+    });
+    let body = header_row.chain(rows);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"query-logs.csv\"",
+            ),
+        ],
+        Body::from_stream(body),
+    ))
+}
+
+/// クエリログ1件をCSVの1行に変換
+fn log_csv_row(log: &QueryLog) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{}\n",
+        log.id,
+        csv_field(&log.timestamp),
+        csv_field(&log.query_name),
+        csv_field(&log.q_type),
+        csv_field(&log.result_type),
+        log.duration_ms,
+        csv_field(log.upstream_server.as_deref().unwrap_or("")),
+        csv_field(log.query_id.as_deref().unwrap_or("")),
+    )
+}
+
+/// CSVのフィールドをエスケープ
+/// 区切り文字・引用符・改行を含む場合は引用符で囲み、表計算ソフトで数式として解釈される先頭文字は `'` を付けて無効化する
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// ログ削除の条件
+#[derive(Debug, Deserialize)]
+struct LogPurgeFilter {
+    /// この日時より前のログのみ削除（RFC 3339 または `YYYY-MM-DD HH:MM:SS`（UTC））
+    before: Option<String>,
+}
+
+/// ログ削除（`?before=` 省略時は全件）
+async fn delete_logs_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(filter): Query<LogPurgeFilter>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let before = match filter.before.as_deref().map(str::trim) {
+        Some(before) if !before.is_empty() => Some(parse_log_timestamp(before).ok_or_else(|| {
+            AppError::Validation(vec![FieldError::new(
+                "before",
+                format!("日時の形式が不正です: {}", before),
+            )])
+        })?),
+        _ => None,
+    };
+
+    let deleted = delete_logs(&state.pool, before.as_deref()).await?;
+    tracing::info!("クエリログを削除: {} 件", deleted);
+    Ok(Json(json!({ "deleted": deleted })))
+}
+
+/// 時系列集計の条件
+#[derive(Debug, Deserialize)]
+struct LogTimeseriesFilter {
+    /// 集計する期間（`30m` / `24h` / `7d` など。既定 `24h`）
+    window: Option<String>,
+    /// 時間帯の幅（既定 `1h`）
+    bucket: Option<String>,
+    /// `result_type` を指定すると結果種別ごとの件数も返す
+    group_by: Option<String>,
+}
+
+/// クエリログの時間帯ごとの件数（ダッシュボードのグラフ用）
+async fn log_timeseries_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(filter): Query<LogTimeseriesFilter>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let mut errors = Vec::new();
+    let window = filter.window.as_deref().unwrap_or("24h");
+    let window_secs = parse_duration_secs(window);
+    if window_secs.is_none() {
+        errors.push(FieldError::new(
+            "window",
+            format!("期間の形式が不正です（例: 24h）: {}", window),
+        ));
+    }
+    let bucket = filter.bucket.as_deref().unwrap_or("1h");
+    let bucket_secs = parse_duration_secs(bucket).filter(|secs| *secs >= 60);
+    if bucket_secs.is_none() {
+        errors.push(FieldError::new(
+            "bucket",
+            format!("時間帯の幅は1分以上で指定してください（例: 1h）: {}", bucket),
+        ));
+    }
+    let by_result_type = match filter.group_by.as_deref() {
+        None | Some("") => false,
+        Some("result_type") => true,
+        Some(other) => {
+            errors.push(FieldError::new(
+                "group_by",
+                format!("group_by には result_type のみ指定できます: {}", other),
+            ));
+            false
+        }
+    };
+    let (Some(window_secs), Some(bucket_secs)) = (window_secs, bucket_secs) else {
+        return Err(AppError::Validation(errors));
+    };
+    if window_secs / bucket_secs > MAX_TIMESERIES_BUCKETS {
+        errors.push(FieldError::new(
+            "bucket",
+            format!("時間帯の数は{}以下にしてください", MAX_TIMESERIES_BUCKETS),
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+
+    let buckets = get_log_timeseries(
+        &state.pool,
+        chrono::Utc::now().timestamp(),
+        window_secs,
+        bucket_secs,
+        by_result_type,
+    )
+    .await?;
+    Ok(Json(json!({
+        "window_secs": window_secs,
+        "bucket_secs": bucket_secs,
+        "buckets": buckets,
+    })))
+}
+
+/// 期間（`<正の整数><s|m|h|d>`）を秒数に変換
+fn parse_duration_secs(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let unit = value.chars().last()?;
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return None,
+    };
+    let amount: i64 = value[..value.len() - 1].parse().ok()?;
+    (amount > 0).then(|| amount.checked_mul(multiplier)).flatten()
+}
+
+/// 日時をログのタイムスタンプ形式（`YYYY-MM-DD HH:MM:SS`、UTC）に変換
+fn parse_log_timestamp(value: &str) -> Option<String> {
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&chrono::Utc).format(FORMAT).to_string());
+    }
+    chrono::NaiveDateTime::parse_from_str(value, FORMAT)
+        .ok()
+        .map(|dt| dt.format(FORMAT).to_string())
+}
+
 /// 設定一覧取得
 async fn get_settings(
     State(state): State<Arc<ApiState>>,
@@ -192,15 +762,183 @@ async fn get_settings(
 }
 
 /// 設定更新
+/// 値を検証してから保存し、再起動なしで反映できる設定（上位DNSなど）はその場で反映する
 async fn update_setting_handler(
     State(state): State<Arc<ApiState>>,
     Path(key): Path<String>,
     Json(req): Json<UpdateSettingRequest>,
 ) -> Result<StatusCode, AppError> {
+    validate_setting(&key, &req.value)?;
     update_setting(&state.pool, &key, &req.value).await?;
+    apply_setting(&state, &key).await?;
     Ok(StatusCode::OK)
 }
 
+/// 設定値のバリデーション
+/// 形式が決まっている設定のみ検証し、それ以外（ホスト名・自由形式の文字列や未知のキー）はそのまま受け付ける
+pub(crate) fn validate_setting(key: &str, value: &str) -> Result<(), AppError> {
+    let value = value.trim();
+    let one_of = |parsed: bool, choices: &str| {
+        (!parsed).then(|| format!("{} には {} のいずれかを指定してください", key, choices))
+    };
+
+    let message = match key {
+        "upstream_primary" | "upstream_secondary" => parse_upstream_server(value).err().map(|_| {
+            format!(
+                "{} には `8.8.8.8`・`8.8.8.8:53` 形式のアドレス、または `udp://`・`tcp://`・`tls://`・`https://` で始まるアドレスを指定してください",
+                key
+            )
+        }),
+        "upstream_timeout_ms" | "log_retention_days" | "dns_workers" | "serve_stale_max_secs" => {
+            (!value.parse::<u32>().is_ok_and(|n| n > 0))
+                .then(|| format!("{} には1以上の整数を指定してください", key))
+        }
+        "upstream_retries" | "min_ttl" | "max_ttl" | "ttl_jitter_percent" | "query_deadline_ms"
+        | "negative_cache_max_ttl" => value
+            .parse::<u32>()
+            .err()
+            .map(|_| format!("{} には0以上の整数を指定してください", key)),
+        // edns-tcp-keepalive のタイムアウトは100ミリ秒単位の16ビット値で通知する
+        "edns_tcp_keepalive_secs" => (!value.parse::<u32>().is_ok_and(|n| n <= 6553))
+            .then(|| format!("{} には0〜6553の整数を指定してください", key)),
+        "max_request_body_bytes" | "max_concurrent_requests" => value
+            .parse::<usize>()
+            .err()
+            .map(|_| format!("{} には0以上の整数を指定してください", key)),
+        "upstream_cache_max_entries" => (!value.parse::<usize>().is_ok_and(|n| n > 0))
+            .then(|| format!("{} には1以上の整数を指定してください", key)),
+        "grpc_port" => value
+            .parse::<u16>()
+            .err()
+            .map(|_| format!("{} には0〜65535のポート番号を指定してください", key)),
+        "upstream_ecs_prefix_v4" | "upstream_ecs_prefix_v6" => value
+            .parse::<u8>()
+            .err()
+            .map(|_| format!("{} にはプレフィックス長を指定してください", key)),
+        "block_private_reverse"
+        | "serve_stale"
+        | "upstream_cache_decrement_ttl"
+        | "offline_mode"
+        | "chase_external_cname"
+        | "web_proxy_protocol" => one_of(matches!(value, "true" | "false"), "true / false"),
+        "miss_response" => one_of(
+            parse_miss_response(value).is_some(),
+            "nxdomain / refused / servfail",
+        ),
+        "answer_order" => one_of(
+            parse_answer_order(value).is_some(),
+            "insertion / random / round_robin",
+        ),
+        "log_filter" => one_of(
+            parse_log_filter(value).is_some(),
+            "all / local_only / forwarded_only / errors_only",
+        ),
+        "content_env_expansion" => one_of(
+            parse_env_expansion(value).is_some(),
+            "off / keep / strict",
+        ),
+        "upstream_bind_addr" if !value.is_empty() => {
+            parse_bind_addr(value).err().map(|e| format!("{:#}", e))
+        }
+        "dns_listen_ipv6" => parse_ipv6_listen_addr(value, 53)
+            .err()
+            .map(|e| format!("{:#}", e)),
+        "upstream_ecs" => parse_ecs(value, 0, 0).err().map(|e| format!("{:#}", e)),
+        "local_zones" | "authoritative_zones" => parse_zone_list(value).err(),
+        "web_unix_socket" if !value.is_empty() && !std::path::Path::new(value).is_absolute() => {
+            Some(format!("{} には絶対パスを指定してください", key))
+        }
+        _ => None,
+    };
+
+    match message {
+        Some(message) => Err(AppError::Validation(vec![FieldError::new("value", message)])),
+        None => Ok(()),
+    }
+}
+
+/// 再起動なしで反映できる設定を適用する（保存後に呼ぶ）
+/// 上位DNS関連の設定は保存済みの設定から上位DNS設定を作り直し、DNSハンドラと共有しているリゾルバーに反映する。
+/// log_retention_days はログ削除のたびに読み直すため、ここでは扱わない
+pub(crate) async fn apply_setting(state: &ApiState, key: &str) -> Result<(), AppError> {
+    let Some(resolver) = &state.upstream else {
+        return Ok(());
+    };
+    // 上位DNS応答キャッシュの設定はキャッシュ作成時に読むため、再起動後に反映する
+    if !key.starts_with("upstream_") || key.starts_with("upstream_cache_") {
+        return Ok(());
+    }
+
+    let config = upstream::load_config(&state.pool).await?;
+    resolver.set_config(config);
+    tracing::info!("上位DNS設定を再構築: {} を変更", key);
+    Ok(())
+}
+
+/// レコード・設定の全体バックアップをダウンロード
+async fn export_backup_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let backup = export_backup(&state.pool).await?;
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"localdns-backup.json\"",
+        )],
+        Json(backup),
+    ))
+}
+
+/// バックアップから復元（レコードは全て置き換え、設定は含まれるキーのみ上書き）
+async fn import_backup_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(backup): Json<Backup>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    if backup.version == 0 || backup.version > BACKUP_VERSION {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "version",
+            format!(
+                "対応していないバックアップ形式のバージョンです: {}（対応: {}以下）",
+                backup.version, BACKUP_VERSION
+            ),
+        )]));
+    }
+
+    // 設定は個別の更新と同じ検証を通してから取り込む（不正なものが1つでもあれば何も変更しない）
+    let errors: Vec<FieldError> = backup
+        .settings
+        .iter()
+        .filter_map(|setting| match validate_setting(&setting.key, &setting.value) {
+            Err(AppError::Validation(errors)) => Some(errors.into_iter().map(|error| {
+                FieldError::new("settings", format!("{}: {}", setting.key, error.message))
+            })),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    if !errors.is_empty() {
+        return Err(AppError::Validation(errors));
+    }
+
+    import_backup(&state.pool, &backup).await?;
+    for setting in &backup.settings {
+        apply_setting(&state, &setting.key).await?;
+    }
+
+    // 復元したレコードで応答するようキャッシュを作り直す
+    if let Err(e) = state.cache.reload().await {
+        tracing::error!("キャッシュ再読み込み失敗: {}", e);
+    }
+    if let Some(handler) = &state.dns_handler {
+        handler.upstream_cache().flush().await;
+    }
+
+    Ok(Json(json!({
+        "records": backup.records.len(),
+        "settings": backup.settings.len(),
+    })))
+}
+
 /// ヘルスチェック
 async fn health_check() -> Json<serde_json::Value> {
     Json(json!({
@@ -209,12 +947,323 @@ async fn health_check() -> Json<serde_json::Value> {
     }))
 }
 
+/// 詳細ヘルスチェック
+/// DB・ログワーカーのどちらかが停止している場合は 503 を返す。
+/// 上位DNSに到達できない場合はローカルレコードでの応答は可能なため 200 の `degraded` とする
+async fn detailed_health_check(
+    State(state): State<Arc<ApiState>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let database_ok = match ping(&state.pool).await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("ヘルスチェック: DB接続失敗: {:?}", e);
+            false
+        }
+    };
+    let log_worker_ok = state.log_worker.is_running();
+    let cache_records = state.cache.count().await;
+
+    let upstreams = match &state.upstream {
+        Some(upstream) => upstream.probe().await,
+        None => Vec::new(),
+    };
+    let upstreams_ok = upstreams.iter().all(|(_, reachable)| *reachable);
+
+    let (status, label) = if !database_ok || !log_worker_ok {
+        (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+    } else if !upstreams_ok {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+
+    let upstreams: Vec<_> = upstreams
+        .iter()
+        .map(|(server, reachable)| json!({ "server": server.to_string(), "reachable": reachable }))
+        .collect();
+
+    (
+        status,
+        Json(json!({
+            "status": label,
+            "service": "local-dns-pro",
+            "checks": {
+                "database": { "ok": database_ok },
+                "cache": { "records": cache_records },
+                "log_worker": {
+                    "ok": log_worker_ok,
+                    "dropped": state.log_worker.dropped_count(),
+                },
+                "upstreams": upstreams,
+            }
+        })),
+    )
+}
+
+/// バージョン情報（ビルド時に build.rs で埋め込んだ値を返す）
+async fn version_info() -> Json<serde_json::Value> {
+    let build_timestamp = env!("BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map(|dt| dt.to_rfc3339());
+
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_commit": env!("GIT_COMMIT_HASH"),
+        "build_timestamp": build_timestamp,
+    }))
+}
+
+/// データベースの情報（適用済みのスキーマバージョンとこのビルドが想定するバージョン）
+async fn db_info(State(state): State<Arc<ApiState>>) -> Result<Json<serde_json::Value>, AppError> {
+    let migration = get_schema_version(&state.pool).await?;
+
+    Ok(Json(json!({
+        "schema_version": migration.as_ref().map(|migration| migration.version),
+        "applied_at": migration.map(|migration| migration.applied_at),
+        "latest_schema_version": SCHEMA_VERSION,
+    })))
+}
+
+/// APIキー一覧（キーそのもの・ハッシュは含まない）
+async fn get_api_keys_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<Json<Vec<ApiKey>>, AppError> {
+    Ok(Json(get_api_keys(&state.pool).await?))
+}
+
+/// APIキー作成
+/// キーそのものはこのレスポンスでのみ返す（保存されるのはハッシュのみ）
+async fn create_api_key_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    if req.name.trim().is_empty() {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "name",
+            "名前を指定してください",
+        )]));
+    }
+    // 最初のキーを作成した時点から認証が有効になるため、キーを管理できなくならないようにする
+    if req.scope != ApiKeyScope::Admin && count_api_keys(&state.pool).await? == 0 {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "scope",
+            "最初のAPIキーは admin スコープで作成してください",
+        )]));
+    }
+
+    let (api_key, key) = create_api_key(&state.pool, &req).await?;
+    tracing::info!("APIキーを作成: {} ({:?})", api_key.name, api_key.scope);
+    Ok(Json(json!({
+        "id": api_key.id,
+        "name": api_key.name,
+        "scope": api_key.scope,
+        "created_at": api_key.created_at,
+        "key": key,
+    })))
+}
+
+/// APIキーの名前・スコープを更新
+async fn update_api_key_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<UpdateApiKeyRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    if req.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+        return Err(AppError::Validation(vec![FieldError::new(
+            "name",
+            "名前を指定してください",
+        )]));
+    }
+    if req.scope.is_some_and(|scope| scope != ApiKeyScope::Admin) {
+        ensure_admin_key_remains(&state.pool, id).await?;
+    }
+
+    if !update_api_key(&state.pool, id, &req).await? {
+        return Err(AppError::NotFound);
+    }
+    Ok(Json(json!({ "success": true })))
+}
+
+/// APIキー削除（全て削除すると認証は無効になる）
+async fn delete_api_key_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    ensure_admin_key_remains(&state.pool, id).await?;
+
+    if !delete_api_key(&state.pool, id).await? {
+        return Err(AppError::NotFound);
+    }
+    Ok(Json(json!({ "success": true })))
+}
+
+/// 指定したキーを admin でなくしても、他のキーが残る場合は admin のキーが残るか確認する
+/// （admin のキーが無くなるとキーを管理できなくなるため 409 を返す）
+async fn ensure_admin_key_remains(pool: &DbPool, id: i64) -> Result<(), AppError> {
+    let keys = get_api_keys(pool).await?;
+    let others: Vec<&ApiKey> = keys.iter().filter(|key| key.id != id).collect();
+    if keys.iter().any(|key| key.id == id && key.scope == ApiKeyScope::Admin)
+        && !others.is_empty()
+        && !others.iter().any(|key| key.scope == ApiKeyScope::Admin)
+    {
+        return Err(AppError::Conflict(
+            "admin スコープのAPIキーが無くなるため変更できません".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// 上位DNSサーバーごとのメトリクスと解析できないレコード数を Prometheus テキスト形式で返す
+async fn metrics_handler(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
+    let mut body = state.upstream_metrics.render();
+    body.push_str("# HELP localdns_invalid_records コンテンツを解析できないレコード数\n");
+    body.push_str("# TYPE localdns_invalid_records gauge\n");
+    body.push_str(&format!(
+        "localdns_invalid_records {}\n",
+        state.cache.invalid_records().await.len()
+    ));
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        body,
+    )
+}
+
+/// 上位DNS応答キャッシュの破棄対象
+#[derive(Debug, Deserialize)]
+struct UpstreamFlushFilter {
+    /// 指定時はクエリ名がこの文字列で始まるエントリのみ破棄
+    name: Option<String>,
+}
+
+/// 上位DNS応答キャッシュの破棄（ローカルレコードのキャッシュは対象外）
+async fn flush_upstream_cache_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(filter): Query<UpstreamFlushFilter>,
+) -> Json<serde_json::Value> {
+    let Some(handler) = &state.dns_handler else {
+        return Json(json!({ "flushed": 0 }));
+    };
+
+    let upstream_cache = handler.upstream_cache();
+    let flushed = match filter.name.as_deref().map(str::trim) {
+        Some(name) if !name.is_empty() => upstream_cache.flush_prefix(name).await,
+        _ => upstream_cache.flush().await,
+    };
+    tracing::info!("上位DNS応答キャッシュを破棄: {} 件", flushed);
+    Json(json!({ "flushed": flushed }))
+}
+
+/// JSON形式の名前解決の問い合わせ
+#[derive(Debug, Deserialize)]
+struct ResolveParams {
+    name: String,
+    /// タイプ名（`A`）または番号（`1`）。省略時は A
+    #[serde(rename = "type")]
+    record_type: Option<String>,
+    /// DNSSEC検証の無効化（`1` / `true`）
+    cd: Option<String>,
+}
+
+/// JSON形式の名前解決（Google / Cloudflare の DNS over HTTPS JSON API 互換）
+/// DNS問い合わせと同じ解決処理（ローカルレコード・上位DNS転送）を使う
+async fn resolve_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(params): Query<ResolveParams>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let raw_name = params.name.trim();
+    let mut name = Name::from_str(raw_name)
+        .ok()
+        .filter(|_| !raw_name.is_empty())
+        .ok_or_else(|| {
+            AppError::Validation(vec![FieldError::new(
+                "name",
+                format!("名前の形式が不正です: {}", raw_name),
+            )])
+        })?;
+    name.set_fqdn(true);
+
+    let raw_type = params.record_type.as_deref().unwrap_or("A").trim();
+    let record_type = parse_resolve_type(raw_type).ok_or_else(|| {
+        AppError::Validation(vec![FieldError::new(
+            "type",
+            format!("レコードタイプが不正です: {}", raw_type),
+        )])
+    })?;
+    let checking_disabled = matches!(params.cd.as_deref(), Some("1") | Some("true"));
+
+    let handler = state
+        .dns_handler
+        .as_ref()
+        .ok_or_else(|| anyhow!("DNSハンドラが設定されていません"))?;
+    let outcome = handler
+        .lookup(&name, record_type, checking_disabled, true, None)
+        .await;
+
+    let mut body = json!({
+        "Status": u16::from(handler.response_code(&outcome)),
+        "TC": false,
+        "RD": true,
+        "RA": handler.recursion_available(),
+        "AD": false,
+        "CD": checking_disabled,
+        "Question": [{ "name": name.to_string(), "type": u16::from(record_type) }],
+    });
+    if !outcome.answers.is_empty() {
+        // ローカル応答では回答を生成したレコードのIDも返す（DNSの応答には含めない）
+        body["Answer"] = outcome
+            .answers
+            .iter()
+            .enumerate()
+            .map(|(index, record)| {
+                let mut answer = resolve_answer_json(record);
+                if let Some(id) = outcome.record_ids.get(index) {
+                    answer["record_id"] = json!(id);
+                }
+                answer
+            })
+            .collect();
+    }
+    if !outcome.name_servers.is_empty() {
+        body["Authority"] = outcome.name_servers.iter().map(resolve_answer_json).collect();
+    }
+
+    Ok(Json(body))
+}
+
+/// タイプ名（大文字小文字は区別しない）または番号をパース
+pub(crate) fn parse_resolve_type(value: &str) -> Option<RecordType> {
+    match value.parse::<u16>() {
+        Ok(code) => Some(RecordType::from(code)),
+        Err(_) => RecordType::from_str(&value.to_ascii_uppercase()).ok(),
+    }
+}
+
+/// レコードを JSON API の Answer / Authority の要素に変換
+fn resolve_answer_json(record: &DnsRecord) -> serde_json::Value {
+    json!({
+        "name": record.name().to_string(),
+        "type": u16::from(record.record_type()),
+        "TTL": record.ttl(),
+        "data": record.data().to_string(),
+    })
+}
+
 /// エラーハンドリング
 #[derive(Debug)]
-enum AppError {
+pub(crate) enum AppError {
     Internal(anyhow::Error),
     NotFound,
-    BadRequest(String),
+    Validation(Vec<FieldError>),
+    Conflict(String),
+    /// 認証が必要、または認証情報が不正
+    Unauthorized,
+    /// 認証済みだが操作の権限が無い
+    Forbidden,
+    /// リクエスト数の上限超過
+    #[allow(dead_code)]
+    TooManyRequests,
 }
 
 impl From<anyhow::Error> for AppError {
@@ -223,27 +1272,74 @@ impl From<anyhow::Error> for AppError {
     }
 }
 
+impl From<Vec<FieldError>> for AppError {
+    fn from(errors: Vec<FieldError>) -> Self {
+        AppError::Validation(errors)
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let (status, message) = match self {
+        // (ステータス, 機械判読用コード, メッセージ, フィールド詳細)
+        let (status, code, message, details) = match self {
             AppError::Internal(e) => {
                 tracing::error!("内部エラー: {:?}", e);
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
+                    "INTERNAL_ERROR",
                     "内部サーバーエラーが発生しました".to_string(),
+                    None,
                 )
             }
             AppError::NotFound => (
                 StatusCode::NOT_FOUND,
+                "NOT_FOUND",
                 "リソースが見つかりません".to_string(),
+                None,
             ),
-            AppError::BadRequest(msg) => {
-                tracing::warn!("不正なリクエスト: {}", msg);
-                (StatusCode::BAD_REQUEST, msg)
+            AppError::Validation(errors) => {
+                let message = errors
+                    .iter()
+                    .map(|e| e.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" / ");
+                tracing::warn!("不正なリクエスト: {}", message);
+                (StatusCode::BAD_REQUEST, "VALIDATION_ERROR", message, Some(errors))
             }
-        };
-
-        (status, Json(json!({ "error": message }))).into_response()
+            AppError::Conflict(msg) => {
+                tracing::warn!("競合: {}", msg);
+                (StatusCode::CONFLICT, "CONFLICT", msg, None)
+            }
+            AppError::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "UNAUTHORIZED",
+                "認証が必要です".to_string(),
+                None,
+            ),
+            AppError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "FORBIDDEN",
+                "この操作を行う権限がありません".to_string(),
+                None,
+            ),
+            AppError::TooManyRequests => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "TOO_MANY_REQUESTS",
+                "リクエストが多すぎます。しばらくしてから再試行してください".to_string(),
+                None,
+            ),
+        };
+
+        let mut body = json!({ "error": message, "code": code });
+        if let Some(details) = details {
+            body["details"] = json!(details);
+        }
+        // 問い合わせ時に照合できるようリクエストIDを含める
+        if let Some(request_id) = crate::web::router::current_request_id() {
+            body["request_id"] = json!(request_id);
+        }
+
+        (status, Json(body)).into_response()
     }
 }
 
@@ -261,10 +1357,40 @@ mod tests {
     async fn setup_test_api() -> Router {
         let pool = init_db("sqlite::memory:").await.unwrap();
         let cache = RecordCache::new(pool.clone()).await.unwrap();
-        let state = ApiState { pool, cache };
+        let state = ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool),
+            upstream: None,
+            dns_handler: None,
+        };
         create_api_routes(state)
     }
 
+    /// AppError をレスポンスに変換し、ステータスとJSONボディを返す
+    async fn error_response(error: AppError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_error_response() {
+        let (status, json) = error_response(AppError::Unauthorized).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["code"], "UNAUTHORIZED");
+        assert!(json.get("details").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_too_many_requests_error_response() {
+        let (status, json) = error_response(AppError::TooManyRequests).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(json["code"], "TOO_MANY_REQUESTS");
+    }
+
     #[tokio::test]
     async fn test_health_check() {
         let app = setup_test_api().await;
@@ -287,6 +1413,379 @@ mod tests {
         assert_eq!(json["service"], "local-dns-pro");
     }
 
+    #[tokio::test]
+    async fn test_detailed_health_check_healthy() {
+        let app = setup_test_api().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/health/detailed")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["checks"]["database"]["ok"], true);
+        assert_eq!(json["checks"]["cache"]["records"], 0);
+        assert_eq!(json["checks"]["log_worker"]["ok"], true);
+        assert!(json["checks"]["upstreams"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_detailed_health_check_unhealthy_when_log_worker_stopped() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        log_worker.shutdown().await;
+        let app = create_api_routes(ApiState {
+            pool,
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker,
+            upstream: None,
+            dns_handler: None,
+        });
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/health/detailed")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["status"], "unhealthy");
+        assert_eq!(json["checks"]["log_worker"]["ok"], false);
+    }
+
+    #[tokio::test]
+    async fn test_version_info() {
+        let app = setup_test_api().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/version")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["git_commit"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_db_info_reports_latest_schema_version() {
+        let app = setup_test_api().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/db/info")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["latest_schema_version"], SCHEMA_VERSION);
+        assert!(json["applied_at"].is_string());
+    }
+
+    /// APIキー付きのリクエストを送り、ステータスを返す
+    async fn send_with_key(app: &Router, method: &str, uri: &str, key: Option<&str>) -> StatusCode {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(key) = key {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", key));
+        }
+        let body = if method == "POST" {
+            Body::from(
+                json!({ "domain_pattern": "app.local.test", "record_type": "A", "content": "10.0.0.1" })
+                    .to_string(),
+            )
+        } else {
+            Body::empty()
+        };
+
+        app.clone()
+            .oneshot(builder.body(body).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_read_scoped_key_cannot_write() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
+        });
+
+        // キーが無い間は認証しない
+        assert_eq!(send_with_key(&app, "GET", "/api/records", None).await, StatusCode::OK);
+
+        let (_, admin) = create_api_key(
+            &pool,
+            &CreateApiKeyRequest { name: "admin".to_string(), scope: ApiKeyScope::Admin },
+        )
+        .await
+        .unwrap();
+        let (_, read) = create_api_key(
+            &pool,
+            &CreateApiKeyRequest { name: "monitoring".to_string(), scope: ApiKeyScope::Read },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(send_with_key(&app, "GET", "/api/records", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            send_with_key(&app, "GET", "/api/records", Some("ldns_wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(send_with_key(&app, "GET", "/api/health", None).await, StatusCode::OK);
+
+        assert_eq!(send_with_key(&app, "GET", "/api/records", Some(&read)).await, StatusCode::OK);
+        assert_eq!(
+            send_with_key(&app, "POST", "/api/records", Some(&read)).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send_with_key(&app, "GET", "/api/keys", Some(&read)).await,
+            StatusCode::FORBIDDEN
+        );
+
+        assert_eq!(send_with_key(&app, "POST", "/api/records", Some(&admin)).await, StatusCode::OK);
+        assert_eq!(send_with_key(&app, "GET", "/api/keys", Some(&admin)).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_are_hashed_and_first_key_must_be_admin() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
+        });
+        let create = |body: serde_json::Value| {
+            Request::builder()
+                .method("POST")
+                .uri("/api/keys")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(create(json!({ "name": "grafana", "scope": "read" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(create(json!({ "name": "ops", "scope": "admin" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let key = json["key"].as_str().unwrap().to_string();
+        let id = json["id"].as_i64().unwrap();
+
+        // 保存されるのはハッシュのみ
+        let stored: String = sqlx::query_scalar("SELECT key_hash FROM api_keys")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_ne!(stored, key);
+        assert_eq!(stored, hash_api_key(&key));
+
+        // 一覧にキー・ハッシュは含まれない
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/keys")
+                    .header(header::AUTHORIZATION, format!("Bearer {}", key))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json[0]["scope"], "admin");
+        assert!(json[0].get("key_hash").is_none());
+
+        // 他のキーが残る状態で最後の admin のキーは削除できない
+        create_api_key(
+            &pool,
+            &CreateApiKeyRequest { name: "grafana".to_string(), scope: ApiKeyScope::Read },
+        )
+        .await
+        .unwrap();
+        let status =
+            send_with_key(&app, "DELETE", &format!("/api/keys/{}", id), Some(&key)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_client_against_in_process_server() {
+        use crate::dns::DnsHandler;
+        use local_dns_pro::client::{ClientError, LocalDnsClient};
+
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache: cache.clone(),
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: log_worker.clone(),
+            upstream: None,
+            dns_handler: Some(DnsHandler::new(cache, log_worker)),
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = LocalDnsClient::new(addr.to_string());
+        let ids = client
+            .create_record(&CreateRecordRequest {
+                domain_pattern: "client.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "10.0.0.9".into(),
+                ttl: 60,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 1);
+
+        let records = client.list_records().await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].domain_pattern, "client.local.test");
+
+        let resolved = client.resolve("client.local.test", "A").await.unwrap();
+        assert_eq!(resolved.status, 0);
+        assert_eq!(resolved.answers[0].data, "10.0.0.9");
+        assert_eq!(resolved.answers[0].record_id, Some(ids[0]));
+
+        client.update_setting("min_ttl", "30").await.unwrap();
+        assert_eq!(get_setting(&pool, "min_ttl").await.unwrap().as_deref(), Some("30"));
+
+        // APIのエラーはコード付きで返す
+        match client.update_setting("min_ttl", "abc").await {
+            Err(ClientError::Api { status, code, .. }) => {
+                assert_eq!(status, 400);
+                assert_eq!(code, "VALIDATION_ERROR");
+            }
+            other => panic!("APIエラーになりません: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unparseable_record_is_listed_as_invalid() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache: cache.clone(),
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
+        });
+
+        // 作成時の検証を経ずに壊れたコンテンツが入った場合を再現する
+        for (domain_pattern, content) in [
+            ("ok.local.test", "10.0.0.1"),
+            ("broken.local.test", "10.0.0"),
+        ] {
+            sqlx::query(
+                "INSERT INTO records (domain_pattern, record_type, content) VALUES (?, 'A', ?)",
+            )
+            .bind(domain_pattern)
+            .bind(content)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        cache.reload().await.unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records/invalid")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json.len(), 1);
+        assert_eq!(json[0]["domain_pattern"], "broken.local.test");
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("localdns_invalid_records 1\n"));
+    }
+
     #[tokio::test]
     async fn test_get_records_empty() {
         let app = setup_test_api().await;
@@ -315,6 +1814,10 @@ mod tests {
         let state = ApiState {
             pool: pool.clone(),
             cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
         };
         let app = create_api_routes(state);
 
@@ -418,6 +1921,60 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
+    #[tokio::test]
+    async fn test_validation_error_code() {
+        let app = setup_test_api().await;
+
+        let create_body = serde_json::json!({
+            "domain_pattern": "app.local.test",
+            "record_type": "A",
+            "content": "invalid-ip",
+            "ttl": 60,
+        });
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/records")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(create_body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["code"], "VALIDATION_ERROR");
+        assert_eq!(json["details"][0]["field"], "content");
+        assert!(json["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_not_found_error_code() {
+        let app = setup_test_api().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records/99999")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["code"], "NOT_FOUND");
+        assert!(json.get("details").is_none());
+    }
+
     #[tokio::test]
     async fn test_create_record_validation_invalid_type() {
         let app = setup_test_api().await;
@@ -452,7 +2009,7 @@ mod tests {
             "domain_pattern": "app.local.test",
             "record_type": "A",
             "content": "192.168.1.100",
-            "ttl": 0,
+            "ttl": -1,
                     });
 
         let response = app
@@ -494,6 +2051,10 @@ mod tests {
         let state = ApiState {
             pool: pool.clone(),
             cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
         };
         let app = create_api_routes(state);
 
@@ -586,6 +2147,10 @@ mod tests {
         let state = ApiState {
             pool: pool.clone(),
             cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
         };
         let app = create_api_routes(state);
 
@@ -631,13 +2196,220 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_get_logs_empty() {
-        let app = setup_test_api().await;
-
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/api/logs")
+    async fn test_update_setting_rejects_invalid_values() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let config = crate::dns::UpstreamConfig::new("8.8.8.8:53", "1.1.1.1:53", 2000).unwrap();
+        let upstream = Arc::new(UpstreamResolver::new(config));
+        let state = ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: Some(upstream.clone()),
+            dns_handler: None,
+        };
+        let app = create_api_routes(state);
+
+        let put_setting = |key: &str, value: &str| {
+            Request::builder()
+                .method("PUT")
+                .uri(format!("/api/settings/{}", key))
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::json!({ "value": value }).to_string()))
+                .unwrap()
+        };
+
+        // 不正な値は 400 で拒否し、保存しない
+        for (key, value) in [
+            ("upstream_primary", "dns.example"),
+            ("upstream_timeout_ms", "0"),
+            ("upstream_timeout_ms", "fast"),
+            ("upstream_bind_addr", "127.0.0.1:5353"),
+            ("upstream_cache_max_entries", "0"),
+        ] {
+            let response = app.clone().oneshot(put_setting(key, value)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{} = {}", key, value);
+        }
+        assert_eq!(
+            get_setting(&pool, "upstream_primary").await.unwrap().as_deref(),
+            Some("8.8.8.8:53")
+        );
+        assert_eq!(
+            get_setting(&pool, "upstream_timeout_ms").await.unwrap().as_deref(),
+            Some("2000")
+        );
+
+        // 正しい値は保存し、上位DNSの設定にその場で反映する
+        let response = app
+            .clone()
+            .oneshot(put_setting("upstream_timeout_ms", "500"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .oneshot(put_setting("upstream_primary", "9.9.9.9"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let config = upstream.config();
+        assert_eq!(config.timeout, std::time::Duration::from_millis(500));
+        assert_eq!(config.primary.to_string(), "9.9.9.9:53");
+    }
+
+    /// 固定のAレコードを返す上位DNSを起動し、受信した問い合わせ数を数える
+    async fn spawn_mock_upstream(
+        answer: std::net::Ipv4Addr,
+    ) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use hickory_proto::op::{Message, MessageType};
+        use hickory_proto::rr::RData;
+        use hickory_proto::serialize::binary::BinDecodable;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let received = Arc::new(AtomicUsize::new(0));
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                received_clone.fetch_add(1, Ordering::SeqCst);
+                let request = Message::from_bytes(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response.set_id(request.id());
+                response.set_message_type(MessageType::Response);
+                for query in request.queries() {
+                    response.add_query(query.clone());
+                    response.add_answer(DnsRecord::from_rdata(
+                        query.name().clone(),
+                        60,
+                        RData::A(answer.into()),
+                    ));
+                }
+                let _ = socket.send_to(&response.to_vec().unwrap(), peer).await;
+            }
+        });
+
+        (addr, received)
+    }
+
+    #[tokio::test]
+    async fn test_update_upstream_setting_switches_server() {
+        use std::net::Ipv4Addr;
+        use std::sync::atomic::Ordering;
+
+        let (first_addr, first_received) = spawn_mock_upstream(Ipv4Addr::new(10, 0, 0, 1)).await;
+        let (second_addr, second_received) = spawn_mock_upstream(Ipv4Addr::new(10, 0, 0, 2)).await;
+
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        update_setting(&pool, "upstream_primary", &first_addr).await.unwrap();
+        update_setting(&pool, "upstream_secondary", &first_addr).await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let upstream = Arc::new(UpstreamResolver::new(
+            upstream::load_config(&pool).await.unwrap(),
+        ));
+        let dns_handler =
+            DnsHandler::new(cache.clone(), log_worker.clone()).with_upstream(upstream.clone());
+        let state = ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: upstream.metrics(),
+            log_worker,
+            upstream: Some(upstream),
+            dns_handler: Some(dns_handler.clone()),
+        };
+        let app = create_api_routes(state);
+
+        let resolve = |name: &str| {
+            let name = Name::from_str(name).unwrap();
+            let dns_handler = dns_handler.clone();
+            async move {
+                let outcome = dns_handler.lookup(&name, RecordType::A, false, true, None).await;
+                outcome.answers[0].data().to_string()
+            }
+        };
+
+        assert_eq!(resolve("before.example.").await, "10.0.0.1");
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/api/settings/upstream_primary")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(json!({ "value": second_addr }).to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // 再起動せずに以降の問い合わせは新しいプライマリに転送される
+        assert_eq!(resolve("after.example.").await, "10.0.0.2");
+        assert_eq!(first_received.load(Ordering::SeqCst), 1);
+        assert_eq!(second_received.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_exposes_upstream_labels() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let upstream_metrics = UpstreamMetrics::new();
+        upstream_metrics.record_failure("8.8.8.8:53".parse().unwrap());
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics,
+            log_worker: LogWorker::new(pool),
+            upstream: None,
+            dns_handler: None,
+        });
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("localdns_upstream_query_failures_total{server=\"8.8.8.8:53\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_limit_is_clamped() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "WITH RECURSIVE seq(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM seq WHERE n < 1200) \
+             INSERT INTO query_logs (query_name, q_type, result_type, duration_ms) \
+             SELECT 'host' || n || '.local.test', 'A', 'LOCAL', 1 FROM seq",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool),
+            upstream: None,
+            dns_handler: None,
+        });
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs?limit=100000")
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -648,70 +2420,1117 @@ mod tests {
 
         let body = response.into_body().collect().await.unwrap().to_bytes();
         let logs: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
-        assert!(logs.is_empty());
+        assert_eq!(logs.len() as i64, MAX_LOG_LIMIT);
     }
 
     #[tokio::test]
-    async fn test_validate_record_ipv6() {
-        // 有効なIPv6
-        let req = CreateRecordRequest {
-            domain_pattern: "app.local.test".to_string(),
-            record_type: "AAAA".to_string(),
-            content: "::1".to_string(),
-            ttl: 60,
-        };
-        assert!(validate_record(&req).is_ok());
+    async fn test_purge_logs() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        for i in 0..3 {
+            log_query(
+                &pool,
+                NewQueryLog {
+                    query_name: format!("host{}.local.test", i),
+                    q_type: "A".to_string(),
+                    result_type: "LOCAL".to_string(),
+                    duration_ms: 1,
+                    upstream_server: None,
+                    query_id: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
+        });
 
-        // 無効なIPv6
-        let req = CreateRecordRequest {
-            domain_pattern: "app.local.test".to_string(),
-            record_type: "AAAA".to_string(),
-            content: "invalid-ipv6".to_string(),
-            ttl: 60,
-        };
-        assert!(validate_record(&req).is_err());
+        // 過去の日時を指定した場合は削除されない
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/api/logs?before=2000-01-01T00:00:00Z")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["deleted"], 0);
+
+        // 全件削除
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/api/logs")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["deleted"], 3);
+
+        assert!(get_recent_logs(&pool, 100).await.unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn test_validate_record_cname() {
-        // 有効なCNAME
-        let req = CreateRecordRequest {
-            domain_pattern: "alias.local.test".to_string(),
-            record_type: "CNAME".to_string(),
-            content: "target.local.test".to_string(),
-            ttl: 60,
-        };
-        assert!(validate_record(&req).is_ok());
+    async fn test_export_logs_csv() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        for i in 0..3 {
+            log_query(
+                &pool,
+                NewQueryLog {
+                    query_name: format!("host{}.local.test", i),
+                    q_type: "A".to_string(),
+                    result_type: "FORWARDED".to_string(),
+                    duration_ms: i,
+                    upstream_server: Some("8.8.8.8:53".to_string()),
+                    query_id: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
+        });
 
-        // 空白を含むCNAME（無効）
-        let req = CreateRecordRequest {
-            domain_pattern: "alias.local.test".to_string(),
-            record_type: "CNAME".to_string(),
-            content: "invalid target".to_string(),
-            ttl: 60,
+        let export = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(
+                    response.headers()[header::CONTENT_TYPE],
+                    "text/csv; charset=utf-8"
+                );
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
         };
-        assert!(validate_record(&req).is_err());
+
+        let csv = export("/api/logs/export?format=csv").await;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "id,timestamp,query_name,q_type,result_type,duration_ms,upstream_server,query_id"
+        );
+        assert_eq!(lines.len(), 4);
+        // 新しい順
+        assert!(lines[1].contains(",host2.local.test,A,FORWARDED,2,8.8.8.8:53,"));
+
+        // 件数指定は /api/logs と同じく新しい順に絞り込む
+        let csv = export("/api/logs/export?format=csv&limit=2").await;
+        assert_eq!(csv.lines().count(), 3);
+
+        // 未対応の形式は 400
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs/export?format=xlsx")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_csv_field_escaping() {
+        assert_eq!(csv_field("example.com"), "example.com");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("=1+1"), "'=1+1");
+    }
+
+    #[tokio::test]
+    async fn test_purge_logs_invalid_before() {
+        let app = setup_test_api().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/api/logs?before=yesterday")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_log_timeseries_zero_fills_buckets() {
+        let app = setup_test_api().await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs/timeseries?window=6h&bucket=1h&group_by=result_type")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let buckets = json["buckets"].as_array().unwrap();
+        assert_eq!(buckets.len(), 6);
+        assert!(buckets.iter().all(|bucket| bucket["count"] == 0));
+        assert!(buckets[0]["by_result_type"].is_object());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs/timeseries?window=24h&bucket=10s")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_parse_duration_secs() {
+        assert_eq!(parse_duration_secs("24h"), Some(86400));
+        assert_eq!(parse_duration_secs("15m"), Some(900));
+        assert_eq!(parse_duration_secs("7d"), Some(604800));
+        assert!(parse_duration_secs("0h").is_none());
+        assert!(parse_duration_secs("1w").is_none());
+        assert!(parse_duration_secs("h").is_none());
+    }
+
+    #[test]
+    fn test_parse_log_timestamp() {
+        assert_eq!(
+            parse_log_timestamp("2024-05-01T09:00:00+09:00").as_deref(),
+            Some("2024-05-01 00:00:00")
+        );
+        assert_eq!(
+            parse_log_timestamp("2024-05-01 00:00:00").as_deref(),
+            Some("2024-05-01 00:00:00")
+        );
+        assert!(parse_log_timestamp("yesterday").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_empty() {
+        let app = setup_test_api().await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let logs: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert!(logs.is_empty());
     }
 
+
+
+
+
+
+
+
+
+
     #[tokio::test]
-    async fn test_validate_record_empty_content() {
-        let req = CreateRecordRequest {
-            domain_pattern: "app.local.test".to_string(),
+    async fn test_restore_conflicts_with_recreated_record() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
+        });
+        let request = || CreateRecordRequest {
+            domain_pattern: "restore.local.test".to_string(),
             record_type: "A".to_string(),
-            content: "   ".to_string(),
+            content: "10.0.0.2".into(),
             ttl: 60,
+            priority: 0,
+            weight: 1,
+            passthrough: false,
+            pattern_type: PatternType::Glob,
+            client_subnet: None,
+            tags: Vec::new(),
         };
-        assert!(validate_record(&req).is_err());
+
+        // 削除したレコードと同じものを作り直してから、古いほうを復元する
+        let old_id = create_record(&pool, request()).await.unwrap()[0];
+        assert!(delete_record(&pool, old_id).await.unwrap());
+        let new_id = create_record(&pool, request()).await.unwrap()[0];
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/api/records/{}/restore", old_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // 古いレコードは削除済みのまま
+        assert!(get_record_by_id(&pool, old_id).await.unwrap().is_none());
+        assert!(get_record_by_id(&pool, new_id).await.unwrap().is_some());
     }
 
     #[tokio::test]
-    async fn test_validate_record_ttl_too_high() {
-        let req = CreateRecordRequest {
-            domain_pattern: "app.local.test".to_string(),
-            record_type: "A".to_string(),
-            content: "192.168.1.1".to_string(),
-            ttl: 100000,
-        };
-        assert!(validate_record(&req).is_err());
+    async fn test_delete_and_restore_record() {
+        let app = setup_test_api().await;
+
+        // レコード作成
+        let create_body = serde_json::json!({
+            "domain_pattern": "restore.local.test",
+            "record_type": "A",
+            "content": "10.0.0.2",
+            "ttl": 60
+        });
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/records")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(create_body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let id = json["id"].as_i64().unwrap();
+
+        // 論理削除
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri(format!("/api/records/{}", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // 削除済み一覧に含まれる
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records/deleted")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let deleted: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0]["id"], id);
+        assert!(deleted[0]["deleted_at"].is_string());
+
+        // 復元
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/api/records/{}/restore", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // 復元後は再び取得できる
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/api/records/{}", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let record: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(record["deleted_at"].is_null());
+
+        // 未削除レコードの復元は NotFound
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/api/records/{}/restore", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_create_record_duplicate_conflict() {
+        let app = setup_test_api().await;
+
+        let post = |body: serde_json::Value| {
+            Request::builder()
+                .method("POST")
+                .uri("/api/records")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let create_body = serde_json::json!({
+            "domain_pattern": "dup.local.test",
+            "record_type": "A",
+            "content": "10.0.0.1",
+            "ttl": 60
+        });
+
+        // 1回目は成功
+        let response = app.clone().oneshot(post(create_body.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // 同一内容の2回目は 409
+        let response = app.clone().oneshot(post(create_body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // コンテンツが異なれば作成できる
+        let other_body = serde_json::json!({
+            "domain_pattern": "dup.local.test",
+            "record_type": "A",
+            "content": "10.0.0.2",
+            "ttl": 60
+        });
+        let response = app.oneshot(post(other_body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_backup_import_validates_and_applies_settings() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let upstream = Arc::new(UpstreamResolver::new(
+            upstream::load_config(&pool).await.unwrap(),
+        ));
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: upstream.metrics(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: Some(upstream.clone()),
+            dns_handler: None,
+        });
+        let import = |settings: Vec<(&str, &str)>| {
+            let backup = Backup {
+                version: BACKUP_VERSION,
+                created_at: "2024-01-01 00:00:00".to_string(),
+                records: Vec::new(),
+                settings: settings
+                    .into_iter()
+                    .map(|(key, value)| Setting {
+                        key: key.to_string(),
+                        value: value.to_string(),
+                    })
+                    .collect(),
+            };
+            app.clone().oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/import/backup")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(serde_json::to_string(&backup).unwrap()))
+                    .unwrap(),
+            )
+        };
+        let before = get_setting(&pool, "upstream_primary").await.unwrap();
+
+        // 不正な設定を含むバックアップは、キーを示して何も取り込まない
+        let response = import(vec![
+            ("log_retention_days", "30"),
+            ("upstream_primary", "not an address"),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("upstream_primary"));
+        assert_eq!(get_setting(&pool, "upstream_primary").await.unwrap(), before);
+        assert_ne!(
+            get_setting(&pool, "log_retention_days").await.unwrap().as_deref(),
+            Some("30")
+        );
+
+        // 取り込んだ上位DNS設定は再起動せずに反映される
+        let response = import(vec![("upstream_primary", "9.9.9.9")]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            upstream.config().primary,
+            upstream::UpstreamServer::Udp("9.9.9.9:53".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backup_round_trip() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache: cache.clone(),
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool.clone()),
+            upstream: None,
+            dns_handler: None,
+        });
+
+        let ids = create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "backup.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: RecordContent::Multiple(vec![
+                    "10.0.0.1".to_string(),
+                    "10.0.0.2".to_string(),
+                ]),
+                ttl: 120,
+                priority: 5,
+                weight: 3,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: vec!["backup".to_string()],
+            },
+        )
+        .await
+        .unwrap();
+        delete_record(&pool, ids[1]).await.unwrap();
+        update_setting(&pool, "log_retention_days", "30").await.unwrap();
+
+        // エクスポート
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/export/backup")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let exported = response.into_body().collect().await.unwrap().to_bytes();
+        let backup: Backup = serde_json::from_slice(&exported).unwrap();
+        assert_eq!(backup.version, BACKUP_VERSION);
+        assert_eq!(backup.records.len(), 2);
+
+        // 全て消してから設定も変更する
+        sqlx::query("DELETE FROM records").execute(&pool).await.unwrap();
+        update_setting(&pool, "log_retention_days", "1").await.unwrap();
+        cache.reload().await.unwrap();
+        assert!(cache.find_matching_record("backup.local.test", "A").await.is_none());
+
+        // インポート
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/import/backup")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(exported.clone()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // IDや論理削除の状態も含めて元に戻っている
+        let restored = export_backup(&pool).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&restored.records).unwrap(),
+            serde_json::to_value(&backup.records).unwrap()
+        );
+        assert_eq!(
+            get_setting(&pool, "log_retention_days").await.unwrap().as_deref(),
+            Some("30")
+        );
+        let record = cache.find_matching_record("backup.local.test", "A").await.unwrap();
+        assert_eq!(record.id, ids[0]);
+
+        // 未対応の新しい形式は取り込まない
+        let mut future = backup;
+        future.version = BACKUP_VERSION + 1;
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/import/backup")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(serde_json::to_string(&future).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_validate_record_endpoint() {
+        let app = setup_test_api().await;
+        let validate = |body: serde_json::Value| {
+            Request::builder()
+                .method("POST")
+                .uri("/api/records/validate")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let valid = serde_json::json!({
+            "domain_pattern": "app.local.test",
+            "record_type": "A",
+            "content": "10.0.0.1",
+            "ttl": 60,
+        });
+        let response = app.clone().oneshot(validate(valid)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["valid"], true);
+
+        let invalid = serde_json::json!({
+            "domain_pattern": "",
+            "record_type": "MX",
+            "content": "10.0.0.1",
+            "ttl": 60,
+            "tags": ["a,b"],
+        });
+        let response = app.clone().oneshot(validate(invalid)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let fields: Vec<&str> = json["details"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|detail| detail["field"].as_str().unwrap())
+            .collect();
+        assert!(fields.contains(&"domain_pattern"));
+        assert!(fields.contains(&"record_type"));
+        assert!(fields.contains(&"tags"));
+
+        // 検証のみでレコードは作成されない
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: Vec<Record> = serde_json::from_slice(&body).unwrap();
+        assert!(records.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_import_dnsmasq() {
+        let app = setup_test_api().await;
+        let import = |config: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri("/api/records/import/dnsmasq")
+                .header("Content-Type", "text/plain")
+                .body(Body::from(config))
+                .unwrap()
+        };
+        let config = "address=/foo.local/10.0.0.1\n\
+                      cname=www.foo.local,foo.local\n\
+                      server=8.8.8.8\n";
+
+        let response = app.clone().oneshot(import(config)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["imported"], 3);
+        assert_eq!(json["skipped"][0]["line"], 3);
+
+        // 同じ設定を再度取り込んでも重複したレコードは作らない
+        let response = app.clone().oneshot(import(config)).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["imported"], 0);
+        assert_eq!(json["skipped"].as_array().unwrap().len(), 4);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records?tag=dnsmasq")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: Vec<Record> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(records.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_create_record_with_multiple_contents() {
+        let app = setup_test_api().await;
+
+        let post = |body: serde_json::Value| {
+            Request::builder()
+                .method("POST")
+                .uri("/api/records")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(post(serde_json::json!({
+                "domain_pattern": "multi.local.test",
+                "record_type": "A",
+                "content": ["10.0.0.1", "10.0.0.2", "10.0.0.3"]
+            })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["ids"].as_array().unwrap().len(), 3);
+        assert_eq!(json["id"], json["ids"][0]);
+
+        // 1件ずつのレコードとして作成されている
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: Vec<Record> = serde_json::from_slice(&body).unwrap();
+        let mut contents: Vec<&str> = records
+            .iter()
+            .filter(|record| record.domain_pattern == "multi.local.test")
+            .map(|record| record.content.as_str())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+
+        // 1つでも不正な値があれば何も作成しない
+        let response = app
+            .oneshot(post(serde_json::json!({
+                "domain_pattern": "invalid.local.test",
+                "record_type": "A",
+                "content": ["10.0.0.1", "not-an-ip"]
+            })))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+
+
+
+    #[tokio::test]
+    async fn test_get_records_filter_by_tag() {
+        let app = setup_test_api().await;
+
+        for (domain, tag) in [("a.local.test", "project-a"), ("b.local.test", "project-b")] {
+            let create_body = serde_json::json!({
+                "domain_pattern": domain,
+                "record_type": "A",
+                "content": "127.0.0.1",
+                "tags": [tag]
+            });
+
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/records")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(create_body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records?tag=project-b")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["domain_pattern"], "b.local.test");
+        assert_eq!(records[0]["tags"], "project-b");
+    }
+
+    #[tokio::test]
+    async fn test_delete_records_by_tag() {
+        let app = setup_test_api().await;
+
+        for (domain, tag) in [
+            ("a.local.test", "proj-x"),
+            ("b.local.test", "proj-x"),
+            ("c.local.test", "proj-y"),
+        ] {
+            let create_body = serde_json::json!({
+                "domain_pattern": domain,
+                "record_type": "A",
+                "content": "127.0.0.1",
+                "tags": [tag]
+            });
+
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/records")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(create_body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/api/records?tag=proj-x")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["deleted"], 2);
+
+        // 他のタグのレコードは残る
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["domain_pattern"], "c.local.test");
+    }
+
+    #[tokio::test]
+    async fn test_delete_records_requires_filter() {
+        let app = setup_test_api().await;
+
+        let create_body = serde_json::json!({
+            "domain_pattern": "keep.local.test",
+            "record_type": "A",
+            "content": "127.0.0.1",
+        });
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/records")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(create_body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // 条件なし・空の条件は拒否する
+        for uri in ["/api/records", "/api/records?tag=&pattern=%20"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("DELETE")
+                        .uri(uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_validation_reports_all_errors() {
+        let app = setup_test_api().await;
+
+        let create_body = serde_json::json!({
+            "domain_pattern": "",
+            "record_type": "MX",
+            "content": "mail.example.com",
+            "ttl": -1,
+        });
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/records")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(create_body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let fields: Vec<&str> = json["details"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|d| d["field"].as_str())
+            .collect();
+        assert_eq!(fields, vec!["domain_pattern", "record_type", "ttl"]);
+
+        let message = json["error"].as_str().unwrap();
+        assert!(message.contains("ドメインパターンを指定してください"));
+        assert!(message.contains("サポートされていないレコードタイプです: MX"));
+        assert!(message.contains("TTLは0秒から86400秒"));
+    }
+
+    #[tokio::test]
+    async fn test_search_records_endpoint() {
+        let app = setup_test_api().await;
+
+        for (domain, content) in [("old.local.test", "192.168.1.50"), ("new.local.test", "10.0.0.1")] {
+            let create_body = serde_json::json!({
+                "domain_pattern": domain,
+                "record_type": "A",
+                "content": content,
+            });
+
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/records")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(create_body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // コンテンツで検索
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records/search?content=192.168.1.50")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["domain_pattern"], "old.local.test");
+
+        // ドメインパターンの部分一致で検索
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/records/search?pattern=new")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let records: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["content"], "10.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_json_local_record() {
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        let id = create_record(
+            &pool,
+            CreateRecordRequest {
+                domain_pattern: "app.local.test".to_string(),
+                record_type: "A".to_string(),
+                content: "192.168.1.10".into(),
+                ttl: 120,
+                priority: 0,
+                weight: 1,
+                passthrough: false,
+                pattern_type: PatternType::Glob,
+                client_subnet: None,
+                tags: Vec::new(),
+            },
+        )
+        .await
+        .unwrap()[0];
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let log_worker = LogWorker::new(pool.clone());
+        let app = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache: cache.clone(),
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: log_worker.clone(),
+            upstream: None,
+            dns_handler: Some(DnsHandler::new(cache, log_worker)),
+        });
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/resolve?name=app.local.test&type=A")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["Status"], 0);
+        assert_eq!(json["Question"][0]["name"], "app.local.test.");
+        assert_eq!(json["Question"][0]["type"], 1);
+        let answers = json["Answer"].as_array().unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0]["name"], "app.local.test.");
+        assert_eq!(answers[0]["type"], 1);
+        assert_eq!(answers[0]["TTL"], 120);
+        assert_eq!(answers[0]["data"], "192.168.1.10");
+        assert_eq!(answers[0]["record_id"], id);
+
+        // 該当レコードが無い場合は NXDOMAIN（Status 3）で Answer なし
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/resolve?name=missing.local.test&type=1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["Status"], 3);
+        assert!(json.get("Answer").is_none());
     }
 }
@@ -0,0 +1,245 @@
+//! dnsmasq の設定ファイルからのレコード取り込み
+//!
+//! `address=/<ドメイン>/<IP>` と `cname=<別名>,<正規名>` の行を同等のレコードに変換する。
+
+use crate::db::{CreateRecordRequest, PatternType};
+use serde::Serialize;
+use std::net::IpAddr;
+
+/// 取り込んだレコードに付けるタグ
+pub const DNSMASQ_TAG: &str = "dnsmasq";
+
+/// 取り込んだレコードのTTL（cname の行でTTLが指定されていない場合）
+const DEFAULT_TTL: i64 = 60;
+
+/// 取り込まなかった行
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedLine {
+    /// 行番号（1始まり）
+    pub line: usize,
+    pub reason: String,
+}
+
+/// dnsmasq の設定の解析結果
+#[derive(Debug, Default)]
+pub struct DnsmasqImport {
+    /// 変換したレコードと元の行番号
+    pub records: Vec<(usize, CreateRecordRequest)>,
+    pub skipped: Vec<SkippedLine>,
+}
+
+/// dnsmasq の設定を解析してレコードに変換
+/// 空行とコメントは無視し、対応していない設定や解析できない行は `skipped` に理由とともに残す
+pub fn parse_dnsmasq(config: &str) -> DnsmasqImport {
+    let mut import = DnsmasqImport::default();
+
+    for (index, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parsed = match line.split_once('=') {
+            Some(("address", value)) => parse_address(value.trim()),
+            Some(("cname", value)) => parse_cname(value.trim()),
+            _ => Err(format!("対応していない設定です: {}", line)),
+        };
+        match parsed {
+            Ok(records) => import
+                .records
+                .extend(records.into_iter().map(|record| (index + 1, record))),
+            Err(reason) => import.skipped.push(SkippedLine {
+                line: index + 1,
+                reason,
+            }),
+        }
+    }
+
+    import
+}
+
+/// `address=/<ドメイン>[/<ドメイン>...]/<IP>` を解析
+/// dnsmasq と同じくドメイン自身とそのサブドメイン全てに応答するよう、完全一致とワイルドカードの2件にする
+fn parse_address(value: &str) -> Result<Vec<CreateRecordRequest>, String> {
+    let Some((domains, ip)) = value
+        .strip_prefix('/')
+        .and_then(|value| value.rsplit_once('/'))
+    else {
+        return Err(format!("address の形式が不正です: {}", value));
+    };
+
+    let ip = ip.trim();
+    if ip.is_empty() || ip == "#" {
+        return Err(format!("ローカルのみで解決する address（{}）には対応していません", value));
+    }
+    let ip: IpAddr = ip
+        .parse()
+        .map_err(|_| format!("IPアドレスが不正です: {}", ip))?;
+    let record_type = if ip.is_ipv4() { "A" } else { "AAAA" };
+
+    let domains = split_domains(domains)?;
+    if domains.iter().any(|domain| *domain == "#") {
+        return Err("全ドメインに一致する address（#）には対応していません".to_string());
+    }
+
+    Ok(domains
+        .into_iter()
+        .flat_map(|domain| {
+            [domain.to_string(), format!("%.{}", domain)]
+                .map(|pattern| record(pattern, record_type, ip.to_string(), DEFAULT_TTL))
+        })
+        .collect())
+}
+
+/// `cname=<別名>[,<別名>...],<正規名>[,<TTL>]` を解析
+fn parse_cname(value: &str) -> Result<Vec<CreateRecordRequest>, String> {
+    let mut fields: Vec<&str> = value.split(',').map(str::trim).collect();
+
+    // 3つ以上の要素があり末尾が数値ならTTL
+    let mut ttl = DEFAULT_TTL;
+    if fields.len() >= 3 {
+        if let Ok(parsed) = fields[fields.len() - 1].parse::<i64>() {
+            ttl = parsed;
+            fields.pop();
+        }
+    }
+
+    let Some((target, aliases)) = fields.split_last() else {
+        return Err(format!("cname の形式が不正です: {}", value));
+    };
+    let target = target.trim_end_matches('.');
+    if aliases.is_empty() || target.is_empty() || aliases.iter().any(|alias| alias.is_empty()) {
+        return Err(format!("cname の形式が不正です: {}", value));
+    }
+
+    Ok(aliases
+        .iter()
+        .map(|alias| {
+            let alias = alias.trim_end_matches('.').to_string();
+            record(alias, "CNAME", target.to_string(), ttl)
+        })
+        .collect())
+}
+
+/// `/` 区切りのドメイン一覧を分割（前後のドットは取り除く）
+fn split_domains(domains: &str) -> Result<Vec<&str>, String> {
+    let domains: Vec<&str> = domains
+        .split('/')
+        .map(|domain| domain.trim().trim_matches('.'))
+        .filter(|domain| !domain.is_empty())
+        .collect();
+    if domains.is_empty() {
+        return Err("ドメインが指定されていません".to_string());
+    }
+    Ok(domains)
+}
+
+fn record(
+    domain_pattern: String,
+    record_type: &str,
+    content: String,
+    ttl: i64,
+) -> CreateRecordRequest {
+    CreateRecordRequest {
+        domain_pattern,
+        record_type: record_type.to_string(),
+        content: content.into(),
+        ttl,
+        priority: 0,
+        weight: 1,
+        passthrough: false,
+        pattern_type: PatternType::Glob,
+        client_subnet: None,
+        tags: vec![DNSMASQ_TAG.to_string()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (ドメインパターン, タイプ, コンテンツ, TTL) の一覧にする
+    fn summary(import: &DnsmasqImport) -> Vec<(String, String, String, i64)> {
+        import
+            .records
+            .iter()
+            .map(|(_, record)| {
+                (
+                    record.domain_pattern.clone(),
+                    record.record_type.clone(),
+                    record.content.values()[0].clone(),
+                    record.ttl,
+                )
+            })
+            .collect()
+    }
+
+    fn entry(
+        pattern: &str,
+        record_type: &str,
+        content: &str,
+        ttl: i64,
+    ) -> (String, String, String, i64) {
+        (pattern.to_string(), record_type.to_string(), content.to_string(), ttl)
+    }
+
+    #[test]
+    fn test_parse_address_includes_subdomains() {
+        let import = parse_dnsmasq(
+            "# 開発環境\n\
+             address=/foo.local/1.2.3.4\n\
+             \n\
+             address=/a.test/b.test/fd00::1\n",
+        );
+
+        assert_eq!(
+            summary(&import),
+            vec![
+                entry("foo.local", "A", "1.2.3.4", 60),
+                entry("%.foo.local", "A", "1.2.3.4", 60),
+                entry("a.test", "AAAA", "fd00::1", 60),
+                entry("%.a.test", "AAAA", "fd00::1", 60),
+                entry("b.test", "AAAA", "fd00::1", 60),
+                entry("%.b.test", "AAAA", "fd00::1", 60),
+            ]
+        );
+        assert!(import.records.iter().all(|(_, record)| record.tags == vec![DNSMASQ_TAG]));
+        assert_eq!(import.records[2].0, 4);
+        assert!(import.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_parse_cname() {
+        let import = parse_dnsmasq(
+            "cname=www.corp.local,web.corp.local\n\
+             cname=a.corp.local,b.corp.local,target.corp.local,300\n",
+        );
+
+        assert_eq!(
+            summary(&import),
+            vec![
+                entry("www.corp.local", "CNAME", "web.corp.local", 60),
+                entry("a.corp.local", "CNAME", "target.corp.local", 300),
+                entry("b.corp.local", "CNAME", "target.corp.local", 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_lines_are_skipped() {
+        let import = parse_dnsmasq(
+            "server=8.8.8.8\n\
+             address=/blocked.test/\n\
+             address=/#/127.0.0.1\n\
+             address=/bad.test/not-an-ip\n\
+             cname=lonely.test\n\
+             address=/ok.test/10.0.0.1\n",
+        );
+
+        assert_eq!(import.records.len(), 2);
+        assert_eq!(
+            import.skipped.iter().map(|skipped| skipped.line).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5]
+        );
+    }
+}
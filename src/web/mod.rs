@@ -1,5 +1,9 @@
 pub mod api;
+pub mod import;
+pub mod proxy;
 pub mod router;
+#[cfg(unix)]
+pub mod unix;
 
 pub use api::create_api_routes;
-pub use router::create_router;
+pub use router::{build_cors_layer, create_router, RequestLimits};
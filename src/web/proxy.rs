@@ -0,0 +1,199 @@
+//! PROXY プロトコル v2 付きの Web API の待ち受け（HAProxy などのロードバランサー配下で使う）
+//!
+//! 接続の先頭で受け取った PROXY ヘッダーから元のクライアントアドレスを取り出し、
+//! `ConnectInfo<SocketAddr>` としてリクエストに付けてから HTTP/1 を処理する。
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{ConnectInfo, Request},
+    Router,
+};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::TcpListener;
+use tower::Service;
+use tracing::{debug, error, warn};
+
+/// PROXY プロトコル v2 のシグネチャ
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// PROXY ヘッダーを受け取るまでの制限時間
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// PROXY プロトコル v2 のヘッダーを読み、元のクライアントアドレスを返す
+///
+/// ロードバランサー自身のヘルスチェックなどの LOCAL コマンドと、TCP 以外のアドレスファミリーは None を返す
+/// （接続元のアドレスをそのまま使う）。ヘッダーが無い・壊れている場合はエラー。
+pub async fn read_proxy_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<Option<SocketAddr>> {
+    let mut header = [0u8; 16];
+    stream
+        .read_exact(&mut header)
+        .await
+        .context("PROXY ヘッダーの読み込みに失敗")?;
+    if header[..12] != SIGNATURE {
+        bail!("PROXY プロトコル v2 のヘッダーがありません");
+    }
+    if header[12] >> 4 != 2 {
+        bail!("未対応の PROXY プロトコルのバージョンです: {}", header[12] >> 4);
+    }
+    let command = header[12] & 0x0f;
+    let family = header[13];
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+
+    let mut payload = vec![0u8; len];
+    stream
+        .read_exact(&mut payload)
+        .await
+        .context("PROXY ヘッダーのアドレスの読み込みに失敗")?;
+
+    match command {
+        // LOCAL: ロードバランサー自身からの接続
+        0 => return Ok(None),
+        1 => {}
+        _ => bail!("未対応の PROXY コマンドです: {}", command),
+    }
+
+    let addr = match family {
+        // TCP over IPv4: 送信元アドレス、宛先アドレス、送信元ポート、宛先ポート
+        0x11 if len >= 12 => {
+            let ip = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            SocketAddr::from((ip, u16::from_be_bytes([payload[8], payload[9]])))
+        }
+        // TCP over IPv6
+        0x21 if len >= 36 => {
+            let octets: [u8; 16] = payload[..16].try_into().unwrap();
+            SocketAddr::from((
+                Ipv6Addr::from(octets),
+                u16::from_be_bytes([payload[32], payload[33]]),
+            ))
+        }
+        0x11 | 0x21 => bail!("PROXY ヘッダーのアドレスが短すぎます: {} バイト", len),
+        _ => return Ok(None),
+    };
+    Ok(Some(addr))
+}
+
+/// PROXY ヘッダー付きの TCP 接続に Web API を提供し続ける
+/// ヘッダーの無い接続は、ロードバランサーを経由しない接続として受け付けずに切断する
+pub async fn serve_proxy_protocol(listener: TcpListener, app: Router) {
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Web API の接続受け付けに失敗: {}", e);
+                continue;
+            }
+        };
+
+        let app = app.clone();
+        tokio::spawn(async move {
+            let header = tokio::time::timeout(HEADER_TIMEOUT, read_proxy_header(&mut stream));
+            let client = match header.await {
+                Ok(Ok(addr)) => addr.unwrap_or(peer),
+                Ok(Err(e)) => {
+                    warn!("PROXY ヘッダーが不正なため切断します: {} ({:#})", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    warn!("PROXY ヘッダーを受信できないため切断します: {}", peer);
+                    return;
+                }
+            };
+
+            let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(client));
+                app.clone().call(request)
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Web API の接続を終了: {} ({})", client, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+
+    /// TCP over IPv4 の PROXY ヘッダーを作成
+    fn proxy_header_v4(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+        let (SocketAddr::V4(src), SocketAddr::V4(dst)) = (src, dst) else {
+            unreachable!();
+        };
+        let mut header = SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        header.extend_from_slice(&src.ip().octets());
+        header.extend_from_slice(&dst.ip().octets());
+        header.extend_from_slice(&src.port().to_be_bytes());
+        header.extend_from_slice(&dst.port().to_be_bytes());
+        header
+    }
+
+    #[tokio::test]
+    async fn test_read_proxy_header() {
+        let src: SocketAddr = "203.0.113.7:51234".parse().unwrap();
+        let header = proxy_header_v4(src, "10.0.0.1:3000".parse().unwrap());
+        assert_eq!(read_proxy_header(&mut header.as_slice()).await.unwrap(), Some(src));
+
+        // IPv6（TLV付き）
+        let mut header = SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x21, 0, 40]);
+        header.extend_from_slice(&"2001:db8::7".parse::<Ipv6Addr>().unwrap().octets());
+        header.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        header.extend_from_slice(&[0xc8, 0x22, 0x0b, 0xb8]);
+        header.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            read_proxy_header(&mut header.as_slice()).await.unwrap(),
+            Some("[2001:db8::7]:51234".parse().unwrap())
+        );
+
+        // LOCAL コマンドはアドレスなし
+        let mut header = SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(read_proxy_header(&mut header.as_slice()).await.unwrap(), None);
+
+        // ヘッダーの無い HTTP リクエストはエラー
+        let request = b"GET /api/health HTTP/1.1\r\n\r\n".to_vec();
+        assert!(read_proxy_header(&mut request.as_slice()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_from_proxy_header() {
+        let app = Router::new().route(
+            "/ip",
+            get(|ConnectInfo(addr): ConnectInfo<SocketAddr>| async move {
+                addr.ip().to_string()
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_proxy_protocol(listener, app));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(&proxy_header_v4("203.0.113.7:51234".parse().unwrap(), addr))
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET /ip HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("203.0.113.7"));
+
+        server.abort();
+    }
+}
@@ -1,33 +1,169 @@
 use axum::{
     body::Body,
-    http::{header, Request, Response, StatusCode},
-    routing::get,
+    extract::DefaultBodyLimit,
+    http::{header, HeaderName, HeaderValue, Request, Response, StatusCode},
+    middleware::{self, Next},
     Router,
 };
+#[cfg(feature = "embedded-ui")]
+use axum::routing::get;
+#[cfg(feature = "embedded-ui")]
 use include_dir::{include_dir, Dir};
-use tower_http::cors::{Any, CorsLayer};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::compression::{
+    predicate::{NotForContentType, Predicate},
+    CompressionLayer, DefaultPredicate,
+};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+use tracing::{info_span, warn, Instrument};
 
 /// ビルド済みのフロントエンドファイルを埋め込み
+#[cfg(feature = "embedded-ui")]
 static STATIC_DIR: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/web-ui/dist");
 
-/// Webルーターを作成
-pub fn create_router(api_router: Router) -> Router {
-    // CORSレイヤー（開発用）
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
+/// リクエストIDのヘッダー（ゲートウェイが付けたものを引き継ぎ、無ければ採番する）
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// 引き継ぐリクエストIDの最大長（ログを汚さないよう、これを超えるものは採番し直す）
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// リクエストID（リクエストの extensions に格納する）
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+tokio::task_local! {
+    /// 処理中のリクエストのID（エラーレスポンスに含めるため）
+    static CURRENT_REQUEST_ID: String;
+}
+
+/// 処理中のリクエストのID（リクエストの処理中以外は None）
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.try_with(String::clone).ok()
+}
+
+/// CORSレイヤーを作成
+/// `allowed_origins` はカンマ区切りのオリジン一覧。空の場合は全オリジンを許可する（開発用）
+pub fn build_cors_layer(allowed_origins: &str) -> CorsLayer {
+    let origins: Vec<HeaderValue> = allowed_origins
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                warn!("不正なCORSオリジンを無視: {}", origin);
+                None
+            }
+        })
+        .collect();
+
+    let allow_origin = if origins.is_empty() {
+        AllowOrigin::from(Any)
+    } else {
+        AllowOrigin::list(origins)
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([REQUEST_ID_HEADER.clone()])
+}
 
-    Router::new()
-        .merge(api_router)
-        // 静的ファイル配信（SPAフォールバック付き）
-        .fallback(get(serve_static))
+/// リクエストの上限（0 は無制限）
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// リクエストボディの最大バイト数（超過時は 413）
+    pub max_body_bytes: usize,
+    /// 同時に処理するリクエスト数の上限（超過分は空きが出るまで待機）
+    pub max_concurrent_requests: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 1024 * 1024,
+            max_concurrent_requests: 256,
+        }
+    }
+}
+
+/// Webルーターを作成
+pub fn create_router(api_router: Router, cors: CorsLayer, limits: RequestLimits) -> Router {
+    let max_body_bytes = match limits.max_body_bytes {
+        0 => usize::MAX,
+        bytes => bytes,
+    };
+    let max_concurrent_requests = match limits.max_concurrent_requests {
+        0 => tokio::sync::Semaphore::MAX_PERMITS,
+        requests => requests,
+    };
+
+    let router = Router::new().merge(api_router);
+    // 静的ファイル配信（SPAフォールバック付き）。UIを埋め込まないビルドでは API 以外は 404
+    #[cfg(feature = "embedded-ui")]
+    let router = router.fallback(get(serve_static));
+    #[cfg(not(feature = "embedded-ui"))]
+    let router = router.fallback(|| async { not_found_response() });
+
+    router
+        // 抽出時の既定の上限（2MB）ではなく設定した上限を使う
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(max_body_bytes))
+        // ルートごとではなくサーバー全体で同時処理数を制限する
+        .layer(GlobalConcurrencyLimitLayer::new(max_concurrent_requests))
+        .layer(compression_layer())
         .layer(cors)
+        .layer(middleware::from_fn(request_id_middleware))
+}
+
+/// `X-Request-Id` を読み取り（無い場合は採番し）、処理全体のスパンとエラーレスポンスに含めて
+/// レスポンスヘッダーでも返す
+async fn request_id_middleware(mut req: Request<Body>, next: Next) -> Response<Body> {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    req.extensions_mut().insert(RequestId(request_id.clone()));
+
+    let span = info_span!(
+        "http_request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.uri().path()
+    );
+    let mut response = CURRENT_REQUEST_ID
+        .scope(request_id.clone(), next.run(req))
+        .instrument(span)
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER.clone(), value);
+    }
+    response
+}
+
+/// レスポンス圧縮レイヤーを作成（gzip/brotli、`Accept-Encoding` に応じて選択）
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(compression_predicate())
+}
+
+/// 圧縮対象の判定
+/// 画像など既定で除外される形式に加え、圧縮済みのWebフォント（woff/woff2）も対象外にする
+fn compression_predicate() -> impl Predicate {
+    DefaultPredicate::new().and(NotForContentType::const_new("font/woff"))
 }
 
 /// 静的ファイルを配信（SPAフォールバック対応）
+#[cfg(feature = "embedded-ui")]
 async fn serve_static(req: Request<Body>) -> Response<Body> {
     let path = req.uri().path().trim_start_matches('/');
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH);
 
     // パスが空または拡張子がない場合はindex.htmlを返す（SPAルーティング対応）
     let file_path = if path.is_empty() || !path.contains('.') {
@@ -38,24 +174,11 @@ async fn serve_static(req: Request<Body>) -> Response<Body> {
 
     // ファイルを取得
     if let Some(file) = STATIC_DIR.get_file(file_path) {
-        let mime_type = get_mime_type(file_path);
-        let body = Body::from(file.contents().to_vec());
-
-        Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, mime_type)
-            .body(body)
-            .unwrap()
+        file_response(file_path, file.contents(), if_none_match)
     } else if file_path != "index.html" {
         // ファイルが見つからない場合はindex.htmlにフォールバック（SPA対応）
         if let Some(index) = STATIC_DIR.get_file("index.html") {
-            let body = Body::from(index.contents().to_vec());
-
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-                .body(body)
-                .unwrap()
+            file_response("index.html", index.contents(), if_none_match)
         } else {
             not_found_response()
         }
@@ -64,7 +187,71 @@ async fn serve_static(req: Request<Body>) -> Response<Body> {
     }
 }
 
+/// 埋め込みファイルのレスポンスを作成
+/// ETag が `If-None-Match` と一致する場合は本文なしの 304 を返す
+#[cfg(feature = "embedded-ui")]
+fn file_response(
+    file_path: &str,
+    contents: &'static [u8],
+    if_none_match: Option<&HeaderValue>,
+) -> Response<Body> {
+    let etag = compute_etag(contents);
+    let builder = Response::builder()
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, cache_control(file_path));
+
+    if if_none_match.is_some_and(|value| etag_matches(value, &etag)) {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    builder
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, get_mime_type(file_path))
+        .body(Body::from(contents))
+        .unwrap()
+}
+
+/// キャッシュ方針を取得
+/// Vite がハッシュ付きファイル名で出力する `assets/` 配下は長期キャッシュし、
+/// それ以外（index.html など）は毎回再検証させる
+#[cfg(feature = "embedded-ui")]
+fn cache_control(file_path: &str) -> &'static str {
+    if file_path.starts_with("assets/") {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    }
+}
+
+/// ファイル内容のハッシュから ETag を作成
+#[cfg(feature = "embedded-ui")]
+fn compute_etag(contents: &[u8]) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    let mut hasher = DefaultHasher::new();
+    hasher.write(contents);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// `If-None-Match` の値（カンマ区切り、弱いETag・`*` を含む）が ETag に一致するか
+#[cfg(feature = "embedded-ui")]
+fn etag_matches(if_none_match: &HeaderValue, etag: &str) -> bool {
+    let Ok(value) = if_none_match.to_str() else {
+        return false;
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
 /// MIMEタイプを取得
+#[cfg(feature = "embedded-ui")]
 fn get_mime_type(path: &str) -> &'static str {
     match path.rsplit('.').next() {
         Some("html") => "text/html; charset=utf-8",
@@ -91,3 +278,281 @@ fn not_found_response() -> Response<Body> {
         .body(Body::from("Not Found"))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    /// CORS設定付きのテスト用ルーターにリクエストを送り、許可オリジンヘッダーを返す
+    async fn allow_origin_header(allowed_origins: &str, origin: &str) -> Option<HeaderValue> {
+        let api_router = Router::new().route("/api/health", get(|| async { "ok" }));
+        let app = create_router(
+            api_router,
+            build_cors_layer(allowed_origins),
+            RequestLimits::default(),
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/health")
+                    .header(header::ORIGIN, origin)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .cloned()
+    }
+
+    #[tokio::test]
+    async fn test_large_logs_response_is_gzip_compressed() {
+        use crate::db::{init_db, log_query, NewQueryLog};
+        use crate::dns::{RecordCache, UpstreamMetrics};
+        use crate::logger::LogWorker;
+        use crate::web::api::ApiState;
+        use crate::web::create_api_routes;
+
+        let pool = init_db("sqlite::memory:").await.unwrap();
+        for i in 0..100 {
+            log_query(
+                &pool,
+                NewQueryLog {
+                    query_name: format!("host{}.local.test", i),
+                    q_type: "A".to_string(),
+                    result_type: "LOCAL".to_string(),
+                    duration_ms: 1,
+                    upstream_server: None,
+                    query_id: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let cache = RecordCache::new(pool.clone()).await.unwrap();
+        let api_router = create_api_routes(ApiState {
+            pool: pool.clone(),
+            cache,
+            upstream_metrics: UpstreamMetrics::new(),
+            log_worker: LogWorker::new(pool),
+            upstream: None,
+            dns_handler: None,
+        });
+        let app = create_router(api_router, build_cors_layer(""), RequestLimits::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/logs")
+                    .header(header::ACCEPT_ENCODING, "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    }
+
+    #[tokio::test]
+    async fn test_request_body_over_limit_is_rejected() {
+        let api_router = Router::new().route(
+            "/api/echo",
+            axum::routing::post(|body: String| async move { body }),
+        );
+        let limits = RequestLimits {
+            max_body_bytes: 16,
+            ..RequestLimits::default()
+        };
+        let app = create_router(api_router, build_cors_layer(""), limits);
+
+        let request = |body: &'static str| {
+            Request::builder()
+                .method("POST")
+                .uri("/api/echo")
+                .header(header::CONTENT_LENGTH, body.len())
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request("small")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(request("this body is longer than sixteen bytes"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[cfg(not(feature = "embedded-ui"))]
+    #[tokio::test]
+    async fn test_api_only_build_returns_404_for_ui() {
+        let api_router = Router::new().route("/api/health", get(|| async { "ok" }));
+        let app = create_router(api_router, build_cors_layer(""), RequestLimits::default());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_echoed_or_generated() {
+        use crate::web::api::AppError;
+
+        let api_router = Router::new()
+            .route("/api/health", get(|| async { "ok" }))
+            .route(
+                "/api/missing",
+                get(|| async { Err::<(), _>(AppError::NotFound) }),
+            );
+        let app = create_router(api_router, build_cors_layer(""), RequestLimits::default());
+
+        // 指定されたIDはそのまま返す
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/health")
+                    .header(&REQUEST_ID_HEADER, "gateway-123")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers().get(&REQUEST_ID_HEADER).unwrap(), "gateway-123");
+
+        // 無い場合は採番し、エラーレスポンスの本文にも含める
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/missing")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let request_id = response
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(request_id.len(), 16);
+
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["request_id"], request_id);
+    }
+
+    #[cfg(feature = "embedded-ui")]
+    #[test]
+    fn test_js_asset_cache_headers() {
+        let contents: &'static [u8] = b"console.log('hello');";
+        let response = file_response("assets/index-abc123.js", contents, None);
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(
+            response.headers().get(header::ETAG).unwrap(),
+            compute_etag(contents).as_str()
+        );
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/javascript; charset=utf-8"
+        );
+    }
+
+    #[cfg(feature = "embedded-ui")]
+    #[test]
+    fn test_index_html_is_no_cache() {
+        let response = file_response("index.html", b"<html></html>", None);
+        assert_eq!(response.headers().get(header::CACHE_CONTROL).unwrap(), "no-cache");
+    }
+
+    #[cfg(feature = "embedded-ui")]
+    #[test]
+    fn test_not_modified_when_etag_matches() {
+        let contents: &'static [u8] = b"console.log('hello');";
+        let etag = HeaderValue::from_str(&compute_etag(contents)).unwrap();
+
+        let response = file_response("assets/index-abc123.js", contents, Some(&etag));
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+
+        // 内容が変わっていれば 200 を返す
+        let changed: &'static [u8] = b"console.log('changed');";
+        let response = file_response("assets/index-abc123.js", changed, Some(&etag));
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[cfg(feature = "embedded-ui")]
+    #[test]
+    fn test_etag_matches_list_and_weak() {
+        let etag = "\"0123456789abcdef\"";
+        let list = HeaderValue::from_static("\"other\", W/\"0123456789abcdef\"");
+        assert!(etag_matches(&list, etag));
+        assert!(etag_matches(&HeaderValue::from_static("*"), etag));
+        assert!(!etag_matches(&HeaderValue::from_static("\"other\""), etag));
+    }
+
+    #[test]
+    fn test_fonts_are_not_compressed() {
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "font/woff2")
+            .body(Body::from(vec![0u8; 1024]))
+            .unwrap();
+        assert!(!compression_predicate().should_compress(&response));
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_any_origin_when_unset() {
+        let header = allow_origin_header("", "http://evil.example").await;
+        assert_eq!(header.unwrap(), "*");
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_listed_origin() {
+        let header = allow_origin_header(
+            "http://localhost:5173, http://dns.local.test",
+            "http://dns.local.test",
+        )
+        .await;
+        assert_eq!(header.unwrap(), "http://dns.local.test");
+    }
+
+    #[tokio::test]
+    async fn test_cors_rejects_unlisted_origin() {
+        let header = allow_origin_header("http://localhost:5173", "http://evil.example").await;
+        assert!(header.is_none());
+    }
+}
@@ -0,0 +1,108 @@
+//! Unix ドメインソケットでの Web API の待ち受け（同じ Pod のサイドカーなどから使う）
+//!
+//! axum 0.7 の `axum::serve` は TCP のみのため、接続ごとに hyper で HTTP/1 を処理する。
+
+use anyhow::{bail, Context, Result};
+use axum::{extract::Request, Router};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use tokio::net::UnixListener;
+use tower::Service;
+use tracing::{debug, error, warn};
+
+/// 待ち受け中のソケットファイル（破棄時に削除する）
+pub struct UnixSocketFile {
+    path: PathBuf,
+}
+
+impl Drop for UnixSocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("ソケットファイルの削除に失敗: {} ({})", self.path.display(), e);
+        }
+    }
+}
+
+/// Unix ドメインソケットにバインドする
+/// 前回の異常終了で残ったソケットファイルは削除してからバインドする（ソケット以外のファイルがある場合はエラー）
+pub fn bind_unix_socket(path: &Path) -> Result<(UnixListener, UnixSocketFile)> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("ソケット以外のファイルが存在します: {}", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("残っていたソケットファイルの削除に失敗: {}", path.display()))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Unix ドメインソケットのバインドに失敗: {}", path.display()))?;
+    Ok((
+        listener,
+        UnixSocketFile {
+            path: path.to_path_buf(),
+        },
+    ))
+}
+
+/// Unix ドメインソケットで受け付けた接続に Web API を提供し続ける
+pub async fn serve_unix(listener: UnixListener, app: Router) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("Unix ドメインソケットの接続受け付けに失敗: {}", e);
+                continue;
+            }
+        };
+
+        let app = app.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+                app.clone().call(request)
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Unix ドメインソケットの接続を終了: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    #[tokio::test]
+    async fn test_request_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!(
+            "local-dns-test-{}-{:08x}.sock",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let app = Router::new().route("/api/health", get(|| async { "ok" }));
+        let (listener, socket_file) = bind_unix_socket(&path).unwrap();
+        let server = tokio::spawn(serve_unix(listener, app));
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /api/health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("ok"));
+
+        // 終了時にソケットファイルを削除する
+        server.abort();
+        drop(socket_file);
+        assert!(!path.exists());
+    }
+}